@@ -0,0 +1,156 @@
+//! The user's settings for REPL sessions, from `~/.jabronirc`.
+//!
+//! Each line sets one thing, like `prompt = "js> "`, and lines starting with `#` are comments:
+//!
+//! ```text
+//! # Scripts to run at the start of each session. Can be given more than once
+//! prelude = ~/scripts/helpers.jab
+//! prompt = "js> "
+//! history-size = 500
+//! # auto, always or never
+//! color = auto
+//! # Builtins to enable: bytes, events or channels
+//! modules = bytes, events
+//! ```
+
+use anyhow::{bail, Context, Result};
+use jabroni::Jabroni;
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// When to color output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// If stdout is a terminal and `NO_COLOR` isn't set
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                atty::is(atty::Stream::Stdout) && env::var_os("NO_COLOR").is_none()
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+/// Builtins that can be enabled in sessions.
+const MODULES: [&str; 3] = ["bytes", "events", "channels"];
+
+#[derive(Debug)]
+pub struct Config {
+    /// Scripts to run at the start of each session, before any given with `--require`.
+    pub prelude: Vec<PathBuf>,
+    pub prompt: String,
+    /// How many entries to keep in the history.
+    pub history_size: Option<usize>,
+    pub color: ColorChoice,
+    /// Builtins to enable, from [`MODULES`].
+    pub modules: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            prelude: Vec::new(),
+            prompt: "Jabroni> ".into(),
+            history_size: None,
+            color: ColorChoice::Auto,
+            modules: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Where the config is kept if no file is given.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(PathBuf::from(env::var_os("HOME")?).join(".jabronirc"))
+    }
+
+    /// Load the config from `path`, or use the defaults if there's no file there.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).with_context(|| format!("In {}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Couldn't read {}", path.display())),
+        }
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let mut config = Self::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), unquote(value.trim())),
+                None => bail!("Line {}: Expected 'setting = value'", number + 1),
+            };
+            match key {
+                "prelude" => config.prelude.push(expand_home(value)),
+                "prompt" => config.prompt = value.into(),
+                "history-size" => {
+                    let size = value.parse().with_context(|| {
+                        format!("Line {}: Expected a number of entries", number + 1)
+                    })?;
+                    config.history_size = Some(size);
+                }
+                "color" => {
+                    config.color = match value {
+                        "auto" => ColorChoice::Auto,
+                        "always" => ColorChoice::Always,
+                        "never" => ColorChoice::Never,
+                        _ => bail!("Line {}: Expected auto, always or never", number + 1),
+                    }
+                }
+                "modules" => {
+                    for module in value.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+                        if !MODULES.contains(&module) {
+                            bail!("Line {}: Unknown module '{}'", number + 1, module);
+                        }
+                        config.modules.push(module.into());
+                    }
+                }
+                _ => bail!("Line {}: Unknown setting '{}'", number + 1, key),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Enable the builtins the config asks for.
+    pub fn enable_modules(&self, jabroni: &mut Jabroni) -> Result<()> {
+        for module in &self.modules {
+            match module.as_str() {
+                "bytes" => jabroni.enable_bytes()?,
+                "events" => jabroni.enable_events()?,
+                "channels" => jabroni.enable_channels()?,
+                _ => unreachable!("modules are checked when parsing"),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Strip the quotes from a quoted value, which can have spaces at its ends.
+fn unquote(value: &str) -> &str {
+    match (value.strip_prefix('"'), value.len() > 1) {
+        (Some(inner), true) => inner.strip_suffix('"').unwrap_or(value),
+        _ => value,
+    }
+}
+
+/// Expand a leading `~/` to the home directory.
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
@@ -0,0 +1,143 @@
+//! The interactive debugger.
+
+use jabroni::{
+    debug::{DebugEvent, Pause},
+    errors::JabroniResult,
+    Jabroni,
+};
+use rustyline::{error::ReadlineError, Editor};
+use std::collections::HashMap;
+
+const PROMPT: &str = "(debug) ";
+
+const HELP: &str = "Commands:
+    break <line>    Pause before statements on a line
+    clear <line>    Remove a breakpoint
+    step            Run to the next statement, going into function calls
+    next            Run to the next statement, stepping over function calls
+    finish          Run until the current function returns
+    continue        Run to the next breakpoint
+    print <expr>    Show the value of an expression where the script is paused
+    backtrace       Show the functions being run
+    quit            Stop debugging";
+
+/// Debug `code`, called `name`, pausing before its first statement and then taking commands
+/// until it finishes or the user quits.
+pub fn run(jabroni: &mut Jabroni, name: &str, code: &str) {
+    let mut debugger = jabroni.debug(code);
+    let mut rl = Editor::<()>::new();
+    if !report(name, debugger.step_into()) {
+        return;
+    }
+    println!("Enter 'help' for a list of commands");
+    loop {
+        let line = match rl.readline(PROMPT) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(err) => {
+                println!("Error: {:?}", err);
+                break;
+            }
+        };
+        rl.add_history_entry(line.as_str());
+        let (command, arg) = match line.trim().split_once(char::is_whitespace) {
+            Some((command, arg)) => (command, arg.trim()),
+            None => (line.trim(), ""),
+        };
+        let event = match command {
+            "" => continue,
+            "step" | "s" => debugger.step_into(),
+            "next" | "n" => debugger.step_over(),
+            "finish" => debugger.step_out(),
+            "continue" | "c" => debugger.resume(),
+            "break" | "b" | "clear" => {
+                match arg.parse() {
+                    Ok(line) if command == "clear" => debugger.clear_breakpoint(line),
+                    Ok(line) => debugger.set_breakpoint(line),
+                    Err(_) => println!("Expected a line number"),
+                }
+                continue;
+            }
+            "print" | "p" => {
+                match debugger.paused() {
+                    Some(pause) => print(&pause, arg),
+                    None => println!("The script isn't paused"),
+                }
+                continue;
+            }
+            "backtrace" | "bt" => {
+                for frame in debugger
+                    .paused()
+                    .map(|pause| pause.stack)
+                    .unwrap_or_default()
+                {
+                    println!("    {}", frame);
+                }
+                continue;
+            }
+            "help" => {
+                println!("{}", HELP);
+                continue;
+            }
+            "quit" | "q" => break,
+            _ => {
+                println!("Unknown command '{}'. Enter 'help' for a list", command);
+                continue;
+            }
+        };
+        if !report(name, event) {
+            break;
+        }
+    }
+}
+
+/// Show what happened when the script ran. Returns whether there's more to debug.
+fn report(name: &str, event: JabroniResult<DebugEvent>) -> bool {
+    match event {
+        Ok(DebugEvent::Paused(pause)) => {
+            let function = pause.function.as_deref().unwrap_or("<script>");
+            let code = pause.code.lines().next().unwrap_or_default();
+            println!("Paused at {}:{} in {}", name, pause.location, function);
+            println!("    {}", code);
+            true
+        }
+        Ok(DebugEvent::Yielded(value)) => {
+            println!("Yielded {}", value);
+            true
+        }
+        Ok(DebugEvent::Finished(value)) => {
+            println!("Finished with {}", value);
+            false
+        }
+        Err(e) => {
+            println!("{:?}", miette::Report::new(e));
+            false
+        }
+    }
+}
+
+/// Print the value of an expression, evaluated with the bindings visible where the script is
+/// paused. The expression runs in a separate interpreter, so assignments don't change the
+/// script's bindings.
+fn print(pause: &Pause, expression: &str) {
+    if expression.is_empty() {
+        println!("Expected an expression");
+        return;
+    }
+    // Inner scopes shadow outer ones
+    let mut bindings = HashMap::new();
+    for scope in pause.scopes.iter().rev() {
+        for (ident, value) in scope {
+            bindings.insert(ident.as_str(), value.clone());
+        }
+    }
+    let mut scratch = Jabroni::new();
+    for (ident, value) in bindings {
+        // Each name is only defined once, so this can't clash
+        scratch.define_constant(ident, value).ok();
+    }
+    match scratch.run_expression(expression) {
+        Ok(value) => println!("{}", value),
+        Err(e) => println!("{:?}", miette::Report::new(e)),
+    }
+}
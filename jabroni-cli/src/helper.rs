@@ -0,0 +1,112 @@
+//! Editing support for the REPL's prompt.
+
+use jabroni::{
+    complete_with,
+    tokens::{tokenize, Token, TokenKind},
+    BindingMap,
+};
+use rustyline::{
+    completion::Completer, highlight::Highlighter, hint::Hinter, validate::Validator, Context,
+    Helper,
+};
+use std::borrow::Cow;
+
+/// Completes identifiers, keywords and members with the bindings the interpreter had when the
+/// line started, and colors input as it's typed if `color` is on.
+#[derive(Default)]
+pub struct ReplHelper {
+    pub bindings: BindingMap,
+    pub color: bool,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let completions = complete_with(line, pos, &self.bindings);
+        let start = completions.first().map_or(pos, |c| c.span.start);
+        Ok((start, completions.into_iter().map(|c| c.label).collect()))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {
+    /// Color each token by its kind. Brackets without a partner are red, and the partner of the
+    /// bracket at the cursor is bold.
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        if !self.color {
+            return Cow::Borrowed(line);
+        }
+        let tokens = tokenize(line);
+        let partners = bracket_partners(line, &tokens);
+        let at_cursor = tokens.iter().position(|token| {
+            token.kind == TokenKind::Bracket && (token.span.start == pos || token.span.end == pos)
+        });
+        let mut highlighted = String::with_capacity(line.len());
+        let mut end = 0;
+        for (i, token) in tokens.iter().enumerate() {
+            highlighted.push_str(&line[end..token.span.start]);
+            end = token.span.end;
+            let style = match token.kind {
+                TokenKind::Bracket if partners[i].is_none() => "1;31",
+                TokenKind::Bracket if at_cursor.and_then(|j| partners[j]) == Some(i) => "1",
+                TokenKind::Keyword => "35",
+                TokenKind::Null | TokenKind::Number => "33",
+                TokenKind::String => "32",
+                TokenKind::Unknown => "31",
+                _ => {
+                    highlighted.push_str(&line[token.span.start..end]);
+                    continue;
+                }
+            };
+            highlighted.push_str(&format!(
+                "\x1b[{}m{}\x1b[0m",
+                style,
+                &line[token.span.start..end]
+            ));
+        }
+        highlighted.push_str(&line[end..]);
+        Cow::Owned(highlighted)
+    }
+
+    fn highlight_char(&self, _: &str, _: usize) -> bool {
+        self.color
+    }
+}
+
+/// Get the index of the bracket each bracket in `tokens` pairs with, if it has one.
+fn bracket_partners(line: &str, tokens: &[Token]) -> Vec<Option<usize>> {
+    let mut partners = vec![None; tokens.len()];
+    let mut open = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind != TokenKind::Bracket {
+            continue;
+        }
+        match &line[token.span.start..token.span.end] {
+            "(" | "{" => open.push(i),
+            closing => {
+                let opening = if closing == ")" { "(" } else { "{" };
+                if let Some(&j) = open.last() {
+                    if &line[tokens[j].span.start..tokens[j].span.end] == opening {
+                        open.pop();
+                        partners[i] = Some(j);
+                        partners[j] = Some(i);
+                    }
+                }
+            }
+        }
+    }
+    partners
+}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
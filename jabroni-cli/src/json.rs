@@ -0,0 +1,403 @@
+//! Writing values and errors as JSON, for `--output json`, and reading the JSON messages sent
+//! to the language server.
+
+use jabroni::{
+    errors::{ErrorKind, JabroniError},
+    Diagnostic, Object, Value as JabroniValue,
+};
+use std::fmt::Write;
+
+/// How deeply arrays and objects can be nested in JSON that's read, so messages can't exhaust
+/// the stack.
+const MAX_DEPTH: usize = 128;
+
+/// Write a value as JSON. Bytes are written as arrays of numbers, and properties that are
+/// functions are left out, as in JavaScript. Functions themselves and objects that contain
+/// themselves can't be written.
+pub fn value(value: &JabroniValue) -> Result<String, JabroniError> {
+    let mut json = String::new();
+    write_value(&mut json, value, &mut Vec::new())?;
+    Ok(json)
+}
+
+fn write_value(
+    json: &mut String,
+    value: &JabroniValue,
+    parents: &mut Vec<Object>,
+) -> Result<(), JabroniError> {
+    match value {
+        JabroniValue::Number(number) => write!(json, "{}", number).unwrap(),
+        JabroniValue::Boolean(boolean) => write!(json, "{}", boolean).unwrap(),
+        JabroniValue::String(string) => json.push_str(&quote(string)),
+        JabroniValue::Null => json.push_str("null"),
+        JabroniValue::Bytes(bytes) => {
+            let bytes: Vec<_> = bytes.iter().map(u8::to_string).collect();
+            write!(json, "[{}]", bytes.join(",")).unwrap();
+        }
+        JabroniValue::Subroutine(_) => {
+            return Err(unrepresentable("Functions can't be written as JSON"))
+        }
+        JabroniValue::Object(object) => {
+            if parents.iter().any(|parent| parent.ptr_eq(object)) {
+                return Err(unrepresentable(
+                    "Objects that contain themselves can't be written as JSON",
+                ));
+            }
+            parents.push(object.clone());
+            json.push('{');
+            let properties = object
+                .borrow()
+                .iter()
+                .map(|(ident, binding)| Ok((ident.to_string(), binding.read()?)))
+                .collect::<Result<Vec<_>, JabroniError>>()?;
+            let properties = properties
+                .iter()
+                .filter(|(_, value)| !matches!(value, JabroniValue::Subroutine(_)));
+            for (i, (ident, value)) in properties.enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+                json.push_str(&quote(ident));
+                json.push(':');
+                write_value(json, value, parents)?;
+            }
+            json.push('}');
+            parents.pop();
+        }
+    }
+    Ok(())
+}
+
+fn unrepresentable(message: &str) -> JabroniError {
+    JabroniError::new(ErrorKind::Type, message.into())
+}
+
+/// Write an error from the script called `name` as a JSON object, like
+/// `{"error":{"kind":"ParseError","message":"...","source":"a.jab","code":"E0301","line":1,...}}`.
+pub fn error(name: &str, error: &JabroniError) -> String {
+    let mut json = format!(
+        "{{\"error\":{{\"kind\":{},\"message\":{},\"source\":{}",
+        quote(error.kind().name()),
+        quote(error.message()),
+        quote(error.source_name().unwrap_or(name))
+    );
+    if let Some(code) = error.code() {
+        write!(json, ",\"code\":{}", quote(&code.to_string())).unwrap();
+    }
+    if let Some(location) = error.location() {
+        write!(
+            json,
+            ",\"line\":{},\"column\":{}",
+            location.line, location.column
+        )
+        .unwrap();
+    }
+    let stack: Vec<_> = error
+        .stack()
+        .iter()
+        .map(|frame| quote(&frame.to_string()))
+        .collect();
+    write!(json, ",\"stack\":[{}]}}}}", stack.join(",")).unwrap();
+    json
+}
+
+/// Write a warning or other diagnostic from the script called `name` as a JSON object.
+pub fn diagnostic(name: &str, diagnostic: &Diagnostic) -> String {
+    let mut json = format!(
+        "{{\"diagnostic\":{{\"severity\":{},\"message\":{},\"source\":{}",
+        quote(&diagnostic.severity.to_string()),
+        quote(&diagnostic.message),
+        quote(name)
+    );
+    if let Some(code) = &diagnostic.code {
+        write!(json, ",\"code\":{}", quote(code)).unwrap();
+    }
+    write!(
+        json,
+        ",\"line\":{},\"column\":{}}}}}",
+        diagnostic.location.line, diagnostic.location.column
+    )
+    .unwrap();
+    json
+}
+
+/// Quote a string for JSON.
+pub fn quote(string: &str) -> String {
+    let mut quoted = String::with_capacity(string.len() + 2);
+    quoted.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A JSON value that's been read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// Members in the order they were written.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Read a JSON document, or `None` if it isn't valid or is nested too deeply.
+    pub fn parse(text: &str) -> Option<Json> {
+        let mut reader = Reader {
+            text: text.as_bytes(),
+            pos: 0,
+            depth: 0,
+        };
+        let json = reader.value()?;
+        reader.skip_whitespace();
+        (reader.pos == text.len()).then(|| json)
+    }
+
+    /// Get a member of an object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(boolean) => write!(f, "{}", boolean),
+            // JSON has no NaN or infinity
+            Json::Number(number) if !number.is_finite() => write!(f, "null"),
+            Json::Number(number) => write!(f, "{}", number),
+            Json::String(string) => write!(f, "{}", quote(string)),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", quote(key), value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    text: &'a [u8],
+    pos: usize,
+    /// How many arrays and objects the reader is inside.
+    depth: usize,
+}
+
+impl Reader<'_> {
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    /// Skip whitespace, then consume `byte` if it's next.
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.peek() == Some(byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Option<Json> {
+        let found = self.text[self.pos..].starts_with(word.as_bytes());
+        found.then(|| {
+            self.pos += word.len();
+            value
+        })
+    }
+
+    fn value(&mut self) -> Option<Json> {
+        self.skip_whitespace();
+        match self.peek()? {
+            b'n' => self.literal("null", Json::Null),
+            b't' => self.literal("true", Json::Bool(true)),
+            b'f' => self.literal("false", Json::Bool(false)),
+            b'"' => self.string().map(Json::String),
+            b'[' => {
+                self.enter()?;
+                let mut items = Vec::new();
+                if !self.eat(b']') {
+                    loop {
+                        items.push(self.value()?);
+                        if self.eat(b']') {
+                            break;
+                        }
+                        self.eat(b',').then(|| ())?;
+                    }
+                }
+                self.depth -= 1;
+                Some(Json::Array(items))
+            }
+            b'{' => {
+                self.enter()?;
+                let mut members = Vec::new();
+                if !self.eat(b'}') {
+                    loop {
+                        self.skip_whitespace();
+                        let key = self.string()?;
+                        self.eat(b':').then(|| ())?;
+                        members.push((key, self.value()?));
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        self.eat(b',').then(|| ())?;
+                    }
+                }
+                self.depth -= 1;
+                Some(Json::Object(members))
+            }
+            _ => {
+                let start = self.pos;
+                while matches!(
+                    self.peek(),
+                    Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')
+                ) {
+                    self.pos += 1;
+                }
+                let number = std::str::from_utf8(&self.text[start..self.pos]).ok()?;
+                number.parse().ok().map(Json::Number)
+            }
+        }
+    }
+
+    /// Consume the bracket that starts an array or object, unless it's nested too deeply.
+    fn enter(&mut self) -> Option<()> {
+        self.depth += 1;
+        self.pos += 1;
+        (self.depth <= MAX_DEPTH).then(|| ())
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if self.peek() != Some(b'"') {
+            return None;
+        }
+        self.pos += 1;
+        let mut string = Vec::new();
+        loop {
+            match self.peek()? {
+                b'"' => break,
+                b'\\' => {
+                    self.pos += 1;
+                    let escaped = match self.peek()? {
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'u' => {
+                            let unit = self.hex()?;
+                            // Characters outside the basic plane are written as surrogate pairs
+                            let code = if (0xd800..0xdc00).contains(&unit)
+                                && self.text[self.pos + 1..].starts_with(b"\\u")
+                            {
+                                self.pos += 2;
+                                let low = self.hex()?;
+                                0x10000 + ((unit - 0xd800) << 10) + (low.checked_sub(0xdc00)?)
+                            } else {
+                                unit
+                            };
+                            char::from_u32(code).unwrap_or('\u{fffd}')
+                        }
+                        byte => byte as char,
+                    };
+                    let mut buffer = [0; 4];
+                    string.extend_from_slice(escaped.encode_utf8(&mut buffer).as_bytes());
+                }
+                byte => string.push(byte),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        String::from_utf8(string).ok()
+    }
+
+    /// Read the four hex digits after `\u`, leaving the position on the last one.
+    fn hex(&mut self) -> Option<u32> {
+        let digits = self.text.get(self.pos + 1..self.pos + 5)?;
+        let code = u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+        self.pos += 4;
+        Some(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_finite_numbers() {
+        assert_eq!(Json::Number(f64::NAN).to_string(), "null");
+        assert_eq!(Json::Number(f64::INFINITY).to_string(), "null");
+        let json = Json::parse("[1e999, 2.5]").unwrap();
+        assert_eq!(json.to_string(), "[null,2.5]");
+    }
+
+    #[test]
+    fn nesting_depth() {
+        let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(Json::parse(&nested(MAX_DEPTH)).is_some());
+        assert!(Json::parse(&nested(MAX_DEPTH + 1)).is_none());
+        assert!(Json::parse(&"{\"a\":".repeat(100_000)).is_none());
+        // Depth is counted by nesting, not by how many arrays there are
+        let siblings = format!("[{}]", vec![nested(MAX_DEPTH - 1); 3].join(","));
+        assert!(Json::parse(&siblings).is_some());
+    }
+}
@@ -0,0 +1,552 @@
+//! The `kernel` subcommand, a Jupyter kernel, so notebooks can have Jabroni cells.
+//!
+//! Jupyter starts the kernel with a connection file, which says where to bind its ZeroMQ sockets
+//! and the key to sign messages with. Requests arrive on the shell and control sockets and are
+//! answered on the same socket, while what cells print and return is published on the iopub
+//! socket. Cells run in one interpreter, so bindings carry over between them as they do in the
+//! REPL. Values are sent as text, and as JSON too when they can be written as JSON, for frontends
+//! that render it.
+//!
+//! To install the kernel, put `kernelspec/kernel.json` in a directory called `jabroni` and run
+//! `jupyter kernelspec install --user <directory>`.
+
+use crate::{
+    json::{self, Json},
+    pretty::Printer,
+    repl,
+};
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use futures_util::future::{self, Either};
+use hmac::{Hmac, Mac};
+use jabroni::{
+    complete, errors::ErrorCode, Binding, BindingMap, Jabroni, Subroutine, Value as JabroniValue,
+};
+use sha2::Sha256;
+use std::{
+    cell::RefCell,
+    fs,
+    path::Path,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use zeromq::{PubSocket, RepSocket, RouterSocket, Socket, SocketRecv, SocketSend, ZmqMessage};
+
+/// Separates the identities of the sender from the rest of a message.
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+const PROTOCOL_VERSION: &str = "5.3";
+
+/// Where to bind the sockets, from the connection file.
+struct Connection {
+    transport: String,
+    ip: String,
+    shell_port: u16,
+    iopub_port: u16,
+    stdin_port: u16,
+    control_port: u16,
+    hb_port: u16,
+    /// Signs messages, unless the connection file turns signing off with an empty key.
+    key: Option<Hmac<Sha256>>,
+}
+
+impl Connection {
+    fn read(path: &Path) -> Result<Self> {
+        let json = Json::parse(&fs::read_to_string(path)?)
+            .ok_or_else(|| anyhow!("{} isn't valid JSON", path.display()))?;
+        let string = |name| {
+            json.get(name)
+                .and_then(Json::as_str)
+                .ok_or_else(|| anyhow!("The connection file has no '{}'", name))
+        };
+        let port = |name| {
+            json.get(name)
+                .and_then(Json::as_f64)
+                .filter(|port| port.fract() == 0.0 && (1.0..=65535.0).contains(port))
+                .map(|port| port as u16)
+                .ok_or_else(|| anyhow!("The connection file has no valid '{}'", name))
+        };
+        let transport = string("transport")?;
+        if transport != "tcp" {
+            bail!("Only the tcp transport is supported, not '{}'", transport);
+        }
+        let key = match (string("signature_scheme")?, string("key")?) {
+            (_, "") => None,
+            ("hmac-sha256", key) => Some(Hmac::new_from_slice(key.as_bytes())?),
+            (scheme, _) => bail!("Unsupported signature scheme '{}'", scheme),
+        };
+        Ok(Self {
+            transport: transport.into(),
+            ip: string("ip")?.into(),
+            shell_port: port("shell_port")?,
+            iopub_port: port("iopub_port")?,
+            stdin_port: port("stdin_port")?,
+            control_port: port("control_port")?,
+            hb_port: port("hb_port")?,
+            key,
+        })
+    }
+
+    fn endpoint(&self, port: u16) -> String {
+        format!("{}://{}:{}", self.transport, self.ip, port)
+    }
+}
+
+/// A request from a frontend.
+struct Request {
+    /// Where the reply goes.
+    identities: Vec<Bytes>,
+    header: Json,
+    content: Json,
+}
+
+impl Request {
+    fn message_type(&self) -> &str {
+        self.header
+            .get("msg_type")
+            .and_then(Json::as_str)
+            .unwrap_or_default()
+    }
+
+    fn string(&self, name: &str) -> &str {
+        self.content
+            .get(name)
+            .and_then(Json::as_str)
+            .unwrap_or_default()
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.content.get(name) == Some(&Json::Bool(true))
+    }
+}
+
+/// Make a JSON object.
+fn object<'a>(members: impl IntoIterator<Item = (&'a str, Json)>) -> Json {
+    Json::Object(
+        members
+            .into_iter()
+            .map(|(name, value)| (name.into(), value))
+            .collect(),
+    )
+}
+
+fn string(string: impl Into<String>) -> Json {
+    Json::String(string.into())
+}
+
+struct Kernel {
+    jabroni: Jabroni,
+    key: Option<Hmac<Sha256>>,
+    /// Identifies the kernel in the headers of its messages.
+    session: String,
+    /// How many messages have been sent, to give each an ID.
+    sent: u64,
+    /// How many cells have run, which frontends show next to them.
+    execution_count: u64,
+    /// What `console.log` printed while the current cell ran.
+    output: Rc<RefCell<String>>,
+    printer: Printer,
+    iopub: PubSocket,
+}
+
+/// Serve the frontend that started the kernel with `connection_file`, running cells in
+/// `jabroni`, until it's shut down.
+pub fn run(jabroni: Jabroni, connection_file: &Path) -> Result<()> {
+    let connection = Connection::read(connection_file)?;
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(serve(jabroni, connection))
+}
+
+async fn serve(mut jabroni: Jabroni, connection: Connection) -> Result<()> {
+    let mut shell = RouterSocket::new();
+    shell
+        .bind(&connection.endpoint(connection.shell_port))
+        .await?;
+    let mut control = RouterSocket::new();
+    control
+        .bind(&connection.endpoint(connection.control_port))
+        .await?;
+    let mut iopub = PubSocket::new();
+    iopub
+        .bind(&connection.endpoint(connection.iopub_port))
+        .await?;
+    // Cells can't read input from the frontend, but the socket is still expected to be there
+    let mut stdin = RouterSocket::new();
+    stdin
+        .bind(&connection.endpoint(connection.stdin_port))
+        .await?;
+    let mut heartbeat = RepSocket::new();
+    heartbeat
+        .bind(&connection.endpoint(connection.hb_port))
+        .await?;
+    // Frontends check the kernel is alive by having it echo messages, even while a cell runs
+    tokio::spawn(async move {
+        while let Ok(message) = heartbeat.recv().await {
+            if heartbeat.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let output = Rc::new(RefCell::new(String::new()));
+    capture_output(&mut jabroni, output.clone());
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    let mut kernel = Kernel {
+        jabroni,
+        key: connection.key,
+        session: format!("{:x}-{:x}", std::process::id(), now.as_nanos()),
+        sent: 0,
+        execution_count: 0,
+        output,
+        printer: Printer::new(false),
+        iopub,
+    };
+    loop {
+        let (message, from_shell) =
+            match future::select(Box::pin(shell.recv()), Box::pin(control.recv())).await {
+                Either::Left((message, _)) => (message?, true),
+                Either::Right((message, _)) => (message?, false),
+            };
+        let request = match kernel.decode(message) {
+            Some(request) => request,
+            // Messages that aren't signed with the key are ignored
+            None => continue,
+        };
+        let socket = match from_shell {
+            true => &mut shell,
+            false => &mut control,
+        };
+        if !kernel.serve(socket, &request).await? {
+            return Ok(());
+        }
+    }
+}
+
+/// Make `console.log` write to `output` instead of stdout, so what cells print can be sent to
+/// the frontend.
+fn capture_output(jabroni: &mut Jabroni, output: Rc<RefCell<String>>) {
+    let log = Subroutine::new_variadic(Box::new(move |_: BindingMap, args| {
+        let line: Vec<_> = args.iter().map(JabroniValue::to_string).collect();
+        let mut output = output.borrow_mut();
+        output.push_str(&line.join(" "));
+        output.push('\n');
+        Ok(JabroniValue::Null)
+    }))
+    .named("log")
+    .documented(
+        "console.log(...values)",
+        "Print values on a line, separated by spaces.",
+    );
+    let console = jabroni
+        .bindings()
+        .get("console")
+        .ok()
+        .and_then(|binding| binding.value().as_object().cloned());
+    if let Some(console) = console {
+        console.borrow_mut().set(
+            "log".into(),
+            Binding::constant(JabroniValue::Subroutine(log)),
+        );
+    }
+}
+
+impl Kernel {
+    /// Sign the parts of a message, as a hex digest.
+    fn sign(&self, parts: &[&[u8]]) -> String {
+        let mut mac = match self.key.clone() {
+            Some(mac) => mac,
+            None => return String::new(),
+        };
+        for part in parts {
+            mac.update(part);
+        }
+        let digest = mac.finalize().into_bytes();
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Check the hex signature of the parts of a message, in constant time so how long it takes
+    /// doesn't reveal how much of a forged signature is right.
+    fn verify(&self, parts: &[&[u8]], signature: &[u8]) -> bool {
+        let mut mac = match self.key.clone() {
+            Some(mac) => mac,
+            None => return true,
+        };
+        for part in parts {
+            mac.update(part);
+        }
+        match decode_hex(signature) {
+            Some(signature) => mac.verify_slice(&signature).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Read a request, if it's well formed and signed with the key.
+    fn decode(&self, message: ZmqMessage) -> Option<Request> {
+        let mut frames = message.into_vec();
+        let delimiter = frames.iter().position(|frame| frame == DELIMITER)?;
+        let parts = frames.split_off(delimiter + 1);
+        frames.pop();
+        let (signature, parts) = parts.split_first()?;
+        let parts = parts.get(..4)?;
+        let parts: Vec<&[u8]> = parts.iter().map(|part| &part[..]).collect();
+        if !self.verify(&parts, signature) {
+            return None;
+        }
+        let parse = |part: &[u8]| Json::parse(std::str::from_utf8(part).ok()?);
+        Some(Request {
+            identities: frames,
+            header: parse(parts[0])?,
+            content: parse(parts[3])?,
+        })
+    }
+
+    /// Make a message of type `message_type` in response to `parent`, addressed to
+    /// `identities`.
+    fn encode(
+        &mut self,
+        identities: &[Bytes],
+        parent: &Request,
+        message_type: &str,
+        content: Json,
+    ) -> ZmqMessage {
+        self.sent += 1;
+        let header = object([
+            ("msg_id", string(format!("{}-{}", self.session, self.sent))),
+            ("session", string(&self.session)),
+            ("username", string("kernel")),
+            ("date", string(timestamp())),
+            ("msg_type", string(message_type)),
+            ("version", string(PROTOCOL_VERSION)),
+        ]);
+        let parts = [
+            header.to_string(),
+            parent.header.to_string(),
+            "{}".to_string(),
+            content.to_string(),
+        ];
+        let signed: Vec<_> = parts.iter().map(String::as_bytes).collect();
+        let signature = self.sign(&signed);
+        let mut frames = identities.to_vec();
+        frames.push(Bytes::from_static(DELIMITER));
+        frames.push(signature.into());
+        frames.extend(parts.map(Bytes::from));
+        ZmqMessage::try_from(frames).unwrap() // Safe because there's always a delimiter
+    }
+
+    /// Publish a message on the iopub socket.
+    async fn publish(&mut self, parent: &Request, message_type: &str, content: Json) -> Result<()> {
+        let message = self.encode(&[], parent, message_type, content);
+        self.iopub.send(message).await?;
+        Ok(())
+    }
+
+    /// Answer a request on `socket`, the socket it came from. Returns whether to keep serving.
+    async fn serve(&mut self, socket: &mut RouterSocket, request: &Request) -> Result<bool> {
+        let state = |state| object([("execution_state", string(state))]);
+        self.publish(request, "status", state("busy")).await?;
+        let mut serving = true;
+        let (reply_type, content) = match request.message_type() {
+            "kernel_info_request" => ("kernel_info_reply", kernel_info()),
+            "execute_request" => ("execute_reply", self.execute(request).await?),
+            "is_complete_request" => ("is_complete_reply", is_complete(request.string("code"))),
+            "complete_request" => ("complete_reply", self.complete(request)),
+            "shutdown_request" => {
+                serving = false;
+                let restart = Json::Bool(request.flag("restart"));
+                let content = object([("status", string("ok")), ("restart", restart)]);
+                ("shutdown_reply", content)
+            }
+            // Other requests, such as for inspecting code or history, aren't supported
+            _ => {
+                self.publish(request, "status", state("idle")).await?;
+                return Ok(true);
+            }
+        };
+        let reply = self.encode(&request.identities, request, reply_type, content);
+        socket.send(reply).await?;
+        self.publish(request, "status", state("idle")).await?;
+        Ok(serving)
+    }
+
+    /// Run a cell, publishing what it prints and returns, and make the reply.
+    async fn execute(&mut self, request: &Request) -> Result<Json> {
+        let code = request.string("code");
+        let silent = request.flag("silent");
+        if !silent {
+            self.execution_count += 1;
+            let input = object([("code", string(code)), ("execution_count", self.count())]);
+            self.publish(request, "execute_input", input).await?;
+        }
+
+        self.output.borrow_mut().clear();
+        let name = format!("In [{}]", self.execution_count);
+        let result = match self.jabroni.run_expression(code) {
+            Err(error) if error.code() == Some(ErrorCode::Syntax) => {
+                self.jabroni.run_script_named(&name, code)
+            }
+            result => result,
+        };
+        let output = self.output.take();
+        if !output.is_empty() && !silent {
+            let stream = object([("name", string("stdout")), ("text", string(output))]);
+            self.publish(request, "stream", stream).await?;
+        }
+
+        match result {
+            Ok(value) => {
+                if value != JabroniValue::Null && !silent {
+                    let mut data = vec![("text/plain", string(self.printer.print(&value)))];
+                    if let Some(json) = json::value(&value).ok().and_then(|json| Json::parse(&json))
+                    {
+                        data.push(("application/json", json));
+                    }
+                    let result = object([
+                        ("execution_count", self.count()),
+                        ("data", object(data)),
+                        ("metadata", object([])),
+                    ]);
+                    self.publish(request, "execute_result", result).await?;
+                }
+                Ok(object([
+                    ("status", string("ok")),
+                    ("execution_count", self.count()),
+                    ("user_expressions", object([])),
+                    ("payload", Json::Array(Vec::new())),
+                ]))
+            }
+            Err(error) => {
+                let mut traceback = vec![string(format!(
+                    "{}: {}",
+                    error.kind().name(),
+                    error.message()
+                ))];
+                traceback.extend(
+                    error
+                        .stack()
+                        .iter()
+                        .map(|frame| string(format!("    at {}", frame))),
+                );
+                let error = [
+                    ("ename", string(error.kind().name())),
+                    ("evalue", string(error.message())),
+                    ("traceback", Json::Array(traceback)),
+                ];
+                if !silent {
+                    self.publish(request, "error", object(error.clone()))
+                        .await?;
+                }
+                let mut reply = vec![
+                    ("status", string("error")),
+                    ("execution_count", self.count()),
+                ];
+                reply.extend(error);
+                Ok(object(reply))
+            }
+        }
+    }
+
+    fn count(&self) -> Json {
+        Json::Number(self.execution_count as f64)
+    }
+
+    /// Complete the word at the cursor. Frontends count positions in characters.
+    fn complete(&self, request: &Request) -> Json {
+        let code = request.string("code");
+        let cursor = request
+            .content
+            .get("cursor_pos")
+            .and_then(Json::as_f64)
+            .unwrap_or_default() as usize;
+        let offset = code
+            .char_indices()
+            .nth(cursor)
+            .map_or(code.len(), |(offset, _)| offset);
+        let characters = |offset: usize| Json::Number(code[..offset].chars().count() as f64);
+        let completions = complete(code, offset, &self.jabroni);
+        let start = completions.first().map_or(offset, |first| first.span.start);
+        let matches = completions
+            .into_iter()
+            .map(|completion| string(completion.label))
+            .collect();
+        object([
+            ("status", string("ok")),
+            ("matches", Json::Array(matches)),
+            ("cursor_start", characters(start)),
+            ("cursor_end", characters(offset)),
+            ("metadata", object([])),
+        ])
+    }
+}
+
+/// Read hex digits as bytes, or `None` if they aren't valid hex.
+fn decode_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+fn kernel_info() -> Json {
+    let version = string(env!("CARGO_PKG_VERSION"));
+    object([
+        ("status", string("ok")),
+        ("protocol_version", string(PROTOCOL_VERSION)),
+        ("implementation", string("jabroni")),
+        ("implementation_version", version.clone()),
+        (
+            "language_info",
+            object([
+                ("name", string("jabroni")),
+                ("version", version),
+                ("mimetype", string("text/x-jabroni")),
+                ("file_extension", string(".jab")),
+            ]),
+        ),
+        ("banner", string("Jabroni")),
+        ("help_links", Json::Array(Vec::new())),
+    ])
+}
+
+/// Say whether a cell can run yet, or stops partway through a string or brackets.
+fn is_complete(code: &str) -> Json {
+    match repl::is_incomplete(code) {
+        true => object([("status", string("incomplete")), ("indent", string(""))]),
+        false => object([("status", string("complete"))]),
+    }
+}
+
+/// The current time in UTC, in ISO 8601 format.
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let (days, seconds) = (now.as_secs() / 86400, now.as_secs() % 86400);
+    // Convert days since 1970 to a date in the proleptic Gregorian calendar
+    let days = days as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        now.subsec_micros()
+    )
+}
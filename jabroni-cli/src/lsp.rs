@@ -0,0 +1,498 @@
+//! The `lsp` subcommand, a language server for editors.
+//!
+//! Messages are JSON-RPC, framed with `Content-Length` headers, over stdin and stdout. Documents
+//! are synced in full on each change. The server reports syntax errors, type errors and lint
+//! warnings, and answers hover, go-to-definition and completion requests.
+
+use crate::json::{quote, Json};
+use anyhow::Result;
+use jabroni::{
+    ast::{self, Expression, ExpressionKind, Script, Span, Statement, StatementKind},
+    errors::Location,
+    lint::Linter,
+    tokens::{tokenize, Token, TokenKind},
+    CompletionKind, Diagnostic, Jabroni, Severity, Value as JabroniValue,
+};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+const METHOD_NOT_FOUND: i32 = -32601;
+const PARSE_ERROR: i32 = -32700;
+
+struct Server {
+    /// Provides the globals that scripts are checked and completed against.
+    jabroni: Jabroni,
+    /// The text of each open document, by URI.
+    documents: HashMap<String, String>,
+    shut_down: bool,
+}
+
+/// Serve requests until the client says to exit, checking scripts against the bindings in
+/// `jabroni`.
+pub fn run(jabroni: Jabroni) -> Result<()> {
+    let mut server = Server {
+        jabroni,
+        documents: HashMap::new(),
+        shut_down: false,
+    };
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+    while let Some(body) = read_message(&mut stdin)? {
+        let message = match Json::parse(&body) {
+            Some(message) => message,
+            None => {
+                send_error(&Json::Null, PARSE_ERROR, "Invalid JSON")?;
+                continue;
+            }
+        };
+        let method = message.get("method").and_then(Json::as_str);
+        let params = message.get("params").unwrap_or(&Json::Null);
+        match (method, message.get("id")) {
+            (Some("exit"), _) => break,
+            (Some(method), Some(id)) => match server.request(method, params) {
+                Some(result) => send(&format!(
+                    "{{\"jsonrpc\":\"2.0\",\"id\":{},\"result\":{}}}",
+                    id, result
+                ))?,
+                None => send_error(id, METHOD_NOT_FOUND, &format!("Unknown method {}", method))?,
+            },
+            (Some(method), None) => server.notification(method, params)?,
+            // Responses to requests the server never makes
+            (None, _) => {}
+        }
+    }
+    std::process::exit(if server.shut_down { 0 } else { 1 })
+}
+
+/// Read the body of the next message, or `None` at the end of input.
+fn read_message(input: &mut impl BufRead) -> Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(value.trim().parse()?);
+            }
+        }
+    }
+    let length = length.ok_or_else(|| anyhow::anyhow!("Message has no Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(String::from_utf8(body)?))
+}
+
+fn send(body: &str) -> Result<()> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn send_error(id: &Json, code: i32, message: &str) -> Result<()> {
+    send(&format!(
+        "{{\"jsonrpc\":\"2.0\",\"id\":{},\"error\":{{\"code\":{},\"message\":{}}}}}",
+        id,
+        code,
+        quote(message)
+    ))
+}
+
+impl Server {
+    /// Answer a request, returning the result as JSON, or `None` if the method isn't supported.
+    fn request(&mut self, method: &str, params: &Json) -> Option<String> {
+        let result = match method {
+            "initialize" => concat!(
+                "{\"capabilities\":{\"textDocumentSync\":1,\"hoverProvider\":true,",
+                "\"definitionProvider\":true,\"completionProvider\":{\"triggerCharacters\":[\".\"]}},",
+                "\"serverInfo\":{\"name\":\"jabroni\"}}"
+            )
+            .into(),
+            "shutdown" => {
+                self.shut_down = true;
+                "null".into()
+            }
+            "textDocument/hover" => self.at_position(params, hover),
+            "textDocument/definition" => self.at_position(params, |code, offset, _| {
+                let uri = params.get("textDocument")?.get("uri")?;
+                let (definition, _) = definition_at(code, offset)?;
+                Some(format!(
+                    "{{\"uri\":{},\"range\":{}}}",
+                    uri,
+                    range(code, definition.span)
+                ))
+            }),
+            "textDocument/completion" => self.at_position(params, |code, offset, jabroni| {
+                Some(complete(code, offset, jabroni))
+            }),
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    /// Run `answer` with the document and offset a request is about, giving `null` if there's
+    /// no answer.
+    fn at_position(
+        &self,
+        params: &Json,
+        answer: impl FnOnce(&str, usize, &Jabroni) -> Option<String>,
+    ) -> String {
+        let code = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri"))
+            .and_then(Json::as_str)
+            .and_then(|uri| self.documents.get(uri));
+        let position = params.get("position");
+        let line = position.and_then(|p| p.get("line")).and_then(Json::as_f64);
+        let character = position
+            .and_then(|p| p.get("character"))
+            .and_then(Json::as_f64);
+        match (code, line, character) {
+            (Some(code), Some(line), Some(character)) => {
+                let offset = offset_at(code, line as usize, character as usize);
+                answer(code, offset, &self.jabroni)
+            }
+            _ => None,
+        }
+        .unwrap_or_else(|| "null".into())
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Result<()> {
+        let document = params.get("textDocument");
+        let uri = match document.and_then(|d| d.get("uri")).and_then(Json::as_str) {
+            Some(uri) => uri.to_string(),
+            None => return Ok(()),
+        };
+        let text = match method {
+            "textDocument/didOpen" => document.and_then(|d| d.get("text")),
+            // Changes are always the whole document, so only the last one matters
+            "textDocument/didChange" => params
+                .get("contentChanges")
+                .and_then(Json::as_array)
+                .and_then(|changes| changes.last())
+                .and_then(|change| change.get("text")),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return publish(&uri, "", &[]);
+            }
+            _ => return Ok(()),
+        };
+        if let Some(text) = text.and_then(Json::as_str) {
+            let mut diagnostics = self.jabroni.typecheck(text);
+            if let Ok(warnings) = Linter::all().lint(text) {
+                diagnostics.extend(warnings);
+            }
+            publish(&uri, text, &diagnostics)?;
+            self.documents.insert(uri, text.into());
+        }
+        Ok(())
+    }
+}
+
+fn publish(uri: &str, code: &str, diagnostics: &[Diagnostic]) -> Result<()> {
+    let diagnostics: Vec<_> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let start = location_offset(code, diagnostic.location);
+            // Underline the token the problem starts at
+            let end = tokenize(code)
+                .iter()
+                .find(|token| token.span.start == start)
+                .map_or(start, |token| token.span.end);
+            let mut json = format!(
+                "{{\"range\":{},\"severity\":{},\"source\":\"jabroni\",\"message\":{}",
+                range(code, Span { start, end }),
+                if diagnostic.severity == Severity::Error {
+                    1
+                } else {
+                    2
+                },
+                quote(&diagnostic.message)
+            );
+            if let Some(code) = &diagnostic.code {
+                json.push_str(&format!(",\"code\":{}", quote(code)));
+            }
+            json.push('}');
+            json
+        })
+        .collect();
+    send(&format!(
+        "{{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/publishDiagnostics\",\
+         \"params\":{{\"uri\":{},\"diagnostics\":[{}]}}}}",
+        quote(uri),
+        diagnostics.join(",")
+    ))
+}
+
+/// A binding declared in a script.
+struct Definition {
+    /// The identifier that declares it.
+    span: Span,
+    /// The part of the script it can be used in.
+    scope: Span,
+    /// How it was declared, such as `function f(a, b)`.
+    detail: String,
+}
+
+/// Collect the bindings declared in `statements`, which are in `scope`.
+fn definitions(statements: &[Statement], scope: Span, found: &mut Vec<(String, Definition)>) {
+    for statement in statements {
+        let mut define = |name: &ast::Ident, scope: Span, detail: String| {
+            let definition = Definition {
+                span: name.span,
+                scope,
+                detail,
+            };
+            found.push((name.name.clone(), definition));
+        };
+        match &statement.kind {
+            StatementKind::Function { name, params, body } => {
+                let names: Vec<_> = params.iter().map(|param| param.name.as_str()).collect();
+                define(
+                    name,
+                    scope,
+                    format!("function {}({})", name.name, names.join(", ")),
+                );
+                for param in params {
+                    define(param, statement.span, format!("(parameter) {}", param.name));
+                }
+                definitions(body, statement.span, found);
+            }
+            StatementKind::Declaration { kind, name, value } => {
+                let keyword = match kind {
+                    ast::DeclarationKind::Const => "const",
+                    ast::DeclarationKind::Let => "let",
+                };
+                define(
+                    name,
+                    scope,
+                    format!("{} {}{}", keyword, name.name, describe_literal(value)),
+                );
+            }
+            StatementKind::Try {
+                body,
+                binding,
+                handler,
+            } => {
+                define(
+                    binding,
+                    statement.span,
+                    format!("(exception) {}", binding.name),
+                );
+                definitions(body, statement.span, found);
+                definitions(handler, statement.span, found);
+            }
+            StatementKind::Import { names, specifier } => {
+                for name in names {
+                    let detail = format!("import {{ {} }} from {}", name.name, quote(specifier));
+                    define(name, scope, detail);
+                }
+            }
+            StatementKind::Block(body) => definitions(body, statement.span, found),
+            _ => {}
+        }
+    }
+}
+
+/// Describe the value a binding is declared with, if it's a literal, like `: number = 5`.
+fn describe_literal(value: &Expression) -> String {
+    match &value.kind {
+        ExpressionKind::Literal(value) => format!(": {} = {}", value.type_name(), show(value)),
+        _ => String::new(),
+    }
+}
+
+/// Show a value as it would be written in a script.
+fn show(value: &JabroniValue) -> String {
+    match value {
+        JabroniValue::String(string) => jabroni::fmt::quote(string),
+        value => value.to_string(),
+    }
+}
+
+/// Find the identifier at `offset`, if there's one there, and the identifiers before it that it's
+/// a member of. Returns the whole chain, like `["console", "log"]`, and the identifier's token.
+fn ident_at(code: &str, offset: usize) -> Option<(Vec<String>, Token)> {
+    let tokens = tokenize(code);
+    let i = tokens.iter().position(|token| {
+        token.kind == TokenKind::Ident && token.span.start <= offset && offset <= token.span.end
+    })?;
+    let mut path = vec![text(code, tokens[i])];
+    let mut j = i;
+    while j >= 2 && text(code, tokens[j - 1]) == "." && tokens[j - 2].kind == TokenKind::Ident {
+        path.insert(0, text(code, tokens[j - 2]));
+        j -= 2;
+    }
+    Some((path, tokens[i]))
+}
+
+fn text(code: &str, token: Token) -> String {
+    code[token.span.start..token.span.end].into()
+}
+
+/// Find where the binding used at `offset` is declared, along with the identifier's token. When
+/// a name is declared more than once, the innermost declaration wins.
+fn definition_at(code: &str, offset: usize) -> Option<(Definition, Token)> {
+    let (path, token) = ident_at(code, offset)?;
+    if path.len() > 1 {
+        return None;
+    }
+    let script = parse_before(code, offset)?;
+    visible(&script, code, offset)
+        .into_iter()
+        .filter(|(name, _)| *name == path[0])
+        .map(|(_, definition)| definition)
+        .min_by_key(|definition| definition.scope.end - definition.scope.start)
+        .map(|definition| (definition, token))
+}
+
+/// Parse a script being edited at `offset`. If it doesn't parse, which is usual while a line is
+/// being typed, the lines before the one being edited are parsed instead.
+fn parse_before(code: &str, offset: usize) -> Option<Script> {
+    ast::parse(code).ok().or_else(|| {
+        let line_start = code[..offset].rfind('\n').map_or(0, |i| i + 1);
+        ast::parse(&code[..line_start]).ok()
+    })
+}
+
+/// The bindings declared in `script` that can be used at `offset`.
+fn visible(script: &Script, code: &str, offset: usize) -> Vec<(String, Definition)> {
+    let mut found = Vec::new();
+    let whole = Span {
+        start: 0,
+        end: code.len(),
+    };
+    definitions(&script.statements, whole, &mut found);
+    found.retain(|(_, definition)| {
+        definition.scope.start <= offset && offset <= definition.scope.end
+    });
+    found
+}
+
+/// Describe the binding at `offset`: how it's declared if it's declared in the script, or its
+/// type and value if it's a global.
+fn hover(code: &str, offset: usize, jabroni: &Jabroni) -> Option<String> {
+    let (markdown, token) = match definition_at(code, offset) {
+        Some((definition, token)) => (code_block(&definition.detail), token),
+        None => {
+            let (path, token) = ident_at(code, offset)?;
+            let mut value = jabroni.bindings().get(&path[0]).ok()?.value().clone();
+            for ident in &path[1..] {
+                let object = value.as_object()?.clone();
+                let member = object.borrow().get(ident).ok()?.value().clone();
+                value = member;
+            }
+            let path = path.join(".");
+            let markdown = match &value {
+                JabroniValue::Subroutine(subroutine) => {
+                    let signature = subroutine
+                        .signature()
+                        .map_or_else(|| format!("{}: {}", path, value), String::from);
+                    match subroutine.doc() {
+                        Some(doc) => format!("{}\n{}", code_block(&signature), doc),
+                        None => code_block(&signature),
+                    }
+                }
+                JabroniValue::Object(_) => code_block(&format!("{}: object", path)),
+                _ => code_block(&format!(
+                    "{}: {} = {}",
+                    path,
+                    value.type_name(),
+                    show(&value)
+                )),
+            };
+            (markdown, token)
+        }
+    };
+    Some(format!(
+        "{{\"contents\":{{\"kind\":\"markdown\",\"value\":{}}},\"range\":{}}}",
+        quote(&markdown),
+        range(code, token.span)
+    ))
+}
+
+fn code_block(code: &str) -> String {
+    format!("```jabroni\n{}\n```\n", code)
+}
+
+/// List what could be typed at `offset`, as completion items.
+fn complete(code: &str, offset: usize, jabroni: &Jabroni) -> String {
+    let items: Vec<_> = jabroni::complete(code, offset, jabroni)
+        .into_iter()
+        .map(|completion| {
+            // Kinds of completion items, from the protocol
+            let kind = match completion.kind {
+                CompletionKind::Function => 3,
+                CompletionKind::Variable => 6,
+                CompletionKind::Property => 10,
+                CompletionKind::Keyword => 14,
+            };
+            let mut item = format!(
+                "{{\"label\":{},\"kind\":{},\"textEdit\":{{\"range\":{},\"newText\":{}}}",
+                quote(&completion.label),
+                kind,
+                range(code, completion.span),
+                quote(&completion.label)
+            );
+            if let Some(detail) = &completion.detail {
+                item.push_str(&format!(",\"detail\":{}", quote(detail)));
+            }
+            item.push('}');
+            item
+        })
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+/// Convert a position in the protocol's terms, a line and a count of UTF-16 code units into it,
+/// to a byte offset into `code`.
+fn offset_at(code: &str, line: usize, character: usize) -> usize {
+    let start: usize = code.split_inclusive('\n').take(line).map(str::len).sum();
+    let mut units = 0;
+    for (i, c) in code[start..].char_indices() {
+        if units >= character || c == '\n' {
+            return start + i;
+        }
+        units += c.len_utf16();
+    }
+    code.len()
+}
+
+/// Convert a byte offset into `code` to a position in the protocol's terms.
+fn position(code: &str, offset: usize) -> String {
+    let before = &code[..offset];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    format!("{{\"line\":{},\"character\":{}}}", line, character)
+}
+
+fn range(code: &str, span: Span) -> String {
+    format!(
+        "{{\"start\":{},\"end\":{}}}",
+        position(code, span.start),
+        position(code, span.end)
+    )
+}
+
+/// Convert a location, with its column in characters, to a byte offset into `code`.
+fn location_offset(code: &str, location: Location) -> usize {
+    let start: usize = code
+        .split_inclusive('\n')
+        .take(location.line - 1)
+        .map(str::len)
+        .sum();
+    code[start..]
+        .char_indices()
+        .nth(location.column - 1)
+        .map_or(code.len(), |(i, _)| start + i)
+}
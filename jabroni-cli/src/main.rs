@@ -1,56 +1,622 @@
 use anyhow::Result;
-use jabroni::{Binding, BindingMap, Jabroni, Subroutine, Value as JabroniValue};
-use rustyline::{error::ReadlineError, Editor};
-use std::{fmt::Debug, fs, path::PathBuf};
+use config::Config;
+use jabroni::{
+    ast,
+    errors::{ErrorCode, ErrorKind, JabroniError, JabroniResult},
+    fmt,
+    lint::{Linter, Rule},
+    tokens::tokenize,
+    Binding, BindingMap, Diagnostic, Jabroni, ModuleLoader, Number, Subroutine,
+    Value as JabroniValue,
+};
+use std::{
+    cell::RefCell,
+    ffi::OsStr,
+    fmt::Debug,
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    str::FromStr,
+};
 use structopt::StructOpt;
 
+mod config;
+mod debugger;
+mod helper;
+mod json;
+#[cfg(feature = "kernel")]
+mod kernel;
+mod lsp;
+mod pretty;
+mod repl;
+mod testing;
+mod watch;
+
 #[derive(Debug, StructOpt)]
-#[structopt(name = "jabroni", about = "Jabroni interpreter")]
+#[structopt(
+    name = "jabroni",
+    about = "Jabroni interpreter",
+    after_help = "EXIT STATUS:
+    0 on success, or the status passed to `exit(n)`
+    1 if an exception wasn't caught
+    2 on a parse error
+    3 on a type error
+    4 on a reference error
+    5 on invalid arguments
+    6 on a double definition
+    7 on a failed conversion
+    8 on a permission error
+    9 on an async error
+    10 on unsupported syntax
+    11 on going past a limit"
+)]
 struct Opt {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+    /// Script to run, or `-` to read it from stdin. Read from stdin too if there's no file and
+    /// stdin isn't a terminal
     file: Option<PathBuf>,
+    /// Evaluate an expression and print its value
+    #[structopt(short, long, conflicts_with_all = &["file", "script-eval"])]
+    eval: Option<String>,
+    /// Run statements, printing the value of the last one unless it's null
+    #[structopt(long, conflicts_with = "file")]
+    script_eval: Option<String>,
+    /// Report syntax errors in the script without running it, exiting with the status for the
+    /// first error as if it had run
+    #[structopt(long, conflicts_with_all = &["eval", "script-eval"])]
+    check: bool,
+    /// Report type errors too when checking, using the types of the interpreter's globals
+    #[structopt(long, requires = "check")]
+    typecheck: bool,
+    /// Report style and correctness problems in the script without running it
+    #[structopt(long, conflicts_with_all = &["eval", "script-eval"])]
+    lint: bool,
+    /// Lint rule to skip, such as `unused-binding`. Can be given more than once
+    #[structopt(long, number_of_values = 1, requires = "lint")]
+    allow: Vec<String>,
+    /// Arguments for the script, given after `--`. Scripts read them with `process.args.at(i)`,
+    /// and count them with `process.args.length`
+    #[structopt(last = true)]
+    args: Vec<String>,
+    /// Script to run first, such as one defining helpers, before the script or REPL. Can be
+    /// given more than once, and they run in order
+    #[structopt(short, long, number_of_values = 1, parse(from_os_str))]
+    require: Vec<PathBuf>,
+    /// Exit with the value of the script as the status if it's a number
+    #[structopt(long)]
+    exit_with_value: bool,
+    /// How to print results: `text`, or `json` to print the value of the script or expression as
+    /// JSON, with errors and warnings as JSON objects on stderr
+    #[structopt(long, default_value = "text", possible_values = &["text", "json"])]
+    output: Output,
+    /// Print how long the script took to parse and to run, to stderr
+    #[structopt(long)]
+    time: bool,
+    /// Print the instructions, allocations, host calls and peak memory the script used, to stderr
+    #[structopt(long)]
+    stats: bool,
+    /// Run the script again whenever it or a module it imports changes
+    #[structopt(long, requires = "file", conflicts_with_all = &["check", "lint"])]
+    watch: bool,
+    /// Print the tokens of the script or expression instead of running it
+    #[structopt(long)]
+    dump_tokens: bool,
+    /// Print the syntax tree of the script or expression instead of running it
+    #[structopt(long)]
+    dump_ast: bool,
+    /// File to configure REPL sessions with, instead of `~/.jabronirc`
+    #[structopt(long, env = "JABRONI_CONFIG", parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// File to keep the REPL's history in, instead of `~/.local/share/jabroni/history`
+    #[structopt(long, env = "JABRONI_HISTORY", parse(from_os_str))]
+    history: Option<PathBuf>,
+}
+
+/// How to print the value of a script, and its errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Text,
+    Json,
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(output: &str) -> Result<Self, String> {
+        match output {
+            "text" => Ok(Output::Text),
+            "json" => Ok(Output::Json),
+            _ => Err(format!("Unknown output format '{}'", output)),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Debug a script, starting paused before its first statement
+    Debug {
+        /// Script to debug
+        file: PathBuf,
+    },
+    /// Run the tests in test scripts, which end in `.test.jab`. Tests are defined with
+    /// `it(name, fn)` and grouped with `describe(name, fn)`, and check things with
+    /// `assert(condition, message)` and `assertEqual(actual, expected)`
+    Test {
+        /// Test script, or directory to look for test scripts in
+        #[structopt(default_value = ".")]
+        path: PathBuf,
+    },
+    /// Run a language server over stdin and stdout, for editors. It reports errors and warnings,
+    /// and shows bindings on hover, goes to their definitions and completes them
+    Lsp,
+    /// Compile a script to a `.jbc` file, which runs like the script without shipping its source
+    Compile {
+        /// Script to compile
+        file: PathBuf,
+        /// File to write, instead of the script's path with a `.jbc` extension
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Run a Jupyter kernel, so notebooks can have Jabroni cells. Bindings carry over between
+    /// cells, as they do in the REPL. Only available if built with the `kernel` feature
+    Kernel {
+        /// Connection file Jupyter passes to the kernel
+        connection_file: PathBuf,
+    },
 }
 
 fn main() -> Result<()> {
     let opt = Opt::from_args();
-    let mut jabroni = build_jabroni_interpreter()?;
+    if opt.dump_tokens || opt.dump_ast {
+        return dump(&opt);
+    }
+    let mut jabroni = build_jabroni_interpreter(opt.args.clone())?;
+    jabroni.set_measure_memory(opt.stats);
 
-    if let Some(file) = opt.file {
-        jabroni.run_script(&fs::read_to_string(file)?)?;
+    if let Some(Command::Debug { file }) = &opt.command {
+        let code = fs::read_to_string(file)?;
+        let root = file.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        preload(&mut jabroni, &opt.require, opt.output)?;
+        jabroni.set_module_loader(FileLoader::new(root));
+        debugger::run(&mut jabroni, &file.display().to_string(), &code);
+    } else if let Some(Command::Test { path }) = &opt.command {
+        let passed = testing::run(path, || {
+            let mut jabroni = build_jabroni_interpreter(opt.args.clone())?;
+            preload(&mut jabroni, &opt.require, opt.output)?;
+            Ok(jabroni)
+        })?;
+        std::process::exit(if passed { 0 } else { 1 });
+    } else if let Some(Command::Lsp) = &opt.command {
+        // Scripts given with --require aren't run, since anything they print would corrupt the
+        // messages on stdout
+        lsp::run(jabroni)?;
+    } else if let Some(Command::Compile { file, output }) = &opt.command {
+        let code = fs::read_to_string(file)?;
+        let script = ast::parse(&code)
+            .unwrap_or_else(|error| fail(opt.output, &file.display().to_string(), error));
+        let bytes = script
+            .to_bytes()
+            .unwrap_or_else(|error| fail(opt.output, &file.display().to_string(), error));
+        let output = output.clone().unwrap_or_else(|| file.with_extension("jbc"));
+        fs::write(&output, bytes)?;
+    } else if let Some(Command::Kernel { connection_file }) = &opt.command {
+        preload(&mut jabroni, &opt.require, opt.output)?;
+        jabroni.set_module_loader(FileLoader::new(PathBuf::new()));
+        run_kernel(jabroni, connection_file)?;
+    } else if let Some(expression) = &opt.eval {
+        preload(&mut jabroni, &opt.require, opt.output)?;
+        jabroni.set_module_loader(FileLoader::new(PathBuf::new()));
+        let value = jabroni.run_expression_named("<eval>", expression);
+        report_costs(&opt, &jabroni);
+        let value = exit_on_error(&jabroni, "<eval>", value, opt.output);
+        print_value(opt.output, "<eval>", &value, true);
+        exit_with_value(opt.exit_with_value, &value);
+    } else if let Some(code) = &opt.script_eval {
+        preload(&mut jabroni, &opt.require, opt.output)?;
+        jabroni.set_module_loader(FileLoader::new(PathBuf::new()));
+        let value = jabroni.run_script_named("<eval>", code);
+        report_costs(&opt, &jabroni);
+        let value = exit_on_error(&jabroni, "<eval>", value, opt.output);
+        print_value(opt.output, "<eval>", &value, value != JabroniValue::Null);
+        exit_with_value(opt.exit_with_value, &value);
+    } else if let (true, Some(file)) = (opt.watch, &opt.file) {
+        watch::run(file, || {
+            let mut jabroni = build_jabroni_interpreter(opt.args.clone())?;
+            preload(&mut jabroni, &opt.require, opt.output)?;
+            Ok(jabroni)
+        })?;
+    } else if let Some(Program {
+        name,
+        code,
+        compiled,
+        root,
+    }) = read_program(opt.file.as_deref())?
+    {
+        if opt.check {
+            let diagnostics = match opt.typecheck {
+                true => jabroni.typecheck(&code),
+                false => Jabroni::check(&code),
+            };
+            report_diagnostics(opt.output, &name, &diagnostics);
+            std::process::exit(diagnostics_status(&diagnostics));
+        }
+        if opt.lint {
+            let mut linter = Linter::all();
+            for name in &opt.allow {
+                let rule = Rule::from_name(name)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown lint rule '{}'", name))?;
+                linter.disable(rule);
+            }
+            let diagnostics = match linter.lint(&code) {
+                Ok(diagnostics) => diagnostics,
+                Err(_) => Jabroni::check(&code),
+            };
+            report_diagnostics(opt.output, &name, &diagnostics);
+            std::process::exit(diagnostics_status(&diagnostics));
+        }
+        preload(&mut jabroni, &opt.require, opt.output)?;
+        jabroni.set_module_loader(FileLoader::new(root));
+        let result = run(&mut jabroni, &name, &code, compiled.as_ref());
+        report_costs(&opt, &jabroni);
+        let value = exit_on_error(&jabroni, &name, result, opt.output);
+        print_value(opt.output, &name, &value, false);
+        exit_with_value(opt.exit_with_value, &value);
+    } else if opt.check || opt.lint {
+        anyhow::bail!("No script given. Pass a file, or '-' to read one from stdin");
     } else {
-        let mut rl = Editor::<()>::new();
-        loop {
-            match rl.readline("Jabroni> ") {
-                Ok(line) => {
-                    rl.add_history_entry(line.as_str());
-                    match jabroni.run_expression(line.trim()) {
-                        Ok(value) => println!("{}", value),
-                        Err(e) => println!("{}", e),
-                    };
-                }
-                Err(ReadlineError::Interrupted) => {
-                    println!("<Ctrl-C>");
-                    break;
-                }
-                Err(ReadlineError::Eof) => {
-                    println!("<Ctrl-D>");
-                    break;
-                }
-                Err(err) => {
-                    println!("Error: {:?}", err);
-                    break;
-                }
+        let config = match opt.config.clone().or_else(Config::default_path) {
+            Some(path) => Config::load(&path)?,
+            None => Config::default(),
+        };
+        let start_session = || {
+            let mut jabroni = build_jabroni_interpreter(opt.args.clone())?;
+            config.enable_modules(&mut jabroni)?;
+            preload(&mut jabroni, &config.prelude, opt.output)?;
+            preload(&mut jabroni, &opt.require, opt.output)?;
+            jabroni.set_module_loader(FileLoader::new(PathBuf::new()));
+            Ok(jabroni)
+        };
+        let mut jabroni = start_session()?;
+        let history = opt.history.clone().or_else(repl::default_history);
+        repl::run(&mut jabroni, start_session, history, &config);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "kernel")]
+fn run_kernel(jabroni: Jabroni, connection_file: &Path) -> Result<()> {
+    kernel::run(jabroni, connection_file)
+}
+
+#[cfg(not(feature = "kernel"))]
+fn run_kernel(_: Jabroni, _: &Path) -> Result<()> {
+    anyhow::bail!("This build has no Jupyter kernel. Build jabroni-cli with the 'kernel' feature")
+}
+
+/// A script to run, and where it came from.
+struct Program {
+    /// The file name, or `<stdin>`.
+    name: String,
+    code: String,
+    /// The syntax tree of a script compiled to a `.jbc` file, which runs instead of the code.
+    compiled: Option<ast::Script>,
+    /// The directory imports are relative to.
+    root: PathBuf,
+}
+
+/// Read the script to run from `file`, or from stdin if the file is `-` or if there's no file
+/// and stdin isn't a terminal. There's no script if there's no file and stdin is a terminal.
+fn read_program(file: Option<&Path>) -> Result<Option<Program>> {
+    match file {
+        Some(file) if file != Path::new("-") => {
+            let (code, compiled) = read_code(file)?;
+            Ok(Some(Program {
+                name: file.display().to_string(),
+                code,
+                compiled,
+                root: file.parent().unwrap_or_else(|| Path::new("")).to_path_buf(),
+            }))
+        }
+        None if atty::is(atty::Stream::Stdin) => Ok(None),
+        _ => {
+            let mut code = String::new();
+            io::stdin().read_to_string(&mut code)?;
+            Ok(Some(Program {
+                name: "<stdin>".into(),
+                code,
+                compiled: None,
+                root: PathBuf::new(),
+            }))
+        }
+    }
+}
+
+/// Read the script in `file`, and its syntax tree if it was compiled to a `.jbc` file. Compiled
+/// scripts are turned back into code too, for checking, linting and dumping, but run as they
+/// were loaded.
+fn read_code(file: &Path) -> Result<(String, Option<ast::Script>)> {
+    if file.extension() != Some(OsStr::new("jbc")) {
+        return Ok((fs::read_to_string(file)?, None));
+    }
+    let script = ast::Script::from_bytes(&fs::read(file)?)?;
+    Ok((fmt::format_script(&script), Some(script)))
+}
+
+/// Run a script called `name`, as its syntax tree if it was compiled.
+fn run(
+    jabroni: &mut Jabroni,
+    name: &str,
+    code: &str,
+    compiled: Option<&ast::Script>,
+) -> JabroniResult<JabroniValue> {
+    match compiled {
+        Some(script) => jabroni.run_compiled(name, script),
+        None => jabroni.run_script_named(name, code),
+    }
+}
+
+/// Print the tokens and/or syntax tree of the code given to the CLI, for debugging the parser.
+fn dump(opt: &Opt) -> Result<()> {
+    let (code, is_expression) = if let Some(expression) = &opt.eval {
+        (expression.clone(), true)
+    } else if let Some(code) = &opt.script_eval {
+        (code.clone(), false)
+    } else {
+        match read_program(opt.file.as_deref())? {
+            Some(program) => (program.code, false),
+            None => anyhow::bail!("No script given. Pass a file, or '-' to read one from stdin"),
+        }
+    };
+    if opt.dump_tokens {
+        for token in tokenize(&code) {
+            let location = token.span.location(&code);
+            println!(
+                "{}:{}\t{:?}\t{}",
+                location.line,
+                location.column,
+                token.kind,
+                &code[token.span.start..token.span.end]
+            );
+        }
+    }
+    if opt.dump_ast {
+        let tree = match is_expression {
+            true => ast::parse_expression(&code).map(|expression| format!("{:#?}", expression)),
+            false => ast::parse(&code).map(|script| format!("{:#?}", script)),
+        };
+        match tree {
+            Ok(tree) => println!("{}", tree),
+            Err(error) => {
+                let status = exit_status(error.kind());
+                eprintln!("{:?}", miette::Report::new(error));
+                std::process::exit(status);
             }
         }
     }
     Ok(())
 }
 
-fn build_jabroni_interpreter() -> Result<Jabroni> {
+/// Run each of `files` in the interpreter, exiting if one fails. Modules they import are
+/// relative to them.
+fn preload(jabroni: &mut Jabroni, files: &[PathBuf], output: Output) -> Result<()> {
+    for file in files {
+        let (code, compiled) = read_code(file)
+            .map_err(|e| anyhow::anyhow!("Couldn't read {}: {}", file.display(), e))?;
+        let root = file.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        jabroni.set_module_loader(FileLoader::new(root));
+        let name = file.display().to_string();
+        let result = run(jabroni, &name, &code, compiled.as_ref());
+        exit_on_error(jabroni, &name, result, output);
+    }
+    Ok(())
+}
+
+/// Print what the last run cost, if asked to with `--time` or `--stats`.
+fn report_costs(opt: &Opt, jabroni: &Jabroni) {
+    let stats = jabroni.last_run_stats();
+    if opt.time {
+        eprintln!("Parse time:   {:.2?}", stats.parse_time);
+        eprintln!("Run time:     {:.2?}", stats.elapsed - stats.parse_time);
+    }
+    if opt.stats {
+        eprintln!("Instructions: {}", stats.instructions);
+        eprintln!("Allocations:  {}", stats.allocations);
+        eprintln!("Host calls:   {}", stats.host_calls);
+        eprintln!("Peak memory:  {} bytes", stats.peak_memory);
+    }
+}
+
+/// Print the warnings from the last run of code called `name`, and exit if it failed, with a
+/// status for the kind of error.
+fn exit_on_error(
+    jabroni: &Jabroni,
+    name: &str,
+    result: JabroniResult<JabroniValue>,
+    output: Output,
+) -> JabroniValue {
+    report_diagnostics(output, name, &jabroni.diagnostics());
+    result.unwrap_or_else(|error| fail(output, name, error))
+}
+
+/// Print an error from code called `name`, and exit with a status for its kind.
+fn fail(output: Output, name: &str, error: JabroniError) -> ! {
+    let status = exit_status(error.kind());
+    match output {
+        Output::Text => eprintln!("{:?}", miette::Report::new(error)),
+        Output::Json => eprintln!("{}", json::error(name, &error)),
+    }
+    std::process::exit(status);
+}
+
+/// Print warnings and other diagnostics from the script called `name` to stderr.
+fn report_diagnostics(output: Output, name: &str, diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        match output {
+            Output::Text => eprintln!("{}:{}", name, diagnostic),
+            Output::Json => eprintln!("{}", json::diagnostic(name, diagnostic)),
+        }
+    }
+}
+
+/// Print the value of code called `name`: as text if `show_text` is true, or as JSON with
+/// `--output json`.
+fn print_value(output: Output, name: &str, value: &JabroniValue, show_text: bool) {
+    match output {
+        Output::Text if show_text => println!("{}", value),
+        Output::Text => (),
+        Output::Json => match json::value(value) {
+            Ok(json) => println!("{}", json),
+            Err(error) => fail(output, name, error),
+        },
+    }
+}
+
+/// The status to exit with for a kind of error, as listed in the help.
+fn exit_status(kind: ErrorKind) -> i32 {
+    match kind {
+        ErrorKind::Exception => 1,
+        ErrorKind::Parse => 2,
+        ErrorKind::Type => 3,
+        ErrorKind::Reference => 4,
+        ErrorKind::InvalidArguments => 5,
+        ErrorKind::DoubleDefinition => 6,
+        ErrorKind::Deserialize => 7,
+        ErrorKind::Permission => 8,
+        ErrorKind::Async => 9,
+        ErrorKind::Unsupported => 10,
+        ErrorKind::Limit => 11,
+    }
+}
+
+/// The status to exit with after reporting diagnostics: 0 if there are none, the status for the
+/// error the first one describes, as if the script had run, or 1 for warnings.
+fn diagnostics_status(diagnostics: &[Diagnostic]) -> i32 {
+    match diagnostics.first() {
+        None => 0,
+        Some(Diagnostic {
+            error_code: Some(code),
+            ..
+        }) => exit_status(code.kind()),
+        Some(_) => 1,
+    }
+}
+
+/// Exit with the value of a script as the status, if `enabled` and it's a number that fits.
+fn exit_with_value(enabled: bool, value: &JabroniValue) {
+    if let (true, Some(status)) = (enabled, value.as_number()) {
+        if let Ok(status) = i32::try_from(*status) {
+            std::process::exit(status);
+        }
+    }
+}
+
+/// Loads modules from files relative to `root`. The extension can be left out of specifiers.
+struct FileLoader {
+    root: PathBuf,
+    /// The files modules have been loaded from.
+    loaded: Rc<RefCell<Vec<PathBuf>>>,
+}
+
+impl FileLoader {
+    fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            loaded: Default::default(),
+        }
+    }
+}
+
+impl ModuleLoader for FileLoader {
+    fn load(&self, specifier: &str) -> JabroniResult<String> {
+        let path = self.root.join(specifier);
+        let candidates = [
+            path.clone(),
+            path.with_extension("jab"),
+            path.with_extension("js"),
+        ];
+        let path = candidates
+            .iter()
+            .find(|path| path.is_file())
+            .ok_or_else(|| {
+                JabroniError::from_code(
+                    ErrorCode::ModuleNotFound,
+                    format!("Could not find module '{specifier}'"),
+                )
+            })?;
+        self.loaded.borrow_mut().push(path.clone());
+        fs::read_to_string(path).map_err(|e| {
+            JabroniError::from_code(
+                ErrorCode::ModuleNotFound,
+                format!("Could not load '{specifier}': {e}"),
+            )
+        })
+    }
+}
+
+/// The `process` object, with the arguments passed to the script.
+fn process_object(args: Vec<String>) -> Result<BindingMap> {
+    let mut script_args = BindingMap::default();
+    script_args.set(
+        "length".into(),
+        Binding::constant(JabroniValue::Number(Number::try_from(args.len())?)),
+    );
+    let at = Subroutine::new(
+        1,
+        Box::new(move |_, values: &mut [JabroniValue]| {
+            let index = *values[0].as_number().ok_or_else(|| {
+                JabroniError::from_code(ErrorCode::InvalidArgument, "Expected an index".into())
+            })?;
+            // Negative indices count back from the end
+            let index = match index < 0 {
+                true => args.len().checked_sub(index.unsigned_abs() as usize),
+                false => Some(index as usize),
+            };
+            Ok(index
+                .and_then(|index| args.get(index))
+                .map_or(JabroniValue::Null, |arg| {
+                    JabroniValue::String(arg.as_str().into())
+                }))
+        }),
+    );
+    script_args.set(
+        "at".into(),
+        Binding::constant(JabroniValue::Subroutine(at.named("at").documented(
+            "process.args.at(index)",
+            "The argument at an index, or null if there's none. Negative indices count back from \
+             the end.",
+        ))),
+    );
+
+    let mut process = BindingMap::default();
+    process.set(
+        "args".into(),
+        Binding::constant(JabroniValue::Object(script_args.into())),
+    );
+    Ok(process)
+}
+
+/// Read a line from stdin without its line ending, or null at the end of the input.
+fn read_line() -> JabroniResult<JabroniValue> {
+    let mut line = String::new();
+    let read = io::stdin().read_line(&mut line).map_err(|e| {
+        JabroniError::new(ErrorKind::Exception, format!("Couldn't read a line: {e}"))
+    })?;
+    if read == 0 {
+        return Ok(JabroniValue::Null);
+    }
+    let line = line.strip_suffix('\n').unwrap_or(&line);
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    Ok(JabroniValue::String(line.into()))
+}
+
+fn build_jabroni_interpreter(args: Vec<String>) -> Result<Jabroni> {
     let mut console = BindingMap::default();
     console.set(
         "log".into(),
-        Binding::constant(JabroniValue::Subroutine(Subroutine::new_variadic(
-            Box::new(|_: BindingMap, args: &mut [JabroniValue]| {
+        Binding::constant(JabroniValue::Subroutine(
+            Subroutine::new_variadic(Box::new(|_: BindingMap, args: &mut [JabroniValue]| {
                 for (i, arg) in args.iter().enumerate() {
                     print!("{}", arg);
                     if i != args.len() - 1 {
@@ -59,11 +625,65 @@ fn build_jabroni_interpreter() -> Result<Jabroni> {
                 }
                 println!();
                 Ok(JabroniValue::Null)
-            }),
-        ))),
+            }))
+            .named("log")
+            .documented(
+                "console.log(...values)",
+                "Print values on a line, separated by spaces.",
+            ),
+        )),
     );
 
     let mut interpreter = Jabroni::new();
-    interpreter.define_constant("console", JabroniValue::Object(console))?;
+    interpreter.define_constant("console", JabroniValue::Object(console.into()))?;
+    let exit = Subroutine::new(
+        1,
+        Box::new(|_, args: &mut [JabroniValue]| {
+            let status = args[0]
+                .as_number()
+                .and_then(|status| i32::try_from(*status).ok())
+                .ok_or_else(|| {
+                    JabroniError::from_code(
+                        ErrorCode::InvalidArgument,
+                        "Expected an exit status".into(),
+                    )
+                })?;
+            io::stdout().flush().ok();
+            std::process::exit(status)
+        }),
+    );
+    let exit = exit.named("exit").documented(
+        "exit(status)",
+        "Stop the script, exiting the process with a status code.",
+    );
+    interpreter.define_constant("exit", JabroniValue::Subroutine(exit))?;
+    interpreter.define_constant(
+        "readLine",
+        JabroniValue::Subroutine(
+            Subroutine::new(0, Box::new(|_, _| read_line()))
+                .named("readLine")
+                .documented(
+                    "readLine()",
+                    "Read a line from stdin, without its line ending, or null at the end of input.",
+                ),
+        ),
+    )?;
+    let prompt = Subroutine::new(
+        1,
+        Box::new(|_, args: &mut [JabroniValue]| {
+            print!("{}", args[0]);
+            io::stdout().flush().ok();
+            read_line()
+        }),
+    );
+    let prompt = prompt.named("prompt").documented(
+        "prompt(question)",
+        "Print question, then read a line from stdin like readLine().",
+    );
+    interpreter.define_constant("prompt", JabroniValue::Subroutine(prompt))?;
+    interpreter.define_constant(
+        "process",
+        JabroniValue::Object(process_object(args)?.into()),
+    )?;
     Ok(interpreter)
 }
@@ -0,0 +1,136 @@
+//! Printing values for people to read, as the REPL shows results.
+
+use jabroni::{fmt::quote, Object, Subroutine, Value as JabroniValue};
+
+/// Objects that fit in this many columns are printed on one line.
+const WIDTH: usize = 72;
+const INDENT: &str = "    ";
+
+/// Prints values with indentation and, optionally, colors, cutting off values too big to read.
+pub struct Printer {
+    /// Color values with ANSI escapes.
+    pub color: bool,
+    /// How deep to print objects inside objects before showing them as `{...}`.
+    pub max_depth: usize,
+    /// How many properties of an object to print before summarizing the rest.
+    pub max_properties: usize,
+    /// How many characters of a string to print before cutting it off.
+    pub max_string: usize,
+}
+
+impl Printer {
+    /// A printer with the default limits.
+    pub fn new(color: bool) -> Self {
+        Self {
+            color,
+            max_depth: 4,
+            max_properties: 50,
+            max_string: 1000,
+        }
+    }
+
+    /// Format a value to print. Strings are printed as they are unless inside an object.
+    pub fn print(&self, value: &JabroniValue) -> String {
+        match value {
+            JabroniValue::String(string) => self.truncate(string),
+            value => self.format(value, 0, &mut Vec::new()),
+        }
+    }
+
+    /// Format a value `depth` objects deep, inside the objects in `parents`.
+    fn format(&self, value: &JabroniValue, depth: usize, parents: &mut Vec<Object>) -> String {
+        match value {
+            JabroniValue::Number(_) | JabroniValue::Boolean(_) => self.paint("33", value),
+            JabroniValue::String(string) => self.paint("32", quote(&self.truncate(string))),
+            JabroniValue::Null => self.paint("1", value),
+            JabroniValue::Bytes(_) => self.paint("35", value),
+            JabroniValue::Subroutine(_) => self.paint("36", value),
+            JabroniValue::Object(object) => self.format_object(object, depth, parents),
+        }
+    }
+
+    fn format_object(&self, object: &Object, depth: usize, parents: &mut Vec<Object>) -> String {
+        if parents.iter().any(|parent| parent.ptr_eq(object)) {
+            return self.paint("36", "[circular]");
+        }
+        let properties = object.borrow();
+        if properties.is_empty() {
+            return "{}".into();
+        }
+        if depth >= self.max_depth {
+            return self.paint("36", "{...}");
+        }
+        parents.push(object.clone());
+        let mut entries: Vec<String> = properties
+            .iter()
+            .take(self.max_properties)
+            .map(|(ident, binding)| {
+                format!(
+                    "{}: {}",
+                    ident,
+                    self.format(
+                        &binding.read().unwrap_or(JabroniValue::Null),
+                        depth + 1,
+                        parents
+                    )
+                )
+            })
+            .collect();
+        parents.pop();
+        if properties.len() > self.max_properties {
+            let more = properties.len() - self.max_properties;
+            entries.push(self.paint("2", format!("... {} more", more)));
+        }
+
+        let inline = format!("{{{}}}", entries.join(", "));
+        let indent = INDENT.len() * depth;
+        if !inline.contains('\n') && indent + visible_width(&inline) <= WIDTH {
+            inline
+        } else {
+            let entries = entries.join(",\n").replace('\n', &format!("\n{}", INDENT));
+            format!("{{\n{}{}\n}}", INDENT, entries)
+        }
+    }
+
+    fn truncate(&self, string: &str) -> String {
+        match string.char_indices().nth(self.max_string) {
+            Some((end, _)) => format!("{}...", &string[..end]),
+            None => string.into(),
+        }
+    }
+
+    /// Color text with an ANSI style, if colors are on.
+    fn paint(&self, style: &str, text: impl ToString) -> String {
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", style, text.to_string())
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// How many columns text takes up, leaving out ANSI escapes.
+fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|&c| c == 'm');
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+/// Describe a function for `:doc` and editors: how to call it, then what it does if it's
+/// documented.
+pub fn doc(subroutine: &Subroutine) -> String {
+    let signature = subroutine
+        .signature()
+        .map_or_else(|| subroutine.to_string(), String::from);
+    match subroutine.doc() {
+        Some(doc) => format!("{}\n\n{}", signature, doc),
+        None => signature,
+    }
+}
@@ -0,0 +1,233 @@
+//! The interactive prompt.
+
+use crate::{
+    config::Config,
+    helper::ReplHelper,
+    pretty::{self, Printer},
+};
+use jabroni::{errors::ErrorCode, Jabroni, Value as JabroniValue};
+use rustyline::{error::ReadlineError, Editor};
+use std::{env, fs, path::PathBuf};
+
+/// Where history is kept if no file is given, under `$XDG_DATA_HOME`, or `~/.local/share` if
+/// that isn't set.
+pub fn default_history() -> Option<PathBuf> {
+    let data = match env::var_os("XDG_DATA_HOME") {
+        Some(data) if !data.is_empty() => PathBuf::from(data),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/share"),
+    };
+    Some(data.join("jabroni/history"))
+}
+
+const HELP: &str = "Enter an expression to see its value, or statements to run them.
+
+Commands:
+    :help          Show this help
+    :doc <expr>    Show how to call a function and what it does
+    :vars          List the bindings in scope and their types
+    :load <file>   Run a script in this session
+    :reset         Start over with a new interpreter
+    :save <file>   Write the entries that ran successfully, and files loaded, to a script
+    :type <expr>   Show the type of an expression's value";
+
+/// Read entries and run them until the user quits, loading history from and saving it to
+/// `history` if there is one. `start_session` makes a new interpreter for `:reset`.
+pub fn run(
+    jabroni: &mut Jabroni,
+    start_session: impl Fn() -> anyhow::Result<Jabroni>,
+    history: Option<PathBuf>,
+    config: &Config,
+) {
+    let mut builder = rustyline::Config::builder();
+    if let Some(size) = config.history_size {
+        builder = builder.max_history_size(size);
+    }
+    let mut rl = Editor::<ReplHelper>::with_config(builder.build());
+    let color = config.color.enabled();
+    rl.set_helper(Some(ReplHelper {
+        color,
+        ..ReplHelper::default()
+    }));
+    let printer = Printer::new(color);
+    // Shown while reading the rest of an entry that isn't complete yet, lined up with the prompt
+    let continuation = format!("{:>1$}", "... ", config.prompt.chars().count());
+    // Entries that ran successfully, and the code of files loaded, for `:save`
+    let mut transcript = Vec::new();
+    if let Some(history) = &history {
+        // There's no history the first time
+        rl.load_history(history).ok();
+    }
+    loop {
+        if let Some(helper) = rl.helper_mut() {
+            helper.bindings = jabroni.bindings().clone();
+        }
+        match read_entry(&mut rl, &config.prompt, &continuation) {
+            Ok(entry) => {
+                if entry.trim().is_empty() {
+                    continue;
+                }
+                rl.add_history_entry(entry.as_str());
+                match entry.trim().strip_prefix(':') {
+                    Some(command) => {
+                        let result = run_command(jabroni, &start_session, &mut transcript, command);
+                        if let Err(err) = result {
+                            println!("Error: {}", err);
+                        }
+                    }
+                    None => {
+                        if let Some(statements) = evaluate(jabroni, &printer, entry.trim()) {
+                            transcript.push(statements);
+                        }
+                    }
+                }
+            }
+            Err(ReadlineError::Interrupted) => {
+                println!("<Ctrl-C>");
+                break;
+            }
+            Err(ReadlineError::Eof) => {
+                println!("<Ctrl-D>");
+                break;
+            }
+            Err(err) => {
+                println!("Error: {:?}", err);
+                break;
+            }
+        }
+    }
+    if let Some(history) = &history {
+        let saved = match history.parent() {
+            Some(parent) => fs::create_dir_all(parent).map_err(ReadlineError::from),
+            None => Ok(()),
+        }
+        .and_then(|_| rl.save_history(history));
+        if let Err(err) = saved {
+            eprintln!("Couldn't save history to {}: {}", history.display(), err);
+        }
+    }
+}
+
+/// Read lines until they form a complete entry. Interrupting a continuation line drops the
+/// entry, leaving it empty.
+fn read_entry(
+    rl: &mut Editor<ReplHelper>,
+    prompt: &str,
+    continuation: &str,
+) -> Result<String, ReadlineError> {
+    let mut entry = rl.readline(prompt)?;
+    // Commands are always one line
+    while !entry.trim_start().starts_with(':') && is_incomplete(&entry) {
+        match rl.readline(continuation) {
+            Ok(line) => {
+                entry.push('\n');
+                entry.push_str(&line);
+            }
+            Err(ReadlineError::Interrupted) => return Ok(String::new()),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(entry)
+}
+
+/// Check if code stops partway through a string or brackets, so the entry goes on to the next
+/// line.
+pub fn is_incomplete(code: &str) -> bool {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut chars = code.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '(' | '{') => depth += 1,
+            (None, ')' | '}') => depth -= 1,
+            (None, _) => (),
+        }
+    }
+    quote.is_some() || depth > 0
+}
+
+/// Run an entry as an expression and print its value, or as statements if it isn't an
+/// expression, printing the value of the last one unless it's null. Returns the entry as
+/// statements for a script if it ran successfully.
+fn evaluate(jabroni: &mut Jabroni, printer: &Printer, entry: &str) -> Option<String> {
+    let result = match jabroni.run_expression(entry) {
+        Err(error) if error.code() == Some(ErrorCode::Syntax) => jabroni
+            .run_script(entry)
+            .map(|value| (value, entry.to_string())),
+        result => result.map(|value| (value, format!("{};", entry))),
+    };
+    match result {
+        Ok((value, statements)) => {
+            if value != JabroniValue::Null {
+                println!("{}", printer.print(&value));
+            }
+            Some(statements)
+        }
+        Err(e) => {
+            println!("{:?}", miette::Report::new(e));
+            None
+        }
+    }
+}
+
+/// Run a command, given without its leading `:`.
+fn run_command(
+    jabroni: &mut Jabroni,
+    start_session: impl Fn() -> anyhow::Result<Jabroni>,
+    transcript: &mut Vec<String>,
+    command: &str,
+) -> anyhow::Result<()> {
+    let (name, arg) = match command.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (command, ""),
+    };
+    match (name, arg) {
+        ("help", _) => println!("{}", HELP),
+        ("vars", _) => {
+            let mut bindings: Vec<_> = jabroni.bindings().iter().collect();
+            bindings.sort_unstable_by_key(|(ident, _)| *ident);
+            for (ident, binding) in bindings {
+                let keyword = if binding.mutable() { "let" } else { "const" };
+                println!("{} {}: {}", keyword, ident, binding.value().type_name());
+            }
+        }
+        ("doc", "") => anyhow::bail!("Expected a function, such as ':doc console.log'"),
+        ("doc", expression) => match jabroni.run_expression(expression) {
+            Ok(JabroniValue::Subroutine(subroutine)) => println!("{}", pretty::doc(&subroutine)),
+            Ok(value) => println!("{} is a {}, not a function", expression, value.type_name()),
+            Err(e) => println!("{:?}", miette::Report::new(e)),
+        },
+        ("load", "") => anyhow::bail!("Expected a file to load"),
+        ("load", path) => {
+            let code = fs::read_to_string(path)?;
+            match jabroni.run_script_named(path, &code) {
+                // The code is saved rather than the command, so saved sessions don't need the file
+                Ok(_) => transcript.push(code.trim_end().to_string()),
+                Err(e) => println!("{:?}", miette::Report::new(e)),
+            }
+        }
+        ("reset", _) => {
+            *jabroni = start_session()?;
+            transcript.clear();
+        }
+        ("save", "") => anyhow::bail!("Expected a file to save to"),
+        ("save", path) => {
+            let mut script = transcript.join("\n");
+            script.push('\n');
+            fs::write(path, script)?;
+            println!("Saved {} entries to {}", transcript.len(), path);
+        }
+        ("type", "") => anyhow::bail!("Expected an expression"),
+        ("type", expression) => match jabroni.run_expression(expression) {
+            Ok(value) => println!("{}", value.type_name()),
+            Err(e) => println!("{:?}", miette::Report::new(e)),
+        },
+        _ => anyhow::bail!("Unknown command ':{}'. Enter ':help' for a list", name),
+    }
+    Ok(())
+}
@@ -0,0 +1,220 @@
+//! The `test` subcommand, which runs test scripts.
+//!
+//! Test scripts are files ending in `.test.jab`. They define tests with `it(name, fn)`, group
+//! them with `describe(name, fn)`, and check things with `assert(condition, message)` and
+//! `assertEqual(actual, expected)`.
+
+use crate::FileLoader;
+use anyhow::Result;
+use jabroni::{
+    errors::{ErrorKind, JabroniError, JabroniResult},
+    Jabroni, Subroutine, Value as JabroniValue,
+};
+use std::{
+    cell::RefCell,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+const EXTENSION: &str = ".test.jab";
+
+/// The outcome of a test.
+struct TestResult {
+    /// The names of the groups the test is in, then the test's own name.
+    name: String,
+    error: Option<JabroniError>,
+}
+
+/// Tests run so far in a file, and the groups being run.
+#[derive(Default)]
+struct Run {
+    groups: Vec<String>,
+    results: Vec<TestResult>,
+}
+
+/// Run the tests in `path`, a test script or a directory to look for them in, each script with a
+/// new interpreter from `new_interpreter`. Returns whether every test passed.
+pub fn run(path: &Path, new_interpreter: impl Fn() -> Result<Jabroni>) -> Result<bool> {
+    if !path.exists() {
+        anyhow::bail!("{} doesn't exist", path.display());
+    }
+    let mut files = Vec::new();
+    find_tests(path, &mut files)?;
+    if files.is_empty() {
+        anyhow::bail!(
+            "No test scripts (*{}) found in {}",
+            EXTENSION,
+            path.display()
+        );
+    }
+    files.sort();
+
+    let (mut passed, mut failed) = (0, 0);
+    let mut failures = Vec::new();
+    for file in &files {
+        let name = file.display().to_string();
+        println!("{}", name);
+        let run = Rc::new(RefCell::new(Run::default()));
+        let mut jabroni = new_interpreter()?;
+        install(&mut jabroni, &run)?;
+        let root = file.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        jabroni.set_module_loader(FileLoader::new(root));
+        let result = fs::read_to_string(file)
+            .map_err(|e| JabroniError::new(ErrorKind::Exception, format!("Couldn't read: {e}")))
+            .and_then(|code| jabroni.run_script_named(&name, &code));
+
+        let mut run = run.borrow_mut();
+        for test in run.results.drain(..) {
+            match test.error {
+                None => {
+                    passed += 1;
+                    println!("    ok    {}", test.name);
+                }
+                Some(error) => {
+                    failed += 1;
+                    println!("    FAIL  {}", test.name);
+                    failures.push((format!("{} > {}", name, test.name), error));
+                }
+            }
+        }
+        // Errors outside of tests fail the whole file
+        if let Err(error) = result {
+            failed += 1;
+            println!("    FAIL  (script)");
+            failures.push((name, error));
+        }
+    }
+
+    for (name, error) in failures {
+        println!("\n---- {} ----", name);
+        println!("{:?}", miette::Report::new(error));
+    }
+    println!(
+        "\n{} passed, {} failed, in {} file{}",
+        passed,
+        failed,
+        files.len(),
+        if files.len() == 1 { "" } else { "s" }
+    );
+    Ok(failed == 0)
+}
+
+/// Collect the test scripts in `path`, looking through directories recursively.
+fn find_tests(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_tests(&path, files)?;
+        } else if path.to_string_lossy().ends_with(EXTENSION) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Define `describe`, `it`, `assert` and `assertEqual`, recording results in `run`.
+fn install(jabroni: &mut Jabroni, run: &Rc<RefCell<Run>>) -> JabroniResult {
+    let groups = run.clone();
+    let describe = Subroutine::new(
+        2,
+        Box::new(move |context, args: &mut [JabroniValue]| {
+            let body = function_arg(&args[1])?;
+            groups.borrow_mut().groups.push(args[0].to_string());
+            let result = body.call(context, &mut []);
+            groups.borrow_mut().groups.pop();
+            result.map(|_| JabroniValue::Null)
+        }),
+    );
+    jabroni.define_constant(
+        "describe",
+        describe
+            .named("describe")
+            .documented(
+                "describe(name, body)",
+                "Group the tests that body defines under a name.",
+            )
+            .into(),
+    )?;
+
+    let results = run.clone();
+    let it = Subroutine::new(
+        2,
+        Box::new(move |context, args: &mut [JabroniValue]| {
+            let test = function_arg(&args[1])?;
+            let mut name = results.borrow().groups.join(" > ");
+            if !name.is_empty() {
+                name.push_str(" > ");
+            }
+            name.push_str(&args[0].to_string());
+            let error = test.call(context, &mut []).err();
+            results
+                .borrow_mut()
+                .results
+                .push(TestResult { name, error });
+            Ok(JabroniValue::Null)
+        }),
+    );
+    jabroni.define_constant(
+        "it",
+        it.named("it")
+            .documented(
+                "it(name, test)",
+                "Define a test, which passes if calling test doesn't throw.",
+            )
+            .into(),
+    )?;
+
+    let assert = Subroutine::new(
+        2,
+        Box::new(|_, args: &mut [JabroniValue]| match &args[0] {
+            JabroniValue::Boolean(true) => Ok(JabroniValue::Null),
+            _ => Err(failure(format!("Assertion failed: {}", args[1]))),
+        }),
+    );
+    jabroni.define_constant(
+        "assert",
+        assert
+            .named("assert")
+            .documented(
+                "assert(condition, message)",
+                "Fail the test with message unless condition is true.",
+            )
+            .into(),
+    )?;
+
+    let assert_equal = Subroutine::new(
+        2,
+        Box::new(
+            |_, args: &mut [JabroniValue]| match args[0].deep_eq(&args[1]) {
+                true => Ok(JabroniValue::Null),
+                false => Err(failure(format!("Expected {}, got {}", args[1], args[0]))),
+            },
+        ),
+    );
+    jabroni.define_constant(
+        "assertEqual",
+        assert_equal
+            .named("assertEqual")
+            .documented(
+                "assertEqual(actual, expected)",
+                "Fail the test unless actual deeply equals expected.",
+            )
+            .into(),
+    )
+}
+
+fn function_arg(value: &JabroniValue) -> JabroniResult<Subroutine> {
+    value
+        .as_subroutine()
+        .cloned()
+        .ok_or_else(|| JabroniError::new(ErrorKind::InvalidArguments, "Expected a function".into()))
+}
+
+fn failure(message: String) -> JabroniError {
+    JabroniError::new(ErrorKind::Exception, message)
+}
@@ -0,0 +1,74 @@
+//! Running a script again each time it changes.
+
+use crate::FileLoader;
+use anyhow::Result;
+use jabroni::Jabroni;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How often to look for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Run the script in `file` with a new interpreter from `new_interpreter`, and again whenever
+/// the file or a module it imported changes, until the process is interrupted. Errors are shown
+/// rather than stopping the watch.
+pub fn run(file: &Path, new_interpreter: impl Fn() -> Result<Jabroni>) -> Result<()> {
+    if file == Path::new("-") {
+        anyhow::bail!("Can't watch stdin. Pass a file to watch");
+    }
+    let name = file.display().to_string();
+    let root = file.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+    loop {
+        // Clear the screen and move to the top
+        print!("\x1b[2J\x1b[H");
+        let loader = FileLoader::new(root.clone());
+        let loaded = loader.loaded.clone();
+        let mut jabroni = new_interpreter()?;
+        jabroni.set_module_loader(loader);
+
+        let start = Instant::now();
+        match fs::read_to_string(file) {
+            Ok(code) => {
+                let result = jabroni.run_script_named(&name, &code);
+                for diagnostic in jabroni.diagnostics() {
+                    eprintln!("{}:{}", name, diagnostic);
+                }
+                if let Err(error) = result {
+                    eprintln!("{:?}", miette::Report::new(error));
+                }
+            }
+            Err(err) => eprintln!("Couldn't read {}: {}", name, err),
+        }
+        println!(
+            "\n[Finished in {:.2?}. Waiting for changes...]",
+            start.elapsed()
+        );
+
+        let mut watched = vec![file.to_path_buf()];
+        watched.extend(loaded.borrow().iter().cloned());
+        wait_for_change(&watched);
+    }
+}
+
+/// Block until one of `files` is modified, created or removed.
+fn wait_for_change(files: &[PathBuf]) {
+    let before = modified_times(files);
+    while modified_times(files) == before {
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn modified_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| {
+            fs::metadata(file)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect()
+}
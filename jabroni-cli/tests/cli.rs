@@ -0,0 +1,174 @@
+//! Run the `jabroni` binary, checking what it prints and the status it exits with.
+
+use std::{
+    fs,
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+};
+
+fn jabroni(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_jabroni"))
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> &str {
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+fn stderr(output: &Output) -> &str {
+    std::str::from_utf8(&output.stderr).unwrap()
+}
+
+/// A directory of scripts for a test, removed when it's done.
+struct Scripts(PathBuf);
+
+impl Scripts {
+    fn new(test: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("jabroni-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    /// Write a script, returning its path.
+    fn write(&self, name: &str, code: &str) -> String {
+        let path = self.0.join(name);
+        fs::write(&path, code).unwrap();
+        path.to_str().unwrap().into()
+    }
+}
+
+impl Drop for Scripts {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn exit_statuses() {
+    for (args, status) in [
+        (&["-e", "1 + 1"][..], 0),
+        (&["--script-eval", "throw 'boom';"], 1),
+        (&["-e", "1 +"], 2),
+        (&["-e", "1 + true"], 3),
+        (&["-e", "missing"], 4),
+        (&["--script-eval", "function f(a) {return a;} f();"], 5),
+        (&["--script-eval", "let a = 1; let a = 2;"], 6),
+        (&["--script-eval", "function f() {return f();} f();"], 11),
+        (&["--script-eval", "exit(3);"], 3),
+        (&["--exit-with-value", "-e", "6 * 7"], 42),
+        // Values that aren't numbers don't set the status
+        (&["--exit-with-value", "-e", "'six'"], 0),
+    ] {
+        let output = jabroni(args);
+        assert_eq!(output.status.code(), Some(status), "{:?}", args);
+    }
+
+    let output = jabroni(&["-e", "missing"]);
+    assert!(stderr(&output).contains("<eval>: ReferenceError: 'missing' does not exist"));
+}
+
+#[test]
+fn json_output() {
+    for (expression, json) in [
+        ("6 * 7", "42"),
+        ("1 == 2", "false"),
+        ("'say \"hi\"'", r#""say \"hi\"""#),
+        ("process", r#"{"args":{"length":2}}"#),
+    ] {
+        let output = jabroni(&["--output", "json", "-e", expression, "--", "a", "b"]);
+        assert!(output.status.success(), "{}", expression);
+        assert_eq!(stdout(&output), format!("{}\n", json));
+    }
+    // Scripts print their value too, even if it's null
+    let output = jabroni(&["--output", "json", "--script-eval", "const x = 1;"]);
+    assert_eq!(stdout(&output), "null\n");
+
+    let output = jabroni(&["--output", "json", "-e", "1 + missing"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(
+        stderr(&output),
+        r#"{"error":{"kind":"ReferenceError","message":"'missing' does not exist","source":"<eval>","code":"E0002","line":1,"column":5,"stack":["at <script> (<eval>:1:5)"]}}"#
+            .to_owned()
+            + "\n"
+    );
+
+    let output = jabroni(&["--output", "json", "-e", "console.log"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stderr(&output).starts_with(r#"{"error":{"kind":"TypeError","#));
+
+    let scripts = Scripts::new("json-output");
+    let broken = scripts.write("broken.jab", "const x = ;\n");
+    let output = jabroni(&["--check", "--output", "json", &broken]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        stderr(&output),
+        format!(
+            r#"{{"diagnostic":{{"severity":"error","message":"Expected expression","source":"{}","line":1,"column":11}}}}"#,
+            broken
+        ) + "\n"
+    );
+}
+
+#[test]
+fn test_subcommand() {
+    let scripts = Scripts::new("test-subcommand");
+    let good = scripts.write(
+        "good.test.jab",
+        "function adds() {assertEqual(1 + 1, 2);}\nit('adds', adds);\n",
+    );
+    let output = jabroni(&["test", &good]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("1 passed, 0 failed"));
+    scripts.write(
+        "bad.test.jab",
+        "function breaks() {assertEqual(1 + 1, 3);}\nit('breaks', breaks);\n",
+    );
+    let output = jabroni(&["test", scripts.0.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("1 passed, 1 failed, in 2 files"));
+}
+
+#[test]
+fn compile_subcommand() {
+    let scripts = Scripts::new("compile-subcommand");
+    let script = scripts.write("answer.jab", "const x = 6;\nreturn x * 7;\n");
+    let compiled = scripts.0.join("answer.jbc");
+    assert!(jabroni(&["compile", &script]).status.success());
+    let output = jabroni(&["--output", "json", compiled.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "42\n");
+
+    let broken = scripts.write("broken.jab", "const x = ;\n");
+    let output = jabroni(&["compile", &broken]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(!scripts.0.join("broken.jbc").exists());
+}
+
+#[test]
+fn check_statuses() {
+    let scripts = Scripts::new("check-statuses");
+    let good = scripts.write("good.jab", "const x = 1;\n");
+    let broken = scripts.write("broken.jab", "const x = ;\n");
+    let mistyped = scripts.write("mistyped.jab", "let n = 1;\nn = 'one';\n");
+    // Checking exits with the status the script would fail with, and runs nothing
+    for (args, status) in [
+        (&["--check", &good][..], 0),
+        (&["--check", &broken], 2),
+        (&["--check", &mistyped], 0),
+        (&["--check", "--typecheck", &mistyped], 3),
+        (&[&broken], 2),
+        (&[&mistyped], 3),
+    ] {
+        let output = jabroni(args);
+        assert_eq!(output.status.code(), Some(status), "{:?}", args);
+    }
+
+    // Code given on the command line can't be checked
+    let output = jabroni(&["--check", "-e", "1 +"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("cannot be used with"));
+}
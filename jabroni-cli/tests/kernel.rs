@@ -0,0 +1,299 @@
+//! Drive the Jupyter kernel over ZeroMQ, as a frontend would.
+#![cfg(feature = "kernel")]
+
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{
+    fs,
+    future::Future,
+    net::TcpListener,
+    path::PathBuf,
+    process::{Child, Command},
+    time::Duration,
+};
+use tokio::time::{sleep, timeout};
+use zeromq::{DealerSocket, ReqSocket, Socket, SocketRecv, SocketSend, SubSocket, ZmqMessage};
+
+const KEY: &str = "b1a2c3d4-secret";
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+
+/// The kernel process, killed if a test fails before shutting it down.
+struct Kernel {
+    child: Child,
+    connection_file: PathBuf,
+}
+
+impl Drop for Kernel {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = fs::remove_file(&self.connection_file);
+    }
+}
+
+/// A message from the kernel, with its header and content left as JSON text.
+struct Message {
+    header: String,
+    parent: String,
+    content: String,
+}
+
+impl Message {
+    fn is(&self, message_type: &str) -> bool {
+        self.header
+            .contains(&format!("\"msg_type\":\"{}\"", message_type))
+    }
+
+    fn answers(&self, msg_id: &str) -> bool {
+        self.parent.contains(&format!("\"msg_id\":\"{}\"", msg_id))
+    }
+}
+
+fn sign(parts: &[&str]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(KEY.as_bytes()).unwrap();
+    for part in parts {
+        mac.update(part.as_bytes());
+    }
+    let digest = mac.finalize().into_bytes();
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn request(msg_id: &str, message_type: &str, content: &str) -> ZmqMessage {
+    let header = format!(
+        r#"{{"msg_id":"{}","session":"test","username":"test","date":"","msg_type":"{}","version":"5.3"}}"#,
+        msg_id, message_type
+    );
+    let parts = [header.as_str(), "{}", "{}", content];
+    let mut frames = vec![Bytes::from_static(DELIMITER), sign(&parts).into()];
+    frames.extend(parts.iter().map(|part| Bytes::from(part.to_string())));
+    ZmqMessage::try_from(frames).unwrap()
+}
+
+/// Read a message from the kernel, checking its signature.
+fn decode(message: ZmqMessage) -> Message {
+    let frames = message.into_vec();
+    let delimiter = frames.iter().position(|frame| frame == DELIMITER).unwrap();
+    let parts: Vec<_> = frames[delimiter + 2..]
+        .iter()
+        .map(|part| String::from_utf8(part.to_vec()).unwrap())
+        .collect();
+    let parts: Vec<_> = parts.iter().map(String::as_str).collect();
+    assert_eq!(frames[delimiter + 1], sign(&parts[..4]));
+    Message {
+        header: parts[0].into(),
+        parent: parts[1].into(),
+        content: parts[3].into(),
+    }
+}
+
+async fn within<T>(future: impl Future<Output = T>) -> T {
+    timeout(Duration::from_secs(10), future)
+        .await
+        .expect("The kernel took too long")
+}
+
+/// Keep trying to connect until the kernel has bound its sockets.
+async fn connect<S: Socket>(socket: &mut S, port: u16) {
+    let endpoint = format!("tcp://127.0.0.1:{}", port);
+    within(async {
+        while socket.connect(&endpoint).await.is_err() {
+            sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+}
+
+/// Start a kernel, with a connection file named after the `test` it's for.
+fn start(test: &str) -> (Kernel, [u16; 5]) {
+    // Find free ports by letting the OS pick them
+    let listeners: Vec<_> = (0..5)
+        .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+        .collect();
+    let ports = [0, 1, 2, 3, 4].map(|i| listeners[i].local_addr().unwrap().port());
+    drop(listeners);
+
+    let connection_file =
+        std::env::temp_dir().join(format!("jabroni-{}-{}.json", test, std::process::id()));
+    fs::write(
+        &connection_file,
+        format!(
+            r#"{{"transport":"tcp","ip":"127.0.0.1","shell_port":{},"iopub_port":{},"stdin_port":{},"control_port":{},"hb_port":{},"signature_scheme":"hmac-sha256","key":"{}","kernel_name":"jabroni"}}"#,
+            ports[0], ports[1], ports[2], ports[3], ports[4], KEY
+        ),
+    )
+    .unwrap();
+    let child = Command::new(env!("CARGO_BIN_EXE_jabroni"))
+        .arg("kernel")
+        .arg(&connection_file)
+        .spawn()
+        .unwrap();
+    let kernel = Kernel {
+        child,
+        connection_file,
+    };
+    (kernel, ports)
+}
+
+/// Send a request on the shell socket, and return the reply with what was published on iopub
+/// while it was handled.
+async fn send(
+    shell: &mut DealerSocket,
+    iopub: &mut SubSocket,
+    msg_id: &str,
+    message_type: &str,
+    content: &str,
+) -> (Message, Vec<Message>) {
+    within(shell.send(request(msg_id, message_type, content)))
+        .await
+        .unwrap();
+    let reply = decode(within(shell.recv()).await.unwrap());
+    assert!(reply.answers(msg_id));
+    let mut published = Vec::new();
+    loop {
+        let message = decode(within(iopub.recv()).await.unwrap());
+        if !message.answers(msg_id) {
+            continue;
+        }
+        let idle = message.is("status") && message.content.contains("idle");
+        published.push(message);
+        if idle {
+            return (reply, published);
+        }
+    }
+}
+
+#[test]
+fn runs_cells() {
+    let (mut kernel, [shell_port, iopub_port, _, _, hb_port]) = start("runs-cells");
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut heartbeat = ReqSocket::new();
+        connect(&mut heartbeat, hb_port).await;
+        within(heartbeat.send("ping".into())).await.unwrap();
+        let echo = within(heartbeat.recv()).await.unwrap();
+        assert_eq!(echo.get(0).unwrap(), &b"ping"[..]);
+
+        let mut shell = DealerSocket::new();
+        connect(&mut shell, shell_port).await;
+        let mut iopub = SubSocket::new();
+        iopub.subscribe("").await.unwrap();
+        connect(&mut iopub, iopub_port).await;
+
+        // Subscriptions take a moment to reach the kernel, so ask for its info until the
+        // status it publishes arrives
+        let mut info = 0;
+        let reply = loop {
+            info += 1;
+            let msg_id = format!("info-{}", info);
+            within(shell.send(request(&msg_id, "kernel_info_request", "{}")))
+                .await
+                .unwrap();
+            let reply = decode(within(shell.recv()).await.unwrap());
+            assert!(reply.answers(&msg_id));
+            if let Ok(Ok(status)) = timeout(Duration::from_millis(200), iopub.recv()).await {
+                decode(status);
+                break reply;
+            }
+        };
+        assert!(reply.is("kernel_info_reply"));
+        assert!(reply.content.contains(r#""implementation":"jabroni""#));
+        // Skip past what was published for the earlier requests
+        while let Ok(Ok(_)) = timeout(Duration::from_millis(200), iopub.recv()).await {}
+
+        let (reply, published) = send(
+            &mut shell,
+            &mut iopub,
+            "define",
+            "execute_request",
+            r#"{"code":"const x = 2; console.log(\"x is\", x);","silent":false}"#,
+        )
+        .await;
+        assert!(reply.is("execute_reply"));
+        assert!(reply.content.contains(r#""status":"ok""#));
+        assert!(reply.content.contains(r#""execution_count":1"#));
+        assert!(published.iter().any(|message| message.is("execute_input")));
+        assert!(published.iter().any(
+            |message| message.is("stream") && message.content.contains(r#""text":"x is 2\n""#)
+        ));
+        assert!(!published.iter().any(|message| message.is("execute_result")));
+
+        // Bindings carry over between cells
+        let (reply, published) = send(
+            &mut shell,
+            &mut iopub,
+            "answer",
+            "execute_request",
+            r#"{"code":"x * 21","silent":false}"#,
+        )
+        .await;
+        assert!(reply.content.contains(r#""execution_count":2"#));
+        let result = published
+            .iter()
+            .find(|message| message.is("execute_result"))
+            .unwrap();
+        assert!(result.content.contains(r#""text/plain":"42""#));
+        assert!(result.content.contains(r#""application/json":42"#));
+
+        let (reply, published) = send(
+            &mut shell,
+            &mut iopub,
+            "fail",
+            "execute_request",
+            r#"{"code":"undefinedThing","silent":false}"#,
+        )
+        .await;
+        assert!(reply.content.contains(r#""status":"error""#));
+        assert!(published.iter().any(|message| message.is("error")));
+
+        let (reply, _) = send(
+            &mut shell,
+            &mut iopub,
+            "complete",
+            "is_complete_request",
+            r#"{"code":"if (x) {"}"#,
+        )
+        .await;
+        assert!(reply.content.contains(r#""status":"incomplete""#));
+
+        let (reply, _) = send(
+            &mut shell,
+            &mut iopub,
+            "shutdown",
+            "shutdown_request",
+            r#"{"restart":false}"#,
+        )
+        .await;
+        assert!(reply.is("shutdown_reply"));
+    });
+    assert!(kernel.child.wait().unwrap().success());
+}
+
+#[test]
+fn ignores_unsigned_requests() {
+    let (mut kernel, [shell_port, ..]) = start("unsigned");
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut shell = DealerSocket::new();
+        connect(&mut shell, shell_port).await;
+        let mut forged = request("forged", "shutdown_request", "{}").into_vec();
+        forged[1] = Bytes::from_static(b"0000");
+        within(shell.send(ZmqMessage::try_from(forged).unwrap()))
+            .await
+            .unwrap();
+        assert!(timeout(Duration::from_millis(500), shell.recv())
+            .await
+            .is_err());
+
+        within(shell.send(request("shutdown", "shutdown_request", "{}")))
+            .await
+            .unwrap();
+        assert!(decode(within(shell.recv()).await.unwrap()).is("shutdown_reply"));
+    });
+    assert!(kernel.child.wait().unwrap().success());
+}
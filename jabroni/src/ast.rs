@@ -0,0 +1,637 @@
+//! Syntax tree of a script, for tools that analyze scripts without running them.
+
+use crate::{
+    errors::{ErrorCode, JabroniError, JabroniResult, Location},
+    state::{collapse, parse_pairs, Rule},
+    utils,
+    value::{Operator, Value},
+};
+use pest::{iterators::Pair, Position};
+
+/// A range of a script, in bytes.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Get the location of the start of the span in `code`, the script it came from.
+    pub fn location(&self, code: &str) -> Location {
+        let (line, column) = Position::new(code, self.start)
+            .map(|position| position.line_col())
+            .unwrap_or((1, 1));
+        Location { line, column }
+    }
+}
+
+impl From<pest::Span<'_>> for Span {
+    fn from(span: pest::Span) -> Self {
+        Self {
+            start: span.start(),
+            end: span.end(),
+        }
+    }
+}
+
+/// An identifier, with where it appears.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+/// A whole script.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Script {
+    pub statements: Vec<Statement>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+/// Whether a declaration is `const` or `let`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum DeclarationKind {
+    Const,
+    Let,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum StatementKind {
+    /// An expression followed by `;`.
+    Expression(Expression),
+    /// `{...}`
+    Block(Vec<Statement>),
+    /// `function name(params) {body}`
+    Function {
+        name: Ident,
+        params: Vec<Ident>,
+        body: Vec<Statement>,
+    },
+    /// `return value;`
+    Return(Expression),
+    /// `yield value;`, where the value is optional.
+    Yield(Option<Expression>),
+    /// `const name = value;` or `let name = value;`
+    Declaration {
+        kind: DeclarationKind,
+        name: Ident,
+        value: Expression,
+    },
+    /// `throw value;`
+    Throw(Expression),
+    /// `try {body} catch (binding) {handler}`
+    Try {
+        body: Vec<Statement>,
+        binding: Ident,
+        handler: Vec<Statement>,
+    },
+    /// `import { names } from 'specifier';`
+    Import {
+        names: Vec<Ident>,
+        specifier: String,
+    },
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum ExpressionKind {
+    /// A number, boolean, string or null literal.
+    Literal(Value),
+    /// A binding.
+    Ident(String),
+    /// An expression in parentheses.
+    Group(Box<Expression>),
+    /// `object.property`
+    Member {
+        object: Box<Expression>,
+        property: Ident,
+    },
+    /// `callee(args)`
+    Call {
+        callee: Box<Expression>,
+        args: Vec<Expression>,
+    },
+    /// `target = value`, where the target is an identifier or member.
+    Assignment {
+        target: Box<Expression>,
+        value: Box<Expression>,
+    },
+    /// `condition ? consequent : alternative`
+    Ternary {
+        condition: Box<Expression>,
+        consequent: Box<Expression>,
+        alternative: Box<Expression>,
+    },
+    /// `lhs operator rhs`. Chains like `a + b - c` are nested from the left.
+    Binary {
+        operator: Operator,
+        lhs: Box<Expression>,
+        rhs: Box<Expression>,
+    },
+}
+
+/// Parse a script into a syntax tree.
+///
+/// # Example
+/// ```
+/// use jabroni::ast::{self, ExpressionKind, StatementKind};
+///
+/// let script = ast::parse("const x = f(1);").unwrap();
+/// match &script.statements[0].kind {
+///     StatementKind::Declaration { name, value, .. } => {
+///         assert_eq!(name.name, "x");
+///         assert!(matches!(value.kind, ExpressionKind::Call { .. }));
+///     }
+///     _ => unreachable!(),
+/// }
+/// ```
+pub fn parse(code: &str) -> JabroniResult<Script> {
+    let pairs = parse_pairs(Rule::jabroni_script, code)?;
+    let mut statements = Vec::new();
+    for pair in pairs {
+        if pair.as_rule() == Rule::statement {
+            statements.push(statement(pair).map_err(|e| locate(e, code))?);
+        }
+    }
+    Ok(Script { statements })
+}
+
+/// Parse a single expression into a syntax tree.
+pub fn parse_expression(code: &str) -> JabroniResult<Expression> {
+    let mut pairs = parse_pairs(Rule::jabroni_expression, code)?;
+    unwrap_expression(pairs.next().unwrap()).map_err(|e| locate(e, code))
+}
+
+/// An error converting a part of the parse tree, which is located once it reaches the top.
+type Conversion<T> = Result<T, (JabroniError, Span)>;
+
+fn locate(error: (JabroniError, Span), code: &str) -> JabroniError {
+    let (error, span) = error;
+    error.located(|| span.location(code), span.end - span.start)
+}
+
+fn ident(pair: Pair<Rule>) -> Ident {
+    Ident {
+        name: pair.as_str().into(),
+        span: pair.as_span().into(),
+    }
+}
+
+fn statements(pair: Pair<Rule>) -> Conversion<Vec<Statement>> {
+    pair.into_inner().map(statement).collect()
+}
+
+fn statement(pair: Pair<Rule>) -> Conversion<Statement> {
+    let span = pair.as_span().into();
+    let kind = match pair.as_rule() {
+        Rule::statement => return statement(pair.into_inner().next().unwrap()),
+        Rule::expression => StatementKind::Expression(unwrap_expression(pair)?),
+        Rule::block_statement => StatementKind::Block(statements(pair)?),
+        Rule::function_statement => {
+            let mut pair = pair.into_inner();
+            let name = ident(pair.next().unwrap());
+            let params = pair.next().unwrap().into_inner().map(ident).collect();
+            let body = statements(pair.next().unwrap())?;
+            StatementKind::Function { name, params, body }
+        }
+        Rule::return_statement => {
+            StatementKind::Return(unwrap_expression(pair.into_inner().next().unwrap())?)
+        }
+        Rule::yield_statement => StatementKind::Yield(match pair.into_inner().next() {
+            Some(pair) => Some(unwrap_expression(pair)?),
+            None => None,
+        }),
+        Rule::declaration_statement => {
+            let mut pair = pair.into_inner();
+            let kind = match pair.next().unwrap().as_str() {
+                "const" => DeclarationKind::Const,
+                _ => DeclarationKind::Let,
+            };
+            let name = ident(pair.next().unwrap());
+            let value = unwrap_expression(pair.next().unwrap())?;
+            StatementKind::Declaration { kind, name, value }
+        }
+        Rule::throw_statement => {
+            StatementKind::Throw(unwrap_expression(pair.into_inner().next().unwrap())?)
+        }
+        Rule::try_statement => {
+            let mut pair = pair.into_inner();
+            let body = statements(pair.next().unwrap())?;
+            let binding = ident(pair.next().unwrap());
+            let handler = statements(pair.next().unwrap())?;
+            StatementKind::Try {
+                body,
+                binding,
+                handler,
+            }
+        }
+        Rule::import_statement => {
+            let mut pair = pair.into_inner();
+            let names = pair.next().unwrap().into_inner().map(ident).collect();
+            let specifier = pair.next().unwrap();
+            let specifier =
+                utils::unquote(specifier.as_str()).map_err(|e| (e, specifier.as_span().into()))?;
+            StatementKind::Import { names, specifier }
+        }
+        rule => return Err(unsupported(rule, span)),
+    };
+    Ok(Statement { kind, span })
+}
+
+/// Convert an `expression` pair that isn't in parentheses, such as the value of a declaration.
+fn unwrap_expression(pair: Pair<Rule>) -> Conversion<Expression> {
+    expression(pair.into_inner().next().unwrap())
+}
+
+fn expression(pair: Pair<Rule>) -> Conversion<Expression> {
+    let pair = collapse(pair);
+    let span: Span = pair.as_span().into();
+    let kind = match pair.as_rule() {
+        // Expressions are only nested in other expressions by parentheses
+        Rule::expression => ExpressionKind::Group(Box::new(unwrap_expression(pair)?)),
+        Rule::numeric_literal => Value::from_numeric_literal(pair.as_str())
+            .map(ExpressionKind::Literal)
+            .map_err(|e| (e, span))?,
+        Rule::string_literal => Value::from_string_literal(pair.as_str())
+            .map(ExpressionKind::Literal)
+            .map_err(|e| (e, span))?,
+        Rule::boolean_literal => ExpressionKind::Literal(Value::Boolean(pair.as_str() == "true")),
+        Rule::null_literal => ExpressionKind::Literal(Value::Null),
+        Rule::ident => ExpressionKind::Ident(pair.as_str().into()),
+        Rule::postfix => {
+            let mut pair = pair.into_inner();
+            let mut chain = expression(pair.next().unwrap())?;
+            for suffix in pair {
+                // Each access or call spans from the start of the chain to the end of the suffix
+                let span = Span {
+                    start: chain.span.start,
+                    end: suffix.as_span().end(),
+                };
+                let kind = match suffix.as_rule() {
+                    Rule::property => {
+                        let property = suffix.into_inner().next().unwrap();
+                        if property.as_rule() != Rule::ident {
+                            return Err((
+                                JabroniError::from_code(
+                                    ErrorCode::Syntax,
+                                    format!(
+                                        "Expected a property name, found '{}'",
+                                        property.as_str()
+                                    ),
+                                ),
+                                property.as_span().into(),
+                            ));
+                        }
+                        ExpressionKind::Member {
+                            object: Box::new(chain),
+                            property: ident(property),
+                        }
+                    }
+                    _ => ExpressionKind::Call {
+                        callee: Box::new(chain),
+                        args: suffix
+                            .into_inner()
+                            .map(expression)
+                            .collect::<Conversion<_>>()?,
+                    },
+                };
+                chain = Expression { kind, span };
+            }
+            return Ok(chain);
+        }
+        Rule::assignment => {
+            let mut pair = pair.into_inner();
+            let target = Box::new(expression(pair.next().unwrap())?);
+            if !matches!(
+                target.kind,
+                ExpressionKind::Ident(_) | ExpressionKind::Member { .. }
+            ) {
+                return Err((
+                    JabroniError::from_code(ErrorCode::Syntax, "Can't assign to this".into()),
+                    target.span,
+                ));
+            }
+            let operator = pair.next().unwrap();
+            if operator.as_str() != "=" {
+                return Err(unsupported(operator.as_rule(), operator.as_span().into()));
+            }
+            let value = Box::new(expression(pair.next().unwrap())?);
+            ExpressionKind::Assignment { target, value }
+        }
+        Rule::ternary => {
+            let mut pair = pair.into_inner();
+            ExpressionKind::Ternary {
+                condition: Box::new(expression(pair.next().unwrap())?),
+                consequent: Box::new(expression(pair.next().unwrap())?),
+                alternative: Box::new(expression(pair.next().unwrap())?),
+            }
+        }
+        Rule::comparison | Rule::inequality | Rule::sum | Rule::product => {
+            let mut pair = pair.into_inner();
+            let mut lhs = expression(pair.next().unwrap())?;
+            while let Some(operator) = pair.next() {
+                let operator = Operator::from_symbol(operator.as_str())
+                    .ok_or_else(|| unsupported(operator.as_rule(), operator.as_span().into()))?;
+                let rhs = expression(pair.next().unwrap())?;
+                let span = Span {
+                    start: lhs.span.start,
+                    end: rhs.span.end,
+                };
+                lhs = Expression {
+                    kind: ExpressionKind::Binary {
+                        operator,
+                        lhs: Box::new(lhs),
+                        rhs: Box::new(rhs),
+                    },
+                    span,
+                };
+            }
+            return Ok(lhs);
+        }
+        rule => return Err(unsupported(rule, span)),
+    };
+    Ok(Expression { kind, span })
+}
+
+fn unsupported(rule: Rule, span: Span) -> (JabroniError, Span) {
+    (
+        JabroniError::from_code(
+            ErrorCode::UnsupportedSyntax,
+            format!("{rule:?} is not supported"),
+        ),
+        span,
+    )
+}
+
+/// Walks a syntax tree, for tools that analyze scripts. Each method visits a node's children by
+/// default, by calling the matching `walk_*` function; override it to do something with the node,
+/// and call the `walk_*` function to keep visiting its children.
+///
+/// # Example
+/// ```
+/// use jabroni::ast::{self, Expression, ExpressionKind, Visitor};
+///
+/// #[derive(Default)]
+/// struct Calls(Vec<String>);
+///
+/// impl Visitor for Calls {
+///     fn visit_expression(&mut self, expression: &Expression) {
+///         if let ExpressionKind::Call { callee, .. } = &expression.kind {
+///             if let ExpressionKind::Ident(name) = &callee.kind {
+///                 self.0.push(name.clone());
+///             }
+///         }
+///         ast::walk_expression(self, expression);
+///     }
+/// }
+///
+/// let script = ast::parse("function f(x) {return g(x);} f(h(1));").unwrap();
+/// let mut calls = Calls::default();
+/// calls.visit_script(&script);
+/// assert_eq!(calls.0, ["g", "f", "h"]);
+/// ```
+pub trait Visitor {
+    fn visit_script(&mut self, script: &Script) {
+        walk_script(self, script);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression);
+    }
+
+    /// Visit an identifier that's declared or named, rather than used as an expression, such as
+    /// a function name, parameter or property.
+    fn visit_ident(&mut self, _ident: &Ident) {}
+}
+
+pub fn walk_script<V: Visitor + ?Sized>(visitor: &mut V, script: &Script) {
+    for statement in &script.statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match &statement.kind {
+        StatementKind::Expression(expression)
+        | StatementKind::Return(expression)
+        | StatementKind::Throw(expression)
+        | StatementKind::Yield(Some(expression)) => visitor.visit_expression(expression),
+        StatementKind::Yield(None) => (),
+        StatementKind::Block(statements) => {
+            for statement in statements {
+                visitor.visit_statement(statement);
+            }
+        }
+        StatementKind::Function { name, params, body } => {
+            visitor.visit_ident(name);
+            for param in params {
+                visitor.visit_ident(param);
+            }
+            for statement in body {
+                visitor.visit_statement(statement);
+            }
+        }
+        StatementKind::Declaration { name, value, .. } => {
+            visitor.visit_ident(name);
+            visitor.visit_expression(value);
+        }
+        StatementKind::Try {
+            body,
+            binding,
+            handler,
+        } => {
+            for statement in body {
+                visitor.visit_statement(statement);
+            }
+            visitor.visit_ident(binding);
+            for statement in handler {
+                visitor.visit_statement(statement);
+            }
+        }
+        StatementKind::Import { names, .. } => {
+            for name in names {
+                visitor.visit_ident(name);
+            }
+        }
+    }
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &Expression) {
+    match &expression.kind {
+        ExpressionKind::Literal(_) | ExpressionKind::Ident(_) => (),
+        ExpressionKind::Group(expression) => visitor.visit_expression(expression),
+        ExpressionKind::Member { object, property } => {
+            visitor.visit_expression(object);
+            visitor.visit_ident(property);
+        }
+        ExpressionKind::Call { callee, args } => {
+            visitor.visit_expression(callee);
+            for arg in args {
+                visitor.visit_expression(arg);
+            }
+        }
+        ExpressionKind::Assignment { target, value } => {
+            visitor.visit_expression(target);
+            visitor.visit_expression(value);
+        }
+        ExpressionKind::Ternary {
+            condition,
+            consequent,
+            alternative,
+        } => {
+            visitor.visit_expression(condition);
+            visitor.visit_expression(consequent);
+            visitor.visit_expression(alternative);
+        }
+        ExpressionKind::Binary { lhs, rhs, .. } => {
+            visitor.visit_expression(lhs);
+            visitor.visit_expression(rhs);
+        }
+    }
+}
+
+/// Walks a syntax tree and lets each node be changed, for tools that transform scripts. Works
+/// like [`Visitor`]. The result can be turned back into code with
+/// [`fmt::format_script`](crate::fmt::format_script).
+///
+/// # Example
+/// ```
+/// use jabroni::{
+///     ast::{self, Expression, ExpressionKind, VisitorMut},
+///     fmt,
+/// };
+///
+/// struct Rename;
+///
+/// impl VisitorMut for Rename {
+///     fn visit_expression_mut(&mut self, expression: &mut Expression) {
+///         if let ExpressionKind::Ident(name) = &mut expression.kind {
+///             if name == "hp" {
+///                 *name = "health".into();
+///             }
+///         }
+///         ast::walk_expression_mut(self, expression);
+///     }
+/// }
+///
+/// let mut script = ast::parse("hp = hp - 1;").unwrap();
+/// Rename.visit_script_mut(&mut script);
+/// assert_eq!(fmt::format_script(&script), "health = health - 1;\n");
+/// ```
+pub trait VisitorMut {
+    fn visit_script_mut(&mut self, script: &mut Script) {
+        walk_script_mut(self, script);
+    }
+
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        walk_statement_mut(self, statement);
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression);
+    }
+
+    /// Visit an identifier that's declared or named, rather than used as an expression, such as
+    /// a function name, parameter or property.
+    fn visit_ident_mut(&mut self, _ident: &mut Ident) {}
+}
+
+pub fn walk_script_mut<V: VisitorMut + ?Sized>(visitor: &mut V, script: &mut Script) {
+    for statement in &mut script.statements {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statement: &mut Statement) {
+    match &mut statement.kind {
+        StatementKind::Expression(expression)
+        | StatementKind::Return(expression)
+        | StatementKind::Throw(expression)
+        | StatementKind::Yield(Some(expression)) => visitor.visit_expression_mut(expression),
+        StatementKind::Yield(None) => (),
+        StatementKind::Block(statements) => {
+            for statement in statements {
+                visitor.visit_statement_mut(statement);
+            }
+        }
+        StatementKind::Function { name, params, body } => {
+            visitor.visit_ident_mut(name);
+            for param in params {
+                visitor.visit_ident_mut(param);
+            }
+            for statement in body {
+                visitor.visit_statement_mut(statement);
+            }
+        }
+        StatementKind::Declaration { name, value, .. } => {
+            visitor.visit_ident_mut(name);
+            visitor.visit_expression_mut(value);
+        }
+        StatementKind::Try {
+            body,
+            binding,
+            handler,
+        } => {
+            for statement in body {
+                visitor.visit_statement_mut(statement);
+            }
+            visitor.visit_ident_mut(binding);
+            for statement in handler {
+                visitor.visit_statement_mut(statement);
+            }
+        }
+        StatementKind::Import { names, .. } => {
+            for name in names {
+                visitor.visit_ident_mut(name);
+            }
+        }
+    }
+}
+
+pub fn walk_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expression: &mut Expression) {
+    match &mut expression.kind {
+        ExpressionKind::Literal(_) | ExpressionKind::Ident(_) => (),
+        ExpressionKind::Group(expression) => visitor.visit_expression_mut(expression),
+        ExpressionKind::Member { object, property } => {
+            visitor.visit_expression_mut(object);
+            visitor.visit_ident_mut(property);
+        }
+        ExpressionKind::Call { callee, args } => {
+            visitor.visit_expression_mut(callee);
+            for arg in args {
+                visitor.visit_expression_mut(arg);
+            }
+        }
+        ExpressionKind::Assignment { target, value } => {
+            visitor.visit_expression_mut(target);
+            visitor.visit_expression_mut(value);
+        }
+        ExpressionKind::Ternary {
+            condition,
+            consequent,
+            alternative,
+        } => {
+            visitor.visit_expression_mut(condition);
+            visitor.visit_expression_mut(consequent);
+            visitor.visit_expression_mut(alternative);
+        }
+        ExpressionKind::Binary { lhs, rhs, .. } => {
+            visitor.visit_expression_mut(lhs);
+            visitor.visit_expression_mut(rhs);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use crate::{
+    channels::Message,
+    errors::{ErrorCode, JabroniError, JabroniResult},
+    value::{Subroutine, Value},
+    Binding, BindingMap,
+};
+use std::{cell::RefCell, convert::TryFrom, future::Future, rc::Rc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    runtime::Handle,
+    time::{self, Instant},
+};
+
+/// A timer set by `setTimeout`.
+struct Timer {
+    id: i64,
+    deadline: Instant,
+    callback: Subroutine,
+    args: Vec<Value>,
+}
+
+#[derive(Default)]
+struct Timers {
+    next_id: i64,
+    /// In the order they were set, so timers with the same deadline run in that order.
+    pending: Vec<Timer>,
+}
+
+/// Timers and networking for scripts, run on a Tokio runtime.
+#[derive(Clone)]
+pub(crate) struct AsyncRuntime {
+    handle: Handle,
+    timers: Rc<RefCell<Timers>>,
+}
+
+impl AsyncRuntime {
+    pub(crate) fn new(handle: Handle) -> Self {
+        Self {
+            handle,
+            timers: Rc::default(),
+        }
+    }
+
+    /// Wait for the timer that's due next, and take it, if any are pending.
+    pub(crate) async fn next_timer(&self) -> JabroniResult<Option<(Subroutine, Vec<Value>)>> {
+        let next = {
+            let timers = self.timers.borrow();
+            let mut pending = timers.pending.iter().enumerate();
+            let first = pending.next().map(|(index, timer)| (index, timer.deadline));
+            pending.fold(first, |next, (index, timer)| match next {
+                Some((_, deadline)) if deadline <= timer.deadline => next,
+                _ => Some((index, timer.deadline)),
+            })
+        };
+        let (index, deadline) = match next {
+            Some(next) => next,
+            None => return Ok(None),
+        };
+        wait(self.handle.spawn(time::sleep_until(deadline))).await?;
+        let timer = self.timers.borrow_mut().pending.remove(index);
+        Ok(Some((timer.callback, timer.args)))
+    }
+
+    /// The `setTimeout(callback, milliseconds, ...args)` builtin.
+    pub(crate) fn set_timeout_builtin(&self) -> Subroutine {
+        let timers = self.timers.clone();
+        Subroutine::new_variadic(Box::new(move |_, args: &mut [Value]| {
+            let (callback, delay, args) = match args {
+                [Value::Subroutine(callback), Value::Number(delay), args @ ..] => {
+                    (callback, delay, args)
+                }
+                _ => {
+                    return Err(JabroniError::from_code(
+                        ErrorCode::InvalidArgument,
+                        "Expected a function and a delay in milliseconds".into(),
+                    ))
+                }
+            };
+            let mut timers = timers.borrow_mut();
+            timers.next_id += 1;
+            let id = timers.next_id;
+            timers.pending.push(Timer {
+                id,
+                deadline: Instant::now() + milliseconds(*delay),
+                callback: callback.clone(),
+                args: args.to_vec(),
+            });
+            Ok(Value::Number(id))
+        }))
+        .named("setTimeout")
+        .documented(
+            "setTimeout(callback, milliseconds, ...args)",
+            "Call callback with args once milliseconds have passed, returning an ID for \
+             clearTimeout. Scripts run asynchronously wait for their timers before finishing.",
+        )
+    }
+
+    /// The `clearTimeout(id)` builtin.
+    pub(crate) fn clear_timeout_builtin(&self) -> Subroutine {
+        let timers = self.timers.clone();
+        Subroutine::new(
+            1,
+            Box::new(move |_, args: &mut [Value]| {
+                if let Value::Number(id) = args[0] {
+                    timers.borrow_mut().pending.retain(|timer| timer.id != id);
+                }
+                Ok(Value::Null)
+            }),
+        )
+        .named("clearTimeout")
+        .documented(
+            "clearTimeout(id)",
+            "Cancel a timer set by setTimeout, if it hasn't run yet.",
+        )
+    }
+
+    /// The `sleep(milliseconds)` builtin.
+    pub(crate) fn sleep_builtin(&self) -> Subroutine {
+        let handle = self.handle.clone();
+        Subroutine::new_async(Some(1), move |_, args| {
+            let handle = handle.clone();
+            async move {
+                let delay = match args[0] {
+                    Value::Number(delay) => milliseconds(delay),
+                    _ => {
+                        return Err(JabroniError::from_code(
+                            ErrorCode::InvalidArgument,
+                            "Expected a delay in milliseconds".into(),
+                        ))
+                    }
+                };
+                wait(handle.spawn(time::sleep(delay))).await?;
+                Ok(Value::Null)
+            }
+        })
+        .named("sleep")
+        .documented("sleep(milliseconds)", "Wait for milliseconds to pass.")
+    }
+
+    /// The `fetch(url)` builtin.
+    pub(crate) fn fetch_builtin(&self) -> Subroutine {
+        let handle = self.handle.clone();
+        Subroutine::new_async(Some(1), move |_, args| {
+            let handle = handle.clone();
+            async move {
+                let url = match &args[0] {
+                    Value::String(url) => url.to_string(),
+                    _ => {
+                        return Err(JabroniError::from_code(
+                            ErrorCode::InvalidArgument,
+                            "Expected a URL".into(),
+                        ))
+                    }
+                };
+                let (status, body) = wait(handle.spawn(get(url))).await?.map_err(|message| {
+                    JabroniError::from_code(ErrorCode::InvalidArgument, message)
+                })?;
+                let mut response = BindingMap::default();
+                response.set("status".into(), Binding::constant(Value::Number(status)));
+                response.set("body".into(), Binding::constant(body.into()));
+                Ok(response.into())
+            }
+        })
+        .named("fetch")
+        .documented(
+            "fetch(url)",
+            "Get an http:// URL, returning an object with the response's status and body. \
+             Responses larger than 4 MiB, and servers that take more than 30 seconds to \
+             respond, are errors.",
+        )
+    }
+}
+
+fn milliseconds(delay: i64) -> Duration {
+    Duration::from_millis(delay.max(0) as u64)
+}
+
+/// Wait for a task spawned on the runtime.
+async fn wait<T>(task: tokio::task::JoinHandle<T>) -> JabroniResult<T> {
+    task.await
+        .map_err(|e| JabroniError::from_code(ErrorCode::CannotSuspend, format!("Task failed: {e}")))
+}
+
+/// How long to wait to connect to a server.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for a server to send its whole response.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The most bytes of a response `fetch` reads, headers included.
+const MAX_RESPONSE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Make an HTTP GET request, returning the response's status and body.
+async fn get(url: String) -> Result<(i64, String), String> {
+    // Anything else could smuggle more lines into the request
+    if url.chars().any(|c| c.is_control() || c.is_whitespace()) {
+        return Err(format!(
+            "Can't fetch {url:?}; URLs can't contain whitespace or control characters"
+        ));
+    }
+    let rest = match url.split_once("://") {
+        Some(("http", rest)) => rest,
+        Some((scheme, _)) => {
+            return Err(format!(
+                "Can't fetch '{url}'; '{scheme}' URLs aren't supported, only http:// URLs are"
+            ))
+        }
+        None => return Err(format!("Can't fetch '{url}'; expected an http:// URL")),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    let address = match authority.contains(':') {
+        true => authority.to_string(),
+        false => format!("{authority}:80"),
+    };
+    let failed = |e: std::io::Error| format!("Couldn't fetch '{url}': {e}");
+    let timed_out = |_| format!("Couldn't fetch '{url}': timed out");
+    let mut stream = time::timeout(CONNECT_TIMEOUT, TcpStream::connect(address))
+        .await
+        .map_err(timed_out)?
+        .map_err(failed)?;
+    // HTTP/1.0, so the response isn't chunked
+    let request = format!("GET {path} HTTP/1.0\r\nHost: {authority}\r\n\r\n");
+    let mut response = Vec::new();
+    let exchange = async {
+        stream.write_all(request.as_bytes()).await?;
+        // Read one byte past the limit, to tell if the response was cut short
+        (&mut stream)
+            .take(MAX_RESPONSE_SIZE + 1)
+            .read_to_end(&mut response)
+            .await
+    };
+    time::timeout(READ_TIMEOUT, exchange)
+        .await
+        .map_err(timed_out)?
+        .map_err(failed)?;
+    if response.len() as u64 > MAX_RESPONSE_SIZE {
+        return Err(format!(
+            "Couldn't fetch '{url}': the response is larger than {MAX_RESPONSE_SIZE} bytes"
+        ));
+    }
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| format!("Couldn't fetch '{url}': invalid response"))?;
+    Ok((status, body.into()))
+}
+
+impl Subroutine {
+    /// Construct a function that runs on a Tokio runtime, for host functions whose futures can
+    /// be sent between threads. Arguments and results are converted to and from [`Message`]s,
+    /// so they can't be functions.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Jabroni, Message, Subroutine};
+    ///
+    /// let runtime = tokio::runtime::Builder::new_current_thread()
+    ///     .enable_all()
+    ///     .build()
+    ///     .unwrap();
+    /// let lookup = Subroutine::new_spawned(runtime.handle(), Some(1), |args| async move {
+    ///     match &args[0] {
+    ///         Message::String(user) => Ok(Message::Number(user.len() as i64)),
+    ///         _ => Ok(Message::Null),
+    ///     }
+    /// });
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.define_constant("lookup", lookup.into()).unwrap();
+    /// let score = runtime
+    ///     .block_on(interpreter.run_script_async("return lookup('ada');"))
+    ///     .unwrap();
+    /// assert_eq!(score, 3.into());
+    /// ```
+    pub fn new_spawned<F, Fut>(handle: &Handle, number_of_args: Option<usize>, callback: F) -> Self
+    where
+        F: Fn(Vec<Message>) -> Fut + 'static,
+        Fut: Future<Output = JabroniResult<Message>> + Send + 'static,
+    {
+        let handle = handle.clone();
+        let callback = Rc::new(callback);
+        Subroutine::new_async(number_of_args, move |_, args| {
+            let (handle, callback) = (handle.clone(), callback.clone());
+            async move {
+                let args = args
+                    .iter()
+                    .map(Message::try_from)
+                    .collect::<JabroniResult<Vec<_>>>()?;
+                let result = wait(handle.spawn(callback(args))).await?;
+                Ok(Value::from(result?))
+            }
+        })
+    }
+}
@@ -0,0 +1,124 @@
+//! Recording what scripts did, so it can be shown to people afterwards.
+
+use crate::{
+    errors::{ErrorCode, ErrorKind, Location},
+    Binding, BindingMap, Object, Value,
+};
+use std::collections::HashMap;
+
+/// Something a script did, recorded in an [`AuditLog`].
+#[derive(Debug, Clone)]
+pub enum AuditEvent {
+    /// A binding was declared or assigned, with the code for the binding, such as `x` or
+    /// `player.hp`.
+    BindingMutated { target: String, value: Value },
+    /// A function defined by the host was called, with the code for the function being called.
+    HostCall { callee: String, args: Vec<Value> },
+    /// A script threw a value with a `throw` statement.
+    Thrown { value: Value },
+    /// A script failed, such as by adding a string to a number, whether or not it was caught.
+    Failed {
+        kind: ErrorKind,
+        code: Option<ErrorCode>,
+        message: String,
+    },
+}
+
+/// An event in an [`AuditLog`], with where it happened.
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    /// The name the script was run with, if any, or the specifier of a module.
+    pub source: Option<String>,
+    /// Where in its script the event happened, if it's known.
+    pub location: Option<Location>,
+    pub event: AuditEvent,
+}
+
+/// What scripts did while auditing, from [`Jabroni::audit_log`](crate::Jabroni::audit_log), in
+/// the order they did it. Values are copied when they're recorded, so changing an object later
+/// doesn't change the log.
+///
+/// # Example
+/// ```
+/// use jabroni::{audit::AuditEvent, Jabroni, Subroutine, Value as JabroniValue};
+///
+/// let mut interpreter = Jabroni::new();
+/// let notify = Subroutine::new(1, Box::new(|_, _| Ok(JabroniValue::Null)));
+/// interpreter.define_constant("notify", notify.into()).unwrap();
+/// interpreter.define_variable("quota", 10.into()).unwrap();
+/// interpreter.start_audit();
+/// interpreter
+///     .run_script_named("tenant.jab", "quota = 99;\nnotify('raised quota');")
+///     .unwrap();
+///
+/// let log = interpreter.stop_audit().unwrap();
+/// let lines: Vec<_> = log.entries.iter().map(|entry| {
+///     let line = entry.location.unwrap().line;
+///     match &entry.event {
+///         AuditEvent::BindingMutated { target, value } => format!("{line}: {target} = {value}"),
+///         AuditEvent::HostCall { callee, args } => format!("{line}: {callee}({})", args[0]),
+///         _ => unreachable!(),
+///     }
+/// }).collect();
+/// assert_eq!(lines, ["1: quota = 99", "2: notify(raised quota)"]);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AuditLog {
+    pub entries: Vec<AuditEntry>,
+}
+
+impl AuditLog {
+    pub(crate) fn record(
+        &mut self,
+        source: Option<&str>,
+        location: Option<Location>,
+        event: AuditEvent,
+    ) {
+        let event = match event {
+            AuditEvent::BindingMutated { target, value } => AuditEvent::BindingMutated {
+                target,
+                value: snapshot(&value, &mut HashMap::new()),
+            },
+            AuditEvent::HostCall { callee, args } => AuditEvent::HostCall {
+                callee,
+                args: args
+                    .iter()
+                    .map(|arg| snapshot(arg, &mut HashMap::new()))
+                    .collect(),
+            },
+            AuditEvent::Thrown { value } => AuditEvent::Thrown {
+                value: snapshot(&value, &mut HashMap::new()),
+            },
+            event => event,
+        };
+        self.entries.push(AuditEntry {
+            source: source.map(Into::into),
+            location,
+            event,
+        });
+    }
+}
+
+/// Copy a value, along with the objects it holds, keeping track of the objects already copied
+/// so cycles are copied as cycles.
+fn snapshot(value: &Value, copies: &mut HashMap<*const (), Object>) -> Value {
+    let object = match value {
+        Value::Object(object) => object,
+        value => return value.clone(),
+    };
+    if let Some(copy) = copies.get(&object.as_ptr()) {
+        return copy.clone().into();
+    }
+    let copy = Object::new(BindingMap::default());
+    copies.insert(object.as_ptr(), copy.clone());
+    let properties: Vec<_> = object
+        .borrow()
+        .iter()
+        .map(|(ident, binding)| (ident.to_string(), binding.value().clone()))
+        .collect();
+    for (ident, value) in properties {
+        let value = snapshot(&value, copies);
+        copy.borrow_mut().set(ident, Binding::constant(value));
+    }
+    copy.into()
+}
@@ -1,34 +1,175 @@
 use crate::{
-    errors::{JabroniError, JabroniResult},
-    value::Value,
+    errors::{ErrorCode, JabroniError, JabroniResult},
+    suggestions, utils,
+    value::{Operator, Value},
 };
+use once_cell::unsync::OnceCell;
+use smallvec::SmallVec;
 use std::{
+    cell::RefCell,
     collections::HashMap,
     fmt::{Debug, Error, Formatter},
+    rc::Rc,
 };
 
+type LazyProvider = Box<dyn FnMut() -> JabroniResult<Value>>;
+
+/// A value that's produced the first time it's needed. Shared between clones of a binding so the
+/// provider only runs until it succeeds.
+#[derive(Clone)]
+struct Lazy(Rc<(RefCell<Option<LazyProvider>>, OnceCell<Value>)>);
+
+impl Lazy {
+    fn force(&self) -> JabroniResult<&Value> {
+        let (provider, value) = &*self.0;
+        value.get_or_try_init(|| {
+            let mut provider = provider.borrow_mut();
+            let produce = provider.as_mut().ok_or_else(|| {
+                JabroniError::from_code(
+                    ErrorCode::UninitializedBinding,
+                    "Lazy binding failed to initialize".into(),
+                )
+            })?;
+            // Keep the provider if it fails, so the next access tries again
+            let value = produce()?;
+            *provider = None;
+            Ok(value)
+        })
+    }
+}
+
+impl Debug for Lazy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self.0 .1.get() {
+            Some(value) => write!(f, "{value:?}"),
+            None => write!(f, "[lazy]"),
+        }
+    }
+}
+
+impl PartialEq for Lazy {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+type Getter = Box<dyn Fn() -> JabroniResult<Value>>;
+type Setter = Box<dyn Fn(Value) -> JabroniResult>;
+
+/// A value owned by the host, accessed through callbacks.
+#[derive(Clone)]
+struct Computed(Rc<(Getter, Option<Setter>)>);
+
+impl Debug for Computed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "[computed]")
+    }
+}
+
+impl PartialEq for Computed {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Where a binding's value comes from.
+#[derive(Debug, Clone, PartialEq)]
+enum Source {
+    Stored,
+    Lazy(Lazy),
+    Computed(Computed),
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Binding {
     mutable: bool,
+    /// Whether scripts are kept from assigning to the binding, even if the host can.
+    read_only: bool,
+    /// Whether the binding is left out when bindings are listed or displayed.
+    hidden: bool,
     value: Value,
+    source: Source,
 }
 
 impl Binding {
     pub const fn new(value: Value, mutable: bool) -> Self {
-        Self { mutable, value }
+        Self {
+            mutable,
+            read_only: false,
+            hidden: false,
+            value,
+            source: Source::Stored,
+        }
     }
 
     pub const fn constant(value: Value) -> Self {
+        Self::new(value, false)
+    }
+
+    pub const fn variable(value: Value) -> Self {
+        Self::new(value, true)
+    }
+
+    /// Construct a constant whose value is only computed when it's first accessed.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Binding, BindingMap, Jabroni, Value as JabroniValue};
+    ///
+    /// let mut catalog = BindingMap::default();
+    /// catalog.set(
+    ///     "size".into(),
+    ///     Binding::lazy(Box::new(|| Ok(JabroniValue::Number(9000)))),
+    /// );
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.define_constant("catalog", JabroniValue::Object(catalog.into()));
+    /// assert_eq!(interpreter.run_expression("catalog.size").unwrap(), 9000.into());
+    /// ```
+    pub fn lazy(provider: LazyProvider) -> Self {
         Self {
-            mutable: false,
-            value,
+            source: Source::Lazy(Lazy(Rc::new((
+                RefCell::new(Some(provider)),
+                OnceCell::new(),
+            )))),
+            ..Self::constant(Value::Null)
         }
     }
 
-    pub const fn variable(value: Value) -> Self {
+    /// Construct a binding backed by host callbacks. The getter is called every time the binding
+    /// is read through [`BindingMap::get_mut`], which is how scripts access bindings. Without a
+    /// setter, the binding is constant.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Binding, BindingMap, Jabroni, Value as JabroniValue};
+    /// use std::{cell::Cell, rc::Rc};
+    ///
+    /// let health = Rc::new(Cell::new(100));
+    /// let (getter, setter) = (health.clone(), health.clone());
+    /// let mut player = BindingMap::default();
+    /// player.set(
+    ///     "health".into(),
+    ///     Binding::computed(
+    ///         Box::new(move || Ok(JabroniValue::Number(getter.get()))),
+    ///         Some(Box::new(move |value| {
+    ///             setter.set(*value.as_number().unwrap());
+    ///             Ok(())
+    ///         })),
+    ///     ),
+    /// );
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.define_constant("player", JabroniValue::Object(player.into()));
+    ///
+    /// interpreter.run_expression("player.health = 50").unwrap();
+    /// assert_eq!(health.get(), 50);
+    /// health.set(75);
+    /// assert_eq!(interpreter.run_expression("player.health").unwrap(), 75.into());
+    /// ```
+    pub fn computed(getter: Getter, setter: Option<Setter>) -> Self {
         Self {
-            mutable: true,
-            value,
+            mutable: setter.is_some(),
+            source: Source::Computed(Computed(Rc::new((getter, setter)))),
+            ..Self::constant(Value::Null)
         }
     }
 
@@ -36,83 +177,663 @@ impl Binding {
         self.mutable
     }
 
-    pub const fn value(&self) -> &Value {
-        &self.value
+    /// Keep scripts from assigning to the binding, while the host can still change it with
+    /// [`Binding::set_value`].
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{errors::ErrorCode, Binding, BindingMap, Jabroni, Value as JabroniValue};
+    ///
+    /// let mut account = BindingMap::default();
+    /// account.set("balance".into(), Binding::variable(10.into()).read_only());
+    /// account.set("token".into(), Binding::constant("secret".into()).hidden());
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter
+    ///     .define_constant("account", JabroniValue::Object(account.into()))
+    ///     .unwrap();
+    ///
+    /// let error = interpreter.run_expression("account.balance = 0").unwrap_err();
+    /// assert_eq!(error.code(), Some(ErrorCode::ReadOnlyProperty));
+    /// // Hidden bindings can be read, but aren't listed
+    /// assert_eq!(interpreter.run_expression("account.token").unwrap(), "secret".into());
+    /// assert_eq!(
+    ///     interpreter.run_expression("account").unwrap().to_string(),
+    ///     "{balance: 10}"
+    /// );
+    /// ```
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Whether scripts are kept from assigning to the binding.
+    pub const fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Leave the binding out when bindings are listed, such as by [`BindingMap::iter`], or when
+    /// the object holding it is displayed, sent over a channel or compared. Scripts can still
+    /// read it by name.
+    pub fn hidden(mut self) -> Self {
+        self.hidden = true;
+        self
+    }
+
+    /// Whether the binding is left out when bindings are listed.
+    pub const fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    /// Whether the binding holds its own value, rather than one supplied by the host on access.
+    pub(crate) fn is_stored(&self) -> bool {
+        matches!(self.source, Source::Stored)
+    }
+
+    /// Compute the value of a lazy binding if that hasn't happened yet. Does nothing for other
+    /// bindings.
+    pub fn force(&self) -> JabroniResult {
+        if let Source::Lazy(lazy) = &self.source {
+            lazy.force()?;
+        }
+        Ok(())
+    }
+
+    /// Bring the value up to date, forcing lazy bindings and calling the getter of computed ones.
+    pub(crate) fn refresh(&mut self) -> JabroniResult {
+        match &self.source {
+            Source::Stored => (),
+            Source::Lazy(lazy) => {
+                lazy.force()?;
+            }
+            Source::Computed(computed) => {
+                self.value = (computed.0 .0)()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The value of the binding. Lazy bindings are forced first, and are null if that fails, so
+    /// use [`Binding::read`] to find out why.
+    /// Computed bindings hold the value from their last refresh, so use [`Binding::read`] to get
+    /// the host's current value.
+    pub fn value(&self) -> &Value {
+        match &self.source {
+            Source::Lazy(lazy) => lazy.force().unwrap_or(&Value::Null),
+            _ => &self.value,
+        }
+    }
+
+    /// Get the current value of the binding, forcing lazy bindings and calling the getter of
+    /// computed ones.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Binding, Value as JabroniValue};
+    /// use std::{cell::Cell, rc::Rc};
+    ///
+    /// let score = Rc::new(Cell::new(1));
+    /// let getter = score.clone();
+    /// let binding = Binding::computed(Box::new(move || Ok(getter.get().into())), None);
+    /// score.set(2);
+    /// assert_eq!(binding.read().unwrap(), JabroniValue::Number(2));
+    /// ```
+    pub fn read(&self) -> JabroniResult<Value> {
+        match &self.source {
+            Source::Stored => Ok(self.value.clone()),
+            Source::Lazy(lazy) => Ok(lazy.force()?.clone()),
+            Source::Computed(computed) => (computed.0 .0)(),
+        }
     }
 
     pub fn value_mut(&mut self) -> &mut Value {
+        // The binding is about to diverge from its clones, so it needs its own copy
+        if let Source::Lazy(lazy) = &self.source {
+            self.value = lazy.0 .1.get().cloned().unwrap_or(Value::Null);
+            self.source = Source::Stored;
+        }
         &mut self.value
     }
 
+    /// Set the value as a script assigning to the binding does, failing if it's read-only.
+    pub(crate) fn assign(&mut self, value: Value) -> JabroniResult {
+        if self.read_only {
+            return Err(JabroniError::from_code(
+                ErrorCode::ReadOnlyProperty,
+                "Cannot assign to binding because it is read-only".into(),
+            ));
+        }
+        self.set_value(value)
+    }
+
     pub fn set_value(&mut self, value: Value) -> JabroniResult {
+        self.refresh()?;
         if std::mem::discriminant(self.value()) != std::mem::discriminant(&value) {
-            return Err(JabroniError::Type(
+            return Err(JabroniError::from_code(
+                ErrorCode::AssignmentTypeMismatch,
                 "Type mismatch in binding assignment".into(),
             ));
         }
 
         if !self.mutable() {
-            return Err(JabroniError::Type(
+            return Err(JabroniError::from_code(
+                ErrorCode::ConstantAssignment,
                 "Cannot mutably access binding because it is constant".into(),
             ));
         }
-        self.value = value;
+        if let Source::Computed(computed) = &self.source {
+            if let Some(setter) = &computed.0 .1 {
+                return setter(value);
+            }
+        }
+        *self.value_mut() = value;
         Ok(())
     }
 }
 
+/// Customizes the behavior of an object. Handlers can intercept access to properties that aren't
+/// bound on the object, so hosts can expose dynamic APIs without enumerating every property up
+/// front, and can overload operators.
+///
+/// # Example
+/// ```
+/// use jabroni::{
+///     errors::JabroniResult, BindingMap, Jabroni, ObjectHandler, Value as JabroniValue,
+/// };
+///
+/// struct Database;
+///
+/// impl ObjectHandler for Database {
+///     fn has(&self, table: &str) -> bool {
+///         table == "users" || table == "orders"
+///     }
+///
+///     fn get(&self, table: &str) -> JabroniResult<JabroniValue> {
+///         Ok(JabroniValue::Number(table.len() as i64))
+///     }
+/// }
+///
+/// let mut interpreter = Jabroni::new();
+/// let db = BindingMap::with_handler(Database);
+/// interpreter.define_constant("db", JabroniValue::Object(db.into()));
+/// assert_eq!(interpreter.run_expression("db.orders").unwrap(), 6.into());
+/// assert!(interpreter.run_expression("db.products").is_err());
+/// ```
+pub trait ObjectHandler {
+    /// Check if the object has a property.
+    fn has(&self, _property: &str) -> bool {
+        false
+    }
+
+    /// Get the value of a property.
+    fn get(&self, property: &str) -> JabroniResult<Value> {
+        Err(JabroniError::from_code(
+            ErrorCode::UndefinedReference,
+            format!("'{property}' does not exist"),
+        ))
+    }
+
+    /// Set the value of a property. Properties are read-only unless this is implemented.
+    fn set(&self, property: &str, _value: Value) -> JabroniResult {
+        Err(JabroniError::from_code(
+            ErrorCode::ReadOnlyProperty,
+            format!("Cannot set '{property}' because it is read-only"),
+        ))
+    }
+
+    /// Call a property as a method. By default, this calls the function returned by
+    /// [`ObjectHandler::get`].
+    fn call(
+        &self,
+        property: &str,
+        context: BindingMap,
+        args: &mut [Value],
+    ) -> JabroniResult<Value> {
+        self.get(property)?
+            .as_subroutine()
+            .ok_or_else(|| {
+                JabroniError::from_code(ErrorCode::NotAFunction, "Not a function".into())
+            })?
+            .call(context, args)
+    }
+
+    /// Apply an operator where this object is one of the operands, `lhs` or `rhs`. Returns `None`
+    /// if the operator isn't overloaded.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{
+    ///     errors::JabroniResult, Binding, BindingMap, Jabroni, ObjectHandler, Operator,
+    ///     Value as JabroniValue,
+    /// };
+    ///
+    /// fn vector(x: i64, y: i64) -> JabroniValue {
+    ///     let mut vector = BindingMap::with_handler(Vector2);
+    ///     vector.set("x".into(), Binding::constant(x.into()));
+    ///     vector.set("y".into(), Binding::constant(y.into()));
+    ///     JabroniValue::Object(vector.into())
+    /// }
+    ///
+    /// fn component(vector: &JabroniValue, name: &str) -> JabroniResult<i64> {
+    ///     Ok(*vector.as_object().unwrap().get(name).unwrap().as_number().unwrap())
+    /// }
+    ///
+    /// struct Vector2;
+    ///
+    /// impl ObjectHandler for Vector2 {
+    ///     fn operate(
+    ///         &self,
+    ///         operator: Operator,
+    ///         lhs: &JabroniValue,
+    ///         rhs: &JabroniValue,
+    ///     ) -> JabroniResult<Option<JabroniValue>> {
+    ///         if operator != Operator::Add {
+    ///             return Ok(None);
+    ///         }
+    ///         Ok(Some(vector(
+    ///             component(lhs, "x")? + component(rhs, "x")?,
+    ///             component(lhs, "y")? + component(rhs, "y")?,
+    ///         )))
+    ///     }
+    /// }
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.define_constant("a", vector(1, 2));
+    /// interpreter.define_constant("b", vector(10, 20));
+    /// interpreter.run_script("const c = a + b;").unwrap();
+    /// assert_eq!(interpreter.run_expression("c.y").unwrap(), 22.into());
+    /// assert!(interpreter.run_expression("a * b").is_err());
+    /// ```
+    fn operate(
+        &self,
+        _operator: Operator,
+        _lhs: &Value,
+        _rhs: &Value,
+    ) -> JabroniResult<Option<Value>> {
+        Ok(None)
+    }
+}
+
+/// Scopes with more bindings than this are indexed by a hash map. Smaller ones, like those of
+/// most function calls, are searched in order, which is quicker than hashing.
+const SMALL_SCOPE: usize = 8;
+
+thread_local! {
+    /// Shared by new scopes until they're changed, so scopes that never bind anything, like most
+    /// blocks, don't allocate.
+    static EMPTY_SCOPE: Rc<Scope> = Rc::default();
+}
+
+fn empty_scope() -> Rc<Scope> {
+    EMPTY_SCOPE.with(Rc::clone)
+}
+
+/// The bindings in one scope, in the order they were first set.
+#[derive(Clone, Default)]
+struct Scope {
+    /// Where each binding is, once there are too many to search.
+    indices: Option<HashMap<Rc<str>, usize>>,
+    /// The first few bindings are kept inline, so small scopes take a single allocation.
+    bindings: SmallVec<[(Rc<str>, Binding); 4]>,
+}
+
+impl Scope {
+    fn position(&self, ident: &str) -> Option<usize> {
+        match &self.indices {
+            Some(indices) => indices.get(ident).copied(),
+            None => self.bindings.iter().position(|(name, _)| &**name == ident),
+        }
+    }
+
+    fn contains_key(&self, ident: &str) -> bool {
+        self.position(ident).is_some()
+    }
+
+    fn get(&self, ident: &str) -> Option<&Binding> {
+        self.position(ident).map(|index| &self.bindings[index].1)
+    }
+
+    fn get_mut(&mut self, ident: &str) -> Option<&mut Binding> {
+        let index = self.position(ident)?;
+        Some(&mut self.bindings[index].1)
+    }
+
+    /// Set a binding, keeping its place if it's already set.
+    fn insert(&mut self, ident: Rc<str>, binding: Binding) {
+        if let Some(index) = self.position(&ident) {
+            self.bindings[index].1 = binding;
+            return;
+        }
+        match &mut self.indices {
+            Some(indices) => {
+                indices.insert(ident.clone(), self.bindings.len());
+            }
+            None if self.bindings.len() == SMALL_SCOPE => {
+                let mut indices: HashMap<_, _> = self
+                    .bindings
+                    .iter()
+                    .enumerate()
+                    .map(|(index, (ident, _))| (ident.clone(), index))
+                    .collect();
+                indices.insert(ident.clone(), self.bindings.len());
+                self.indices = Some(indices);
+            }
+            None => (),
+        }
+        self.bindings.push((ident, binding));
+    }
+
+    fn remove(&mut self, ident: &str) -> Option<Binding> {
+        let index = self.position(ident)?;
+        let (_, binding) = self.bindings.remove(index);
+        if let Some(indices) = &mut self.indices {
+            indices.remove(ident);
+            for (ident, _) in &self.bindings[index..] {
+                *indices.get_mut(ident).unwrap() -= 1;
+            }
+        }
+        Some(binding)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&str, &Binding)> {
+        self.bindings
+            .iter()
+            .map(|(ident, binding)| (&**ident, binding))
+    }
+
+    fn values(&self) -> impl Iterator<Item = &Binding> {
+        self.bindings.iter().map(|(_, binding)| binding)
+    }
+}
+
+/// Bindings in nested scopes, or the properties of an object. Bindings in each scope are kept in
+/// the order they were first set.
 #[derive(Clone)]
 pub struct BindingMap {
-    maps: Vec<HashMap<String, Binding>>,
+    /// Scopes are shared between clones until one of them changes, so making a context for a
+    /// function call doesn't copy every binding.
+    maps: Vec<Rc<Scope>>,
+    handler: Option<Rc<dyn ObjectHandler>>,
+    /// Bindings that forward to the handler, kept so they can be borrowed like any other.
+    proxies: HashMap<String, Binding>,
 }
 
 impl Default for BindingMap {
     fn default() -> Self {
         Self {
-            maps: vec![HashMap::default()],
+            maps: vec![empty_scope()],
+            handler: None,
+            proxies: HashMap::default(),
         }
     }
 }
 
 impl BindingMap {
+    /// Construct an object whose unbound properties are handled by `handler`.
+    pub fn with_handler(handler: impl ObjectHandler + 'static) -> Self {
+        Self {
+            handler: Some(Rc::new(handler)),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn handler(&self) -> Option<&Rc<dyn ObjectHandler>> {
+        self.handler.as_ref()
+    }
+
     pub fn new_context(&self) -> Self {
         let mut clone = self.clone();
-        clone.maps.push(Default::default());
+        clone.maps.push(empty_scope());
+        clone
+    }
+
+    /// Construct a new context with only the outermost scope of this one.
+    pub(crate) fn new_root_context(&self) -> Self {
+        let mut clone = self.clone();
+        clone.maps.truncate(1);
+        clone.maps.push(empty_scope());
         clone
     }
 
+    pub fn push_scope(&mut self) {
+        self.maps.push(empty_scope());
+    }
+
+    pub fn pop_scope(&mut self) {
+        debug_assert!(self.maps.len() > 1);
+        self.maps.pop();
+    }
+
     pub fn has_on_top(&self, ident: &str) -> bool {
         debug_assert!(!self.maps.is_empty());
-        if self.maps[self.maps.len() - 1].get(ident).is_some() {
+        if self.maps[self.maps.len() - 1].contains_key(ident) {
             return true;
         }
         false
     }
 
+    /// Check if the innermost scope is the outermost one.
+    pub(crate) fn in_outermost_scope(&self) -> bool {
+        self.maps.len() == 1
+    }
+
+    /// Check if the binding `ident` refers to is in the outermost scope.
+    pub(crate) fn is_outermost(&self, ident: &str) -> bool {
+        match self.maps.iter().rposition(|map| map.contains_key(ident)) {
+            Some(index) => index == 0,
+            None => false,
+        }
+    }
+
+    /// Check if a binding is visible without computing its value.
+    pub fn contains(&self, ident: &str) -> bool {
+        self.maps.iter().any(|map| map.contains_key(ident))
+    }
+
     pub fn set(&mut self, ident: String, value: Binding) {
+        self.set_interned(utils::intern(&ident), value);
+    }
+
+    /// Set a binding whose identifier has already been interned, which saves allocating it.
+    pub(crate) fn set_interned(&mut self, ident: Rc<str>, value: Binding) {
         debug_assert!(!self.maps.is_empty());
         let length = self.maps.len();
-        self.maps[length - 1].insert(ident, value);
+        Rc::make_mut(&mut self.maps[length - 1]).insert(ident, value);
+    }
+
+    /// Remove a binding from the innermost scope.
+    pub fn remove(&mut self, ident: &str) -> Option<Binding> {
+        debug_assert!(!self.maps.is_empty());
+        let length = self.maps.len();
+        match self.maps[length - 1].contains_key(ident) {
+            true => Rc::make_mut(&mut self.maps[length - 1]).remove(ident),
+            false => None,
+        }
     }
 
     pub fn get(&self, ident: &str) -> JabroniResult<&Binding> {
         debug_assert!(!self.maps.is_empty());
         for map in self.maps.iter().rev() {
             if let Some(entry) = map.get(ident) {
+                entry.force()?;
                 return Ok(entry);
             }
         }
-        Err(JabroniError::Reference(format!("'{ident}' does not exist")))
+        Err(self.missing(ident))
+    }
+
+    /// Make the error for a binding that doesn't exist, suggesting a visible one with a similar
+    /// name.
+    fn missing(&self, ident: &str) -> JabroniError {
+        let message = match suggestions::closest(ident, self.iter().map(|(ident, _)| ident)) {
+            Some(suggestion) => format!("'{ident}' does not exist; did you mean '{suggestion}'?"),
+            None => format!("'{ident}' does not exist"),
+        };
+        JabroniError::from_code(ErrorCode::UndefinedReference, message)
+    }
+
+    /// Iterate over the bindings in the innermost scope.
+    pub(crate) fn iter_innermost(&self) -> impl Iterator<Item = (&str, &Binding)> {
+        debug_assert!(!self.maps.is_empty());
+        self.maps[self.maps.len() - 1].iter()
+    }
+
+    /// Get the bindings in each scope, innermost scope first.
+    pub(crate) fn scopes(&self) -> Vec<Vec<(&str, &Binding)>> {
+        self.maps
+            .iter()
+            .rev()
+            .map(|map| map.iter().collect())
+            .collect()
+    }
+
+    /// Iterate over the values the map holds itself, in every scope, rather than ones provided on
+    /// access, along with whether the scope holding each one is shared with another map.
+    pub(crate) fn stored_values(&self) -> impl Iterator<Item = (&Value, bool)> {
+        self.maps.iter().flat_map(|map| {
+            let shared = Rc::strong_count(map) > 1;
+            map.values()
+                .filter(|binding| binding.is_stored())
+                .map(move |binding| (binding.value(), shared))
+        })
+    }
+
+    /// Get the binding for `ident` in the innermost scope, to change or set it in place.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Binding, BindingMap, Value as JabroniValue};
+    ///
+    /// let mut counts: BindingMap = [("apples".to_string(), Binding::variable(1.into()))]
+    ///     .into_iter()
+    ///     .collect();
+    /// for fruit in ["apples", "pears", "apples"] {
+    ///     let count = counts
+    ///         .entry(fruit.into())
+    ///         .or_insert(Binding::variable(0.into()))
+    ///         .value_mut();
+    ///     *count = JabroniValue::Number(count.as_number().unwrap() + 1);
+    /// }
+    /// assert_eq!(counts.len(), 2);
+    /// assert_eq!(counts.get("apples").unwrap().value(), &JabroniValue::Number(3));
+    /// assert_eq!(counts.get("pears").unwrap().value(), &JabroniValue::Number(1));
+    /// ```
+    pub fn entry(&mut self, ident: String) -> BindingEntry<'_> {
+        debug_assert!(!self.maps.is_empty());
+        let length = self.maps.len();
+        BindingEntry {
+            scope: Rc::make_mut(&mut self.maps[length - 1]),
+            ident: utils::intern(&ident),
+        }
+    }
+
+    /// The number of bindings [`BindingMap::iter`] visits.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Check if there are no bindings for [`BindingMap::iter`] to visit.
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Iterate over the visible bindings, innermost scope first. Hidden bindings are skipped.
+    /// Computed bindings aren't refreshed, so read them with [`Binding::read`].
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Binding)> {
+        self.maps
+            .iter()
+            .rev()
+            .enumerate()
+            .flat_map(move |(depth, map)| {
+                map.iter().filter(move |(ident, binding)| {
+                    // Skip bindings shadowed by an inner scope
+                    !binding.is_hidden()
+                        && !self
+                            .maps
+                            .iter()
+                            .rev()
+                            .take(depth)
+                            .any(|inner| inner.contains_key(ident))
+                })
+            })
     }
 
     pub fn get_mut(&mut self, ident: &str) -> JabroniResult<&mut Binding> {
         debug_assert!(!self.maps.is_empty());
-        for map in self.maps.iter_mut().rev() {
-            if let Some(entry) = map.get_mut(ident) {
-                return Ok(entry);
+        if let Some(index) = self.maps.iter().rposition(|map| map.contains_key(ident)) {
+            let scope = Rc::make_mut(&mut self.maps[index]);
+            let entry = scope.get_mut(ident).unwrap(); // Safe because we just found it
+            entry.refresh()?;
+            return Ok(entry);
+        }
+        if let Some(handler) = self.handler.clone().filter(|handler| handler.has(ident)) {
+            let proxy = self.proxies.entry(ident.into()).or_insert_with(|| {
+                let (property, getter) = (ident.to_string(), handler.clone());
+                let (ident, setter) = (ident.to_string(), handler);
+                Binding::computed(
+                    Box::new(move || getter.get(&property)),
+                    Some(Box::new(move |value| setter.set(&ident, value))),
+                )
+            });
+            proxy.refresh()?;
+            return Ok(proxy);
+        }
+        Err(self.missing(ident))
+    }
+}
+
+impl FromIterator<(String, Binding)> for BindingMap {
+    fn from_iter<I: IntoIterator<Item = (String, Binding)>>(bindings: I) -> Self {
+        let mut map = Self::default();
+        map.extend(bindings);
+        map
+    }
+}
+
+/// Sets each binding in the innermost scope, replacing any with the same name.
+impl Extend<(String, Binding)> for BindingMap {
+    fn extend<I: IntoIterator<Item = (String, Binding)>>(&mut self, bindings: I) {
+        for (ident, binding) in bindings {
+            self.set(ident, binding);
+        }
+    }
+}
+
+/// A binding in the innermost scope of a [`BindingMap`], which may not be set yet. Made by
+/// [`BindingMap::entry`].
+pub struct BindingEntry<'a> {
+    scope: &'a mut Scope,
+    ident: Rc<str>,
+}
+
+impl<'a> BindingEntry<'a> {
+    /// The name of the binding.
+    pub fn key(&self) -> &str {
+        &self.ident
+    }
+
+    /// Get the binding, setting it to `default` first if it isn't set.
+    pub fn or_insert(self, default: Binding) -> &'a mut Binding {
+        self.or_insert_with(|| default)
+    }
+
+    /// Get the binding, setting it to the result of `default` first if it isn't set.
+    pub fn or_insert_with(self, default: impl FnOnce() -> Binding) -> &'a mut Binding {
+        let index = match self.scope.position(&self.ident) {
+            Some(index) => index,
+            None => {
+                self.scope.insert(self.ident, default());
+                self.scope.bindings.len() - 1
             }
+        };
+        &mut self.scope.bindings[index].1
+    }
+
+    /// Change the binding if it's set.
+    pub fn and_modify(self, f: impl FnOnce(&mut Binding)) -> Self {
+        if let Some(binding) = self.scope.get_mut(&self.ident) {
+            f(binding);
         }
-        Err(JabroniError::Reference(format!("'{ident}' does not exist")))
+        self
     }
 }
 
@@ -121,7 +842,7 @@ impl Debug for BindingMap {
         write!(f, "{{")?;
         for map in self.maps.iter() {
             write!(f, "\t{{")?;
-            for (ident, binding) in map {
+            for (ident, binding) in map.iter() {
                 write!(f, "\t\t\"{ident}\": {binding:?})")?;
             }
             write!(f, "\t}},")?;
@@ -0,0 +1,222 @@
+use crate::{
+    binding::{Binding, BindingMap},
+    errors::{ErrorCode, JabroniError, JabroniResult},
+    value::{Number, Subroutine, Value},
+};
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn invalid_argument(message: &str) -> JabroniError {
+    JabroniError::from_code(ErrorCode::InvalidArgument, message.into())
+}
+
+fn bytes_arg(value: &Value) -> JabroniResult<&[u8]> {
+    value
+        .as_bytes()
+        .map(Vec::as_slice)
+        .ok_or_else(|| invalid_argument("Expected bytes"))
+}
+
+fn string_arg(value: &Value) -> JabroniResult<&str> {
+    value
+        .as_string()
+        .map(AsRef::as_ref)
+        .ok_or_else(|| invalid_argument("Expected a string"))
+}
+
+fn index_arg(value: &Value) -> JabroniResult<Number> {
+    value
+        .as_number()
+        .copied()
+        .ok_or_else(|| invalid_argument("Expected an index"))
+}
+
+/// Turn an index that may count back from the end into one from the start, clamped to the
+/// length.
+fn clamp_index(index: Number, length: usize) -> usize {
+    if index < 0 {
+        length.saturating_sub(index.unsigned_abs() as usize)
+    } else {
+        (index as usize).min(length)
+    }
+}
+
+fn length(bytes: &[u8]) -> JabroniResult<Value> {
+    Number::try_from(bytes.len())
+        .map(Value::Number)
+        .map_err(|_| invalid_argument("Too many bytes to count"))
+}
+
+/// Get the byte at `index`, counting back from the end if it's negative, or null if there's no
+/// byte there.
+fn at(bytes: &[u8], index: Number) -> Value {
+    let index = if index < 0 {
+        bytes.len().checked_sub(index.unsigned_abs() as usize)
+    } else {
+        Some(index as usize)
+    };
+    index
+        .and_then(|index| bytes.get(index))
+        .map_or(Value::Null, |byte| Value::Number((*byte).into()))
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> JabroniResult<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return Err(invalid_argument("Expected an even number of hex digits"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| invalid_argument("Expected hex digits"))
+        })
+        .collect()
+}
+
+fn to_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn from_base64(encoded: &str) -> JabroniResult<Vec<u8>> {
+    let invalid = || invalid_argument("Expected base64");
+    let trimmed = encoded.trim_end_matches('=');
+    if encoded.len() % 4 != 0 || encoded.len() - trimmed.len() > 2 {
+        return Err(invalid());
+    }
+    let mut bytes = Vec::with_capacity(trimmed.len() * 3 / 4);
+    for chunk in trimmed.as_bytes().chunks(4) {
+        let mut group = 0u32;
+        for (i, digit) in chunk.iter().enumerate() {
+            let value = BASE64.iter().position(|c| c == digit).ok_or_else(invalid)?;
+            group |= (value as u32) << (18 - 6 * i);
+        }
+        // Each digit after the first adds a byte
+        for i in 0..chunk.len() - 1 {
+            bytes.push((group >> (16 - 8 * i)) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+/// Make a builtin, named after the start of its signature.
+fn builtin(
+    number_of_args: usize,
+    signature: &str,
+    doc: &str,
+    callback: impl Fn(&[Value]) -> JabroniResult<Value> + 'static,
+) -> Binding {
+    let name = signature.split('(').next().unwrap_or(signature);
+    let subroutine = Subroutine::new(number_of_args, Box::new(move |_, args| callback(args)))
+        .named(name)
+        .documented(&format!("bytes.{signature}"), doc);
+    Binding::constant(Value::Subroutine(subroutine))
+}
+
+/// The `bytes` object, with functions for working with binary data.
+pub(crate) fn namespace() -> BindingMap {
+    let mut namespace = BindingMap::default();
+    let mut add = |name: &str, binding| namespace.set(name.into(), binding);
+    add(
+        "length",
+        builtin(1, "length(bytes)", "The number of bytes.", |args| {
+            length(bytes_arg(&args[0])?)
+        }),
+    );
+    add(
+        "at",
+        builtin(
+            2,
+            "at(bytes, index)",
+            "The byte at an index, or null if there's none.",
+            |args| Ok(at(bytes_arg(&args[0])?, index_arg(&args[1])?)),
+        ),
+    );
+    add(
+        "slice",
+        builtin(
+            3,
+            "slice(bytes, start, end)",
+            "The bytes from start up to end.",
+            |args| {
+                let bytes = bytes_arg(&args[0])?;
+                let start = clamp_index(index_arg(&args[1])?, bytes.len());
+                let end = clamp_index(index_arg(&args[2])?, bytes.len()).max(start);
+                Ok(Value::Bytes(bytes[start..end].to_vec()))
+            },
+        ),
+    );
+    add(
+        "toHex",
+        builtin(
+            1,
+            "toHex(bytes)",
+            "Encode bytes as lowercase hex.",
+            |args| Ok(Value::String(to_hex(bytes_arg(&args[0])?).into())),
+        ),
+    );
+    add(
+        "fromHex",
+        builtin(1, "fromHex(string)", "Decode bytes from hex.", |args| {
+            Ok(Value::Bytes(from_hex(string_arg(&args[0])?)?))
+        }),
+    );
+    add(
+        "toBase64",
+        builtin(
+            1,
+            "toBase64(bytes)",
+            "Encode bytes as padded base64.",
+            |args| Ok(Value::String(to_base64(bytes_arg(&args[0])?).into())),
+        ),
+    );
+    add(
+        "fromBase64",
+        builtin(
+            1,
+            "fromBase64(string)",
+            "Decode bytes from padded base64.",
+            |args| Ok(Value::Bytes(from_base64(string_arg(&args[0])?)?)),
+        ),
+    );
+    namespace
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64() {
+        for (bytes, encoded) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (&[0, 255, 128, 7], "AP+ABw=="),
+        ] {
+            assert_eq!(to_base64(bytes), encoded);
+            assert_eq!(from_base64(encoded).unwrap(), bytes);
+        }
+        assert!(from_base64("Zg=").is_err());
+        assert!(from_base64("Z===").is_err());
+        assert!(from_base64("Zg!=").is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use crate::{
+    binding::{Binding, BindingMap},
+    errors::{ErrorCode, JabroniError, JabroniResult},
+    value::{Subroutine, Value},
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    convert::TryFrom,
+    rc::Rc,
+    sync::mpsc::{Receiver, Sender},
+};
+
+/// A value sent over a channel. Unlike [`Value`], messages can be sent between threads, so they
+/// can't contain functions.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Message {
+    /// Number type
+    Number(i64),
+    /// Boolean type
+    Boolean(bool),
+    /// String type
+    String(String),
+    /// Bytes type
+    Bytes(Vec<u8>),
+    /// Object type, with its fields
+    Object(HashMap<String, Message>),
+    /// Null type
+    Null,
+}
+
+impl TryFrom<&Value> for Message {
+    type Error = JabroniError;
+
+    fn try_from(value: &Value) -> JabroniResult<Self> {
+        Self::from_value(value, &mut Vec::new())
+    }
+}
+
+impl Message {
+    /// Convert a value, failing if it's an object that's in `ancestors`, since messages can't
+    /// contain themselves.
+    fn from_value(value: &Value, ancestors: &mut Vec<*const ()>) -> JabroniResult<Self> {
+        Ok(match value {
+            Value::Number(value) => Message::Number(*value),
+            Value::Boolean(value) => Message::Boolean(*value),
+            Value::String(value) => Message::String(value.to_string()),
+            Value::Bytes(value) => Message::Bytes(value.clone()),
+            Value::Null => Message::Null,
+            Value::Object(object) => {
+                if ancestors.contains(&object.as_ptr()) {
+                    return Err(JabroniError::from_code(
+                        ErrorCode::UnsendableValue,
+                        "Objects that contain themselves can't be sent over a channel".into(),
+                    ));
+                }
+                ancestors.push(object.as_ptr());
+                let mut fields = HashMap::new();
+                for (ident, binding) in object.borrow().iter() {
+                    let mut binding = binding.clone();
+                    binding.refresh()?;
+                    fields.insert(
+                        ident.into(),
+                        Message::from_value(binding.value(), ancestors)?,
+                    );
+                }
+                ancestors.pop();
+                Message::Object(fields)
+            }
+            Value::Subroutine(_) => {
+                return Err(JabroniError::from_code(
+                    ErrorCode::UnsendableValue,
+                    "Functions can't be sent over a channel".into(),
+                ))
+            }
+        })
+    }
+}
+
+impl From<Message> for Value {
+    fn from(message: Message) -> Value {
+        match message {
+            Message::Number(value) => Value::Number(value),
+            Message::Boolean(value) => Value::Boolean(value),
+            Message::String(value) => Value::String(value.into()),
+            Message::Bytes(value) => Value::Bytes(value),
+            Message::Null => Value::Null,
+            Message::Object(fields) => {
+                let mut object = BindingMap::default();
+                for (ident, message) in fields {
+                    object.set(ident, Binding::constant(message.into()));
+                }
+                Value::Object(object.into())
+            }
+        }
+    }
+}
+
+struct Endpoint {
+    sender: Sender<Message>,
+    receiver: Receiver<Message>,
+}
+
+/// Channels connected by the host, by name.
+#[derive(Clone, Default)]
+pub struct Channels {
+    endpoints: Rc<RefCell<HashMap<String, Endpoint>>>,
+}
+
+impl Channels {
+    pub fn add(&self, name: &str, sender: Sender<Message>, receiver: Receiver<Message>) {
+        self.endpoints
+            .borrow_mut()
+            .insert(name.into(), Endpoint { sender, receiver });
+    }
+
+    /// Get the name of the channel a script passed to a builtin.
+    fn name(channel: &Value) -> JabroniResult<Rc<str>> {
+        channel
+            .as_object()
+            .and_then(|channel| channel.get("name"))
+            .and_then(|name| name.as_string().cloned())
+            .ok_or_else(|| {
+                JabroniError::from_code(ErrorCode::InvalidArgument, "Expected a channel".into())
+            })
+    }
+
+    fn with_endpoint<T>(&self, name: &str, f: impl FnOnce(&Endpoint) -> T) -> JabroniResult<T> {
+        match self.endpoints.borrow().get(name) {
+            Some(endpoint) => Ok(f(endpoint)),
+            None => Err(JabroniError::from_code(
+                ErrorCode::UnknownChannel,
+                format!("No channel named '{name}'"),
+            )),
+        }
+    }
+
+    /// The `channel(name)` builtin.
+    pub fn channel_builtin(&self) -> Subroutine {
+        let channels = self.clone();
+        Subroutine::new(
+            1,
+            Box::new(move |_, args: &mut [Value]| {
+                let name = args[0].as_string().ok_or_else(|| {
+                    JabroniError::from_code(ErrorCode::InvalidArgument, "Expected a name".into())
+                })?;
+                channels.with_endpoint(name, |_| ())?;
+                let mut channel = BindingMap::default();
+                channel.set(
+                    "name".into(),
+                    Binding::constant(Value::String(name.clone())),
+                );
+                Ok(Value::Object(channel.into()))
+            }),
+        )
+        .named("channel")
+        .documented(
+            "channel(name)",
+            "Open a channel connected by the host, to send and receive messages with.",
+        )
+    }
+
+    /// The `send(channel, value)` builtin.
+    pub fn send_builtin(&self) -> Subroutine {
+        let channels = self.clone();
+        Subroutine::new(
+            2,
+            Box::new(move |_, args: &mut [Value]| {
+                let name = Self::name(&args[0])?;
+                let message = Message::try_from(&args[1])?;
+                channels
+                    .with_endpoint(&name, |endpoint| endpoint.sender.send(message))?
+                    .map_err(|_| {
+                        JabroniError::from_code(
+                            ErrorCode::ChannelClosed,
+                            format!("Channel '{name}' is closed"),
+                        )
+                    })?;
+                Ok(Value::Null)
+            }),
+        )
+        .named("send")
+        .documented(
+            "send(channel, value)",
+            "Send a copy of value to the host. Functions can't be sent.",
+        )
+    }
+
+    /// The `receive(channel)` builtin. Blocks until a message arrives, or returns null if the
+    /// channel is closed.
+    pub fn receive_builtin(&self) -> Subroutine {
+        let channels = self.clone();
+        Subroutine::new(
+            1,
+            Box::new(move |_, args: &mut [Value]| {
+                let name = Self::name(&args[0])?;
+                let message = channels.with_endpoint(&name, |endpoint| endpoint.receiver.recv())?;
+                Ok(message.map(Value::from).unwrap_or(Value::Null))
+            }),
+        )
+        .named("receive")
+        .documented(
+            "receive(channel)",
+            "Wait for a message from the host, or return null if the channel is closed.",
+        )
+    }
+}
@@ -0,0 +1,718 @@
+//! Running a subset of JavaScript by translating it into Jabroni.
+//!
+//! On top of what Jabroni already accepts, the subset has:
+//! - `var`, which is the same as `let`
+//! - Arrow functions and function expressions, if they're the value of a declaration, like
+//!   `const double = (x) => x * 2;`
+//! - Template strings, like `` `Hello ${name}` ``
+//! - `undefined`, which is the same as `null`
+//! - Negative numbers, comments, and semicolons left out at the ends of lines
+//!
+//! Other JavaScript, like loops, `if` statements, arrays and object literals, is rejected with an
+//! error saying what isn't supported.
+
+use crate::errors::{ErrorCode, JabroniError, JabroniResult, Location, Source};
+use pest::Position;
+use std::sync::Arc;
+
+/// Joins the parts of a template string. Defined as a hidden binding by
+/// [`Jabroni::run_js`](crate::Jabroni::run_js).
+pub(crate) const TEMPLATE_HELPER: &str = "__template";
+
+const OPERATORS: [&str; 33] = [
+    "===", "!==", "**=", "...", "&&=", "||=", "??=", "=>", "==", "!=", "<=", ">=", "&&", "||",
+    "??", "?.", "++", "--", "+=", "-=", "*=", "/=", "%=", "**", "=", "<", ">", "+", "-", "*", "/",
+    "%", "!",
+];
+/// Operators Jabroni has, which are kept as they are.
+const SUPPORTED_OPERATORS: [&str; 13] = [
+    "===", "!==", "==", "!=", "<=", ">=", "=", "<", ">", "+", "-", "*", "/",
+];
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Kind {
+    Word,
+    Number,
+    String,
+    Template,
+    Operator,
+    /// `.`, `,`, `;`, `:` or `?`
+    Punctuation,
+    Open,
+    Close,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token {
+    kind: Kind,
+    start: usize,
+    end: usize,
+}
+
+/// Translate JavaScript into Jabroni. Lines are kept where they are, so code in the translation
+/// is on the same line as in the JavaScript, though usually not in the same column.
+///
+/// # Example
+/// ```
+/// use jabroni::compat;
+///
+/// let js = "var x = 1 // one\nconst add = (a, b) => a + b\n";
+/// assert_eq!(
+///     compat::translate(js).unwrap(),
+///     "let x = 1;       \nfunction add(a, b) { return a + b; }\n"
+/// );
+/// let error = compat::translate("for (;;) {}").unwrap_err();
+/// assert_eq!(error.message(), "Loops aren't supported");
+/// ```
+pub fn translate(code: &str) -> JabroniResult<String> {
+    Ok(Translator::new(code, 0, code.len(), false)?.run()?.code)
+}
+
+/// Like [`translate`], also returning a map from the translation back to the JavaScript.
+///
+/// # Example
+/// ```
+/// use jabroni::{compat, errors::Location};
+///
+/// let (translation, map) = compat::translate_mapped("var x = `${y}`").unwrap();
+/// assert_eq!(translation, "let x = __template('', (y), '');");
+/// // `y` in the translation is `y` in the JavaScript
+/// let y = Location { line: 1, column: 25 };
+/// assert_eq!(map.original(y), Some(Location { line: 1, column: 12 }));
+/// ```
+pub fn translate_mapped(code: &str) -> JabroniResult<(String, SourceMap)> {
+    let translation = Translator::new(code, 0, code.len(), false)?.run()?;
+    let map = SourceMap {
+        original: Arc::new(Source::new(None, code)),
+        translation: Source::new(None, &translation.code),
+        segments: translation.segments,
+    };
+    Ok((translation.code, map))
+}
+
+/// Where the code in a translation came from in the JavaScript, used to point errors and
+/// debugger positions at the JavaScript.
+#[derive(Debug)]
+pub struct SourceMap {
+    original: Arc<Source>,
+    translation: Source,
+    segments: Vec<Segment>,
+}
+
+impl SourceMap {
+    /// Find where in the JavaScript the code at `location` in the translation came from, if it
+    /// came from anywhere.
+    pub fn original(&self, location: Location) -> Option<Location> {
+        let offset = self.translation.offset(location)?;
+        let index = self
+            .segments
+            .partition_point(|segment| segment.translation <= offset)
+            .checked_sub(1)?;
+        let segment = &self.segments[index];
+        let original = segment.original + (offset - segment.translation).min(segment.length);
+        let (line, column) = Position::new(self.original.code(), original)?.line_col();
+        Some(Location { line, column })
+    }
+
+    /// The JavaScript.
+    pub fn original_code(&self) -> &str {
+        self.original.code()
+    }
+
+    /// The translation.
+    pub fn translation(&self) -> &str {
+        self.translation.code()
+    }
+
+    pub(crate) fn original_source(&self) -> &Arc<Source> {
+        &self.original
+    }
+}
+
+/// Text in a translation that came from an offset in the JavaScript.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    translation: usize,
+    original: usize,
+    /// How much of the text is the JavaScript as it is, and maps byte for byte.
+    length: usize,
+}
+
+/// Translated code, with where each part of it came from.
+#[derive(Default)]
+struct Translation {
+    code: String,
+    segments: Vec<Segment>,
+}
+
+impl Translation {
+    /// Add text that came from `original` in the JavaScript.
+    fn push(&mut self, text: &str, original: usize) {
+        self.push_segment(text, original, 0);
+    }
+
+    /// Add text that's the JavaScript at `original` as it is.
+    fn copy(&mut self, text: &str, original: usize) {
+        self.push_segment(text, original, text.len());
+    }
+
+    fn push_segment(&mut self, text: &str, original: usize, length: usize) {
+        self.segments.push(Segment {
+            translation: self.code.len(),
+            original,
+            length,
+        });
+        self.code.push_str(text);
+    }
+
+    fn append(&mut self, other: Translation) {
+        let shift = self.code.len();
+        self.segments
+            .extend(other.segments.into_iter().map(|segment| Segment {
+                translation: segment.translation + shift,
+                ..segment
+            }));
+        self.code.push_str(&other.code);
+    }
+
+    /// Add the space between tokens, with comments blanked out but their line breaks kept.
+    fn blank(&mut self, gap: &str, original: usize) {
+        let blanked: String = gap
+            .chars()
+            .map(|c| if c == '\n' { '\n' } else { ' ' })
+            .collect();
+        if blanked.len() == gap.len() {
+            self.copy(&blanked, original);
+        } else {
+            self.push(&blanked, original);
+        }
+    }
+}
+
+/// Translates the tokens in part of the code.
+struct Translator<'a> {
+    code: &'a str,
+    tokens: Vec<Token>,
+    /// Whether a semicolon should be inserted after each token, as JavaScript does.
+    semicolons: Vec<bool>,
+    /// Where the part being translated starts and ends.
+    start: usize,
+    end: usize,
+    /// Whether it's a single expression, inside a template string, rather than a script.
+    expression: bool,
+    output: Translation,
+}
+
+impl<'a> Translator<'a> {
+    fn new(code: &'a str, start: usize, end: usize, expression: bool) -> JabroniResult<Self> {
+        let tokens = lex(code, start, end)?;
+        let semicolons = (0..tokens.len())
+            .map(|i| !expression && needs_semicolon(code, &tokens, i))
+            .collect();
+        Ok(Self {
+            code,
+            tokens,
+            semicolons,
+            start,
+            end,
+            expression,
+            output: Translation::default(),
+        })
+    }
+
+    fn text(&self, i: usize) -> &'a str {
+        let token = self.tokens[i];
+        &self.code[token.start..token.end]
+    }
+
+    fn is(&self, i: usize, text: &str) -> bool {
+        i < self.tokens.len() && self.text(i) == text
+    }
+
+    fn error(&self, i: usize, message: &str) -> JabroniError {
+        let token = self.tokens[i];
+        let (line, column) = Position::new(self.code, token.start).unwrap().line_col();
+        JabroniError::from_code(ErrorCode::UnsupportedSyntax, message.into())
+            .located(|| Location { line, column }, token.end - token.start)
+            .in_source(Some(&Arc::new(Source::new(None, self.code))))
+    }
+
+    fn run(mut self) -> JabroniResult<Translation> {
+        // How many brackets are open
+        let mut depth = 0;
+        // Depths of functions whose body is an expression, which end with the statement
+        let mut expression_bodies: Vec<usize> = Vec::new();
+        // Depths of functions whose body is a block, which may be followed by a `;` to drop
+        let mut block_bodies: Vec<usize> = Vec::new();
+        let mut gap_start = self.start;
+        let mut i = 0;
+        while i < self.tokens.len() {
+            let token = self.tokens[i];
+            self.output
+                .blank(&self.code[gap_start..token.start], gap_start);
+            gap_start = token.end;
+            let text = self.text(i);
+            match (token.kind, text) {
+                (Kind::Open, "[") | (Kind::Close, "]") => {
+                    return Err(self.error(i, "Arrays aren't supported"))
+                }
+                (Kind::Close, _) => {
+                    while expression_bodies.last() == Some(&depth) {
+                        expression_bodies.pop();
+                        self.output.push("; }", token.start);
+                    }
+                    // Unmatched brackets are left for the parser to report
+                    depth = depth.saturating_sub(1);
+                    self.output.copy(text, token.start);
+                    if block_bodies.last() == Some(&depth) {
+                        block_bodies.pop();
+                        // Function declarations aren't followed by a `;`
+                        if self.is(i + 1, ";") {
+                            gap_start = self.tokens[i + 1].end;
+                            i += 1;
+                        }
+                        i += 1;
+                        continue;
+                    }
+                }
+                (Kind::Punctuation, ";") if expression_bodies.last() == Some(&depth) => {
+                    expression_bodies.pop();
+                    self.output.push("; }", token.start);
+                    i += 1;
+                    continue;
+                }
+                (Kind::Open, "{") if i > 0 && self.starts_object(i - 1) => {
+                    return Err(self.error(i, "Object literals aren't supported"))
+                }
+                (Kind::Open, _) => {
+                    depth += 1;
+                    self.output.copy(text, token.start);
+                }
+                (Kind::Word, "var" | "let" | "const") if !self.expression => {
+                    if let Some(next) = self.function_declaration(i)? {
+                        gap_start = self.tokens[next - 1].end;
+                        if self.is(next, "{") {
+                            block_bodies.push(depth);
+                        } else {
+                            let body = self.tokens[next].start;
+                            if body == gap_start {
+                                self.output.push(" { return ", body);
+                            } else {
+                                self.output.push(" { return", body);
+                            }
+                            expression_bodies.push(depth);
+                        }
+                        i = next;
+                        continue;
+                    }
+                    if text == "var" {
+                        self.output.push("let", token.start);
+                    } else {
+                        self.output.copy(text, token.start);
+                    }
+                }
+                (Kind::Word, "undefined") => self.output.push("null", token.start),
+                (Kind::Word, word) => {
+                    if let Some(message) = unsupported_word(word) {
+                        return Err(self.error(i, &message));
+                    }
+                    if word.contains('$') {
+                        return Err(self.error(i, "Names can't contain '$'"));
+                    }
+                    self.output.copy(word, token.start);
+                }
+                (Kind::Number, number) => {
+                    let hex = number.starts_with("0x") || number.starts_with("0X");
+                    if !hex && number.contains(['.', 'e', 'E']) {
+                        return Err(self.error(i, "Only whole numbers are supported"));
+                    }
+                    self.output.copy(number, token.start);
+                }
+                (Kind::Template, _) => self.template(i)?,
+                (Kind::Operator, "=>") => {
+                    return Err(self.error(
+                        i,
+                        "Arrow functions can only be the value of a declaration, like \
+                         'const double = (x) => x * 2;'",
+                    ))
+                }
+                // Jabroni doesn't have unary minus
+                (Kind::Operator, "-")
+                    if self.starts_operand(i)
+                        && self.tokens.get(i + 1).map(|t| t.kind) == Some(Kind::Number)
+                        && self.tokens[i + 1].start == token.end
+                        && !self.text(i + 1).contains('.') =>
+                {
+                    let negative = format!("(0 - {})", self.text(i + 1));
+                    self.output.push(&negative, token.start);
+                    gap_start = self.tokens[i + 1].end;
+                    i += 1;
+                }
+                (Kind::Operator, operator) if !SUPPORTED_OPERATORS.contains(&operator) => {
+                    return Err(self.error(i, &format!("The '{operator}' operator isn't supported")))
+                }
+                _ => self.output.copy(text, token.start),
+            }
+            if self.semicolons[i] {
+                if expression_bodies.last() == Some(&depth) {
+                    expression_bodies.pop();
+                    self.output.push("; }", token.end);
+                } else {
+                    self.output.push(";", token.end);
+                }
+            }
+            i += 1;
+        }
+        self.output
+            .blank(&self.code[gap_start..self.end], gap_start);
+        for _ in expression_bodies {
+            self.output.push("; }", self.end);
+        }
+        Ok(self.output)
+    }
+
+    /// Translate a declaration of a function, like `const f = (a) => ...` or
+    /// `const f = function (a) {...}`, into the start of a function declaration. Returns the
+    /// index of the token that starts its body, or `None` if it isn't a function.
+    fn function_declaration(&mut self, i: usize) -> JabroniResult<Option<usize>> {
+        let is_word = |j: usize| self.tokens.get(j).map(|t| t.kind) == Some(Kind::Word);
+        if !is_word(i + 1) || !self.is(i + 2, "=") {
+            return Ok(None);
+        }
+        let (params, body) = if self.is(i + 3, "function") && self.is(i + 4, "(") {
+            let (params, close) = self.params(i + 5)?;
+            (params, close + 1)
+        } else if is_word(i + 3) && self.is(i + 4, "=>") {
+            (vec![i + 3], i + 5)
+        } else if self.is(i + 3, "(") {
+            match self.params(i + 4) {
+                Ok((params, close)) if self.is(close + 1, "=>") => (params, close + 2),
+                // It's a parenthesized expression
+                _ => return Ok(None),
+            }
+        } else {
+            return Ok(None);
+        };
+        if body >= self.tokens.len() {
+            return Err(self.error(body - 1, "Expected the function's body"));
+        }
+        let start = self.tokens[i].start;
+        self.output.push("function ", start);
+        self.output.copy(self.text(i + 1), self.tokens[i + 1].start);
+        self.output.push("(", start);
+        for (n, &param) in params.iter().enumerate() {
+            if n > 0 {
+                self.output.push(", ", start);
+            }
+            self.output.copy(self.text(param), self.tokens[param].start);
+        }
+        self.output.push(")", start);
+        // Keep the lines the declaration was on
+        let head = &self.code[start..self.tokens[body - 1].end];
+        let lines = head.matches('\n').count();
+        if lines > 0 {
+            self.output
+                .push(&"\n".repeat(lines), self.tokens[body - 1].start);
+        }
+        Ok(Some(body))
+    }
+
+    /// Read parameters starting at `i`, up to a `)`. Returns the indices of their tokens and the
+    /// index of the `)`.
+    fn params(&self, mut i: usize) -> JabroniResult<(Vec<usize>, usize)> {
+        let mut params = Vec::new();
+        loop {
+            match self.tokens.get(i).map(|token| (token.kind, self.text(i))) {
+                Some((Kind::Close, ")")) => return Ok((params, i)),
+                Some((Kind::Word, word)) if unsupported_word(word).is_none() => {
+                    params.push(i);
+                    i += 1;
+                    if self.is(i, ",") {
+                        i += 1;
+                    } else if !self.is(i, ")") {
+                        return Err(self.error(
+                            i.min(self.tokens.len() - 1),
+                            "Default values and destructuring aren't supported",
+                        ));
+                    }
+                }
+                _ => {
+                    return Err(
+                        self.error(i.min(self.tokens.len() - 1), "Expected a parameter name")
+                    )
+                }
+            }
+        }
+    }
+
+    /// Whether a `{` after token `i` starts an object literal, rather than a block.
+    fn starts_object(&self, i: usize) -> bool {
+        matches!(
+            self.text(i),
+            "=" | "(" | "," | ":" | "?" | "return" | "yield" | "throw"
+        ) || self.expression
+    }
+
+    /// Whether token `i` is where an operand goes, rather than after one.
+    fn starts_operand(&self, i: usize) -> bool {
+        i == 0
+            || matches!(self.tokens[i - 1].kind, Kind::Operator | Kind::Open)
+            || matches!(
+                self.text(i - 1),
+                "," | "?" | ":" | ";" | "return" | "yield" | "throw"
+            )
+    }
+
+    /// Translate a template string into a call joining its parts, or a plain string if it
+    /// doesn't have any expressions.
+    fn template(&mut self, i: usize) -> JabroniResult {
+        let token = self.tokens[i];
+        let code = self.code;
+        let mut parts = Translation::default();
+        let mut text = String::new();
+        // Where the text being read starts
+        let mut text_start = token.start;
+        let mut chars = code[token.start + 1..token.end - 1]
+            .char_indices()
+            .peekable();
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, c @ ('`' | '$'))) => text.push(c),
+                    Some((_, c)) => {
+                        text.push('\\');
+                        text.push(c);
+                    }
+                    None => {}
+                },
+                '\'' => text.push_str("\\'"),
+                '$' if chars.peek().map(|(_, c)| *c) == Some('{') => {
+                    chars.next();
+                    let start = token.start + 1 + offset + 2;
+                    let end = template_expression_end(code, start)
+                        .expect("template strings are checked when lexing");
+                    parts.push(&format!("'{}', (", std::mem::take(&mut text)), text_start);
+                    parts.append(Translator::new(code, start, end, true)?.run()?);
+                    parts.push("), ", end);
+                    while chars
+                        .peek()
+                        .map_or(false, |(offset, _)| token.start + 1 + offset <= end)
+                    {
+                        chars.next();
+                    }
+                    text_start = end;
+                }
+                c => text.push(c),
+            }
+        }
+        parts.push(&format!("'{}'", text), text_start);
+        if parts.segments.len() == 1 {
+            self.output.append(parts);
+        } else {
+            self.output
+                .push(&format!("{}(", TEMPLATE_HELPER), token.start);
+            self.output.append(parts);
+            self.output.push(")", token.end - 1);
+        }
+        Ok(())
+    }
+}
+
+/// Whether JavaScript would insert a semicolon after token `i`: it ends a line or comes before a
+/// `}`, ends an expression, and the next token can't continue it.
+fn needs_semicolon(code: &str, tokens: &[Token], i: usize) -> bool {
+    let token = tokens[i];
+    let text = &code[token.start..token.end];
+    let ends_expression = match token.kind {
+        Kind::Word => unsupported_word(text).is_none() && !is_keyword(text),
+        Kind::Number | Kind::String | Kind::Template => true,
+        Kind::Close => text == ")",
+        _ => false,
+    } || matches!(text, "true" | "false" | "null" | "undefined" | "yield");
+    if !ends_expression {
+        return false;
+    }
+    let next = match tokens.get(i + 1) {
+        Some(next) => next,
+        None => return true,
+    };
+    let next_text = &code[next.start..next.end];
+    if next_text == "}" {
+        return true;
+    }
+    let continues =
+        matches!(next.kind, Kind::Operator | Kind::Punctuation | Kind::Close) || next_text == "(";
+    let paren_depth =
+        tokens[..=i]
+            .iter()
+            .fold(0i32, |depth, token| match &code[token.start..token.end] {
+                "(" => depth + 1,
+                ")" => depth - 1,
+                _ => depth,
+            });
+    code[token.end..next.start].contains('\n') && !continues && paren_depth <= 0
+}
+
+fn is_keyword(word: &str) -> bool {
+    matches!(
+        word,
+        "function"
+            | "return"
+            | "const"
+            | "let"
+            | "var"
+            | "throw"
+            | "try"
+            | "catch"
+            | "import"
+            | "from"
+    )
+}
+
+/// Explain why a word isn't supported, if it's part of JavaScript that Jabroni doesn't have.
+fn unsupported_word(word: &str) -> Option<String> {
+    let message = match word {
+        "if" | "else" => {
+            "'if' statements aren't supported. Use the ternary operator, 'condition ? a : b'"
+        }
+        "for" | "while" | "do" | "break" | "continue" => "Loops aren't supported",
+        "switch" | "case" | "default" => "'switch' statements aren't supported",
+        "class" | "new" | "this" | "super" | "extends" => "Classes aren't supported",
+        "async" | "await" => "Async functions aren't supported",
+        "export" => "Exports aren't supported. Every top-level binding can be imported",
+        "typeof" | "instanceof" | "in" | "of" | "delete" | "void" | "finally" | "with"
+        | "debugger" => return Some(format!("'{word}' isn't supported")),
+        _ => return None,
+    };
+    Some(message.into())
+}
+
+/// Split part of the code into tokens, leaving out whitespace and comments.
+fn lex(code: &str, start: usize, end: usize) -> JabroniResult<Vec<Token>> {
+    let error = |offset: usize, message: &str| {
+        let (line, column) = Position::new(code, offset).unwrap().line_col();
+        JabroniError::from_code(ErrorCode::Syntax, message.into())
+            .located(|| Location { line, column }, 1)
+            .in_source(Some(&Arc::new(Source::new(None, code))))
+    };
+    let mut tokens = Vec::new();
+    let mut i = start;
+    while i < end {
+        let rest = &code[i..end];
+        let c = rest.chars().next().unwrap();
+        let (kind, length) = if c.is_whitespace() {
+            i += c.len_utf8();
+            continue;
+        } else if rest.starts_with("//") {
+            i += rest.find('\n').unwrap_or(rest.len());
+            continue;
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            match comment.find("*/") {
+                Some(close) => i += close + 4,
+                None => return Err(error(i, "Comment isn't closed")),
+            }
+            continue;
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '$' {
+            let length = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+                .unwrap_or(rest.len());
+            (Kind::Word, length)
+        } else if c.is_ascii_digit() {
+            let length = rest
+                .char_indices()
+                .find(|&(j, c)| {
+                    !(c.is_ascii_alphanumeric()
+                        || c == '.'
+                        || (matches!(c, '+' | '-') && rest[..j].ends_with(['e', 'E'])))
+                })
+                .map_or(rest.len(), |(j, _)| j);
+            (Kind::Number, length)
+        } else if c == '\'' || c == '"' {
+            match string_end(rest, c) {
+                Some(length) => (Kind::String, length),
+                None => return Err(error(i, "String isn't closed")),
+            }
+        } else if c == '`' {
+            match template_end(code, i) {
+                Some(close) if close < end => (Kind::Template, close + 1 - i),
+                _ => return Err(error(i, "Template string isn't closed")),
+            }
+        } else if let Some(operator) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            (Kind::Operator, operator.len())
+        } else if matches!(c, '.' | ',' | ';' | ':' | '?') {
+            (Kind::Punctuation, 1)
+        } else if matches!(c, '(' | '{' | '[') {
+            (Kind::Open, 1)
+        } else if matches!(c, ')' | '}' | ']') {
+            (Kind::Close, 1)
+        } else {
+            return Err(error(i, &format!("Unexpected character '{c}'")));
+        };
+        tokens.push(Token {
+            kind,
+            start: i,
+            end: i + length,
+        });
+        i += length;
+    }
+    Ok(tokens)
+}
+
+/// Find the length of the string at the start of `code`, which is quoted with `quote`.
+fn string_end(code: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in code.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '\n' => return None,
+            c if c == quote => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Find the offset of the backtick that closes the template string starting at `start`.
+fn template_end(code: &str, start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    while i < code.len() {
+        let rest = &code[i..];
+        if let Some(escaped) = rest.strip_prefix('\\') {
+            i += 1 + escaped.chars().next()?.len_utf8();
+        } else if rest.starts_with('`') {
+            return Some(i);
+        } else if rest.starts_with("${") {
+            i = template_expression_end(code, i + 2)? + 1;
+        } else {
+            i += rest.chars().next()?.len_utf8();
+        }
+    }
+    None
+}
+
+/// Find the offset of the `}` that ends the expression in a template string starting at `start`.
+fn template_expression_end(code: &str, start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < code.len() {
+        let rest = &code[i..];
+        let c = rest.chars().next()?;
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            '\'' | '"' => {
+                i += string_end(rest, c)?;
+                continue;
+            }
+            '`' => {
+                i = template_end(code, i)? + 1;
+                continue;
+            }
+            _ => {}
+        }
+        i += c.len_utf8();
+    }
+    None
+}
@@ -0,0 +1,727 @@
+//! Saving syntax trees as bytes, so scripts can be shipped compiled rather than as source.
+
+use crate::{
+    ast::{
+        DeclarationKind, Expression, ExpressionKind, Ident, Script, Span, Statement, StatementKind,
+    },
+    errors::{ErrorCode, JabroniError, JabroniResult},
+    tokens::KEYWORDS,
+    utils::MAX_NESTING,
+    value::{Operator, Value},
+};
+
+/// Starts every compiled script.
+const MAGIC: &[u8; 4] = b"JBC\0";
+/// The version of the format, changed whenever it changes. Scripts compiled with other versions
+/// aren't loaded.
+const VERSION: u8 = 1;
+/// How deeply statements and expressions can nest, so loading a corrupt script can't overflow
+/// the stack. Parsed scripts nest brackets at most [`MAX_NESTING`] deep, and each level can add
+/// a statement or expression besides the brackets. Scripts that nest deeper anyway, like long
+/// chains of calls, aren't compiled, so everything compiled can be loaded.
+const MAX_DEPTH: usize = MAX_NESTING * 2;
+/// Tags a chain of binary operations, which is written differently from other expressions.
+const BINARY: u8 = 7;
+const OPERATORS: [Operator; 12] = [
+    Operator::Add,
+    Operator::Subtract,
+    Operator::Multiply,
+    Operator::Divide,
+    Operator::Equal,
+    Operator::NotEqual,
+    Operator::StrictEqual,
+    Operator::StrictNotEqual,
+    Operator::Greater,
+    Operator::GreaterOrEqual,
+    Operator::Less,
+    Operator::LessOrEqual,
+];
+
+impl Script {
+    /// Serialize the script, such as to save it to a `.jbc` file. Its formatting isn't kept.
+    /// Load it with [`Script::from_bytes`], and run it with
+    /// [`Jabroni::run_compiled`](crate::Jabroni::run_compiled).
+    ///
+    /// Fails if the script nests too deeply to be loaded again, or if a literal isn't a boolean,
+    /// string, null or number that isn't negative, which parsed scripts can't have but scripts
+    /// changed with a [`VisitorMut`](crate::ast::VisitorMut) can.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{ast::{self, Script}, fmt};
+    ///
+    /// let bytes = ast::parse("function double(x){return x*2;}")
+    ///     .unwrap()
+    ///     .to_bytes()
+    ///     .unwrap();
+    /// let script = Script::from_bytes(&bytes).unwrap();
+    /// let code = fmt::format_script(&script);
+    /// assert_eq!(code, "function double(x) {\n    return x * 2;\n}\n");
+    /// ```
+    pub fn to_bytes(&self) -> JabroniResult<Vec<u8>> {
+        let mut writer = Writer {
+            bytes: MAGIC.to_vec(),
+            depth: 0,
+        };
+        writer.bytes.push(VERSION);
+        writer.statements(&self.statements)?;
+        Ok(writer.bytes)
+    }
+
+    /// Deserialize a script serialized with [`Script::to_bytes`], checking that it's a valid
+    /// script compiled with the same version of the format.
+    pub fn from_bytes(bytes: &[u8]) -> JabroniResult<Script> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(invalid("Not a compiled script".into()));
+        }
+        match bytes.get(MAGIC.len()) {
+            Some(&VERSION) => {}
+            Some(version) => {
+                return Err(invalid(format!(
+                    "Script was compiled with format version {version}, but only version \
+                     {VERSION} can be loaded"
+                )))
+            }
+            None => return Err(invalid("Compiled script is empty".into())),
+        }
+        let mut reader = Reader {
+            bytes,
+            position: MAGIC.len() + 1,
+            depth: 0,
+        };
+        let statements = reader.statements()?;
+        if reader.position != bytes.len() {
+            return Err(reader.error("Unexpected data after the script"));
+        }
+        Ok(Script { statements })
+    }
+}
+
+fn invalid(message: String) -> JabroniError {
+    JabroniError::from_code(ErrorCode::InvalidCompiledScript, message)
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+    /// How deeply the statement or expression being written is nested.
+    depth: usize,
+}
+
+impl Writer {
+    /// Write an unsigned number in as few bytes as it needs, seven bits at a time.
+    fn unsigned(&mut self, mut number: u64) {
+        while number >= 0x80 {
+            self.bytes.push(number as u8 | 0x80);
+            number >>= 7;
+        }
+        self.bytes.push(number as u8);
+    }
+
+    fn string(&mut self, string: &str) {
+        self.unsigned(string.len() as u64);
+        self.bytes.extend_from_slice(string.as_bytes());
+    }
+
+    fn span(&mut self, span: Span) {
+        self.unsigned(span.start as u64);
+        self.unsigned((span.end - span.start) as u64);
+    }
+
+    fn ident(&mut self, ident: &Ident) {
+        self.string(&ident.name);
+        self.span(ident.span);
+    }
+
+    fn idents(&mut self, idents: &[Ident]) {
+        self.unsigned(idents.len() as u64);
+        for ident in idents {
+            self.ident(ident);
+        }
+    }
+
+    /// Go a level deeper into the script, as the reader will when loading it.
+    fn enter(&mut self) -> JabroniResult {
+        if self.depth == MAX_DEPTH {
+            return Err(JabroniError::from_code(
+                ErrorCode::NestingTooDeep,
+                format!("Script nests more than {MAX_DEPTH} levels deep, so it can't be compiled"),
+            ));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> JabroniResult {
+        self.unsigned(statements.len() as u64);
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> JabroniResult {
+        self.enter()?;
+        let tag = match &statement.kind {
+            StatementKind::Expression(_) => 0,
+            StatementKind::Block(_) => 1,
+            StatementKind::Function { .. } => 2,
+            StatementKind::Return(_) => 3,
+            StatementKind::Yield(_) => 4,
+            StatementKind::Declaration { .. } => 5,
+            StatementKind::Throw(_) => 6,
+            StatementKind::Try { .. } => 7,
+            StatementKind::Import { .. } => 8,
+        };
+        self.bytes.push(tag);
+        self.span(statement.span);
+        match &statement.kind {
+            StatementKind::Expression(value)
+            | StatementKind::Return(value)
+            | StatementKind::Throw(value) => self.expression(value)?,
+            StatementKind::Block(body) => self.statements(body)?,
+            StatementKind::Function { name, params, body } => {
+                self.ident(name);
+                self.idents(params);
+                self.statements(body)?;
+            }
+            StatementKind::Yield(value) => match value {
+                Some(value) => {
+                    self.bytes.push(1);
+                    self.expression(value)?;
+                }
+                None => self.bytes.push(0),
+            },
+            StatementKind::Declaration { kind, name, value } => {
+                self.bytes.push(match kind {
+                    DeclarationKind::Const => 0,
+                    DeclarationKind::Let => 1,
+                });
+                self.ident(name);
+                self.expression(value)?;
+            }
+            StatementKind::Try {
+                body,
+                binding,
+                handler,
+            } => {
+                self.statements(body)?;
+                self.ident(binding);
+                self.statements(handler)?;
+            }
+            StatementKind::Import { names, specifier } => {
+                self.idents(names);
+                self.string(specifier);
+            }
+        }
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn expression(&mut self, expression: &Expression) -> JabroniResult {
+        self.enter()?;
+        if let ExpressionKind::Binary { .. } = expression.kind {
+            self.binary(expression)?;
+            self.depth -= 1;
+            return Ok(());
+        }
+        let tag = match &expression.kind {
+            ExpressionKind::Literal(_) => 0,
+            ExpressionKind::Ident(_) => 1,
+            ExpressionKind::Group(_) => 2,
+            ExpressionKind::Member { .. } => 3,
+            ExpressionKind::Call { .. } => 4,
+            ExpressionKind::Assignment { .. } => 5,
+            ExpressionKind::Ternary { .. } => 6,
+            ExpressionKind::Binary { .. } => unreachable!(),
+        };
+        self.bytes.push(tag);
+        self.span(expression.span);
+        match &expression.kind {
+            ExpressionKind::Literal(value) => match value {
+                Value::Null => self.bytes.push(0),
+                Value::Boolean(false) => self.bytes.push(1),
+                Value::Boolean(true) => self.bytes.push(2),
+                Value::Number(number) if *number >= 0 => {
+                    self.bytes.push(3);
+                    self.unsigned(*number as u64);
+                }
+                Value::String(string) => {
+                    self.bytes.push(4);
+                    self.string(string);
+                }
+                value => {
+                    return Err(JabroniError::from_code(
+                        ErrorCode::InvalidLiteral,
+                        format!("Can't compile the {} literal {value}", value.type_name()),
+                    ))
+                }
+            },
+            ExpressionKind::Ident(name) => self.string(name),
+            ExpressionKind::Group(inner) => self.expression(inner)?,
+            ExpressionKind::Member { object, property } => {
+                self.expression(object)?;
+                self.ident(property);
+            }
+            ExpressionKind::Call { callee, args } => {
+                self.expression(callee)?;
+                self.unsigned(args.len() as u64);
+                for arg in args {
+                    self.expression(arg)?;
+                }
+            }
+            ExpressionKind::Assignment { target, value } => {
+                self.expression(target)?;
+                self.expression(value)?;
+            }
+            ExpressionKind::Ternary {
+                condition,
+                consequent,
+                alternative,
+            } => {
+                self.expression(condition)?;
+                self.expression(consequent)?;
+                self.expression(alternative)?;
+            }
+            ExpressionKind::Binary { .. } => unreachable!(),
+        }
+        self.depth -= 1;
+        Ok(())
+    }
+
+    /// Write a chain of binary operations, like `a + b - c`, flat, so they don't nest for each
+    /// operation.
+    fn binary(&mut self, expression: &Expression) -> JabroniResult {
+        let mut operations = Vec::new();
+        let mut first = expression;
+        while let ExpressionKind::Binary { operator, lhs, rhs } = &first.kind {
+            operations.push((operator, first.span, rhs));
+            first = lhs;
+        }
+        self.bytes.push(BINARY);
+        self.expression(first)?;
+        self.unsigned(operations.len() as u64);
+        for (operator, span, rhs) in operations.into_iter().rev() {
+            let index = OPERATORS.iter().position(|op| op == operator).unwrap();
+            self.bytes.push(index as u8);
+            self.span(span);
+            self.expression(rhs)?;
+        }
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    /// How deeply the statement or expression being read is nested.
+    depth: usize,
+}
+
+impl Reader<'_> {
+    fn error(&self, message: &str) -> JabroniError {
+        invalid(format!("{} at byte {}", message, self.position))
+    }
+
+    fn byte(&mut self) -> JabroniResult<u8> {
+        let byte = *self
+            .bytes
+            .get(self.position)
+            .ok_or_else(|| self.error("Compiled script ends early"))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn unsigned(&mut self) -> JabroniResult<u64> {
+        let mut number = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            number |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(number);
+            }
+        }
+        Err(self.error("Number is too long"))
+    }
+
+    /// Read a count or offset, which must be small enough to point into the script.
+    fn size(&mut self) -> JabroniResult<usize> {
+        match usize::try_from(self.unsigned()?) {
+            Ok(size) if size <= u32::MAX as usize => Ok(size),
+            _ => Err(self.error("Size is too big")),
+        }
+    }
+
+    fn string(&mut self) -> JabroniResult<String> {
+        let length = self.size()?;
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + length)
+            .ok_or_else(|| self.error("Compiled script ends early"))?;
+        let string =
+            String::from_utf8(bytes.to_vec()).map_err(|_| self.error("String isn't UTF-8"))?;
+        self.position += length;
+        Ok(string)
+    }
+
+    fn span(&mut self) -> JabroniResult<Span> {
+        let start = self.size()?;
+        let length = self.size()?;
+        Ok(Span {
+            start,
+            end: start + length,
+        })
+    }
+
+    /// Read an identifier's name, which must be one the parser would accept.
+    fn name(&mut self) -> JabroniResult<String> {
+        let name = self.string()?;
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !KEYWORDS.contains(&name.as_str())
+            && name != "null";
+        if !valid {
+            return Err(self.error(&format!("'{}' isn't an identifier", name)));
+        }
+        Ok(name)
+    }
+
+    fn ident(&mut self) -> JabroniResult<Ident> {
+        Ok(Ident {
+            name: self.name()?,
+            span: self.span()?,
+        })
+    }
+
+    fn idents(&mut self) -> JabroniResult<Vec<Ident>> {
+        (0..self.size()?).map(|_| self.ident()).collect()
+    }
+
+    /// Go a level deeper into the script. Levels aren't left if reading fails, since the
+    /// reader isn't used after that.
+    fn enter(&mut self) -> JabroniResult {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("Script nests too deeply"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn statements(&mut self) -> JabroniResult<Vec<Statement>> {
+        (0..self.size()?).map(|_| self.statement()).collect()
+    }
+
+    fn statement(&mut self) -> JabroniResult<Statement> {
+        self.enter()?;
+        let tag = self.byte()?;
+        let span = self.span()?;
+        // Each kind is read by its own function, which keeps the stack frames for each level
+        // small
+        let kind = match tag {
+            0 => StatementKind::Expression(self.expression()?),
+            1 => StatementKind::Block(self.statements()?),
+            2 => self.function()?,
+            3 => StatementKind::Return(self.expression()?),
+            4 => self.yield_statement()?,
+            5 => self.declaration()?,
+            6 => StatementKind::Throw(self.expression()?),
+            7 => self.try_statement()?,
+            8 => StatementKind::Import {
+                names: self.idents()?,
+                specifier: self.string()?,
+            },
+            _ => return Err(self.error("Invalid statement")),
+        };
+        self.depth -= 1;
+        Ok(Statement { kind, span })
+    }
+
+    fn function(&mut self) -> JabroniResult<StatementKind> {
+        Ok(StatementKind::Function {
+            name: self.ident()?,
+            params: self.idents()?,
+            body: self.statements()?,
+        })
+    }
+
+    fn yield_statement(&mut self) -> JabroniResult<StatementKind> {
+        Ok(StatementKind::Yield(match self.byte()? {
+            0 => None,
+            1 => Some(self.expression()?),
+            _ => return Err(self.error("Invalid yield")),
+        }))
+    }
+
+    fn declaration(&mut self) -> JabroniResult<StatementKind> {
+        Ok(StatementKind::Declaration {
+            kind: match self.byte()? {
+                0 => DeclarationKind::Const,
+                1 => DeclarationKind::Let,
+                _ => return Err(self.error("Invalid declaration")),
+            },
+            name: self.ident()?,
+            value: self.expression()?,
+        })
+    }
+
+    fn try_statement(&mut self) -> JabroniResult<StatementKind> {
+        Ok(StatementKind::Try {
+            body: self.statements()?,
+            binding: self.ident()?,
+            handler: self.statements()?,
+        })
+    }
+
+    fn expression(&mut self) -> JabroniResult<Expression> {
+        self.enter()?;
+        let tag = self.byte()?;
+        if tag == BINARY {
+            let expression = self.binary()?;
+            self.depth -= 1;
+            return Ok(expression);
+        }
+        let span = self.span()?;
+        let kind = match tag {
+            0 => self.literal()?,
+            1 => ExpressionKind::Ident(self.name()?),
+            2 => ExpressionKind::Group(self.boxed()?),
+            3 => self.member()?,
+            4 => self.call()?,
+            5 => self.assignment()?,
+            6 => self.ternary()?,
+            _ => return Err(self.error("Invalid expression")),
+        };
+        self.depth -= 1;
+        Ok(Expression { kind, span })
+    }
+
+    fn boxed(&mut self) -> JabroniResult<Box<Expression>> {
+        self.expression().map(Box::new)
+    }
+
+    fn literal(&mut self) -> JabroniResult<ExpressionKind> {
+        Ok(ExpressionKind::Literal(match self.byte()? {
+            0 => Value::Null,
+            1 => Value::Boolean(false),
+            2 => Value::Boolean(true),
+            // Literals can't be negative
+            3 => Value::Number(
+                i64::try_from(self.unsigned()?).map_err(|_| self.error("Number is too big"))?,
+            ),
+            4 => Value::String(self.string()?.into()),
+            _ => return Err(self.error("Invalid literal")),
+        }))
+    }
+
+    fn member(&mut self) -> JabroniResult<ExpressionKind> {
+        Ok(ExpressionKind::Member {
+            object: self.boxed()?,
+            property: self.ident()?,
+        })
+    }
+
+    fn call(&mut self) -> JabroniResult<ExpressionKind> {
+        Ok(ExpressionKind::Call {
+            callee: self.boxed()?,
+            args: (0..self.size()?)
+                .map(|_| self.expression())
+                .collect::<JabroniResult<_>>()?,
+        })
+    }
+
+    fn assignment(&mut self) -> JabroniResult<ExpressionKind> {
+        let target = self.boxed()?;
+        if !matches!(
+            target.kind,
+            ExpressionKind::Ident(_) | ExpressionKind::Member { .. }
+        ) {
+            return Err(self.error("Invalid assignment"));
+        }
+        Ok(ExpressionKind::Assignment {
+            target,
+            value: self.boxed()?,
+        })
+    }
+
+    fn ternary(&mut self) -> JabroniResult<ExpressionKind> {
+        Ok(ExpressionKind::Ternary {
+            condition: self.boxed()?,
+            consequent: self.boxed()?,
+            alternative: self.boxed()?,
+        })
+    }
+
+    /// Read a chain of binary operations, without nesting for each one.
+    fn binary(&mut self) -> JabroniResult<Expression> {
+        let mut lhs = self.expression()?;
+        for _ in 0..self.size()? {
+            let operator = *OPERATORS
+                .get(usize::from(self.byte()?))
+                .ok_or_else(|| self.error("Invalid operator"))?;
+            let span = self.span()?;
+            lhs = Expression {
+                kind: ExpressionKind::Binary {
+                    operator,
+                    lhs: Box::new(lhs),
+                    rhs: self.boxed()?,
+                },
+                span,
+            };
+        }
+        Ok(lhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ast, fmt,
+        sandbox::{Construct, Sandbox},
+        Binding, BindingMap, Jabroni,
+    };
+
+    #[test]
+    fn round_trips() {
+        let code = "
+import { a, b } from './lib.jab';
+function f(x, y) {
+    try {
+        throw x.y(1, 'two', true, null);
+    } catch (e) {
+        yield;
+        yield e;
+    }
+    return x = y ? 9223372036854775807 : (a + b) * 2 <= 3;
+}
+{
+    let z = f(1, 2);
+}
+const s = 'café';";
+        let script = ast::parse(code).unwrap();
+        let bytes = script.to_bytes().unwrap();
+        assert!(bytes.starts_with(b"JBC\0\x01"));
+        assert_eq!(Script::from_bytes(&bytes).unwrap(), script);
+        assert_eq!(
+            fmt::format_script(&Script::from_bytes(&bytes).unwrap()),
+            fmt::format_script(&script)
+        );
+
+        // Chains of operations don't count as nesting
+        let chain = format!("const x = {};", vec!["1"; 1000].join(" + "));
+        let script = ast::parse(&chain).unwrap();
+        assert_eq!(
+            Script::from_bytes(&script.to_bytes().unwrap()).unwrap(),
+            script
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_scripts() {
+        let bytes = ast::parse("const x = f(1 + 2);")
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        let message = |bytes: &[u8]| Script::from_bytes(bytes).unwrap_err().message().to_string();
+        assert_eq!(message(b"const x = 1;"), "Not a compiled script");
+        assert_eq!(message(b"JBC\0"), "Compiled script is empty");
+        assert_eq!(
+            message(b"JBC\0\x02\0"),
+            "Script was compiled with format version 2, but only version 1 can be loaded"
+        );
+        // Every truncation fails, rather than loading part of the script
+        for length in 5..bytes.len() {
+            let error = Script::from_bytes(&bytes[..length]).unwrap_err();
+            assert_eq!(error.code(), Some(ErrorCode::InvalidCompiledScript));
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            message(&trailing),
+            format!("Unexpected data after the script at byte {}", bytes.len())
+        );
+
+        // Identifiers are checked, so loading can't turn into different code
+        let script = ast::parse("x;").unwrap();
+        let mut bytes = script.to_bytes().unwrap();
+        let x = bytes.iter().rposition(|&byte| byte == b'x').unwrap();
+        bytes[x] = b';';
+        assert_eq!(
+            message(&bytes),
+            format!("';' isn't an identifier at byte {}", x + 1)
+        );
+
+        // Nesting is limited
+        // An expression statement, then groups in groups
+        let mut bytes = b"JBC\0\x01\x01\0\0\0".to_vec();
+        for _ in 0..MAX_DEPTH {
+            bytes.extend_from_slice(&[2, 0, 0]);
+        }
+        assert!(message(&bytes).starts_with("Script nests too deeply"));
+    }
+
+    #[test]
+    fn refuses_scripts_it_cant_load() {
+        // Calls nest in the syntax tree, but not in the code
+        let chain = |calls| format!("f{};", "()".repeat(calls));
+        let script = ast::parse(&chain(100)).unwrap();
+        assert_eq!(
+            Script::from_bytes(&script.to_bytes().unwrap()).unwrap(),
+            script
+        );
+        let error = ast::parse(&chain(300)).unwrap().to_bytes().unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::NestingTooDeep));
+
+        // Changed trees can have literals the parser can't produce
+        let mut script = ast::parse("const x = 1;").unwrap();
+        for value in [Value::Number(-1), Value::from(vec![1, 2])] {
+            if let StatementKind::Declaration { value: literal, .. } =
+                &mut script.statements[0].kind
+            {
+                literal.kind = ExpressionKind::Literal(value);
+            }
+            let error = script.to_bytes().unwrap_err();
+            assert_eq!(error.code(), Some(ErrorCode::InvalidLiteral));
+        }
+    }
+
+    #[test]
+    fn runs_trees() {
+        let new_interpreter = || {
+            let mut jabroni = Jabroni::new();
+            let mut counter = BindingMap::default();
+            counter.set("count".into(), Binding::variable(0.into()));
+            jabroni.define_constant("counter", counter.into()).unwrap();
+            jabroni
+        };
+        // Trees run the same as the code they were parsed from
+        for code in [
+            "function count(n) {return n == 0 ? 0 : count(n - 1) + 1;}\n\
+             function bump() {counter.count = counter.count + 1;}\n\
+             bump(); bump();\n\
+             return count(counter.count) * 10 - 3;",
+            "let caught = 'nothing';\n\
+             try {throw 'oops';} catch (e) {caught = e.message;}\n\
+             return caught;",
+            "const s = 'a'; {let s = 'b';} return s === 'a' ? (1 <= 2) : null;",
+        ] {
+            let script =
+                Script::from_bytes(&ast::parse(code).unwrap().to_bytes().unwrap()).unwrap();
+            let mut jabroni = new_interpreter();
+            let value = jabroni.run_compiled("test.jbc", &script).unwrap();
+            assert_eq!(value, new_interpreter().run_script(code).unwrap());
+            assert!(jabroni.last_run_stats().instructions > 0);
+        }
+
+        // Errors name the script they came from
+        let script = ast::parse("function f() {return missing;}\nf();").unwrap();
+        let error = Jabroni::new().run_compiled("f.jbc", &script).unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::UndefinedReference));
+        assert_eq!(error.source_name(), Some("f.jbc"));
+
+        // Trees are checked by the sandbox like code
+        let mut sandboxed = Jabroni::new();
+        sandboxed.set_sandbox(Sandbox::new().without(Construct::Calls));
+        let error = sandboxed.run_compiled("f.jbc", &script).unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::Sandboxed));
+    }
+}
@@ -0,0 +1,361 @@
+//! Completing identifiers and members as scripts are typed.
+
+use crate::{
+    ast::{self, DeclarationKind, Script, Span, Statement, StatementKind},
+    tokens::{tokenize, TokenKind, KEYWORDS},
+    BindingMap, Jabroni, Object, Value,
+};
+
+/// What a [`Completion`] is.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum CompletionKind {
+    Keyword,
+    /// A binding that isn't a function
+    Variable,
+    /// A binding or member that's a function
+    Function,
+    /// A member of an object that isn't a function
+    Property,
+}
+
+/// Something that could be typed at the cursor.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Completion {
+    /// The text to insert.
+    pub label: String,
+    pub kind: CompletionKind,
+    /// More about what it is, such as how a function is called.
+    pub detail: Option<String>,
+    /// The part of the script the text replaces: the start of the word before the cursor, up to
+    /// the cursor.
+    pub span: Span,
+}
+
+/// List what could be typed at `offset` in `code`, sorted by label. After a `.`, these are the
+/// members of the object before it, if it's an object `jabroni` has. Otherwise they're keywords,
+/// the bindings `jabroni` has, and the bindings declared in the script that can be used at the
+/// cursor. Nothing is run, and scripts don't have to parse: if one doesn't, only the lines
+/// before the cursor's are looked at for bindings.
+///
+/// # Example
+/// ```
+/// use jabroni::{complete, CompletionKind, Jabroni};
+///
+/// let jabroni = Jabroni::new();
+/// let code = "function area(width, height) { return wi";
+/// let completions = complete(code, code.len(), &jabroni);
+/// assert_eq!(completions[0].label, "width");
+/// assert_eq!(completions[0].kind, CompletionKind::Variable);
+/// assert_eq!(completions[0].span.start, code.len() - 2);
+/// ```
+pub fn complete(code: &str, offset: usize, jabroni: &Jabroni) -> Vec<Completion> {
+    complete_with(code, offset, jabroni.bindings())
+}
+
+/// Like [`complete`], completing with `bindings` rather than an interpreter's, for callers that
+/// keep a copy of them.
+pub fn complete_with(code: &str, offset: usize, bindings: &BindingMap) -> Vec<Completion> {
+    if in_string(code, offset) {
+        return Vec::new();
+    }
+    let before = &code[..offset];
+    let start = before
+        .char_indices()
+        .rev()
+        .find(|(_, c)| !(c.is_ascii_alphanumeric() || *c == '_' || *c == '.'))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let word = &before[start..];
+    // Numbers, like `1.5`, aren't completed
+    if word.starts_with(|c: char| c.is_ascii_digit()) {
+        return Vec::new();
+    }
+    let (path, prefix) = word.rsplit_once('.').unwrap_or(("", word));
+    let span = Span {
+        start: offset - prefix.len(),
+        end: offset,
+    };
+
+    let mut completions = Vec::new();
+    let mut add = |label: &str, kind, detail| {
+        // Earlier completions shadow later ones with the same label
+        if label.starts_with(prefix) && !completions.iter().any(|c: &Completion| c.label == label) {
+            completions.push(Completion {
+                label: label.into(),
+                kind,
+                detail,
+                span,
+            });
+        }
+    };
+    if path.is_empty() {
+        if let Some(script) = parse_before(code, offset) {
+            for (name, kind, detail) in declarations(&script, code, offset) {
+                add(&name, kind, Some(detail));
+            }
+        }
+        for (ident, binding) in bindings.iter() {
+            let (kind, detail) = describe(binding.value(), CompletionKind::Variable);
+            add(ident, kind, Some(detail));
+        }
+        for keyword in KEYWORDS {
+            add(keyword, CompletionKind::Keyword, None);
+        }
+    } else if let Some(object) = object_at(bindings, path) {
+        for (ident, binding) in object.borrow().iter() {
+            let (kind, detail) = describe(binding.value(), CompletionKind::Property);
+            add(ident, kind, Some(detail));
+        }
+    }
+    completions.sort_by(|a, b| a.label.cmp(&b.label));
+    completions
+}
+
+/// Whether `offset` is inside a string, where there's nothing to complete.
+fn in_string(code: &str, offset: usize) -> bool {
+    tokenize(code).iter().any(|token| {
+        let text = &code[token.span.start..token.span.end];
+        // Strings start with a one-byte quote, and may be missing the one that ends them
+        let closed = || text.len() > 1 && text.ends_with(&text[..1]);
+        token.kind == TokenKind::String
+            && token.span.start < offset
+            && (offset < token.span.end || !closed())
+    })
+}
+
+/// The kind of completion for a value, and its detail: how it's called if it's a function, or its
+/// type otherwise.
+fn describe(value: &Value, otherwise: CompletionKind) -> (CompletionKind, String) {
+    match value {
+        Value::Subroutine(subroutine) => (
+            CompletionKind::Function,
+            subroutine
+                .signature()
+                .map_or_else(|| subroutine.to_string(), String::from),
+        ),
+        value => (otherwise, value.type_name().into()),
+    }
+}
+
+/// Look up the object at `path`, a chain of identifiers such as `a.b`, without running anything.
+fn object_at(bindings: &BindingMap, path: &str) -> Option<Object> {
+    let mut idents = path.split('.');
+    let mut object = bindings
+        .get(idents.next()?)
+        .ok()?
+        .value()
+        .as_object()?
+        .clone();
+    for ident in idents {
+        let member = object
+            .borrow()
+            .get(ident)
+            .ok()?
+            .value()
+            .as_object()?
+            .clone();
+        object = member;
+    }
+    Some(object)
+}
+
+/// Parse a script being edited at `offset`. Scripts usually don't parse while they're being
+/// typed, so if it doesn't, the script up to the cursor is parsed instead, finished off with a
+/// placeholder and whatever closing brackets it's missing. If that doesn't parse either, the
+/// line being typed is left out too.
+fn parse_before(code: &str, offset: usize) -> Option<Script> {
+    let line_start = code[..offset].rfind('\n').map_or(0, |i| i + 1);
+    ast::parse(code)
+        .ok()
+        .or_else(|| ast::parse(&finish(&code[..offset])).ok())
+        .or_else(|| ast::parse(&finish(&code[..line_start])).ok())
+}
+
+/// Finish off the start of a script so it might parse.
+fn finish(code: &str) -> String {
+    let mut open = Vec::new();
+    for token in tokenize(code) {
+        match (&code[token.span.start..token.span.end], open.last()) {
+            ("(" | "{", _) => open.push(&code[token.span.start..token.span.end]),
+            (")", Some(&"(")) | ("}", Some(&"{")) => {
+                open.pop();
+            }
+            _ => {}
+        }
+    }
+    let mut finished = format!("{}_", code);
+    for bracket in open.iter().rev() {
+        match *bracket {
+            "(" => finished.push(')'),
+            // End the statement the placeholder is in before closing its block
+            _ if finished.ends_with('}') => finished.push('}'),
+            _ => finished.push_str(";}"),
+        }
+    }
+    if !finished.ends_with('}') {
+        finished.push(';');
+    }
+    finished
+}
+
+/// A binding declared in a script, with the part of the script it can be used in.
+struct Declaration {
+    name: String,
+    /// The identifier that declares it.
+    span: Span,
+    scope: Span,
+    kind: CompletionKind,
+    /// Whether it can be used before it's declared, as functions can.
+    hoisted: bool,
+    detail: String,
+}
+
+/// The bindings declared in `script` that can be used at `offset`, innermost first.
+fn declarations(
+    script: &Script,
+    code: &str,
+    offset: usize,
+) -> Vec<(String, CompletionKind, String)> {
+    let mut found = Vec::new();
+    let whole = Span {
+        start: 0,
+        end: code.len(),
+    };
+    collect(&script.statements, whole, &mut found);
+    found.retain(|declaration| {
+        declaration.scope.start <= offset
+            && offset <= declaration.scope.end
+            // Not the name being typed in its own declaration
+            && declaration.span.end != offset
+            && (declaration.hoisted || declaration.span.start < offset)
+    });
+    // The sort is stable, so names declared twice in a scope keep their first declaration
+    found.sort_by_key(|declaration| declaration.scope.end - declaration.scope.start);
+    found
+        .into_iter()
+        .map(|declaration| (declaration.name, declaration.kind, declaration.detail))
+        .collect()
+}
+
+/// Collect the bindings declared in `statements`, which are in `scope`.
+fn collect(statements: &[Statement], scope: Span, found: &mut Vec<Declaration>) {
+    for statement in statements {
+        let mut declare = |name: &ast::Ident, scope, kind, hoisted, detail| {
+            found.push(Declaration {
+                name: name.name.clone(),
+                span: name.span,
+                scope,
+                kind,
+                hoisted,
+                detail,
+            });
+        };
+        match &statement.kind {
+            StatementKind::Function { name, params, body } => {
+                let names: Vec<_> = params.iter().map(|param| param.name.as_str()).collect();
+                let signature = format!("{}({})", name.name, names.join(", "));
+                declare(name, scope, CompletionKind::Function, true, signature);
+                for param in params {
+                    let detail = "parameter".into();
+                    declare(
+                        param,
+                        statement.span,
+                        CompletionKind::Variable,
+                        true,
+                        detail,
+                    );
+                }
+                collect(body, statement.span, found);
+            }
+            StatementKind::Declaration { kind, name, .. } => {
+                let keyword = match kind {
+                    DeclarationKind::Const => "const",
+                    DeclarationKind::Let => "let",
+                };
+                declare(name, scope, CompletionKind::Variable, false, keyword.into());
+            }
+            StatementKind::Try {
+                body,
+                binding,
+                handler,
+            } => {
+                let detail = "exception".into();
+                declare(
+                    binding,
+                    statement.span,
+                    CompletionKind::Variable,
+                    true,
+                    detail,
+                );
+                collect(body, statement.span, found);
+                collect(handler, statement.span, found);
+            }
+            StatementKind::Import { names, specifier } => {
+                for name in names {
+                    let detail = format!("import from {}", crate::fmt::quote(specifier));
+                    declare(name, scope, CompletionKind::Variable, false, detail);
+                }
+            }
+            StatementKind::Block(body) => collect(body, statement.span, found),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Subroutine;
+
+    fn labels(code: &str, jabroni: &Jabroni) -> Vec<String> {
+        // The cursor is at the `|`
+        let offset = code.find('|').unwrap();
+        let code = code.replace('|', "");
+        complete(&code, offset, jabroni)
+            .into_iter()
+            .map(|completion| completion.label)
+            .collect()
+    }
+
+    #[test]
+    fn completions() {
+        let mut jabroni = Jabroni::new();
+        jabroni.enable_bytes().unwrap();
+        let log = Subroutine::new_variadic(Box::new(|_, _| Ok(Value::Null)));
+        let mut console = BindingMap::default();
+        console.set("log".into(), crate::Binding::constant(log.into()));
+        jabroni
+            .define_constant("console", Value::Object(console.into()))
+            .unwrap();
+
+        // Globals, keywords and bindings declared before the cursor
+        assert_eq!(
+            labels("const count = 1;\nco|\nconst later = 2;", &jabroni),
+            ["console", "const", "count"]
+        );
+        // Functions can be used before they're declared, and parameters only inside them
+        assert_eq!(
+            labels("f|;\nfunction first(a) { return a; }", &jabroni),
+            ["false", "first", "from", "function"]
+        );
+        assert_eq!(
+            labels("function f(alpha) { return al| }", &jabroni),
+            ["alpha"]
+        );
+        assert!(labels("function f(alpha) {}\nal|", &jabroni).is_empty());
+        // Members of objects the interpreter has
+        assert_eq!(labels("console.|", &jabroni), ["log"]);
+        assert_eq!(labels("bytes.to|", &jabroni), ["toBase64", "toHex"]);
+        assert!(labels("missing.|", &jabroni).is_empty());
+        // Nothing in strings or numbers
+        assert!(labels("'co|'", &jabroni).is_empty());
+        assert!(labels("'co|", &jabroni).is_empty());
+        assert!(labels("1.|", &jabroni).is_empty());
+
+        let completions = complete("console.lo", 10, &jabroni);
+        assert_eq!(completions[0].kind, CompletionKind::Function);
+        assert_eq!(completions[0].span, Span { start: 8, end: 10 });
+        // Identifiers are ASCII, so there's no word to complete after other characters
+        let completions = complete("'é'.é", "'é'.é".len(), &jabroni);
+        assert!(completions.iter().all(|c| c.span.start == c.span.end));
+    }
+}
@@ -0,0 +1,123 @@
+use crate::errors::Location;
+use std::collections::HashMap;
+
+/// A range of code in a script.
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct Region {
+    /// The name the script was run with, if any, or the specifier of a module.
+    pub source: Option<String>,
+    pub start: Location,
+    /// Where the region ends, exclusive.
+    pub end: Location,
+}
+
+impl Region {
+    fn sort_key(&self) -> (Option<&str>, usize, usize) {
+        (self.source.as_deref(), self.start.line, self.start.column)
+    }
+}
+
+/// How many times the condition of a ternary went each way.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Branch {
+    pub condition: Region,
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Which statements and branches ran while collecting coverage, from
+/// [`Jabroni::coverage`](crate::Jabroni::coverage). Statements and branches in scripts that ran
+/// are included even if they didn't run themselves.
+///
+/// # Example
+/// ```
+/// use jabroni::Jabroni;
+///
+/// let mut interpreter = Jabroni::new();
+/// interpreter.start_coverage();
+/// interpreter
+///     .run_script_named("rules.jab", "function unused() {return 1;}\nconst x = 1 == 1 ? 2 : 3;")
+///     .unwrap();
+/// let coverage = interpreter.coverage().unwrap();
+/// let hits: Vec<_> = coverage
+///     .statements
+///     .iter()
+///     .map(|(region, hits)| (region.start.line, *hits))
+///     .collect();
+/// assert_eq!(hits, [(1, 1), (1, 0), (2, 1)]);
+/// assert_eq!((coverage.branches[0].taken, coverage.branches[0].not_taken), (1, 0));
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Coverage {
+    /// Statements and how many times they ran, in the order they appear. Blocks aren't included,
+    /// since the statements in them are.
+    pub statements: Vec<(Region, u64)>,
+    /// Conditions of ternaries, in the order they appear.
+    pub branches: Vec<Branch>,
+}
+
+impl Coverage {
+    /// Get the fraction of statements that ran at least once, from 0 to 1. Scripts without
+    /// statements are fully covered.
+    pub fn statement_rate(&self) -> f64 {
+        if self.statements.is_empty() {
+            return 1.0;
+        }
+        let covered = self.statements.iter().filter(|(_, hits)| *hits > 0).count();
+        covered as f64 / self.statements.len() as f64
+    }
+}
+
+/// Counts statements and branches as they run while coverage is on.
+#[derive(Default)]
+pub(crate) struct Collector {
+    statements: HashMap<Region, u64>,
+    branches: HashMap<Region, (u64, u64)>,
+}
+
+impl Collector {
+    /// Note that a statement exists, so it's reported even if it never runs.
+    pub(crate) fn add_statement(&mut self, region: Region) {
+        self.statements.entry(region).or_insert(0);
+    }
+
+    /// Note that a ternary exists, so it's reported even if it never runs.
+    pub(crate) fn add_branch(&mut self, condition: Region) {
+        self.branches.entry(condition).or_insert((0, 0));
+    }
+
+    pub(crate) fn hit_statement(&mut self, region: Region) {
+        *self.statements.entry(region).or_insert(0) += 1;
+    }
+
+    pub(crate) fn hit_branch(&mut self, condition: Region, taken: bool) {
+        let counts = self.branches.entry(condition).or_insert((0, 0));
+        match taken {
+            true => counts.0 += 1,
+            false => counts.1 += 1,
+        }
+    }
+
+    pub(crate) fn report(&self) -> Coverage {
+        let mut statements: Vec<_> = self
+            .statements
+            .iter()
+            .map(|(region, hits)| (region.clone(), *hits))
+            .collect();
+        statements.sort_by(|(a, _), (b, _)| a.sort_key().cmp(&b.sort_key()));
+        let mut branches: Vec<_> = self
+            .branches
+            .iter()
+            .map(|(condition, (taken, not_taken))| Branch {
+                condition: condition.clone(),
+                taken: *taken,
+                not_taken: *not_taken,
+            })
+            .collect();
+        branches.sort_by(|a, b| a.condition.sort_key().cmp(&b.condition.sort_key()));
+        Coverage {
+            statements,
+            branches,
+        }
+    }
+}
@@ -0,0 +1,389 @@
+//! Conversion of Jabroni values into Rust types via serde.
+use crate::{
+    ast::{self, StatementKind},
+    binding::{Binding, BindingMap},
+    errors::{ErrorCode, JabroniError, JabroniResult, Source},
+    value::Value,
+    Jabroni,
+};
+use serde::de::{
+    self, value::StrDeserializer, DeserializeOwned, DeserializeSeed, Error as _, IntoDeserializer,
+    MapAccess, Visitor,
+};
+use std::{
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+};
+
+/// Deserialize a Rust value from a Jabroni value.
+///
+/// # Example
+/// ```
+/// use jabroni::{from_value, Value as JabroniValue};
+/// let number: i64 = from_value(&JabroniValue::Number(42)).unwrap();
+/// assert_eq!(number, 42);
+/// ```
+pub fn from_value<T: DeserializeOwned>(value: &Value) -> JabroniResult<T> {
+    deserialize(value).map_err(|e| JabroniError::from_code(ErrorCode::Deserialize, e.to_string()))
+}
+
+fn deserialize<T: DeserializeOwned>(value: &Value) -> Result<T, DeserializeError> {
+    T::deserialize(ValueDeserializer {
+        value,
+        ancestors: Vec::new(),
+    })
+}
+
+/// Run a config script and deserialize it into a Rust value. The script can use the bindings in
+/// `env`. Its config is the value it returns, or if it doesn't return one, an object of the
+/// bindings it declares at its top level, leaving out functions. Errors about a field declared by
+/// the script point to its declaration.
+///
+/// # Example
+/// ```
+/// use jabroni::{eval_config, Binding, BindingMap, Value};
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize, Debug)]
+/// struct Config {
+///     host: String,
+///     port: u16,
+/// }
+///
+/// let mut env = BindingMap::default();
+/// env.set("production".into(), Binding::constant(Value::Boolean(true)));
+/// let script = "
+///     const host = production ? 'example.com' : 'localhost';
+///     const port = 8000 + 80;
+/// ";
+/// let config: Config = eval_config(script, env.clone()).unwrap();
+/// assert_eq!((config.host.as_str(), config.port), ("example.com", 8080));
+///
+/// let error = eval_config::<Config>("const host = 'a';\nconst port = -1;", env).unwrap_err();
+/// assert_eq!(error.location().unwrap().line, 2);
+/// ```
+pub fn eval_config<T: DeserializeOwned>(source: &str, env: BindingMap) -> JabroniResult<T> {
+    let mut jabroni = Jabroni::with_bindings(env);
+    let returned = jabroni.run_script(source)?;
+    let from_bindings = returned == Value::Null;
+    let config = if from_bindings {
+        let mut fields = BindingMap::default();
+        for (ident, binding) in jabroni.bindings().iter() {
+            if jabroni.bindings().has_on_top(ident)
+                && !matches!(binding.value(), Value::Subroutine(_))
+            {
+                fields.set(ident.into(), binding.clone());
+            }
+        }
+        Value::Object(fields.into())
+    } else {
+        returned
+    };
+    deserialize(&config).map_err(|e| {
+        let error = JabroniError::from_code(ErrorCode::Deserialize, e.to_string());
+        let declaration = e
+            .path
+            .first()
+            .filter(|_| from_bindings)
+            .and_then(|field| declaration(source, field));
+        match declaration {
+            Some(span) => error
+                .located(|| span.location(source), span.end - span.start)
+                .in_source(Some(&Arc::new(Source::new(None, source)))),
+            None => error,
+        }
+    })
+}
+
+/// Find where a binding is declared at the top level of a script.
+fn declaration(source: &str, field: &str) -> Option<ast::Span> {
+    ast::parse(source)
+        .ok()?
+        .statements
+        .into_iter()
+        .find_map(|statement| match statement.kind {
+            StatementKind::Declaration { name, .. } if name.name == field => Some(name.span),
+            _ => None,
+        })
+}
+
+/// Error produced while deserializing, along with the path of the offending field.
+#[derive(Debug)]
+struct DeserializeError {
+    path: Vec<String>,
+    message: String,
+}
+
+impl DeserializeError {
+    fn at(mut self, field: &str) -> Self {
+        self.path.insert(0, field.into());
+        self
+    }
+}
+
+impl Display for DeserializeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path.join("."), self.message)
+        }
+    }
+}
+
+impl std::error::Error for DeserializeError {}
+
+impl de::Error for DeserializeError {
+    fn custom<T: Display>(message: T) -> Self {
+        Self {
+            path: Vec::new(),
+            message: message.to_string(),
+        }
+    }
+}
+
+struct ValueDeserializer<'a> {
+    value: &'a Value,
+    /// Objects the value is in, so objects that contain themselves fail instead of recursing
+    /// forever.
+    ancestors: Vec<*const ()>,
+}
+
+impl<'de, 'a> de::Deserializer<'de> for ValueDeserializer<'a> {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Value::Number(value) => visitor.visit_i64(*value),
+            Value::Boolean(value) => visitor.visit_bool(*value),
+            Value::String(value) => visitor.visit_str(value),
+            Value::Bytes(value) => visitor.visit_bytes(value),
+            Value::Null => visitor.visit_unit(),
+            Value::Object(object) if self.ancestors.contains(&object.as_ptr()) => Err(
+                de::Error::custom("cannot deserialize an object that contains itself"),
+            ),
+            Value::Object(object) => visitor.visit_map(ObjectAccess {
+                entries: object
+                    .borrow()
+                    .iter()
+                    .map(|(key, binding)| (key.to_string(), binding.clone()))
+                    .collect(),
+                index: 0,
+                ancestors: [self.ancestors, vec![object.as_ptr()]].concat(),
+            }),
+            Value::Subroutine(_) => Err(de::Error::custom("cannot deserialize a function")),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Value::String(value) => {
+                let deserializer: StrDeserializer<Self::Error> = value.as_ref().into_deserializer();
+                visitor.visit_enum(deserializer)
+            }
+            _ => Err(de::Error::custom("expected a string for enum variant")),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct ObjectAccess {
+    entries: Vec<(String, Binding)>,
+    ancestors: Vec<*const ()>,
+    index: usize,
+}
+
+impl<'de> MapAccess<'de> for ObjectAccess {
+    type Error = DeserializeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.entries.get(self.index) {
+            Some((key, _)) => {
+                let deserializer: StrDeserializer<Self::Error> = key.as_str().into_deserializer();
+                seed.deserialize(deserializer).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let (key, binding) = &mut self.entries[self.index];
+        self.index += 1;
+        binding
+            .refresh()
+            .map_err(|e| DeserializeError::custom(e).at(key))?;
+        seed.deserialize(ValueDeserializer {
+            value: binding.value(),
+            ancestors: self.ancestors.clone(),
+        })
+        .map_err(|e| e.at(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{errors::ErrorKind, BindingMap, Jabroni};
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    enum Mode {
+        Fast,
+        Slow,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Server {
+        port: u16,
+        verbose: bool,
+        mode: Mode,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Config {
+        name: String,
+        server: Server,
+        backup: Option<String>,
+    }
+
+    fn config_state(port: Value) -> Jabroni {
+        let mut server = BindingMap::default();
+        server.set("port".into(), Binding::constant(port));
+        server.set("verbose".into(), Binding::constant(Value::Boolean(true)));
+        server.set(
+            "mode".into(),
+            Binding::constant(Value::String("Slow".into())),
+        );
+        let mut config = BindingMap::default();
+        config.set(
+            "name".into(),
+            Binding::constant(Value::String("jabroni".into())),
+        );
+        config.set(
+            "server".into(),
+            Binding::constant(Value::Object(server.into())),
+        );
+        config.set("backup".into(), Binding::constant(Value::Null));
+
+        let mut state = Jabroni::new();
+        state
+            .define_constant("config", Value::Object(config.into()))
+            .unwrap();
+        state
+    }
+
+    #[test]
+    fn primitives() {
+        assert_eq!(from_value::<i32>(&Value::Number(-4)).unwrap(), -4);
+        assert!(from_value::<bool>(&Value::Boolean(true)).unwrap());
+        assert_eq!(
+            from_value::<String>(&Value::String("hi".into())).unwrap(),
+            "hi"
+        );
+        assert_eq!(from_value::<Option<i32>>(&Value::Null).unwrap(), None);
+        assert_eq!(
+            from_value::<Mode>(&Value::String("Fast".into())).unwrap(),
+            Mode::Fast
+        );
+        assert!(from_value::<u8>(&Value::Number(-4)).is_err());
+        assert!(from_value::<String>(&Value::Number(4)).is_err());
+    }
+
+    #[test]
+    fn eval_typed() {
+        let mut state = config_state(Value::Number(8080));
+        assert_eq!(
+            state.eval_typed::<Config>("config").unwrap(),
+            Config {
+                name: "jabroni".into(),
+                server: Server {
+                    port: 8080,
+                    verbose: true,
+                    mode: Mode::Slow,
+                },
+                backup: None,
+            }
+        );
+        assert_eq!(state.eval_typed::<u16>("4000+4000").unwrap(), 8000);
+    }
+
+    #[test]
+    fn error_has_field_path() {
+        let mut state = config_state(Value::String("8080".into()));
+        let err = state.eval_typed::<Config>("config").unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::Deserialize));
+        assert!(err.to_string().contains("server.port"));
+
+        // Errors from evaluation are passed through
+        assert!(matches!(
+            state.eval_typed::<Config>("nothing").unwrap_err().kind(),
+            ErrorKind::Reference
+        ));
+    }
+
+    #[test]
+    fn config_scripts() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Limits {
+            retries: u8,
+            timeout: Option<u32>,
+        }
+
+        let mut env = BindingMap::default();
+        env.set("base".into(), Binding::constant(Value::Number(5)));
+        // Top-level declarations are the fields. Functions and the environment are left out
+        let script = "function double(x) { return x * 2; }\nconst retries = double(base);";
+        assert_eq!(
+            eval_config::<Limits>(script, env.clone()).unwrap(),
+            Limits {
+                retries: 10,
+                timeout: None
+            }
+        );
+        // Unless the script returns its config
+        let state = config_state(Value::Number(80));
+        let config = state.bindings().get("config").unwrap().clone();
+        let mut env = BindingMap::default();
+        env.set("defaults".into(), config);
+        let config: Config = eval_config("const x = 1;\nreturn defaults;", env.clone()).unwrap();
+        assert_eq!(config.server.port, 80);
+
+        // Errors point to the field's declaration
+        let script = "const retries = 1;\nlet timeout = 'soon';";
+        let err = eval_config::<Limits>(script, env.clone()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Deserialize);
+        assert!(err.to_string().contains("timeout"));
+        assert_eq!(err.location().map(|l| (l.line, l.column)), Some((2, 5)));
+        assert_eq!(err.source_code(), Some(script));
+        // Errors from running the script are passed through
+        assert_eq!(
+            eval_config::<Limits>("const retries = missing;", env)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::Reference
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! Pausing scripts at breakpoints, stepping through them, and inspecting their bindings.
+
+use crate::{
+    compat::SourceMap,
+    errors::{ErrorCode, Frame, JabroniError, JabroniResult, Location, Source},
+    state::{Evaluation, Runtime},
+    utils,
+    value::Value,
+};
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+/// Where a script paused, and what it could see there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pause {
+    /// Where the statement about to run starts.
+    pub location: Location,
+    /// The statement about to run.
+    pub code: String,
+    /// The function being run, or `None` at the top level of the script.
+    pub function: Option<String>,
+    /// The visible bindings in each scope, innermost scope first. Bindings in each scope are
+    /// sorted by name, and shadowed bindings are included.
+    pub scopes: Vec<Vec<(String, Value)>>,
+    /// The functions being run, innermost first. The innermost frame is at the statement about
+    /// to run, and the others are at the call to the next function in.
+    pub stack: Vec<Frame>,
+}
+
+impl Pause {
+    /// Get the value of a binding as the script sees it.
+    pub fn get(&self, ident: &str) -> Option<&Value> {
+        self.scopes
+            .iter()
+            .flatten()
+            .find(|(name, _)| name == ident)
+            .map(|(_, value)| value)
+    }
+}
+
+/// What happened when a [`Debugger`] let its script run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugEvent {
+    /// The script paused at a breakpoint or after a step.
+    Paused(Pause),
+    /// The script yielded a value with a `yield` statement.
+    Yielded(Value),
+    /// The script finished, with the value of its last statement.
+    Finished(Value),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum StepMode {
+    /// Only pause at breakpoints.
+    Continue,
+    /// Pause at the next statement.
+    Into,
+    /// Pause at the next statement that isn't in a function called from the current one.
+    Over(usize),
+    /// Pause at the next statement in a function that called the current one.
+    Out(usize),
+}
+
+/// The state of a debugging session, shared with the interpreter running the script.
+pub(crate) struct Session {
+    /// The script being debugged. Code from other scripts, like modules, doesn't pause.
+    pub(crate) source: Arc<Source>,
+    breakpoints: BTreeSet<usize>,
+    mode: StepMode,
+    /// Calls to functions from the script that are in progress, outermost first, as frames for
+    /// the functions they were made from.
+    pub(crate) calls: Vec<Frame>,
+    pause: Option<Pause>,
+}
+
+impl Session {
+    pub(crate) fn new(source: Arc<Source>) -> Self {
+        Self {
+            source,
+            breakpoints: BTreeSet::new(),
+            mode: StepMode::Continue,
+            calls: Vec::new(),
+            pause: None,
+        }
+    }
+
+    /// Check if the statement at `location` should pause.
+    pub(crate) fn should_pause(&self, location: Location) -> bool {
+        self.breakpoints.contains(&location.line)
+            || match self.mode {
+                StepMode::Continue => false,
+                StepMode::Into => true,
+                StepMode::Over(depth) => self.calls.len() <= depth,
+                StepMode::Out(depth) => self.calls.len() < depth,
+            }
+    }
+
+    pub(crate) fn pause(&mut self, pause: Pause) {
+        self.pause = Some(pause);
+    }
+}
+
+/// Suspends evaluation once, for the debugger to take over.
+#[derive(Default)]
+pub(crate) struct Suspend {
+    suspended: bool,
+}
+
+impl Future for Suspend {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+        if self.suspended {
+            return Poll::Ready(());
+        }
+        self.suspended = true;
+        context.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// A script being debugged, started with [`Jabroni::debug`](crate::Jabroni::debug). The script
+/// doesn't run until it's resumed or stepped.
+///
+/// # Example
+/// ```
+/// use jabroni::{debug::DebugEvent, Jabroni, Value as JabroniValue};
+///
+/// let mut interpreter = Jabroni::new();
+/// let code = "function square(n) {\n    return n * n;\n}\nconst x = square(3);\nconst y = x + 1;";
+/// let mut debugger = interpreter.debug(code);
+/// debugger.set_breakpoint(2);
+/// match debugger.resume().unwrap() {
+///     DebugEvent::Paused(pause) => {
+///         assert_eq!(pause.location.line, 2);
+///         assert_eq!(pause.function.as_deref(), Some("square"));
+///         assert_eq!(pause.get("n"), Some(&JabroniValue::Number(3)));
+///     }
+///     event => panic!("{:?}", event),
+/// }
+/// match debugger.step_over().unwrap() {
+///     DebugEvent::Paused(pause) => {
+///         assert_eq!(pause.code, "const y = x + 1;");
+///         assert_eq!(pause.get("x"), Some(&JabroniValue::Number(9)));
+///     }
+///     event => panic!("{:?}", event),
+/// }
+/// assert_eq!(debugger.resume().unwrap(), DebugEvent::Finished(JabroniValue::Null));
+/// ```
+pub struct Debugger<'a> {
+    evaluation: Option<Evaluation<'a>>,
+    runtime: Rc<RefCell<Runtime>>,
+    /// Where the script came from, if it was translated from JavaScript.
+    map: Option<SourceMap>,
+}
+
+impl<'a> Debugger<'a> {
+    pub(crate) fn new(evaluation: Evaluation<'a>, runtime: Rc<RefCell<Runtime>>) -> Self {
+        Self {
+            evaluation: Some(evaluation),
+            runtime,
+            map: None,
+        }
+    }
+
+    /// Report positions in the JavaScript the script was translated from.
+    pub(crate) fn mapped(mut self, map: SourceMap) -> Self {
+        self.map = Some(map);
+        self
+    }
+
+    /// Point a pause in a translated script at the JavaScript.
+    fn original(&self, mut pause: Pause) -> Pause {
+        if let Some(map) = &self.map {
+            // Functions from the translation don't have a source name
+            let frames = pause
+                .stack
+                .iter_mut()
+                .filter(|frame| frame.source_name.is_none())
+                .filter_map(|frame| frame.location.as_mut());
+            for location in std::iter::once(&mut pause.location).chain(frames) {
+                *location = map.original(*location).unwrap_or(*location);
+            }
+        }
+        pause
+    }
+
+    fn with_session<T>(&self, callback: impl FnOnce(&mut Session) -> T) -> T {
+        // The session lasts as long as the debugger
+        callback(self.runtime.borrow_mut().debug.as_mut().unwrap())
+    }
+
+    /// Pause before running any statement that starts on `line`.
+    pub fn set_breakpoint(&mut self, line: usize) {
+        self.with_session(|session| session.breakpoints.insert(line));
+    }
+
+    /// Stop pausing at `line`.
+    pub fn clear_breakpoint(&mut self, line: usize) {
+        self.with_session(|session| session.breakpoints.remove(&line));
+    }
+
+    /// Get the lines with breakpoints, in order.
+    pub fn breakpoints(&self) -> Vec<usize> {
+        self.with_session(|session| session.breakpoints.iter().copied().collect())
+    }
+
+    /// Get where the script is paused, if it is.
+    pub fn paused(&self) -> Option<Pause> {
+        let pause = self.with_session(|session| session.pause.clone())?;
+        Some(self.original(pause))
+    }
+
+    /// Run until the next breakpoint, or until the script yields or finishes.
+    pub fn resume(&mut self) -> JabroniResult<DebugEvent> {
+        self.run(StepMode::Continue)
+    }
+
+    /// Run until the next statement, including statements in functions it calls.
+    pub fn step_into(&mut self) -> JabroniResult<DebugEvent> {
+        self.run(StepMode::Into)
+    }
+
+    /// Run until the next statement, without pausing in functions called by the current
+    /// statement unless they reach a breakpoint.
+    pub fn step_over(&mut self) -> JabroniResult<DebugEvent> {
+        let depth = self.with_session(|session| session.calls.len());
+        self.run(StepMode::Over(depth))
+    }
+
+    /// Run until the current function returns to its caller.
+    pub fn step_out(&mut self) -> JabroniResult<DebugEvent> {
+        let depth = self.with_session(|session| session.calls.len());
+        self.run(StepMode::Out(depth))
+    }
+
+    /// Whether the script has finished.
+    pub fn is_complete(&self) -> bool {
+        self.evaluation.is_none()
+    }
+
+    fn run(&mut self, mode: StepMode) -> JabroniResult<DebugEvent> {
+        self.with_session(|session| {
+            session.mode = mode;
+            session.pause = None;
+        });
+        self.runtime.borrow_mut().yielded = None;
+        let evaluation = self.evaluation.as_mut().ok_or_else(|| {
+            JabroniError::from_code(
+                ErrorCode::ScriptFinished,
+                "Cannot resume a script that has finished".into(),
+            )
+        })?;
+        match utils::poll(evaluation.as_mut()) {
+            Poll::Ready(result) => {
+                self.evaluation = None;
+                match &self.map {
+                    Some(map) => result.map_err(|e| e.mapped(map)),
+                    None => result,
+                }
+                .map(DebugEvent::Finished)
+            }
+            Poll::Pending => {
+                let pause = self.with_session(|session| session.pause.clone());
+                let yielded = self.runtime.borrow_mut().yielded.take();
+                match (pause, yielded) {
+                    (Some(pause), _) => Ok(DebugEvent::Paused(self.original(pause))),
+                    (None, Some(value)) => Ok(DebugEvent::Yielded(value)),
+                    (None, None) => Err(JabroniError::from_code(
+                        ErrorCode::CannotSuspend,
+                        "Cannot wait for an asynchronous function while debugging".into(),
+                    )),
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Debugger<'_> {
+    fn drop(&mut self) {
+        self.runtime.borrow_mut().debug = None;
+    }
+}
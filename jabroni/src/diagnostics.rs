@@ -0,0 +1,43 @@
+use crate::errors::{ErrorCode, Location};
+use std::fmt::{Display, Formatter};
+
+/// How serious a [`Diagnostic`] is.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Severity {
+    /// The script can't run.
+    Error,
+    /// The script can run, but probably doesn't do what was intended.
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in a script, such as by [`Jabroni::check`](crate::Jabroni::check).
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// Identifies the kind of problem, such as the name of the lint rule that found it.
+    pub code: Option<String>,
+    /// Where the problem starts.
+    pub location: Location,
+    /// For errors, what the script would fail with if it ran.
+    pub error_code: Option<ErrorCode>,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}: {}", self.location, self.severity)?;
+        if let Some(code) = &self.code {
+            write!(f, "[{code}]")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
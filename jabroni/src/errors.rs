@@ -1,26 +1,613 @@
 //! Errors for use within this crate.
 
-/// The error type used ubiquitously within this crate.
-#[derive(thiserror::Error, Debug)]
-pub enum JabroniError {
+use crate::{channels::Message, compat::SourceMap, value::Value};
+use std::{
+    convert::{Infallible, TryFrom},
+    fmt::{Display, Formatter},
+    sync::Arc,
+};
+
+/// The kinds of error that can occur.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ErrorKind {
     /// Problem parsing.
-    #[error("ParseError: {0}")]
-    Parse(String),
+    Parse,
     /// Type mismatch.
-    #[error("TypeError: {0}")]
-    Type(String),
+    Type,
     /// Binding doesn't exist
-    #[error("ReferenceError: {0}")]
-    Reference(String),
+    Reference,
     /// Bad arguments.
-    #[error("InvalidArgumentsError: {0}")]
-    InvalidArguments(String),
+    InvalidArguments,
     /// Defining a variable or constant twice.
-    #[error("DoubleDefinitionError: {0}")]
-    DoubleDefinition(String),
+    DoubleDefinition,
+    /// Value couldn't be converted to the requested Rust type.
+    Deserialize,
+    /// Script tried to use a capability it wasn't granted.
+    Permission,
+    /// An asynchronous function couldn't complete while running synchronously.
+    Async,
+    /// Syntax the interpreter doesn't support yet.
+    Unsupported,
+    /// Something went past a limit, such as how deeply code can nest or how big numbers can be.
+    Limit,
     /// Exception thrown in code
-    #[error("Uncaught exception: {0}")]
-    Exception(String),
+    Exception,
+}
+
+impl ErrorKind {
+    /// The name scripts see for the error, as the `name` of a caught error.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::Parse => "ParseError",
+            ErrorKind::Type => "TypeError",
+            ErrorKind::Reference => "ReferenceError",
+            ErrorKind::InvalidArguments => "InvalidArgumentsError",
+            ErrorKind::DoubleDefinition => "DoubleDefinitionError",
+            ErrorKind::Deserialize => "DeserializeError",
+            ErrorKind::Permission => "PermissionError",
+            ErrorKind::Async => "AsyncError",
+            ErrorKind::Unsupported => "UnsupportedError",
+            ErrorKind::Limit => "LimitError",
+            ErrorKind::Exception => "Exception",
+        }
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            ErrorKind::Exception => write!(f, "Uncaught exception"),
+            _ => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+/// Identifies a specific failure, so hosts can handle it without matching on messages. Displayed
+/// as its stable code, such as `E0001`.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum ErrorCode {
+    /// A binding was defined twice in the same scope.
+    DoubleDefinition = 1,
+    /// A binding or property doesn't exist.
+    UndefinedReference = 2,
+    /// A lazy binding couldn't be initialized.
+    UninitializedBinding = 3,
+    /// A constant was assigned to.
+    ConstantAssignment = 101,
+    /// A binding was assigned a value of a different type.
+    AssignmentTypeMismatch = 102,
+    /// A read-only property was assigned to.
+    ReadOnlyProperty = 103,
+    /// A member was accessed on something that isn't an object.
+    NotAnObject = 104,
+    /// Something that isn't a function was called.
+    NotAFunction = 105,
+    /// An operator was applied to a value of the wrong type.
+    OperandTypeMismatch = 106,
+    /// Null was compared with `==` or `!=`.
+    NullComparison = 107,
+    /// Values of a type that can't be compared were compared.
+    IncomparableValues = 108,
+    /// A condition wasn't a boolean.
+    NonBooleanCondition = 109,
+    /// A value that can't leave the interpreter, such as a function, was sent over a channel.
+    UnsendableValue = 110,
+    /// A value wasn't the type the host asked for.
+    UnexpectedType = 111,
+    /// An object or function was used as a key.
+    UnhashableValue = 112,
+    /// A script declared or assigned to a global while globals were frozen.
+    FrozenGlobal = 113,
+    /// A function was called with the wrong number of arguments.
+    ArgumentCount = 201,
+    /// A function was called with an argument it can't use.
+    InvalidArgument = 202,
+    /// The code isn't valid syntax.
+    Syntax = 301,
+    /// A literal couldn't be converted to a value.
+    InvalidLiteral = 302,
+    /// The code uses syntax the interpreter doesn't support yet.
+    UnsupportedSyntax = 303,
+    /// The code uses `==` or `!=` where only `===` and `!==` are allowed.
+    LooseEquality = 304,
+    /// A compiled script is corrupt, or was compiled with another version of the format.
+    InvalidCompiledScript = 305,
+    /// The code uses an identifier or construct the interpreter's sandbox denies.
+    Sandboxed = 306,
+    /// A module couldn't be found or loaded.
+    ModuleNotFound = 401,
+    /// A module imports itself, directly or indirectly.
+    CircularImport = 402,
+    /// An imported binding isn't defined by the module.
+    MissingExport = 403,
+    /// A script threw an exception that wasn't caught.
+    UncaughtException = 501,
+    /// A message was sent over a channel with no receiver.
+    ChannelClosed = 502,
+    /// A script opened a channel the host didn't add.
+    UnknownChannel = 503,
+    /// A script used a capability it wasn't granted.
+    PermissionDenied = 504,
+    /// A script waited or yielded where it couldn't be suspended.
+    CannotSuspend = 505,
+    /// A resumable script was resumed after it finished.
+    ScriptFinished = 506,
+    /// A value couldn't be converted to the requested Rust type.
+    Deserialize = 507,
+    /// A nondeterministic function was defined in a deterministic interpreter.
+    Nondeterministic = 508,
+    /// A function that isn't pure was called by an expression that must not have side effects.
+    ImpureCall = 509,
+    /// An interpreter's bindings couldn't be saved or restored, such as a function defined by
+    /// the host without a name.
+    Snapshot = 510,
+    /// Code nests brackets, ternaries or assignments too deeply to parse.
+    NestingTooDeep = 601,
+    /// Evaluation nested deeper than the interpreter's limit, such as by runaway recursion.
+    RecursionTooDeep = 602,
+    /// Arithmetic produced a number too big or small to represent.
+    NumberOverflow = 603,
+}
+
+impl ErrorCode {
+    /// The kind of error this code belongs to.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ErrorCode::DoubleDefinition => ErrorKind::DoubleDefinition,
+            ErrorCode::UndefinedReference
+            | ErrorCode::UninitializedBinding
+            | ErrorCode::ModuleNotFound
+            | ErrorCode::CircularImport
+            | ErrorCode::MissingExport
+            | ErrorCode::UnknownChannel => ErrorKind::Reference,
+            ErrorCode::ConstantAssignment
+            | ErrorCode::AssignmentTypeMismatch
+            | ErrorCode::ReadOnlyProperty
+            | ErrorCode::NotAnObject
+            | ErrorCode::NotAFunction
+            | ErrorCode::OperandTypeMismatch
+            | ErrorCode::NullComparison
+            | ErrorCode::IncomparableValues
+            | ErrorCode::NonBooleanCondition
+            | ErrorCode::UnsendableValue
+            | ErrorCode::UnexpectedType
+            | ErrorCode::UnhashableValue
+            | ErrorCode::FrozenGlobal => ErrorKind::Type,
+            ErrorCode::ArgumentCount | ErrorCode::InvalidArgument => ErrorKind::InvalidArguments,
+            ErrorCode::Syntax | ErrorCode::InvalidLiteral | ErrorCode::InvalidCompiledScript => {
+                ErrorKind::Parse
+            }
+            ErrorCode::UnsupportedSyntax
+            | ErrorCode::LooseEquality
+            | ErrorCode::Sandboxed
+            | ErrorCode::Snapshot => ErrorKind::Unsupported,
+            ErrorCode::UncaughtException | ErrorCode::ChannelClosed => ErrorKind::Exception,
+            ErrorCode::PermissionDenied | ErrorCode::Nondeterministic | ErrorCode::ImpureCall => {
+                ErrorKind::Permission
+            }
+            ErrorCode::CannotSuspend | ErrorCode::ScriptFinished => ErrorKind::Async,
+            ErrorCode::Deserialize => ErrorKind::Deserialize,
+            ErrorCode::NestingTooDeep | ErrorCode::RecursionTooDeep | ErrorCode::NumberOverflow => {
+                ErrorKind::Limit
+            }
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "E{:04}", *self as u16)
+    }
+}
+
+/// A position in a script.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub struct Location {
+    /// Line number, starting from 1.
+    pub line: usize,
+    /// Column number in characters, starting from 1.
+    pub column: usize,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// A script that's being run, kept so errors can show where in it they came from.
+#[derive(Debug)]
+pub(crate) struct Source {
+    name: Option<String>,
+    code: String,
+}
+
+impl Source {
+    pub(crate) fn new(name: Option<&str>, code: &str) -> Self {
+        Self {
+            name: name.map(String::from),
+            code: code.into(),
+        }
+    }
+
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub(crate) fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Get the byte offset of a location in the code, if it's in the code.
+    pub(crate) fn offset(&self, location: Location) -> Option<usize> {
+        let line = self.code.split_inclusive('\n').nth(location.line - 1)?;
+        let line_start = line.as_ptr() as usize - self.code.as_ptr() as usize;
+        let column = line
+            .char_indices()
+            .map(|(offset, _)| offset)
+            .chain(std::iter::once(line.len()))
+            .nth(location.column - 1)?;
+        Some(line_start + column)
+    }
+}
+
+/// A function that was running when an error occurred.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Frame {
+    /// Name of the function, or `None` for the top level of a script.
+    pub function: Option<String>,
+    /// Name of the script the function is defined in, if it was run with a name.
+    pub source_name: Option<String>,
+    /// Where the function was when the error occurred: the error itself for the innermost
+    /// frame, or the call to the next function in for the others.
+    pub location: Option<Location>,
+}
+
+impl Frame {
+    pub(crate) fn new(
+        function: Option<&str>,
+        source: Option<&Arc<Source>>,
+        location: Option<Location>,
+    ) -> Self {
+        Self {
+            function: function.map(String::from),
+            source_name: source.and_then(|source| source.name.clone()),
+            location,
+        }
+    }
+}
+
+impl Display for Frame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "at {}", self.function.as_deref().unwrap_or("<script>"))?;
+        match (&self.source_name, self.location) {
+            (Some(source_name), Some(location)) => write!(f, " ({}:{})", source_name, location),
+            (Some(source_name), None) => write!(f, " ({})", source_name),
+            (None, Some(location)) => write!(f, " ({})", location),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// The error type used ubiquitously within this crate.
+///
+/// # Example
+/// ```
+/// use jabroni::{
+///     errors::{ErrorCode, ErrorKind, Location},
+///     Jabroni,
+/// };
+///
+/// let mut interpreter = Jabroni::new();
+/// let error = interpreter
+///     .run_script_named("plugin.jab", "const x = y;")
+///     .unwrap_err();
+/// assert_eq!(error.kind(), ErrorKind::Reference);
+/// assert_eq!(error.code(), Some(ErrorCode::UndefinedReference));
+/// assert_eq!(error.source_name(), Some("plugin.jab"));
+/// assert_eq!(error.location(), Some(Location { line: 1, column: 11 }));
+/// assert_eq!(
+///     error.to_string(),
+///     "plugin.jab: ReferenceError: 'y' does not exist"
+/// );
+/// ```
+#[derive(Debug)]
+pub struct JabroniError {
+    kind: ErrorKind,
+    code: Option<ErrorCode>,
+    message: String,
+    source: Option<Arc<Source>>,
+    location: Option<Location>,
+    /// Length of the code the error came from, in bytes.
+    length: usize,
+    stack: Vec<Frame>,
+    /// The value that was thrown, for exceptions.
+    payload: Option<Box<Message>>,
+}
+
+impl JabroniError {
+    /// Construct a new error.
+    pub fn new(kind: ErrorKind, message: String) -> Self {
+        Self {
+            kind,
+            code: None,
+            message,
+            source: None,
+            location: None,
+            length: 0,
+            stack: Vec::new(),
+            payload: None,
+        }
+    }
+
+    /// Construct a new error for a specific failure.
+    pub fn from_code(code: ErrorCode, message: String) -> Self {
+        Self {
+            code: Some(code),
+            ..Self::new(code.kind(), message)
+        }
+    }
+
+    /// Construct an exception that throws `value`, as a script's `throw` statement does. Host
+    /// functions can return it to throw a value scripts can catch. The message is the value, or
+    /// the `message` property of an object.
+    ///
+    /// A `catch` block sees the properties of a thrown object, and other values as its `value`
+    /// property, along with the usual `name`, `message` and `stack`. Values that contain
+    /// functions can't be kept, since errors can be sent between threads, so only their message
+    /// is.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{
+    ///     errors::JabroniError, Binding, BindingMap, Jabroni, Subroutine, Value as JabroniValue,
+    /// };
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// let fetch = Subroutine::new(
+    ///     0,
+    ///     Box::new(|_, _| {
+    ///         let mut error = BindingMap::default();
+    ///         error.set("message".into(), Binding::constant(JabroniValue::String("Not found".into())));
+    ///         error.set("code".into(), Binding::constant(JabroniValue::Number(404)));
+    ///         Err(JabroniError::throw(JabroniValue::Object(error.into())))
+    ///     }),
+    /// );
+    /// interpreter
+    ///     .define_constant("fetch", JabroniValue::Subroutine(fetch))
+    ///     .unwrap();
+    /// assert_eq!(
+    ///     interpreter
+    ///         .run_script("try { fetch(); } catch (e) { return e.code; }")
+    ///         .unwrap(),
+    ///     JabroniValue::Number(404)
+    /// );
+    ///
+    /// let error = interpreter.run_script("throw 42;").unwrap_err();
+    /// assert_eq!(error.payload(), Some(JabroniValue::Number(42)));
+    /// ```
+    pub fn throw(value: Value) -> Self {
+        let message = match &value {
+            Value::Object(object) => object
+                .get("message")
+                .and_then(|message| message.as_string().map(ToString::to_string))
+                .unwrap_or_else(|| "[object]".into()),
+            value => value.to_string(),
+        };
+        Self {
+            payload: Message::try_from(&value).ok().map(Box::new),
+            ..Self::from_code(ErrorCode::UncaughtException, message)
+        }
+    }
+
+    /// The value that was thrown, if this is an exception from a `throw` statement or
+    /// [`JabroniError::throw`], and the value could be kept.
+    pub fn payload(&self) -> Option<Value> {
+        self.payload.as_deref().cloned().map(Value::from)
+    }
+
+    /// The kind of error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The specific failure, unless the error was constructed without one.
+    pub fn code(&self) -> Option<ErrorCode> {
+        self.code
+    }
+
+    /// The error message, without the kind or where it came from.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The name of the script the error came from, if it was run with a name.
+    pub fn source_name(&self) -> Option<&str> {
+        self.source.as_ref()?.name.as_deref()
+    }
+
+    /// The code of the script the error came from, if it came from a script.
+    pub fn source_code(&self) -> Option<&str> {
+        self.source.as_ref().map(|source| source.code.as_str())
+    }
+
+    /// Where in the script the error came from, if it came from a script.
+    pub fn location(&self) -> Option<Location> {
+        self.location
+    }
+
+    /// The functions that were running when the error occurred, innermost first. Errors caught
+    /// by a script only have the frames between where they were raised and where they were
+    /// caught.
+    pub fn stack(&self) -> &[Frame] {
+        &self.stack
+    }
+
+    /// Set where the error came from, and the length of the code there, unless it's already been
+    /// set by a more specific part of the script.
+    pub(crate) fn located(mut self, locate: impl FnOnce() -> Location, length: usize) -> Self {
+        if self.location.is_none() {
+            self.location = Some(locate());
+            self.length = length;
+        }
+        self
+    }
+
+    /// Attribute the error to a script, unless it's already attributed to one.
+    pub(crate) fn in_source(mut self, source: Option<&Arc<Source>>) -> Self {
+        if self.source.is_none() {
+            self.source = source.cloned();
+        }
+        self
+    }
+
+    /// Attribute an error from `code`, which is embedded in a larger text, to that text instead.
+    /// `start` is where the code starts in the text. Errors from elsewhere, such as functions
+    /// defined by other scripts, are left alone.
+    pub(crate) fn embedded(mut self, code: &str, text: &Arc<Source>, start: Location) -> Self {
+        if self.source_code() != Some(code) {
+            return self;
+        }
+        let shift = |location: Location| match location.line {
+            1 => Location {
+                line: start.line,
+                column: start.column + location.column - 1,
+            },
+            line => Location {
+                line: start.line + line - 1,
+                column: location.column,
+            },
+        };
+        self.location = self.location.map(shift);
+        // Frames outside functions are in the embedded code
+        for frame in &mut self.stack {
+            if frame.function.is_none() && frame.source_name.is_none() {
+                frame.location = frame.location.map(shift);
+            }
+        }
+        self.source = Some(text.clone());
+        self
+    }
+
+    /// Point an error raised in code translated from JavaScript at the JavaScript.
+    pub(crate) fn mapped(mut self, map: &SourceMap) -> Self {
+        if self.source_code() != Some(map.translation()) {
+            return self;
+        }
+        let original = |location: Location| map.original(location).unwrap_or(location);
+        self.location = self.location.map(original);
+        // Functions from the translation don't have a source name either
+        for frame in &mut self.stack {
+            if frame.source_name.is_none() {
+                frame.location = frame.location.map(original);
+            }
+        }
+        self.source = Some(map.original_source().clone());
+        self
+    }
+
+    /// Record the function the error was raised in, unless a call inside it already has.
+    pub(crate) fn raised_in(
+        mut self,
+        function: Option<&str>,
+        source: Option<&Arc<Source>>,
+    ) -> Self {
+        if self.stack.is_empty() {
+            self.stack.push(Frame::new(function, source, self.location));
+        }
+        self
+    }
+
+    /// Record the function that made a call the error came out of.
+    pub(crate) fn called_from(
+        mut self,
+        function: Option<&str>,
+        source: Option<&Arc<Source>>,
+        location: impl Into<Option<Location>>,
+    ) -> Self {
+        self.stack
+            .push(Frame::new(function, source, location.into()));
+        self
+    }
+}
+
+impl Display for JabroniError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        if let Some(source_name) = self.source_name() {
+            write!(f, "{}: ", source_name)?;
+        }
+        write!(f, "{}: {}", self.kind, self.message)
+    }
+}
+
+impl std::error::Error for JabroniError {}
+
+/// Lets conversions that can't fail stand in for ones that can, such as converting a value into
+/// itself.
+impl From<Infallible> for JabroniError {
+    fn from(never: Infallible) -> Self {
+        match never {}
+    }
+}
+
+#[cfg(feature = "miette")]
+impl miette::SourceCode for Source {
+    fn read_span<'a>(
+        &'a self,
+        span: &miette::SourceSpan,
+        context_lines_before: usize,
+        context_lines_after: usize,
+    ) -> Result<Box<dyn miette::SpanContents<'a> + 'a>, miette::MietteError> {
+        let contents = self
+            .code
+            .read_span(span, context_lines_before, context_lines_after)?;
+        Ok(match &self.name {
+            Some(name) => Box::new(miette::MietteSpanContents::new_named(
+                name.clone(),
+                contents.data(),
+                *contents.span(),
+                contents.line(),
+                contents.column(),
+                contents.line_count(),
+            )),
+            None => contents,
+        })
+    }
+}
+
+/// Lets errors be rendered with the code they came from, with
+/// [`miette::Report`](https://docs.rs/miette/latest/miette/struct.Report.html).
+#[cfg(feature = "miette")]
+impl miette::Diagnostic for JabroniError {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        match self.code {
+            Some(code) => Some(Box::new(code)),
+            None => Some(Box::new(self.kind)),
+        }
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        if self.stack.is_empty() {
+            return None;
+        }
+        let stack: Vec<_> = self.stack.iter().map(Frame::to_string).collect();
+        Some(Box::new(stack.join("\n")))
+    }
+
+    fn source_code(&self) -> Option<&dyn miette::SourceCode> {
+        self.source
+            .as_deref()
+            .map(|source| source as &dyn miette::SourceCode)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = miette::LabeledSpan> + '_>> {
+        let offset = self.source.as_ref()?.offset(self.location?)?;
+        Some(Box::new(std::iter::once(miette::LabeledSpan::new(
+            Some(self.message.clone()),
+            offset,
+            self.length,
+        ))))
+    }
 }
 
 /// The result type used ubiquitously within this crate.
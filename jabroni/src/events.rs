@@ -0,0 +1,80 @@
+use crate::{
+    binding::BindingMap,
+    errors::{ErrorCode, JabroniError, JabroniResult},
+    value::{Subroutine, Value},
+};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+/// Event handlers registered by scripts or the host.
+#[derive(Clone, Default)]
+pub struct Events {
+    handlers: Rc<RefCell<HashMap<String, Vec<Subroutine>>>>,
+}
+
+impl Events {
+    pub fn on(&self, event: &str, handler: Subroutine) {
+        self.handlers
+            .borrow_mut()
+            .entry(event.into())
+            .or_default()
+            .push(handler);
+    }
+
+    /// Call every handler registered for `event` with `args`, in the order they were registered.
+    pub fn emit(&self, event: &str, context: &BindingMap, args: &[Value]) -> JabroniResult {
+        // Handlers may register more handlers, so don't hold the borrow while calling them
+        let handlers = self.handlers.borrow().get(event).cloned();
+        for handler in handlers.unwrap_or_default() {
+            handler.call(context.new_context(), &mut args.to_vec())?;
+        }
+        Ok(())
+    }
+
+    /// The `on(event, handler)` builtin.
+    pub fn on_builtin(&self) -> Subroutine {
+        let events = self.clone();
+        Subroutine::new(
+            2,
+            Box::new(move |_, args: &mut [Value]| {
+                let (event, handler) = match args {
+                    [Value::String(event), Value::Subroutine(handler)] => (event, handler),
+                    _ => {
+                        return Err(JabroniError::from_code(
+                            ErrorCode::InvalidArgument,
+                            "Expected an event name and a handler".into(),
+                        ))
+                    }
+                };
+                events.on(event, handler.clone());
+                Ok(Value::Null)
+            }),
+        )
+        .named("on")
+        .documented(
+            "on(event, handler)",
+            "Call handler whenever event is emitted, with the arguments it's emitted with.",
+        )
+    }
+
+    /// The `emit(event, ...args)` builtin.
+    pub fn emit_builtin(&self) -> Subroutine {
+        let events = self.clone();
+        Subroutine::new_variadic(Box::new(move |context, args: &mut [Value]| {
+            match args.split_first() {
+                Some((Value::String(event), args)) => {
+                    events.emit(event, &context, args)?;
+                    Ok(Value::Null)
+                }
+                _ => Err(JabroniError::from_code(
+                    ErrorCode::InvalidArgument,
+                    "Expected an event name".into(),
+                )),
+            }
+        }))
+        .named("emit")
+        .documented(
+            "emit(event, ...args)",
+            "Call the handlers registered for event with args.",
+        )
+    }
+}
@@ -0,0 +1,284 @@
+//! Pretty-printing of scripts.
+
+use crate::{
+    ast::{self, DeclarationKind, Expression, ExpressionKind, Script, Statement, StatementKind},
+    errors::JabroniResult,
+    utils,
+    value::Value,
+};
+
+const INDENT: &str = "    ";
+
+/// Format a script with canonical indentation and spacing, failing if it can't be parsed. Each
+/// statement goes on its own line, and single blank lines between statements are kept, as is a
+/// shebang line.
+///
+/// # Example
+/// ```
+/// let formatted = jabroni::fmt::format("function add(a,b){return a+b;} const x=add( 1,2 );");
+/// assert_eq!(
+///     formatted.unwrap(),
+///     "function add(a, b) {\n    return a + b;\n}\nconst x = add(1, 2);\n"
+/// );
+/// ```
+pub fn format(code: &str) -> JabroniResult<String> {
+    let script = ast::parse(code)?;
+    let shebang = &code[..utils::shebang_len(code)];
+    let mut formatter = Formatter {
+        code: Some(code),
+        output: match shebang.is_empty() {
+            true => String::new(),
+            false => format!("{shebang}\n"),
+        },
+        depth: 0,
+    };
+    formatter.statements(&script.statements);
+    Ok(formatter.output)
+}
+
+/// Turn a syntax tree back into code, such as after changing it with a
+/// [`VisitorMut`](crate::ast::VisitorMut). Strings are written with single quotes, and there are
+/// no blank lines between statements.
+pub fn format_script(script: &Script) -> String {
+    let mut formatter = Formatter {
+        code: None,
+        output: String::new(),
+        depth: 0,
+    };
+    formatter.statements(&script.statements);
+    formatter.output
+}
+
+/// Quote a string so it reads back as the same string.
+///
+/// ```
+/// assert_eq!(jabroni::fmt::quote("it's"), "'it\\'s'");
+/// ```
+pub fn quote(string: &str) -> String {
+    format!("'{}'", string.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+struct Formatter<'a> {
+    /// The code that was parsed, if any. The syntax tree may not match code it didn't come from.
+    code: Option<&'a str>,
+    output: String,
+    depth: usize,
+}
+
+impl Formatter<'_> {
+    fn line(&mut self, text: &str) {
+        self.output.push_str(&INDENT.repeat(self.depth));
+        self.output.push_str(text);
+        self.output.push('\n');
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        for (i, statement) in statements.iter().enumerate() {
+            if let (Some(code), true) = (self.code, i > 0) {
+                let between = &code[statements[i - 1].span.end..statement.span.start];
+                if between.matches('\n').count() > 1 {
+                    self.output.push('\n');
+                }
+            }
+            self.statement(statement);
+        }
+    }
+
+    /// Write `header {`, the statements, and `}` on separate lines, or `header {}` if there are
+    /// no statements.
+    fn block(&mut self, header: &str, statements: &[Statement]) {
+        if statements.is_empty() {
+            self.line(&format!("{header}{{}}"));
+            return;
+        }
+        self.line(&format!("{header}{{"));
+        self.indented(statements);
+        self.line("}");
+    }
+
+    fn indented(&mut self, statements: &[Statement]) {
+        self.depth += 1;
+        self.statements(statements);
+        self.depth -= 1;
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Expression(expression) => {
+                let line = format!("{};", self.expression(expression));
+                self.line(&line);
+            }
+            StatementKind::Block(statements) => self.block("", statements),
+            StatementKind::Function { name, params, body } => {
+                let params: Vec<_> = params.iter().map(|param| param.name.as_str()).collect();
+                let header = format!("function {}({}) ", name.name, params.join(", "));
+                self.block(&header, body);
+            }
+            StatementKind::Return(expression) => {
+                let line = format!("return {};", self.expression(expression));
+                self.line(&line);
+            }
+            StatementKind::Yield(None) => self.line("yield;"),
+            StatementKind::Yield(Some(expression)) => {
+                let line = format!("yield {};", self.expression(expression));
+                self.line(&line);
+            }
+            StatementKind::Declaration { kind, name, value } => {
+                let keyword = match kind {
+                    DeclarationKind::Const => "const",
+                    DeclarationKind::Let => "let",
+                };
+                let line = format!("{} {} = {};", keyword, name.name, self.expression(value));
+                self.line(&line);
+            }
+            StatementKind::Throw(expression) => {
+                let line = format!("throw {};", self.expression(expression));
+                self.line(&line);
+            }
+            StatementKind::Try {
+                body,
+                binding,
+                handler,
+            } => {
+                self.line("try {");
+                self.indented(body);
+                self.line(&format!("}} catch ({}) {{", binding.name));
+                self.indented(handler);
+                self.line("}");
+            }
+            StatementKind::Import { names, specifier } => {
+                let names: Vec<_> = names.iter().map(|name| name.name.as_str()).collect();
+                self.line(&format!(
+                    "import {{ {} }} from {};",
+                    names.join(", "),
+                    quote(specifier)
+                ));
+            }
+        }
+    }
+
+    fn expression(&self, expression: &Expression) -> String {
+        match &expression.kind {
+            // Keep literals as written, so quotes and escapes don't change
+            ExpressionKind::Literal(_) if self.code.is_some() => {
+                self.code.unwrap()[expression.span.start..expression.span.end].into()
+            }
+            ExpressionKind::Literal(Value::String(string)) => quote(string),
+            ExpressionKind::Literal(value) => value.to_string(),
+            ExpressionKind::Ident(name) => name.clone(),
+            ExpressionKind::Group(expression) => format!("({})", self.expression(expression)),
+            ExpressionKind::Member { object, property } => {
+                format!("{}.{}", self.expression(object), property.name)
+            }
+            ExpressionKind::Call { callee, args } => {
+                let args: Vec<_> = args.iter().map(|arg| self.expression(arg)).collect();
+                format!("{}({})", self.expression(callee), args.join(", "))
+            }
+            ExpressionKind::Assignment { target, value } => {
+                format!("{} = {}", self.expression(target), self.expression(value))
+            }
+            ExpressionKind::Ternary {
+                condition,
+                consequent,
+                alternative,
+            } => format!(
+                "{} ? {} : {}",
+                self.expression(condition),
+                self.expression(consequent),
+                self.expression(alternative)
+            ),
+            ExpressionKind::Binary { operator, lhs, rhs } => format!(
+                "{} {} {}",
+                self.expression(lhs),
+                operator.symbol(),
+                self.expression(rhs)
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_code() {
+        let code = "import {a,b} from 'lib';
+function f(x){const y=x*(2+1);
+return y>1?'big':\"small\";}
+
+try{ { f( 1 ); } throw 'oops'; }catch(e){let z = e.message;z=null;}
+function g(){}  yield;";
+        let expected = "import { a, b } from 'lib';
+function f(x) {
+    const y = x * (2 + 1);
+    return y > 1 ? 'big' : \"small\";
+}
+
+try {
+    {
+        f(1);
+    }
+    throw 'oops';
+} catch (e) {
+    let z = e.message;
+    z = null;
+}
+function g() {}
+yield;
+";
+        assert_eq!(format(code).unwrap(), expected);
+        // Formatting is idempotent
+        assert_eq!(format(expected).unwrap(), expected);
+        assert!(format("const x = ;").is_err());
+    }
+
+    #[test]
+    fn format_chains() {
+        assert_eq!(
+            format("get( ).server . port=f(1)( 2 ).x;").unwrap(),
+            "get().server.port = f(1)(2).x;\n"
+        );
+    }
+
+    #[test]
+    fn format_shebang() {
+        assert_eq!(
+            format("#!/usr/bin/env jabroni\nconst x=1;").unwrap(),
+            "#!/usr/bin/env jabroni\nconst x = 1;\n"
+        );
+    }
+
+    #[test]
+    fn rewrite_script() {
+        use ast::{Ident, VisitorMut};
+
+        // Rename `x` wherever it's declared or used
+        struct Rename;
+        impl VisitorMut for Rename {
+            fn visit_expression_mut(&mut self, expression: &mut Expression) {
+                if let ExpressionKind::Ident(name) = &mut expression.kind {
+                    if name == "x" {
+                        *name = "count".into();
+                    }
+                }
+                ast::walk_expression_mut(self, expression);
+            }
+
+            fn visit_ident_mut(&mut self, ident: &mut Ident) {
+                if ident.name == "x" {
+                    ident.name = "count".into();
+                }
+            }
+        }
+
+        let mut script =
+            ast::parse("function f(x) {return x > 1 ? \"it's\" : null;}\n\nconst x = f(2) + x;")
+                .unwrap();
+        Rename.visit_script_mut(&mut script);
+        assert_eq!(
+            format_script(&script),
+            "function f(count) {\n    return count > 1 ? 'it\\'s' : null;\n}\nconst count = f(2) + count;\n"
+        );
+    }
+}
@@ -0,0 +1,574 @@
+//! Compiling functions into native code once they've been called often enough to be worth it.
+//! Only functions that work with nothing but numbers and booleans are compiled: their parameters,
+//! locals, arithmetic, comparisons, ternaries and calls to themselves. Everything else is
+//! interpreted, as are calls to compiled functions whose arguments are of other types than the
+//! ones they were compiled for.
+//!
+//! Compiled functions don't report errors themselves. When one fails, such as by overflowing or
+//! recursing too deeply, the call is interpreted from the start instead, which raises the error
+//! the interpreter would have. This is safe because compiled functions have no side effects.
+
+use crate::{
+    ast::{self, Expression, ExpressionKind, Statement, StatementKind},
+    binding::BindingMap,
+    value::{Operator, Value},
+};
+use cranelift_codegen::{
+    ir::{
+        condcodes::IntCC, types, AbiParam, Block, FuncRef, InstBuilder, MemFlags, UserFuncName,
+        Value as Register,
+    },
+    settings::{self, Configurable},
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+use std::mem;
+
+/// How many times a function is called before it's compiled.
+pub(crate) const HOT_CALLS: u32 = 100;
+
+/// How deeply compiled functions can call themselves before the call is interpreted instead.
+/// Interpreted calls grow the stack as they need to, but native ones can't.
+const MAX_NATIVE_DEPTH: usize = 1024;
+
+/// What the JIT has done, kept by the runtime while it's on.
+#[derive(Default)]
+pub(crate) struct Jit {
+    /// Names of the functions compiled so far, in the order they were compiled.
+    compiled: Vec<String>,
+}
+
+impl Jit {
+    pub(crate) fn compiled(&self) -> &[String] {
+        &self.compiled
+    }
+}
+
+/// The types of values compiled functions work with, both held in 64-bit registers.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Kind {
+    Number,
+    Boolean,
+}
+
+impl Kind {
+    fn of(value: &Value) -> Option<Self> {
+        match value {
+            Value::Number(_) => Some(Self::Number),
+            Value::Boolean(_) => Some(Self::Boolean),
+            _ => None,
+        }
+    }
+}
+
+/// Takes the arguments, how many more calls can nest, and where to flag a failure.
+type Entry = unsafe extern "C" fn(*const i64, i64, *mut i64) -> i64;
+
+/// A function compiled to native code for arguments of particular types.
+struct Native {
+    /// Owns the code `entry` points to.
+    _module: JITModule,
+    entry: Entry,
+    name: String,
+    params: Vec<Kind>,
+    returns: Kind,
+    /// Whether it calls itself, by a name that has to still refer to it.
+    recursive: bool,
+}
+
+enum State {
+    /// Counting calls until the function is hot.
+    Cold,
+    Native(Box<Native>),
+    /// The function can't be compiled, so it's always interpreted.
+    Interpreted,
+}
+
+/// A function defined by a script, which is compiled once it's hot.
+pub(crate) struct HotFunction {
+    /// The `function` statement that defined it.
+    definition: String,
+    calls: u32,
+    state: State,
+}
+
+impl HotFunction {
+    pub(crate) fn new(definition: &str) -> Self {
+        Self {
+            definition: definition.into(),
+            calls: 0,
+            state: State::Cold,
+        }
+    }
+
+    /// Run the function as native code, compiling it for the types of `args` if it just became
+    /// hot. Returns `None` if it has to be interpreted instead. `depth` is how many more calls can
+    /// nest inside this one.
+    pub(crate) fn call(
+        &mut self,
+        jit: &mut Jit,
+        context: &BindingMap,
+        args: &[Value],
+        depth: usize,
+    ) -> Option<Value> {
+        if let State::Cold = self.state {
+            self.calls += 1;
+            if self.calls < HOT_CALLS {
+                return None;
+            }
+            self.state = match compile(&self.definition, args) {
+                Some(native) => {
+                    jit.compiled.push(native.name.clone());
+                    State::Native(Box::new(native))
+                }
+                None => State::Interpreted,
+            };
+        }
+        match &self.state {
+            State::Native(native) => native.call(&self.definition, context, args, depth),
+            _ => None,
+        }
+    }
+}
+
+impl Native {
+    fn call(
+        &self,
+        definition: &str,
+        context: &BindingMap,
+        args: &[Value],
+        depth: usize,
+    ) -> Option<Value> {
+        if args.len() != self.params.len() {
+            return None;
+        }
+        let args = self
+            .params
+            .iter()
+            .zip(args)
+            .map(|(kind, arg)| match (kind, arg) {
+                (Kind::Number, Value::Number(number)) => Some(*number),
+                (Kind::Boolean, Value::Boolean(boolean)) => Some(*boolean as i64),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        // Functions are looked up where they're called, so the name could refer to another one
+        if self.recursive {
+            let binding = context.get(&self.name).ok()?;
+            let callee = binding.value().as_subroutine()?;
+            if callee.definition() != Some(definition) {
+                return None;
+            }
+        }
+
+        let mut failed = 0;
+        let depth = depth.min(MAX_NATIVE_DEPTH) as i64;
+        // SAFETY: `args` holds as many numbers as the code reads, and `failed` outlives the call
+        let result = unsafe { (self.entry)(args.as_ptr(), depth, &mut failed) };
+        if failed != 0 {
+            return None;
+        }
+        Some(match self.returns {
+            Kind::Number => Value::Number(result),
+            Kind::Boolean => Value::Boolean(result != 0),
+        })
+    }
+}
+
+/// Compile a function for arguments of the types of `args`, if it can be.
+fn compile(definition: &str, args: &[Value]) -> Option<Native> {
+    let script = ast::parse(definition).ok()?;
+    let (name, params, body) = match &script.statements.first()?.kind {
+        StatementKind::Function { name, params, body } => (name, params, body),
+        _ => return None,
+    };
+    if params.len() != args.len() {
+        return None;
+    }
+    let kinds = args.iter().map(Kind::of).collect::<Option<Vec<_>>>()?;
+    // What calls to itself return isn't known until the body is compiled, so guess
+    let params: Vec<_> = params.iter().map(|param| param.name.as_str()).collect();
+    [Kind::Number, Kind::Boolean]
+        .into_iter()
+        .find_map(|returns| compile_returning(&name.name, &params, &kinds, body, returns))
+}
+
+/// Compile a function, assuming calls to itself return `returns`.
+fn compile_returning<'a>(
+    name: &'a str,
+    params: &[&'a str],
+    kinds: &[Kind],
+    body: &'a [Statement],
+    returns: Kind,
+) -> Option<Native> {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").ok()?;
+    let isa = cranelift_native::builder()
+        .ok()?
+        .finish(settings::Flags::new(flags))
+        .ok()?;
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+    let pointer = module.target_config().pointer_type();
+    let mut builder_context = FunctionBuilderContext::new();
+
+    // The body takes its arguments in registers, so it can call itself cheaply
+    let mut signature = module.make_signature();
+    for _ in params {
+        signature.params.push(AbiParam::new(types::I64));
+    }
+    signature.params.push(AbiParam::new(types::I64));
+    signature.params.push(AbiParam::new(pointer));
+    signature.returns.push(AbiParam::new(types::I64));
+    let body_id = module
+        .declare_function("body", Linkage::Local, &signature)
+        .ok()?;
+    let mut context = module.make_context();
+    context.func.signature = signature;
+    context.func.name = UserFuncName::user(0, body_id.as_u32());
+    let (returns, recursive) = {
+        let mut builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+        let this = module.declare_func_in_func(body_id, builder.func);
+        let start = builder.create_block();
+        builder.append_block_params_for_function_params(start);
+        builder.switch_to_block(start);
+        let registers = builder.block_params(start).to_vec();
+        let fail = builder.create_block();
+        let mut compiler = Compiler {
+            builder,
+            function: name,
+            params: kinds.to_vec(),
+            returns,
+            locals: params
+                .iter()
+                .zip(kinds)
+                .zip(&registers)
+                .map(|((param, kind), register)| (*param, *register, *kind))
+                .collect(),
+            this,
+            depth: registers[params.len()],
+            failed: registers[params.len() + 1],
+            fail,
+            recursive: false,
+        };
+        let (result, kind) = compiler.statements(body)?;
+        let recursive = compiler.recursive;
+        if recursive && kind != returns {
+            return None;
+        }
+        let (mut builder, failed) = (compiler.builder, compiler.failed);
+        builder.ins().return_(&[result]);
+
+        builder.switch_to_block(fail);
+        let flag = builder.ins().iconst(types::I64, 1);
+        builder.ins().store(MemFlags::trusted(), flag, failed, 0);
+        let zero = builder.ins().iconst(types::I64, 0);
+        builder.ins().return_(&[zero]);
+        builder.seal_all_blocks();
+        builder.finalize();
+        (kind, recursive)
+    };
+    module.define_function(body_id, &mut context).ok()?;
+    module.clear_context(&mut context);
+
+    // The entry point takes its arguments from memory, so it can be called with any number
+    let mut signature = module.make_signature();
+    signature.params.push(AbiParam::new(pointer));
+    signature.params.push(AbiParam::new(types::I64));
+    signature.params.push(AbiParam::new(pointer));
+    signature.returns.push(AbiParam::new(types::I64));
+    let entry_id = module
+        .declare_function("entry", Linkage::Local, &signature)
+        .ok()?;
+    context.func.signature = signature;
+    context.func.name = UserFuncName::user(0, entry_id.as_u32());
+    {
+        let mut builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+        let body = module.declare_func_in_func(body_id, builder.func);
+        let start = builder.create_block();
+        builder.append_block_params_for_function_params(start);
+        builder.switch_to_block(start);
+        let registers = builder.block_params(start).to_vec();
+        let mut args: Vec<_> = (0..params.len())
+            .map(|i| {
+                let offset = (i * mem::size_of::<i64>()) as i32;
+                builder
+                    .ins()
+                    .load(types::I64, MemFlags::trusted(), registers[0], offset)
+            })
+            .collect();
+        args.extend([registers[1], registers[2]]);
+        let call = builder.ins().call(body, &args);
+        let result = builder.inst_results(call)[0];
+        builder.ins().return_(&[result]);
+        builder.seal_all_blocks();
+        builder.finalize();
+    }
+    module.define_function(entry_id, &mut context).ok()?;
+    module.clear_context(&mut context);
+    module.finalize_definitions().ok()?;
+
+    let code = module.get_finalized_function(entry_id);
+    // SAFETY: the code was just compiled with the signature of `Entry`, and the module that owns
+    // it is kept alongside it
+    let entry = unsafe { mem::transmute::<*const u8, Entry>(code) };
+    Some(Native {
+        _module: module,
+        entry,
+        name: name.into(),
+        params: kinds.to_vec(),
+        returns,
+        recursive,
+    })
+}
+
+/// Translates the body of a function into Cranelift IR. Methods return `None` when they find
+/// something that can't be compiled.
+struct Compiler<'a> {
+    builder: FunctionBuilder<'a>,
+    /// The name of the function, which it can call itself by.
+    function: &'a str,
+    params: Vec<Kind>,
+    /// What calls to the function itself are assumed to return.
+    returns: Kind,
+    /// Parameters and locals declared so far.
+    locals: Vec<(&'a str, Register, Kind)>,
+    this: FuncRef,
+    /// How many more calls can nest.
+    depth: Register,
+    /// Where to flag a failure.
+    failed: Register,
+    /// Flags a failure and returns.
+    fail: Block,
+    recursive: bool,
+}
+
+impl<'a> Compiler<'a> {
+    /// Compile the statements of the body, returning the value of the last one, which is what
+    /// the function returns.
+    fn statements(&mut self, statements: &'a [Statement]) -> Option<(Register, Kind)> {
+        let mut value = None;
+        for statement in statements {
+            value = match &statement.kind {
+                StatementKind::Expression(expression) | StatementKind::Return(expression) => {
+                    Some(self.expression(expression)?)
+                }
+                StatementKind::Declaration { name, value, .. } => {
+                    if name.name == self.function || self.local(&name.name).is_some() {
+                        return None;
+                    }
+                    let (register, kind) = self.expression(value)?;
+                    self.locals.push((&name.name, register, kind));
+                    // Declarations evaluate to null
+                    None
+                }
+                _ => return None,
+            };
+        }
+        value
+    }
+
+    fn local(&self, ident: &str) -> Option<(Register, Kind)> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|(name, ..)| *name == ident)
+            .map(|(_, register, kind)| (*register, *kind))
+    }
+
+    fn expression(&mut self, expression: &'a Expression) -> Option<(Register, Kind)> {
+        match &expression.kind {
+            ExpressionKind::Literal(Value::Number(number)) => {
+                Some((self.builder.ins().iconst(types::I64, *number), Kind::Number))
+            }
+            ExpressionKind::Literal(Value::Boolean(boolean)) => Some((
+                self.builder.ins().iconst(types::I64, *boolean as i64),
+                Kind::Boolean,
+            )),
+            ExpressionKind::Ident(ident) => self.local(ident),
+            ExpressionKind::Group(expression) => self.expression(expression),
+            ExpressionKind::Binary { operator, lhs, rhs } => {
+                let lhs = self.expression(lhs)?;
+                let rhs = self.expression(rhs)?;
+                self.binary(*operator, lhs, rhs)
+            }
+            ExpressionKind::Ternary {
+                condition,
+                consequent,
+                alternative,
+            } => {
+                let (condition, kind) = self.expression(condition)?;
+                if kind != Kind::Boolean {
+                    return None;
+                }
+                let consequent_block = self.builder.create_block();
+                let alternative_block = self.builder.create_block();
+                let merge = self.builder.create_block();
+                self.builder.append_block_param(merge, types::I64);
+                self.builder
+                    .ins()
+                    .brif(condition, consequent_block, &[], alternative_block, &[]);
+                self.builder.switch_to_block(consequent_block);
+                let (consequent, kind) = self.expression(consequent)?;
+                self.builder.ins().jump(merge, &[consequent]);
+                self.builder.switch_to_block(alternative_block);
+                let (alternative, alternative_kind) = self.expression(alternative)?;
+                if alternative_kind != kind {
+                    return None;
+                }
+                self.builder.ins().jump(merge, &[alternative]);
+                self.builder.switch_to_block(merge);
+                Some((self.builder.block_params(merge)[0], kind))
+            }
+            ExpressionKind::Call { callee, args } => {
+                match &callee.kind {
+                    ExpressionKind::Ident(ident)
+                        if ident == self.function && self.local(ident).is_none() => {}
+                    _ => return None,
+                }
+                if args.len() != self.params.len() {
+                    return None;
+                }
+                let mut registers = Vec::new();
+                for (arg, param) in args.iter().zip(self.params.clone()) {
+                    let (register, kind) = self.expression(arg)?;
+                    if kind != param {
+                        return None;
+                    }
+                    registers.push(register);
+                }
+                let exhausted = self.builder.ins().icmp_imm(IntCC::Equal, self.depth, 0);
+                self.continue_unless(exhausted);
+                registers.push(self.builder.ins().iadd_imm(self.depth, -1));
+                registers.push(self.failed);
+                let call = self.builder.ins().call(self.this, &registers);
+                let result = self.builder.inst_results(call)[0];
+                let failed =
+                    self.builder
+                        .ins()
+                        .load(types::I64, MemFlags::trusted(), self.failed, 0);
+                self.continue_unless(failed);
+                self.recursive = true;
+                Some((result, self.returns))
+            }
+            _ => None,
+        }
+    }
+
+    fn binary(
+        &mut self,
+        operator: Operator,
+        (lhs, lhs_kind): (Register, Kind),
+        (rhs, rhs_kind): (Register, Kind),
+    ) -> Option<(Register, Kind)> {
+        use Kind::*;
+        let comparison = match (operator, lhs_kind, rhs_kind) {
+            (Operator::Add | Operator::Subtract | Operator::Multiply, Number, Number) => {
+                let (result, overflowed) = match operator {
+                    Operator::Add => self.builder.ins().sadd_overflow(lhs, rhs),
+                    Operator::Subtract => self.builder.ins().ssub_overflow(lhs, rhs),
+                    _ => self.builder.ins().smul_overflow(lhs, rhs),
+                };
+                self.continue_unless(overflowed);
+                return Some((result, Number));
+            }
+            (Operator::Greater, Number, Number) => IntCC::SignedGreaterThan,
+            (Operator::GreaterOrEqual, Number, Number) => IntCC::SignedGreaterThanOrEqual,
+            (Operator::Less, Number, Number) => IntCC::SignedLessThan,
+            (Operator::LessOrEqual, Number, Number) => IntCC::SignedLessThanOrEqual,
+            (Operator::Equal | Operator::StrictEqual, lhs_kind, rhs_kind)
+                if lhs_kind == rhs_kind =>
+            {
+                IntCC::Equal
+            }
+            (Operator::NotEqual | Operator::StrictNotEqual, lhs_kind, rhs_kind)
+                if lhs_kind == rhs_kind =>
+            {
+                IntCC::NotEqual
+            }
+            _ => return None,
+        };
+        let result = self.builder.ins().icmp(comparison, lhs, rhs);
+        Some((self.builder.ins().uextend(types::I64, result), Boolean))
+    }
+
+    /// Fail if `condition` is nonzero, and otherwise carry on in a new block.
+    fn continue_unless(&mut self, condition: Register) {
+        let next = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(condition, self.fail, &[], next, &[]);
+        self.builder.switch_to_block(next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{errors::ErrorCode, Jabroni};
+
+    const SCRIPT: &str = "
+        function score(a, b) {
+            const total = a * 3 + b;
+            return total > 10 === (b < 0) ? total : 0 - total;
+        }
+        function even(n) {return n === 0 ? true : n === 1 ? false : even(n - 2);}
+        function fib(n) {return n < 2 ? n : fib(n - 1) + fib(n - 2);}
+        const factor = 2;
+        function scaled(x) {return x * factor;}
+    ";
+
+    fn interpreters() -> (Jabroni, Jabroni) {
+        let mut jit = Jabroni::new();
+        jit.set_jit(true);
+        jit.run_script(SCRIPT).unwrap();
+        let mut interpreter = Jabroni::new();
+        interpreter.run_script(SCRIPT).unwrap();
+        (jit, interpreter)
+    }
+
+    #[test]
+    fn compiles_hot_functions() {
+        let (mut jit, mut interpreter) = interpreters();
+        for i in 0..HOT_CALLS as i64 * 2 {
+            for call in [
+                format!("score({i} - 50, 7 - {})", i % 13),
+                format!("even({})", i % 40),
+                format!("scaled({i})"),
+            ] {
+                assert_eq!(
+                    jit.run_expression(&call).unwrap(),
+                    interpreter.run_expression(&call).unwrap(),
+                    "{call}"
+                );
+            }
+        }
+        assert_eq!(jit.run_expression("fib(20)").unwrap(), 6765.into());
+        assert_eq!(jit.native_functions(), ["even", "score", "fib"]);
+    }
+
+    #[test]
+    fn falls_back_to_interpreting() {
+        let (mut jit, _) = interpreters();
+        jit.set_max_depth(500);
+        jit.run_expression("fib(15)").unwrap();
+        assert_eq!(jit.native_functions(), ["fib"]);
+
+        // Failures are raised by the interpreter
+        let error = jit
+            .run_expression("score(4611686018427387904, 0)")
+            .unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::NumberOverflow));
+        let error = jit.run_expression("fib(600)").unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::RecursionTooDeep));
+        // Arguments of other types than it was compiled for are interpreted
+        let error = jit.run_expression("fib(true)").unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::OperandTypeMismatch));
+
+        // Functions are interpreted while they're being watched
+        jit.start_profiling();
+        jit.run_expression("fib(10)").unwrap();
+        let profile = jit.stop_profiling().unwrap();
+        assert_eq!(profile.functions[0].calls, 177);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{
+    errors::{ErrorCode, JabroniError, JabroniResult},
+    value::Value,
+};
+use std::{convert::TryFrom, rc::Rc};
+
+/// A value that can be hashed, for use as a key in maps and sets. Only numbers, booleans,
+/// strings, bytes and null can be keys. Objects and functions can't, since they change or have
+/// no meaningful equality.
+///
+/// # Example
+/// ```
+/// use jabroni::{errors::ErrorKind, HashKey, Jabroni};
+/// use std::{collections::HashMap, convert::TryFrom};
+///
+/// let mut interpreter = Jabroni::new();
+/// let mut scores = HashMap::new();
+/// for (code, score) in [("'ann'", 3), ("1 + 1", 5), ("'ann'", 4)] {
+///     let key = HashKey::try_from(interpreter.run_expression(code).unwrap()).unwrap();
+///     *scores.entry(key).or_insert(0) += score;
+/// }
+/// assert_eq!(scores[&HashKey::String("ann".into())], 7);
+/// assert_eq!(scores[&HashKey::Number(2)], 5);
+///
+/// interpreter.run_script("function f() {return 1;}").unwrap();
+/// let error = HashKey::try_from(interpreter.run_expression("f").unwrap()).unwrap_err();
+/// assert_eq!(error.kind(), ErrorKind::Type);
+/// ```
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub enum HashKey {
+    /// Number type
+    Number(i64),
+    /// Boolean type
+    Boolean(bool),
+    /// String type
+    String(Rc<str>),
+    /// Bytes type
+    Bytes(Vec<u8>),
+    /// Null type
+    Null,
+}
+
+impl TryFrom<&Value> for HashKey {
+    type Error = JabroniError;
+
+    fn try_from(value: &Value) -> JabroniResult<Self> {
+        HashKey::try_from(value.clone())
+    }
+}
+
+impl TryFrom<Value> for HashKey {
+    type Error = JabroniError;
+
+    fn try_from(value: Value) -> JabroniResult<Self> {
+        Ok(match value {
+            Value::Number(value) => HashKey::Number(value),
+            Value::Boolean(value) => HashKey::Boolean(value),
+            Value::String(value) => HashKey::String(value),
+            Value::Bytes(value) => HashKey::Bytes(value),
+            Value::Null => HashKey::Null,
+            Value::Object(_) | Value::Subroutine(_) => {
+                return Err(JabroniError::from_code(
+                    ErrorCode::UnhashableValue,
+                    format!("A {} can't be used as a key", value.type_name()),
+                ))
+            }
+        })
+    }
+}
+
+impl From<HashKey> for Value {
+    fn from(key: HashKey) -> Value {
+        match key {
+            HashKey::Number(value) => Value::Number(value),
+            HashKey::Boolean(value) => Value::Boolean(value),
+            HashKey::String(value) => Value::String(value),
+            HashKey::Bytes(value) => Value::Bytes(value),
+            HashKey::Null => Value::Null,
+        }
+    }
+}
@@ -1,11 +1,60 @@
 #[macro_use]
 extern crate pest_derive;
 
+pub mod ast;
+#[cfg(feature = "tokio")]
+mod async_runtime;
+pub mod audit;
 mod binding;
+mod bytes;
+mod channels;
+pub mod compat;
+mod compiled;
+mod completion;
+pub mod coverage;
+#[cfg(feature = "serde")]
+mod de;
+pub mod debug;
+mod diagnostics;
 pub mod errors;
+mod events;
+pub mod fmt;
+#[cfg(feature = "jit")]
+mod jit;
+mod key;
+pub mod lint;
+mod module;
+mod object;
+mod permissions;
+pub mod profile;
+pub mod reflect;
+pub mod sandbox;
+#[cfg(feature = "serde")]
+pub mod snapshot;
 mod state;
+mod stats;
+#[cfg(feature = "unicode-normalization")]
+mod strings;
+mod suggestions;
+pub mod template;
+pub mod testing;
+pub mod tokens;
+mod trace;
+mod typecheck;
 mod utils;
 mod value;
-pub use binding::{Binding, BindingMap};
-pub use state::Jabroni;
-pub use value::{Subroutine, Value};
+mod warnings;
+pub use binding::{Binding, BindingEntry, BindingMap, ObjectHandler};
+pub use channels::Message;
+pub use completion::{complete, complete_with, Completion, CompletionKind};
+#[cfg(feature = "serde")]
+pub use de::{eval_config, from_value};
+pub use diagnostics::{Diagnostic, Severity};
+pub use key::HashKey;
+pub use module::ModuleLoader;
+pub use object::Object;
+pub use permissions::{Permission, Permissions};
+pub use state::{Incremental, Jabroni, Progress, ReloadConflict, Resumable, Step};
+pub use stats::RunStats;
+pub use trace::Tracer;
+pub use value::{Coercion, Equality, Number, Operator, Subroutine, Value, ValueVisitor};
@@ -0,0 +1,335 @@
+//! Style and correctness checks for scripts, which can be turned on and off individually.
+
+use crate::{
+    ast::{self, Expression, ExpressionKind, Script, Span, Statement, StatementKind, Visitor},
+    diagnostics::{Diagnostic, Severity},
+    errors::JabroniResult,
+    value::{Operator, Value},
+};
+use std::collections::HashSet;
+
+/// A check done by a [`Linter`].
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum Rule {
+    /// Bindings declared in a function body that are never used
+    UnusedBinding,
+    /// Conditions that always have the same value
+    ConstantCondition,
+    /// Statements after a `return` or `throw`
+    UnreachableCode,
+    /// `==` or `!=` between literals of different types, which always fails
+    MixedTypeComparison,
+}
+
+impl Rule {
+    const ALL: [Rule; 4] = [
+        Rule::UnusedBinding,
+        Rule::ConstantCondition,
+        Rule::UnreachableCode,
+        Rule::MixedTypeComparison,
+    ];
+
+    /// The name of the rule, used as the code of its diagnostics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::UnusedBinding => "unused-binding",
+            Rule::ConstantCondition => "constant-condition",
+            Rule::UnreachableCode => "unreachable-code",
+            Rule::MixedTypeComparison => "mixed-type-comparison",
+        }
+    }
+
+    /// Find a rule by its name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|rule| rule.name() == name)
+    }
+}
+
+/// Checks scripts against a set of [`Rule`]s. Every rule is enabled by default.
+///
+/// # Example
+/// ```
+/// use jabroni::lint::{Linter, Rule};
+///
+/// let linter = Linter::all().without(Rule::ConstantCondition);
+/// let diagnostics = linter.lint("const x = 1 == '1'; const y = true ? 1 : 2;").unwrap();
+/// assert_eq!(diagnostics.len(), 1);
+/// assert_eq!(diagnostics[0].code.as_deref(), Some("mixed-type-comparison"));
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Linter {
+    enabled: HashSet<Rule>,
+}
+
+impl Linter {
+    /// Enable every rule.
+    pub fn all() -> Self {
+        Self {
+            enabled: Rule::ALL.iter().copied().collect(),
+        }
+    }
+
+    /// Enable no rules.
+    pub fn none() -> Self {
+        Self {
+            enabled: HashSet::new(),
+        }
+    }
+
+    /// Enable a rule.
+    pub fn with(mut self, rule: Rule) -> Self {
+        self.enable(rule);
+        self
+    }
+
+    /// Disable a rule.
+    pub fn without(mut self, rule: Rule) -> Self {
+        self.disable(rule);
+        self
+    }
+
+    /// Enable a rule.
+    pub fn enable(&mut self, rule: Rule) {
+        self.enabled.insert(rule);
+    }
+
+    /// Disable a rule.
+    pub fn disable(&mut self, rule: Rule) {
+        self.enabled.remove(&rule);
+    }
+
+    /// Check if a rule is enabled.
+    pub fn enables(&self, rule: Rule) -> bool {
+        self.enabled.contains(&rule)
+    }
+
+    /// Check a script, failing if it can't be parsed. Diagnostics are in the order they appear
+    /// in the script.
+    pub fn lint(&self, code: &str) -> JabroniResult<Vec<Diagnostic>> {
+        Ok(self.lint_script(&ast::parse(code)?, code))
+    }
+
+    /// Check a script that's already been parsed from `code`.
+    pub fn lint_script(&self, script: &Script, code: &str) -> Vec<Diagnostic> {
+        let mut lints = Vec::new();
+        self.statements(&script.statements, &mut lints);
+        lints.sort_by_key(|(_, span, _)| span.start);
+        lints
+            .into_iter()
+            .filter(|(rule, _, _)| self.enables(*rule))
+            .map(|(rule, span, message)| Diagnostic {
+                severity: Severity::Warning,
+                message,
+                code: Some(rule.name().into()),
+                location: span.location(code),
+                error_code: None,
+            })
+            .collect()
+    }
+
+    fn statements(&self, statements: &[Statement], lints: &mut Vec<(Rule, Span, String)>) {
+        let mut exit = None;
+        for statement in statements {
+            if let Some(keyword) = exit.take() {
+                let message = format!("Unreachable code after '{keyword}'");
+                lints.push((Rule::UnreachableCode, statement.span, message));
+            }
+            exit = match statement.kind {
+                StatementKind::Return(_) => Some("return"),
+                StatementKind::Throw(_) => Some("throw"),
+                _ => None,
+            };
+            self.statement(statement, lints);
+        }
+    }
+
+    fn statement(&self, statement: &Statement, lints: &mut Vec<(Rule, Span, String)>) {
+        match &statement.kind {
+            StatementKind::Expression(expression)
+            | StatementKind::Return(expression)
+            | StatementKind::Throw(expression)
+            | StatementKind::Yield(Some(expression))
+            | StatementKind::Declaration {
+                value: expression, ..
+            } => ExpressionLints(lints).visit_expression(expression),
+            StatementKind::Yield(None) | StatementKind::Import { .. } => (),
+            StatementKind::Block(statements) => self.statements(statements, lints),
+            StatementKind::Function { body, .. } => {
+                unused_bindings(body, lints);
+                self.statements(body, lints);
+            }
+            StatementKind::Try { body, handler, .. } => {
+                self.statements(body, lints);
+                self.statements(handler, lints);
+            }
+        }
+    }
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// Checks expressions and their subexpressions.
+struct ExpressionLints<'a>(&'a mut Vec<(Rule, Span, String)>);
+
+impl Visitor for ExpressionLints<'_> {
+    fn visit_expression(&mut self, expression: &Expression) {
+        match &expression.kind {
+            ExpressionKind::Ternary { condition, .. } if is_constant(condition) => {
+                let message = "Condition always has the same value".into();
+                self.0
+                    .push((Rule::ConstantCondition, condition.span, message));
+            }
+            ExpressionKind::Binary { operator, lhs, rhs }
+                if matches!(operator, Operator::Equal | Operator::NotEqual) =>
+            {
+                if let (Some(lhs), Some(rhs)) = (literal(lhs), literal(rhs)) {
+                    if lhs.type_name() != rhs.type_name() {
+                        let message = format!(
+                            "Comparing {} with {} using '{}' always fails; use '{}='",
+                            lhs.type_name(),
+                            rhs.type_name(),
+                            operator.symbol(),
+                            operator.symbol()
+                        );
+                        self.0
+                            .push((Rule::MixedTypeComparison, expression.span, message));
+                    }
+                }
+            }
+            _ => (),
+        }
+        ast::walk_expression(self, expression);
+    }
+}
+
+/// Collects the names of bindings used in expressions.
+struct Uses<'a>(&'a mut HashSet<String>);
+
+impl Visitor for Uses<'_> {
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let ExpressionKind::Ident(ident) = &expression.kind {
+            self.0.insert(ident.clone());
+        }
+        ast::walk_expression(self, expression);
+    }
+}
+
+/// Get the value of an expression if it's a literal, possibly in parentheses.
+fn literal(expression: &Expression) -> Option<&Value> {
+    match &expression.kind {
+        ExpressionKind::Literal(value) => Some(value),
+        ExpressionKind::Group(expression) => literal(expression),
+        _ => None,
+    }
+}
+
+/// Check if an expression only involves literals.
+fn is_constant(expression: &Expression) -> bool {
+    match &expression.kind {
+        ExpressionKind::Literal(_) => true,
+        ExpressionKind::Group(expression) => is_constant(expression),
+        ExpressionKind::Binary { lhs, rhs, .. } => is_constant(lhs) && is_constant(rhs),
+        _ => false,
+    }
+}
+
+/// Find bindings declared in a function body, but not in functions nested in it, that are never
+/// used. Nested functions can see the bindings of their caller, so uses in them count.
+fn unused_bindings(body: &[Statement], lints: &mut Vec<(Rule, Span, String)>) {
+    let mut declared = Vec::new();
+    let mut used = HashSet::new();
+    for statement in body {
+        statement_bindings(statement, &mut declared, &mut used, true);
+    }
+    for ident in declared {
+        if !used.contains(&ident.name) {
+            let message = format!("'{}' is declared but never used", ident.name);
+            lints.push((Rule::UnusedBinding, ident.span, message));
+        }
+    }
+}
+
+fn statement_bindings<'a>(
+    statement: &'a Statement,
+    declared: &mut Vec<&'a ast::Ident>,
+    used: &mut HashSet<String>,
+    top: bool,
+) {
+    match &statement.kind {
+        StatementKind::Expression(expression)
+        | StatementKind::Return(expression)
+        | StatementKind::Throw(expression)
+        | StatementKind::Yield(Some(expression)) => Uses(used).visit_expression(expression),
+        StatementKind::Yield(None) | StatementKind::Import { .. } => (),
+        StatementKind::Declaration { name, value, .. } => {
+            if top {
+                declared.push(name);
+            }
+            Uses(used).visit_expression(value);
+        }
+        StatementKind::Block(statements) => {
+            for statement in statements {
+                statement_bindings(statement, declared, used, top);
+            }
+        }
+        StatementKind::Function { body, .. } => {
+            for statement in body {
+                statement_bindings(statement, declared, used, false);
+            }
+        }
+        StatementKind::Try { body, handler, .. } => {
+            for statement in body.iter().chain(handler) {
+                statement_bindings(statement, declared, used, top);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lint() {
+        let code = "
+function f(n) {
+    const unused = 1;
+    const used = n;
+    function g() { return used; }
+    return g();
+    f(1);
+}
+const a = (1 + 2) == 3 ? 1 == '1' : null != false;
+try { throw 1; const b = 2; } catch (e) { return e; }";
+        let messages: Vec<_> = Linter::all()
+            .lint(code)
+            .unwrap()
+            .into_iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "3:11: warning[unused-binding]: 'unused' is declared but never used",
+                "7:5: warning[unreachable-code]: Unreachable code after 'return'",
+                "9:12: warning[constant-condition]: Condition always has the same value",
+                "9:26: warning[mixed-type-comparison]: \
+                 Comparing number with string using '==' always fails; use '==='",
+                "9:37: warning[mixed-type-comparison]: \
+                 Comparing null with boolean using '!=' always fails; use '!=='",
+                "10:16: warning[unreachable-code]: Unreachable code after 'throw'",
+            ]
+        );
+
+        let linter = Linter::none().with(Rule::UnusedBinding);
+        let diagnostics = linter.lint(code).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(Rule::from_name("unused-binding"), Some(Rule::UnusedBinding));
+        assert_eq!(Rule::from_name("bogus"), None);
+        assert!(Linter::all().lint("const x = ;").is_err());
+    }
+}
@@ -0,0 +1,32 @@
+use crate::errors::JabroniResult;
+
+/// Supplies the source of modules imported by scripts, such as `utils` in
+/// `import { helper } from 'utils';`.
+///
+/// # Example
+/// ```
+/// use jabroni::{
+///     errors::{ErrorCode, JabroniError, JabroniResult},
+///     Jabroni, ModuleLoader,
+/// };
+///
+/// struct Prelude;
+///
+/// impl ModuleLoader for Prelude {
+///     fn load(&self, specifier: &str) -> JabroniResult<String> {
+///         match specifier {
+///             "math" => Ok("function square(x) {return x * x;}".into()),
+///             _ => Err(JabroniError::from_code(ErrorCode::ModuleNotFound, format!("No module '{specifier}'"))),
+///         }
+///     }
+/// }
+///
+/// let mut interpreter = Jabroni::new();
+/// interpreter.set_module_loader(Prelude);
+/// interpreter.run_script("import { square } from 'math';").unwrap();
+/// assert_eq!(interpreter.run_expression("square(4)").unwrap(), 16.into());
+/// ```
+pub trait ModuleLoader {
+    /// Get the source code of a module.
+    fn load(&self, specifier: &str) -> JabroniResult<String>;
+}
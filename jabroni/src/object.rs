@@ -0,0 +1,287 @@
+use crate::{binding::BindingMap, fmt, value::Value};
+use std::{
+    cell::{Ref, RefCell, RefMut},
+    collections::HashMap,
+    fmt::{Debug, Display, Error, Formatter},
+    rc::{Rc, Weak},
+};
+
+/// A Jabroni object. Clones of an object share its properties, so changes made through one are
+/// seen through all of them, as with objects in JavaScript.
+///
+/// # Example
+/// ```
+/// use jabroni::{Binding, BindingMap, Jabroni, Object, Value as JabroniValue};
+///
+/// let mut properties = BindingMap::default();
+/// properties.set("x".into(), Binding::variable(JabroniValue::Number(0)));
+/// let point = Object::new(properties);
+///
+/// let mut interpreter = Jabroni::new();
+/// interpreter
+///     .define_constant("point", JabroniValue::Object(point.clone()))
+///     .unwrap();
+/// interpreter.run_script("let alias = point; alias.x = 1;").unwrap();
+/// assert_eq!(point.get("x"), Some(JabroniValue::Number(1)));
+///
+/// // Copies don't share properties
+/// let copy = point.copy();
+/// interpreter.run_script("point.x = 2;").unwrap();
+/// assert_eq!(copy.get("x"), Some(JabroniValue::Number(1)));
+/// ```
+///
+/// Objects that contain themselves, directly or through other objects, aren't freed when
+/// they're no longer used until [`Object::collect_cycles`] is called.
+#[derive(Clone)]
+pub struct Object(Rc<RefCell<BindingMap>>);
+
+thread_local! {
+    /// Every object on this thread, so cycles can be found without knowing what refers to them.
+    static OBJECTS: RefCell<Vec<Weak<RefCell<BindingMap>>>> = RefCell::new(Vec::new());
+}
+
+impl Object {
+    pub fn new(properties: BindingMap) -> Self {
+        let object = Rc::new(RefCell::new(properties));
+        OBJECTS.with(|objects| {
+            let mut objects = objects.borrow_mut();
+            // Forget freed objects every so often, so this doesn't grow forever
+            if objects.len() == objects.capacity() {
+                objects.retain(|object| object.strong_count() > 0);
+            }
+            objects.push(Rc::downgrade(&object));
+        });
+        Self(object)
+    }
+
+    /// Borrow the object's properties.
+    ///
+    /// # Panics
+    /// Panics if the properties are being changed.
+    pub fn borrow(&self) -> Ref<'_, BindingMap> {
+        self.0.borrow()
+    }
+
+    /// Borrow the object's properties to change them.
+    ///
+    /// # Panics
+    /// Panics if the properties are already borrowed.
+    pub fn borrow_mut(&self) -> RefMut<'_, BindingMap> {
+        self.0.borrow_mut()
+    }
+
+    /// Get the current value of a property, if it exists and can be read.
+    pub fn get(&self, ident: &str) -> Option<Value> {
+        let properties = self.borrow();
+        properties.get(ident).ok()?.read().ok()
+    }
+
+    /// Make a new object with the same properties. Objects in the properties are still shared.
+    pub fn copy(&self) -> Self {
+        Self::new(self.borrow().clone())
+    }
+
+    /// Check if two objects are the same object, rather than objects with the same properties.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Identifies the object, for noticing when it's been seen before.
+    pub(crate) fn as_ptr(&self) -> *const () {
+        Rc::as_ptr(&self.0) as *const ()
+    }
+
+    /// Free objects on this thread that are only kept alive by cycles, such as an object in one
+    /// of its own properties, by removing their properties. Returns how many objects were freed.
+    ///
+    /// Objects that can still be used from outside the cycle, like by the host, a binding or a
+    /// function, are left alone.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Binding, BindingMap, Jabroni, Object, Value as JabroniValue};
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// let mut node = BindingMap::default();
+    /// node.set("next".into(), Binding::variable(JabroniValue::Object(Object::default())));
+    /// interpreter
+    ///     .define_variable("node", JabroniValue::Object(node.into()))
+    ///     .unwrap();
+    /// interpreter.run_script("node.next = node;").unwrap();
+    /// // Still in use by the interpreter
+    /// assert_eq!(Object::collect_cycles(), 0);
+    ///
+    /// drop(interpreter);
+    /// assert_eq!(Object::collect_cycles(), 1);
+    /// assert_eq!(Object::collect_cycles(), 0);
+    /// ```
+    pub fn collect_cycles() -> usize {
+        let objects: Vec<_> = OBJECTS.with(|objects| {
+            let mut objects = objects.borrow_mut();
+            objects.retain(|object| object.strong_count() > 0);
+            objects.iter().filter_map(Weak::upgrade).map(Self).collect()
+        });
+        let index: HashMap<_, _> = objects
+            .iter()
+            .enumerate()
+            .map(|(i, object)| (object.as_ptr(), i))
+            .collect();
+        // The objects each object refers to, and whether it's the only one holding the reference
+        let edges = |object: &Self| -> Option<Vec<(usize, bool)>> {
+            let properties = object.0.try_borrow().ok()?;
+            Some(
+                properties
+                    .stored_values()
+                    .filter_map(|(value, shared)| Some((value.as_object()?, shared)))
+                    .map(|(object, shared)| (index[&object.as_ptr()], !shared))
+                    .collect(),
+            )
+        };
+
+        // Count references from outside the objects' properties, ignoring the ones made above.
+        // Objects being changed can't be looked at, so they're assumed to be in use. References
+        // from properties shared with copies or the host count as outside ones, since the object
+        // holding them isn't the only thing that can use them.
+        let mut external: Vec<_> = objects
+            .iter()
+            .map(|object| Rc::strong_count(&object.0) - 1)
+            .collect();
+        let mut reachable = vec![false; objects.len()];
+        let mut edges: Vec<_> = objects.iter().map(edges).collect();
+        for (i, targets) in edges.iter().enumerate() {
+            match targets {
+                Some(targets) => targets
+                    .iter()
+                    .filter(|(_, owned)| *owned)
+                    .for_each(|&(j, _)| external[j] -= 1),
+                None => reachable[i] = true,
+            }
+        }
+
+        // Anything referred to from outside is in use, along with everything it refers to
+        let mut pending: Vec<_> = (0..objects.len())
+            .filter(|&i| reachable[i] || external[i] > 0)
+            .collect();
+        while let Some(i) = pending.pop() {
+            reachable[i] = true;
+            for (j, _) in edges[i].take().unwrap_or_default() {
+                if !reachable[j] {
+                    pending.push(j);
+                }
+            }
+        }
+
+        let garbage: Vec<_> = objects
+            .into_iter()
+            .zip(reachable)
+            .filter(|(_, reachable)| !reachable)
+            .map(|(object, _)| object)
+            .collect();
+        for object in &garbage {
+            // Taken out first so dropping properties can't find the object borrowed
+            let properties = std::mem::take(&mut *object.borrow_mut());
+            drop(properties);
+        }
+        garbage.len()
+    }
+}
+
+impl Default for Object {
+    fn default() -> Self {
+        Self::new(BindingMap::default())
+    }
+}
+
+impl From<BindingMap> for Object {
+    fn from(properties: BindingMap) -> Self {
+        Self::new(properties)
+    }
+}
+
+thread_local! {
+    /// Objects being formatted, so objects that contain themselves aren't formatted forever.
+    static FORMATTING: RefCell<Vec<*const ()>> = RefCell::new(Vec::new());
+}
+
+impl Object {
+    /// Format the object with `format`, or write `[circular]` if it's already being formatted
+    /// because it contains itself.
+    fn format_once(
+        &self,
+        f: &mut Formatter<'_>,
+        format: impl FnOnce(&BindingMap, &mut Formatter<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let ptr = self.as_ptr();
+        if FORMATTING.with(|formatting| formatting.borrow().contains(&ptr)) {
+            return write!(f, "[circular]");
+        }
+        let properties = match self.0.try_borrow() {
+            Ok(properties) => properties,
+            Err(_) => return write!(f, "[object]"),
+        };
+        FORMATTING.with(|formatting| formatting.borrow_mut().push(ptr));
+        let result = format(&properties, f);
+        FORMATTING.with(|formatting| formatting.borrow_mut().pop());
+        result
+    }
+}
+
+impl Debug for Object {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        self.format_once(f, |properties, f| properties.fmt(f))
+    }
+}
+
+/// Displayed like `{name: 'Ann', age: 30}`, or over multiple lines with the alternate flag
+/// (`{:#}`). Objects that contain themselves are shown as `[circular]` where they appear in
+/// themselves.
+///
+/// # Example
+/// ```
+/// use jabroni::{Binding, BindingMap, Object, Value as JabroniValue};
+///
+/// let mut position = BindingMap::default();
+/// position.set("x".into(), Binding::constant(JabroniValue::Number(1)));
+/// let mut player = BindingMap::default();
+/// player.set("name".into(), Binding::constant(JabroniValue::String("Ann".into())));
+/// player.set("position".into(), Binding::constant(JabroniValue::Object(position.into())));
+/// let player = Object::new(player);
+///
+/// assert_eq!(player.to_string(), "{name: 'Ann', position: {x: 1}}");
+/// assert_eq!(
+///     format!("{player:#}"),
+///     "{\n    name: 'Ann',\n    position: {\n        x: 1\n    }\n}"
+/// );
+/// ```
+impl Display for Object {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        self.format_once(f, |properties, f| {
+            let properties: Vec<_> = properties
+                .iter()
+                .map(|(ident, binding)| {
+                    // Properties the host fails to provide are shown as null
+                    let value = match binding.read().unwrap_or(Value::Null) {
+                        Value::String(string) => fmt::quote(&string),
+                        value if f.alternate() => format!("{value:#}"),
+                        value => value.to_string(),
+                    };
+                    format!("{ident}: {value}")
+                })
+                .collect();
+            if properties.is_empty() {
+                write!(f, "{{}}")
+            } else if f.alternate() {
+                let properties = properties.join(",\n").replace('\n', "\n    ");
+                write!(f, "{{\n    {properties}\n}}")
+            } else {
+                write!(f, "{{{}}}", properties.join(", "))
+            }
+        })
+    }
+}
+
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other)
+    }
+}
@@ -0,0 +1,113 @@
+use crate::errors::{ErrorCode, JabroniError, JabroniResult};
+use std::collections::HashSet;
+
+/// A capability that host functions can require with [`Jabroni::guard`](crate::Jabroni::guard).
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum Permission {
+    /// Read files
+    FsRead,
+    /// Create, modify or delete files
+    FsWrite,
+    /// Access the network
+    Net,
+    /// Read environment variables
+    Env,
+    /// Read the clock
+    Time,
+    /// Generate random numbers
+    Random,
+}
+
+impl Permission {
+    const ALL: [Permission; 6] = [
+        Permission::FsRead,
+        Permission::FsWrite,
+        Permission::Net,
+        Permission::Env,
+        Permission::Time,
+        Permission::Random,
+    ];
+
+    /// Whether functions that need this capability can return different results for the same
+    /// arguments.
+    pub(crate) fn is_nondeterministic(self) -> bool {
+        matches!(self, Permission::Time | Permission::Random)
+    }
+}
+
+/// The set of capabilities granted to scripts run by an interpreter. Everything is granted by
+/// default.
+///
+/// # Example
+/// ```
+/// use jabroni::{Permission, Permissions};
+///
+/// let permissions = Permissions::none().with(Permission::Time);
+/// assert!(permissions.allows(Permission::Time));
+/// assert!(!permissions.allows(Permission::Net));
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Permissions {
+    granted: HashSet<Permission>,
+}
+
+impl Permissions {
+    /// Grant every capability.
+    pub fn all() -> Self {
+        Self {
+            granted: Permission::ALL.iter().copied().collect(),
+        }
+    }
+
+    /// Grant no capabilities.
+    pub fn none() -> Self {
+        Self {
+            granted: HashSet::new(),
+        }
+    }
+
+    /// Grant a capability.
+    pub fn with(mut self, permission: Permission) -> Self {
+        self.allow(permission);
+        self
+    }
+
+    /// Revoke a capability.
+    pub fn without(mut self, permission: Permission) -> Self {
+        self.deny(permission);
+        self
+    }
+
+    /// Grant a capability.
+    pub fn allow(&mut self, permission: Permission) {
+        self.granted.insert(permission);
+    }
+
+    /// Revoke a capability.
+    pub fn deny(&mut self, permission: Permission) {
+        self.granted.remove(&permission);
+    }
+
+    /// Check if a capability is granted.
+    pub fn allows(&self, permission: Permission) -> bool {
+        self.granted.contains(&permission)
+    }
+
+    /// Fail with a permission error if a capability isn't granted.
+    pub fn check(&self, permission: Permission) -> JabroniResult {
+        if self.allows(permission) {
+            Ok(())
+        } else {
+            Err(JabroniError::from_code(
+                ErrorCode::PermissionDenied,
+                format!("Script isn't allowed {permission:?} access"),
+            ))
+        }
+    }
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self::all()
+    }
+}
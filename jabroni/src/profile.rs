@@ -0,0 +1,160 @@
+use crate::errors::Location;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Time spent in a function, from [`Jabroni::profile`](crate::Jabroni::profile).
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct FunctionProfile {
+    /// The code used to call the function, such as `update` or `player.move`.
+    pub name: String,
+    pub calls: u64,
+    /// Time spent in the function, including in functions it called. Recursive calls are only
+    /// counted once.
+    pub inclusive: Duration,
+    /// Time spent in the function itself.
+    pub exclusive: Duration,
+}
+
+/// Time spent evaluating an expression, from [`Jabroni::profile`](crate::Jabroni::profile).
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ExpressionProfile {
+    pub location: Location,
+    pub code: String,
+    pub evaluations: u64,
+    /// Time spent evaluating the expression, including its subexpressions and the functions it
+    /// calls.
+    pub time: Duration,
+}
+
+/// What scripts spent their time on while profiling.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Profile {
+    /// Functions, slowest first by exclusive time.
+    pub functions: Vec<FunctionProfile>,
+    /// Expressions, slowest first.
+    pub expressions: Vec<ExpressionProfile>,
+    /// Exclusive time spent in each chain of calls, outermost function first.
+    stacks: Vec<(Vec<String>, Duration)>,
+}
+
+impl Profile {
+    /// Render the time spent in each chain of calls in the folded stack format used by flame
+    /// graph tools, such as `outer;inner 120`, in microseconds.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::Jabroni;
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.start_profiling();
+    /// interpreter
+    ///     .run_script("function inner() {return 1;} function outer() {return inner();} outer();")
+    ///     .unwrap();
+    /// let folded = interpreter.profile().unwrap().folded_stacks();
+    /// let stacks: Vec<_> = folded.lines().map(|line| line.rsplit_once(' ').unwrap().0).collect();
+    /// assert_eq!(stacks, ["outer", "outer;inner"]);
+    /// ```
+    pub fn folded_stacks(&self) -> String {
+        let mut folded = String::new();
+        for (stack, time) in &self.stacks {
+            folded.push_str(&format!("{} {}\n", stack.join(";"), time.as_micros()));
+        }
+        folded
+    }
+}
+
+struct Call {
+    name: String,
+    start: Instant,
+    /// Time spent in functions called from this one.
+    children: Duration,
+}
+
+/// Records time spent by scripts while profiling is on.
+#[derive(Default)]
+pub(crate) struct Profiler {
+    calls: Vec<Call>,
+    functions: HashMap<String, FunctionProfile>,
+    expressions: HashMap<(Location, String), ExpressionProfile>,
+    stacks: HashMap<Vec<String>, Duration>,
+}
+
+impl Profiler {
+    pub(crate) fn enter(&mut self, name: &str) {
+        self.calls.push(Call {
+            name: name.into(),
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    pub(crate) fn exit(&mut self) {
+        let call = match self.calls.pop() {
+            Some(call) => call,
+            None => return,
+        };
+        let inclusive = call.start.elapsed();
+        let exclusive = inclusive.saturating_sub(call.children);
+        if let Some(caller) = self.calls.last_mut() {
+            caller.children += inclusive;
+        }
+
+        let mut stack: Vec<_> = self.calls.iter().map(|call| call.name.clone()).collect();
+        let recursive = stack.contains(&call.name);
+        stack.push(call.name.clone());
+        *self.stacks.entry(stack).or_default() += exclusive;
+
+        let function = self
+            .functions
+            .entry(call.name.clone())
+            .or_insert_with(|| FunctionProfile {
+                name: call.name,
+                calls: 0,
+                inclusive: Duration::ZERO,
+                exclusive: Duration::ZERO,
+            });
+        function.calls += 1;
+        function.exclusive += exclusive;
+        if !recursive {
+            function.inclusive += inclusive;
+        }
+    }
+
+    pub(crate) fn record_expression(&mut self, location: Location, code: &str, time: Duration) {
+        let expression = self
+            .expressions
+            .entry((location, code.into()))
+            .or_insert_with(|| ExpressionProfile {
+                location,
+                code: code.into(),
+                evaluations: 0,
+                time: Duration::ZERO,
+            });
+        expression.evaluations += 1;
+        expression.time += time;
+    }
+
+    pub(crate) fn report(&self) -> Profile {
+        let mut functions: Vec<_> = self.functions.values().cloned().collect();
+        functions.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then(a.name.cmp(&b.name)));
+        let mut expressions: Vec<_> = self.expressions.values().cloned().collect();
+        expressions.sort_by(|a, b| {
+            b.time.cmp(&a.time).then(
+                (a.location.line, a.location.column).cmp(&(b.location.line, b.location.column)),
+            )
+        });
+        let mut stacks: Vec<_> = self
+            .stacks
+            .iter()
+            .map(|(stack, time)| (stack.clone(), *time))
+            .collect();
+        stacks.sort();
+        Profile {
+            functions,
+            expressions,
+            stacks,
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Describing the functions scripts can call, for tools like editors and API browsers.
+
+use crate::{
+    binding::BindingMap,
+    errors::{ErrorCode, JabroniError, JabroniResult},
+    object::Object,
+    value::{Subroutine, Value},
+};
+use std::collections::HashSet;
+
+/// A parameter of a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Parameter {
+    pub name: String,
+    /// The type the function expects, such as `number`, if it's given.
+    pub type_name: Option<String>,
+}
+
+impl Parameter {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            type_name: None,
+        }
+    }
+
+    /// Give the parameter a type, such as `number` or `string`.
+    pub fn typed(mut self, type_name: &str) -> Self {
+        self.type_name = Some(type_name.into());
+        self
+    }
+}
+
+/// What's known about a function, from [`Jabroni::describe`](crate::Jabroni::describe).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Description {
+    /// Where scripts find the function, such as `bytes.slice`.
+    pub path: String,
+    pub name: Option<String>,
+    /// How to call the function, if it's documented, defined by a script or its parameters are
+    /// named.
+    pub signature: Option<String>,
+    /// What the function does, if it's documented.
+    pub doc: Option<String>,
+    /// The number of arguments the function takes, or `None` if it's variadic.
+    pub arity: Option<usize>,
+    /// The function's parameters, if they're known.
+    pub parameters: Option<Vec<Parameter>>,
+    /// Whether the function was defined by a script, rather than the host.
+    pub scripted: bool,
+}
+
+impl Description {
+    fn new(path: &str, subroutine: &Subroutine) -> Self {
+        let parameters = subroutine.parameters().map(<[Parameter]>::to_vec);
+        let signature = subroutine.signature().map(String::from).or_else(|| {
+            let parameters: Vec<_> = parameters
+                .as_ref()?
+                .iter()
+                .map(|parameter| match &parameter.type_name {
+                    Some(type_name) => format!("{}: {type_name}", parameter.name),
+                    None => parameter.name.clone(),
+                })
+                .collect();
+            Some(format!("{path}({})", parameters.join(", ")))
+        });
+        Self {
+            path: path.into(),
+            name: subroutine.name().map(String::from),
+            signature,
+            doc: subroutine.doc().map(String::from),
+            arity: subroutine.arity(),
+            parameters,
+            scripted: subroutine.is_scripted(),
+        }
+    }
+}
+
+/// Describe the function at `path`, a chain of identifiers such as `a.b`, without running
+/// anything.
+pub(crate) fn describe(bindings: &BindingMap, path: &str) -> JabroniResult<Description> {
+    let mut idents = path.split('.');
+    let mut value = bindings
+        .get(idents.next().unwrap_or_default())?
+        .value()
+        .clone();
+    for ident in idents {
+        let object = value.as_object().ok_or_else(|| {
+            JabroniError::from_code(
+                ErrorCode::NotAnObject,
+                format!("Cannot describe '{path}' because '{ident}' isn't in an object"),
+            )
+        })?;
+        let member = object.borrow().get(ident)?.value().clone();
+        value = member;
+    }
+    match value {
+        Value::Subroutine(subroutine) => Ok(Description::new(path, &subroutine)),
+        value => Err(JabroniError::from_code(
+            ErrorCode::NotAFunction,
+            format!(
+                "Cannot describe '{path}' because it's a {}",
+                value.type_name()
+            ),
+        )),
+    }
+}
+
+/// Describe every function reachable from `bindings`, sorted by path.
+pub(crate) fn describe_all(bindings: &BindingMap) -> Vec<Description> {
+    let mut descriptions = Vec::new();
+    let mut seen = HashSet::new();
+    for (ident, binding) in bindings.iter() {
+        collect(ident, binding.value(), &mut descriptions, &mut seen);
+    }
+    descriptions.sort_by(|a, b| a.path.cmp(&b.path));
+    descriptions
+}
+
+/// Describe the function at `path`, or the functions in the object there, visiting each object
+/// once so cycles end.
+fn collect(
+    path: &str,
+    value: &Value,
+    descriptions: &mut Vec<Description>,
+    seen: &mut HashSet<*const ()>,
+) {
+    match value {
+        Value::Subroutine(subroutine) => descriptions.push(Description::new(path, subroutine)),
+        Value::Object(object) if seen.insert(Object::as_ptr(object)) => {
+            for (ident, binding) in object.borrow().iter() {
+                collect(
+                    &format!("{path}.{ident}"),
+                    binding.value(),
+                    descriptions,
+                    seen,
+                );
+            }
+        }
+        _ => (),
+    }
+}
@@ -0,0 +1,304 @@
+//! Restricting which identifiers and language constructs scripts can use.
+
+use crate::{
+    ast::{
+        self, Expression, ExpressionKind, Ident, Script, Span, Statement, StatementKind, Visitor,
+    },
+    errors::{ErrorCode, JabroniError, JabroniResult},
+};
+use std::collections::HashSet;
+
+/// A language construct a [`Sandbox`] can deny.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum Construct {
+    /// `function` statements
+    Functions,
+    /// Function calls
+    Calls,
+    /// `const` and `let` declarations
+    Declarations,
+    /// Assignments to bindings and properties
+    Assignments,
+    /// `try` and `throw` statements
+    Exceptions,
+    /// `yield` statements
+    Yield,
+    /// `import` statements
+    Imports,
+    /// Ternary expressions
+    Ternaries,
+}
+
+impl Construct {
+    fn description(&self) -> &'static str {
+        match self {
+            Construct::Functions => "function definitions",
+            Construct::Calls => "function calls",
+            Construct::Declarations => "declarations",
+            Construct::Assignments => "assignments",
+            Construct::Exceptions => "exceptions",
+            Construct::Yield => "'yield'",
+            Construct::Imports => "imports",
+            Construct::Ternaries => "ternary expressions",
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+enum Idents {
+    Denied(HashSet<String>),
+    Allowed(HashSet<String>),
+}
+
+/// The identifiers and constructs scripts are allowed to use, checked before they run, so a
+/// script that breaks the rules doesn't run at all. Identifiers are checked wherever they
+/// appear: as bindings, declared names, parameters and properties. Everything is allowed by
+/// default.
+///
+/// Set one on an interpreter with [`Jabroni::set_sandbox`](crate::Jabroni::set_sandbox), or
+/// check code directly.
+///
+/// # Example
+/// ```
+/// use jabroni::sandbox::{Construct, Sandbox};
+///
+/// let sandbox = Sandbox::formula().deny("secrets");
+/// assert!(sandbox
+///     .check_expression("price * (quantity > 10 ? 9 : 10)")
+///     .is_ok());
+/// assert!(sandbox.check("function forever() {return forever();}").is_err());
+/// assert!(sandbox.check_expression("secrets.password").is_err());
+/// assert!(!sandbox.allows(Construct::Functions));
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Sandbox {
+    denied: HashSet<Construct>,
+    idents: Idents,
+}
+
+impl Sandbox {
+    /// Allow every identifier and construct.
+    pub fn new() -> Self {
+        Self {
+            denied: HashSet::new(),
+            idents: Idents::Denied(HashSet::new()),
+        }
+    }
+
+    /// Deny everything but expressions that read bindings and call functions, like spreadsheet
+    /// formulas. Scripts can't define functions, so they can't recurse, and always finish unless
+    /// a host function doesn't.
+    pub fn formula() -> Self {
+        Self::new()
+            .without(Construct::Functions)
+            .without(Construct::Declarations)
+            .without(Construct::Assignments)
+            .without(Construct::Exceptions)
+            .without(Construct::Yield)
+            .without(Construct::Imports)
+    }
+
+    /// Allow a construct.
+    pub fn with(mut self, construct: Construct) -> Self {
+        self.denied.remove(&construct);
+        self
+    }
+
+    /// Deny a construct.
+    pub fn without(mut self, construct: Construct) -> Self {
+        self.denied.insert(construct);
+        self
+    }
+
+    /// Deny an identifier. Replaces the allow-list, if there is one.
+    pub fn deny(mut self, ident: &str) -> Self {
+        match &mut self.idents {
+            Idents::Denied(denied) => {
+                denied.insert(ident.into());
+            }
+            Idents::Allowed(_) => self.idents = Idents::Denied([ident.into()].into()),
+        }
+        self
+    }
+
+    /// Deny every identifier but `idents`, including the names of the script's own bindings.
+    /// Replaces the deny-list, if there is one.
+    pub fn allow_only<'a>(mut self, idents: impl IntoIterator<Item = &'a str>) -> Self {
+        self.idents = Idents::Allowed(idents.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Check if a construct is allowed.
+    pub fn allows(&self, construct: Construct) -> bool {
+        !self.denied.contains(&construct)
+    }
+
+    /// Check if an identifier is allowed.
+    pub fn allows_ident(&self, ident: &str) -> bool {
+        match &self.idents {
+            Idents::Denied(denied) => !denied.contains(ident),
+            Idents::Allowed(allowed) => allowed.contains(ident),
+        }
+    }
+
+    /// Check if everything is allowed.
+    pub(crate) fn is_open(&self) -> bool {
+        self.denied.is_empty() && self.idents == Idents::Denied(HashSet::new())
+    }
+
+    /// Check a script, failing if it can't be parsed or uses something that's denied.
+    pub fn check(&self, code: &str) -> JabroniResult {
+        self.check_script(&ast::parse(code)?, code)
+    }
+
+    /// Check an expression, failing if it can't be parsed or uses something that's denied.
+    pub fn check_expression(&self, code: &str) -> JabroniResult {
+        let mut checker = Checker::new(self);
+        checker.visit_expression(&ast::parse_expression(code)?);
+        checker.finish(code)
+    }
+
+    /// Check a script that's already been parsed from `code`, failing at the first thing it
+    /// uses that's denied.
+    pub fn check_script(&self, script: &Script, code: &str) -> JabroniResult {
+        let mut checker = Checker::new(self);
+        checker.visit_script(script);
+        checker.finish(code)
+    }
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Finds the first identifier or construct a sandbox denies.
+struct Checker<'a> {
+    sandbox: &'a Sandbox,
+    violation: Option<(Span, String)>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(sandbox: &'a Sandbox) -> Self {
+        Self {
+            sandbox,
+            violation: None,
+        }
+    }
+
+    fn construct(&mut self, construct: Construct, span: Span) {
+        if self.violation.is_none() && !self.sandbox.allows(construct) {
+            let message = format!("Sandbox doesn't allow {}", construct.description());
+            self.violation = Some((span, message));
+        }
+    }
+
+    fn ident(&mut self, ident: &str, span: Span) {
+        if self.violation.is_none() && !self.sandbox.allows_ident(ident) {
+            let message = format!("Sandbox doesn't allow '{ident}'");
+            self.violation = Some((span, message));
+        }
+    }
+
+    fn finish(self, code: &str) -> JabroniResult {
+        match self.violation {
+            Some((span, message)) => Err(JabroniError::from_code(ErrorCode::Sandboxed, message)
+                .located(|| span.location(code), span.end - span.start)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Visitor for Checker<'_> {
+    fn visit_statement(&mut self, statement: &Statement) {
+        let construct = match statement.kind {
+            StatementKind::Function { .. } => Some(Construct::Functions),
+            StatementKind::Declaration { .. } => Some(Construct::Declarations),
+            StatementKind::Throw(_) | StatementKind::Try { .. } => Some(Construct::Exceptions),
+            StatementKind::Yield(_) => Some(Construct::Yield),
+            StatementKind::Import { .. } => Some(Construct::Imports),
+            _ => None,
+        };
+        if let Some(construct) = construct {
+            self.construct(construct, statement.span);
+        }
+        ast::walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match &expression.kind {
+            ExpressionKind::Ident(ident) => self.ident(ident, expression.span),
+            ExpressionKind::Call { .. } => self.construct(Construct::Calls, expression.span),
+            ExpressionKind::Assignment { .. } => {
+                self.construct(Construct::Assignments, expression.span)
+            }
+            ExpressionKind::Ternary { .. } => self.construct(Construct::Ternaries, expression.span),
+            _ => (),
+        }
+        ast::walk_expression(self, expression);
+    }
+
+    fn visit_ident(&mut self, ident: &Ident) {
+        self.ident(&ident.name, ident.span);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constructs() {
+        let denied = |construct: Construct, code: &str| {
+            assert!(Sandbox::new().check(code).is_ok());
+            let error = Sandbox::new().without(construct).check(code).unwrap_err();
+            assert_eq!(error.code(), Some(ErrorCode::Sandboxed));
+            // Allowing it again undoes denying it
+            let sandbox = Sandbox::new().without(construct).with(construct);
+            assert!(sandbox.check(code).is_ok());
+        };
+        denied(Construct::Functions, "function f() {return 1;}");
+        denied(Construct::Calls, "{const x = f(1);}");
+        denied(
+            Construct::Declarations,
+            "function f() {let x = 1; return x;}",
+        );
+        denied(Construct::Assignments, "x = 1;");
+        denied(Construct::Exceptions, "try {x;} catch (e) {}");
+        denied(Construct::Exceptions, "throw 1;");
+        denied(Construct::Yield, "yield;");
+        denied(Construct::Imports, "import { x } from 'y';");
+        denied(Construct::Ternaries, "return (a ? b : c) + 1;");
+
+        let error = Sandbox::formula()
+            .check("const total = 1;\nx = total;")
+            .unwrap_err();
+        assert_eq!(error.location().unwrap().line, 1);
+        assert!(error.to_string().contains("declarations"));
+        assert!(Sandbox::formula().check_expression("f(a.b) + 1").is_ok());
+        assert!(Sandbox::formula().check_expression("a = 1").is_err());
+        assert!(Sandbox::new().is_open());
+        assert!(!Sandbox::formula().is_open());
+    }
+
+    #[test]
+    fn idents() {
+        let sandbox = Sandbox::new().deny("env").deny("exit");
+        assert!(sandbox.check_expression("environment + 1").is_ok());
+        assert!(sandbox.check_expression("env").is_err());
+        assert!(sandbox.check_expression("os.exit(1)").is_err());
+        assert!(sandbox.check("function f(env) {}").is_err());
+        assert!(sandbox.check("try {} catch (exit) {}").is_err());
+
+        let sandbox = Sandbox::new().allow_only(["price", "round"]);
+        assert!(sandbox.allows_ident("price"));
+        assert!(!sandbox.allows_ident("env"));
+        assert!(sandbox.check_expression("round(price * 2)").is_ok());
+        let error = sandbox.check_expression("round(price * tax)").unwrap_err();
+        assert_eq!(error.location().unwrap().column, 15);
+        assert!(sandbox.check("const total = price;").is_err());
+        // Denying replaces the allow-list
+        assert!(sandbox.deny("env").check_expression("tax").is_ok());
+    }
+}
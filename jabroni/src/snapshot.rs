@@ -0,0 +1,329 @@
+//! Saving what scripts have defined, so a session can outlive the process running it.
+
+use crate::{
+    binding::{Binding, BindingMap},
+    errors::{ErrorCode, JabroniError, JabroniResult},
+    object::Object,
+    value::{Subroutine, Value},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The bindings of an interpreter, in every scope, taken with
+/// [`Jabroni::snapshot`](crate::Jabroni::snapshot) and restored with
+/// [`Jabroni::restore`](crate::Jabroni::restore). Serialize it with any serde format to keep it
+/// across restarts.
+///
+/// Functions defined by scripts are saved as their code. Functions defined by the host are saved
+/// by name, and linked to functions with the same name when restored. Objects shared between
+/// bindings stay shared, even if they contain themselves. Lazy and computed bindings are left
+/// out, for the host to define again.
+///
+/// # Example
+/// ```
+/// use jabroni::{snapshot::Snapshot, Jabroni, Subroutine, Value as JabroniValue};
+///
+/// let double = || {
+///     Subroutine::new(
+///         1,
+///         Box::new(|_, args| Ok(JabroniValue::Number(args[0].as_number().unwrap() * 2))),
+///     )
+///     .named("double")
+/// };
+///
+/// let mut session = Jabroni::new();
+/// session.define_constant("double", double().into()).unwrap();
+/// session
+///     .run_script("let score = 2; function bump() {return double(score) + 1;}")
+///     .unwrap();
+/// let saved = serde_json::to_string(&session.snapshot().unwrap()).unwrap();
+///
+/// // After a restart
+/// let snapshot: Snapshot = serde_json::from_str(&saved).unwrap();
+/// let mut session = Jabroni::new();
+/// session.restore(&snapshot, &[double()]).unwrap();
+/// assert_eq!(session.run_expression("bump()").unwrap(), 5.into());
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// The bindings in each scope, outermost first.
+    scopes: Vec<Vec<SavedBinding>>,
+    /// The properties of each object, which values refer to by index.
+    objects: Vec<Vec<SavedBinding>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct SavedBinding {
+    ident: String,
+    value: SavedValue,
+    mutable: bool,
+    read_only: bool,
+    hidden: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+enum SavedValue {
+    Number(i64),
+    Boolean(bool),
+    String(String),
+    Bytes(Vec<u8>),
+    Object(usize),
+    /// A function defined by a script, as its `function` statement.
+    Function(String),
+    /// A function defined by the host, by name.
+    Native(String),
+    Null,
+}
+
+fn snapshot_error(message: String) -> JabroniError {
+    JabroniError::from_code(ErrorCode::Snapshot, message)
+}
+
+impl Snapshot {
+    /// Save the bindings in every scope of `bindings`.
+    pub(crate) fn take(bindings: &BindingMap) -> JabroniResult<Self> {
+        let mut saver = Saver::default();
+        let scopes = bindings
+            .scopes()
+            .into_iter()
+            .rev()
+            .map(|scope| saver.save_bindings(scope))
+            .collect::<JabroniResult<_>>()?;
+        Ok(Self {
+            scopes,
+            objects: saver.objects,
+        })
+    }
+
+    /// Rebuild the bindings in each scope, outermost first. Functions defined by scripts are
+    /// defined again by `define`, and functions defined by the host are found in `natives`.
+    pub(crate) fn bindings(
+        &self,
+        natives: &[Subroutine],
+        define: impl Fn(&str) -> JabroniResult<Subroutine>,
+    ) -> JabroniResult<Vec<Vec<(String, Binding)>>> {
+        let natives: HashMap<_, _> = natives
+            .iter()
+            .filter_map(|native| Some((native.name()?, native)))
+            .collect();
+        let objects: Vec<_> = (0..self.objects.len())
+            .map(|_| Object::new(BindingMap::default()))
+            .collect();
+        let loader = Loader {
+            natives,
+            objects: &objects,
+            define,
+        };
+        for (object, properties) in objects.iter().zip(&self.objects) {
+            for (ident, binding) in loader.load_bindings(properties)? {
+                object.borrow_mut().set(ident, binding);
+            }
+        }
+        self.scopes
+            .iter()
+            .map(|scope| loader.load_bindings(scope))
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct Saver {
+    objects: Vec<Vec<SavedBinding>>,
+    /// Where each object that's been saved is in `objects`.
+    indices: HashMap<*const (), usize>,
+}
+
+impl Saver {
+    fn save_bindings<'a>(
+        &mut self,
+        bindings: impl IntoIterator<Item = (&'a str, &'a Binding)>,
+    ) -> JabroniResult<Vec<SavedBinding>> {
+        bindings
+            .into_iter()
+            .filter(|(_, binding)| binding.is_stored())
+            .map(|(ident, binding)| {
+                Ok(SavedBinding {
+                    ident: ident.into(),
+                    value: self.save_value(binding.value())?,
+                    mutable: binding.mutable(),
+                    read_only: binding.is_read_only(),
+                    hidden: binding.is_hidden(),
+                })
+            })
+            .collect()
+    }
+
+    fn save_value(&mut self, value: &Value) -> JabroniResult<SavedValue> {
+        Ok(match value {
+            Value::Number(number) => SavedValue::Number(*number),
+            Value::Boolean(boolean) => SavedValue::Boolean(*boolean),
+            Value::String(string) => SavedValue::String(string.to_string()),
+            Value::Bytes(bytes) => SavedValue::Bytes(bytes.clone()),
+            Value::Object(object) => SavedValue::Object(self.save_object(object)?),
+            Value::Subroutine(subroutine) => match (subroutine.definition(), subroutine.name()) {
+                (Some(definition), _) => SavedValue::Function(definition.into()),
+                (None, Some(name)) => SavedValue::Native(name.into()),
+                (None, None) => {
+                    return Err(snapshot_error(
+                        "Can't save a function defined by the host without a name".into(),
+                    ))
+                }
+            },
+            Value::Null => SavedValue::Null,
+        })
+    }
+
+    fn save_object(&mut self, object: &Object) -> JabroniResult<usize> {
+        if let Some(&index) = self.indices.get(&object.as_ptr()) {
+            return Ok(index);
+        }
+        let properties = object.borrow();
+        if properties.handler().is_some() {
+            return Err(snapshot_error("Can't save an object with a handler".into()));
+        }
+        // Reserve the object's place first, so objects that contain it refer back to it
+        let index = self.objects.len();
+        self.objects.push(Vec::new());
+        self.indices.insert(object.as_ptr(), index);
+        // Hidden properties are kept, and properties replace those they shadow
+        let scopes = properties.scopes().into_iter().rev().flatten();
+        self.objects[index] = self.save_bindings(scopes)?;
+        Ok(index)
+    }
+}
+
+struct Loader<'a, F> {
+    natives: HashMap<&'a str, &'a Subroutine>,
+    objects: &'a [Object],
+    define: F,
+}
+
+impl<F: Fn(&str) -> JabroniResult<Subroutine>> Loader<'_, F> {
+    fn load_bindings(&self, bindings: &[SavedBinding]) -> JabroniResult<Vec<(String, Binding)>> {
+        bindings
+            .iter()
+            .map(|saved| {
+                let mut binding = Binding::new(self.load_value(&saved.value)?, saved.mutable);
+                if saved.read_only {
+                    binding = binding.read_only();
+                }
+                if saved.hidden {
+                    binding = binding.hidden();
+                }
+                Ok((saved.ident.clone(), binding))
+            })
+            .collect()
+    }
+
+    fn load_value(&self, value: &SavedValue) -> JabroniResult<Value> {
+        Ok(match value {
+            SavedValue::Number(number) => Value::Number(*number),
+            SavedValue::Boolean(boolean) => Value::Boolean(*boolean),
+            SavedValue::String(string) => Value::String(string.as_str().into()),
+            SavedValue::Bytes(bytes) => Value::Bytes(bytes.clone()),
+            SavedValue::Object(index) => self
+                .objects
+                .get(*index)
+                .ok_or_else(|| snapshot_error(format!("Snapshot has no object {index}")))?
+                .clone()
+                .into(),
+            SavedValue::Function(definition) => (self.define)(definition)?.into(),
+            SavedValue::Native(name) => match self.natives.get(name.as_str()) {
+                Some(&native) => native.clone().into(),
+                None => {
+                    return Err(snapshot_error(format!(
+                        "No function named '{name}' to restore"
+                    )))
+                }
+            },
+            SavedValue::Null => Value::Null,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Jabroni;
+
+    fn round_trip(snapshot: &Snapshot) -> Snapshot {
+        serde_json::from_str(&serde_json::to_string(snapshot).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn restores_bindings() {
+        let log = Subroutine::new(1, Box::new(|_, _| Ok(Value::Null))).named("log");
+        let mut state = Jabroni::new();
+        state.define_constant("log", log.clone().into()).unwrap();
+        let mut config = BindingMap::default();
+        config.set(
+            "secret".into(),
+            Binding::constant("hunter2".into()).hidden(),
+        );
+        config.set("limit".into(), Binding::variable(5.into()).read_only());
+        let config = Value::from(config);
+        // Objects that contain themselves are saved as cycles
+        config
+            .as_object()
+            .unwrap()
+            .borrow_mut()
+            .set("self".into(), Binding::constant(config.clone()));
+        state.define_constant("config", config).unwrap();
+        state
+            .define_variable("blob", vec![0xca, 0xfe].into())
+            .unwrap();
+        state
+            .run_script(
+                "let counter = 1; const alias = config;\n\
+                 function next() {log(counter); return counter + config.limit;}",
+            )
+            .unwrap();
+        let snapshot = round_trip(&state.snapshot().unwrap());
+
+        let mut restored = Jabroni::new();
+        restored.restore(&snapshot, &[log]).unwrap();
+        assert_eq!(restored.run_expression("next()").unwrap(), 6.into());
+        assert_eq!(
+            restored.run_expression("blob").unwrap(),
+            vec![0xca, 0xfe].into()
+        );
+        assert!(restored.run_expression("counter = 2").is_ok());
+        assert!(restored.run_expression("alias = 2").is_err());
+        assert!(restored.run_expression("config.limit = 1").is_err());
+        assert_eq!(
+            restored.run_expression("config.self.secret").unwrap(),
+            "hunter2".into()
+        );
+        let (alias, config) = (
+            restored.run_expression("alias").unwrap(),
+            restored.run_expression("config.self").unwrap(),
+        );
+        assert!(alias
+            .as_object()
+            .unwrap()
+            .ptr_eq(config.as_object().unwrap()));
+        assert!(config
+            .as_object()
+            .unwrap()
+            .borrow()
+            .get("secret")
+            .unwrap()
+            .is_hidden());
+    }
+
+    #[test]
+    fn native_functions_need_names() {
+        let mut state = Jabroni::new();
+        let anonymous = Subroutine::new(0, Box::new(|_, _| Ok(Value::Null)));
+        state.define_constant("f", anonymous.into()).unwrap();
+        let error = state.snapshot().unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::Snapshot));
+
+        let mut state = Jabroni::new();
+        let named = Subroutine::new(0, Box::new(|_, _| Ok(Value::Null))).named("f");
+        state.define_constant("f", named.into()).unwrap();
+        let snapshot = state.snapshot().unwrap();
+        let error = Jabroni::new().restore(&snapshot, &[]).unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::Snapshot));
+    }
+}
@@ -1,506 +1,5850 @@
+#[cfg(feature = "tokio")]
+use crate::async_runtime::AsyncRuntime;
+#[cfg(feature = "jit")]
+use crate::jit::{HotFunction, Jit};
 use crate::{
+    ast,
+    audit::{AuditEvent, AuditLog},
     binding::{Binding, BindingMap},
-    errors::{JabroniError, JabroniResult},
-    value::Subroutine,
-    Value,
+    bytes,
+    channels::{Channels, Message},
+    compat,
+    coverage::{Collector, Coverage, Region},
+    debug::{Debugger, Pause, Session, Suspend},
+    diagnostics::{Diagnostic, Severity},
+    errors::{ErrorCode, Frame, JabroniError, JabroniResult, Location, Source},
+    events::Events,
+    module::ModuleLoader,
+    object::Object,
+    permissions::{Permission, Permissions},
+    profile::{Profile, Profiler},
+    reflect::{self, Description, Parameter},
+    sandbox::{Construct, Sandbox},
+    stats::RunStats,
+    template::Template,
+    trace::Tracer,
+    typecheck, utils,
+    value::{Args, Coercion, Equality, Operator, Subroutine},
+    warnings, Value,
+};
+use pest::{
+    error::{ErrorVariant, InputLocation, LineColLocation},
+    iterators::{Pair, Pairs},
+    Parser, Position, Span,
+};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    future::Future,
+    iter,
+    pin::Pin,
+    rc::Rc,
+    sync::{
+        mpsc::{Receiver, Sender},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Instant,
 };
-use pest::{iterators::Pair, Parser};
+
+mod tree;
 
 #[derive(Parser)]
 #[grammar = "jabroni.pest"]
-struct IdentParser;
+pub(crate) struct IdentParser;
 
-#[derive(Default)]
-pub struct Jabroni {
-    bindings: BindingMap,
-}
+type Resolver = Box<dyn Fn(&str) -> Option<Value>>;
 
-impl Jabroni {
-    pub fn new() -> Self {
-        Self::default()
+/// Evaluation of a statement or expression, which may be waiting on an async function.
+pub(crate) type Evaluation<'a> = Pin<Box<dyn Future<Output = JabroniResult<Value>> + 'a>>;
+
+type BodyPairs<'a> = Pairs<'a, Rule>;
+
+self_cell::self_cell!(
+    /// The statements of a function defined by a script, parsed once when the function is defined
+    /// so calls don't parse them again. The parsed statements borrow the text they're kept with.
+    struct FunctionBody {
+        owner: Rc<str>,
+
+        #[covariant]
+        dependent: BodyPairs,
     }
+);
 
-    pub fn define_constant(&mut self, ident: &str, value: Value) -> JabroniResult {
-        self.define_binding(ident, value, false)
+impl FunctionBody {
+    fn parse(code: &str) -> JabroniResult<Self> {
+        Self::try_new(code.into(), |code| parse_pairs(Rule::jabroni_script, code))
     }
 
-    pub fn define_variable(&mut self, ident: &str, value: Value) -> JabroniResult {
-        self.define_binding(ident, value, true)
+    fn pairs(&self) -> Pairs<'_, Rule> {
+        self.borrow_dependent().clone()
     }
+}
 
-    pub fn update_variable(&mut self, ident: &str, value: Value) -> JabroniResult {
-        self.bindings.get_mut(ident)?.set_value(value)?;
+/// Where an expression leads, which can be read, assigned to or called.
+enum Place<'a> {
+    /// A binding in scope, by name.
+    Ident(Pair<'a, Rule>),
+    /// A property of an object.
+    Member(Object, Pair<'a, Rule>),
+    /// A value that isn't kept anywhere, such as what a call returned.
+    Value(Value),
+}
 
-        Ok(())
+/// Describe an error from the parser.
+fn parse_error_message(error: &pest::error::Error<Rule>) -> String {
+    match &error.variant {
+        ErrorVariant::ParsingError { positives, .. } if !positives.is_empty() => {
+            let expected: Vec<_> = positives
+                .iter()
+                // A shebang is only allowed at the start, and isn't what anyone means to write
+                .filter(|rule| **rule != Rule::shebang)
+                .map(|rule| match rule {
+                    // Every expression starts with one, so the parser names it when it can't
+                    // find an expression
+                    Rule::postfix => "expression".into(),
+                    rule => format!("{:?}", rule),
+                })
+                .collect();
+            format!("Expected {}", expected.join(" or "))
+        }
+        ErrorVariant::ParsingError { .. } => "Unexpected input".into(),
+        ErrorVariant::CustomError { message } => message.clone(),
     }
+}
 
-    fn define_binding(&mut self, ident: &str, value: Value, mutable: bool) -> JabroniResult {
-        let ident = ident.to_string();
-        if self.bindings.has_on_top(&ident) {
-            return Err(JabroniError::DoubleDefinition(format!(
-                "Cannot define '{ident}' because it has already been defined"
-            )));
-        }
-        self.bindings.set(ident, Binding::new(value, mutable));
-        Ok(())
+/// Convert an error from the parser.
+pub(crate) fn parse_error(error: pest::error::Error<Rule>) -> JabroniError {
+    let (line, column) = match error.line_col {
+        LineColLocation::Pos(position) => position,
+        LineColLocation::Span(start, _) => start,
+    };
+    JabroniError::from_code(ErrorCode::Syntax, parse_error_message(&error))
+        .located(|| Location { line, column }, 0)
+}
+
+/// Parse code, failing before the parser runs if it nests too deeply to parse without
+/// overflowing the stack.
+pub(crate) fn parse_pairs(rule: Rule, code: &str) -> JabroniResult<Pairs<'_, Rule>> {
+    if let Some(offset) = utils::nesting_overflow(code) {
+        let (line, column) = Position::new(code, offset).unwrap().line_col();
+        return Err(nesting_error().located(|| Location { line, column }, 1));
     }
+    IdentParser::parse(rule, code).map_err(parse_error)
+}
 
-    pub fn run_expression(&mut self, code: &str) -> JabroniResult<Value> {
-        let mut pairs = IdentParser::parse(Rule::jabroni_expression, code)
-            .map_err(|e| JabroniError::Parse(format!("{}", e)))?;
+fn nesting_error() -> JabroniError {
+    JabroniError::from_code(
+        ErrorCode::NestingTooDeep,
+        format!(
+            "Code is nested more than {} levels deep",
+            utils::MAX_NESTING
+        ),
+    )
+}
 
-        self.interpret_expression(pairs.next().unwrap())
+/// Get the expression an operator rule stands for. The grammar matches an operand with no
+/// operator as that operator's rule with a single child, so it never has to parse the operand
+/// twice.
+pub(crate) fn collapse(mut pair: Pair<Rule>) -> Pair<Rule> {
+    while matches!(
+        pair.as_rule(),
+        Rule::assignment
+            | Rule::ternary
+            | Rule::comparison
+            | Rule::inequality
+            | Rule::sum
+            | Rule::product
+            | Rule::postfix
+    ) {
+        let mut inner = pair.clone().into_inner();
+        let operand = inner.next().unwrap();
+        if inner.next().is_some() {
+            break;
+        }
+        pair = operand;
     }
+    pair
+}
 
-    pub fn run_script(&mut self, code: &str) -> JabroniResult<Value> {
-        let pairs = IdentParser::parse(Rule::jabroni_script, code)
-            .map_err(|e| JabroniError::Parse(format!("{}", e)))?;
+/// Get the location of the start of `span`, in a script that starts at `origin`.
+fn locate(span: &Span, origin: Option<Location>) -> Location {
+    locate_position(span.start_pos(), origin)
+}
 
-        let mut value = Value::Null;
-        for pair in pairs {
-            match pair.as_rule() {
-                Rule::statement => {
-                    value = self.interpret_statement(pair)?;
-                }
-                Rule::EOI => (),
-                _ => panic!("Unexpected rule found while running script"),
+fn locate_position(position: Position, origin: Option<Location>) -> Location {
+    let (line, column) = position.line_col();
+    match origin {
+        Some(origin) if line == 1 => Location {
+            line: origin.line,
+            column: origin.column + column - 1,
+        },
+        Some(origin) => Location {
+            line: origin.line + line - 1,
+            column,
+        },
+        None => Location { line, column },
+    }
+}
+
+/// Make the object a `catch` block sees for an error, with its `name`, `message` and `stack`,
+/// and the properties of a thrown object or the `value` that was thrown.
+fn error_object(error: &JabroniError) -> Value {
+    let stack: Vec<_> = error
+        .stack()
+        .iter()
+        .map(|frame| frame.to_string())
+        .collect();
+    let mut object = BindingMap::default();
+    for (ident, value) in [
+        ("name", error.kind().name().to_string()),
+        ("message", error.message().to_string()),
+        ("stack", stack.join("\n")),
+    ] {
+        object.set(ident.into(), Binding::constant(Value::String(value.into())));
+    }
+    match error.payload() {
+        Some(Value::Object(payload)) => {
+            for (ident, binding) in payload.borrow().iter() {
+                object.set(ident.into(), binding.clone());
             }
         }
-        Ok(value)
+        Some(value) => object.set("value".into(), Binding::constant(value)),
+        None => (),
     }
+    Value::Object(object.into())
+}
 
-    fn interpret_lvalue<'a>(
-        pair: Pair<Rule>,
-        bindings: &'a mut BindingMap,
-    ) -> JabroniResult<&'a mut Binding> {
-        match pair.as_rule() {
-            Rule::ident => bindings.get_mut(pair.as_str()),
-            Rule::kernel => Self::interpret_lvalue(pair.into_inner().next().unwrap(), bindings),
-            Rule::member_access => {
-                let mut pair = pair.into_inner();
-                let object: &mut BindingMap = bindings
-                    .get_mut(pair.next().unwrap().as_str())?
-                    .value_mut()
-                    .as_object_mut()
-                    .ok_or_else(|| JabroniError::Type("Not an object".into()))?;
-                Self::interpret_lvalue(pair.next().unwrap(), object)
+/// A definition that couldn't be applied when reloading a script.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReloadConflict {
+    /// A declaration changed between `const` and `let`. The existing binding was kept.
+    DeclarationKind(String),
+    /// A declaration's value changed type. The existing value was kept.
+    Type(String),
+    /// A function has the same name as a binding that isn't a function, so it wasn't defined.
+    NotAFunction(String),
+}
+
+/// The outcome of resuming a [`Resumable`] script.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// The script yielded a value, and can be resumed.
+    Yielded(Value),
+    /// The script finished, with the value of its last statement.
+    Complete(Value),
+}
+
+/// A script that suspends itself at each `yield` statement, started with
+/// [`Jabroni::run_resumable`].
+pub struct Resumable<'a> {
+    evaluation: Option<Evaluation<'a>>,
+    runtime: Rc<RefCell<Runtime>>,
+}
+
+impl Resumable<'_> {
+    /// Run the script until it yields or finishes.
+    pub fn resume(&mut self) -> JabroniResult<Step> {
+        let evaluation = self.evaluation.as_mut().ok_or_else(|| {
+            JabroniError::from_code(
+                ErrorCode::ScriptFinished,
+                "Cannot resume a script that has finished".into(),
+            )
+        })?;
+        self.runtime.borrow_mut().yielded = None;
+        match utils::poll(evaluation.as_mut()) {
+            Poll::Ready(result) => {
+                self.evaluation = None;
+                result.map(Step::Complete)
             }
-            _ => Err(JabroniError::Parse(format!(
-                "Cannot make out lvalue expression: {}",
-                pair.as_str()
-            ))),
+            Poll::Pending => match self.runtime.borrow_mut().yielded.take() {
+                Some(value) => Ok(Step::Yielded(value)),
+                None => Err(JabroniError::from_code(
+                    ErrorCode::CannotSuspend,
+                    "Cannot wait for an asynchronous function in a resumable script".into(),
+                )),
+            },
         }
     }
 
-    fn interpret_expression(&mut self, pair: Pair<Rule>) -> JabroniResult<Value> {
-        match pair.as_rule() {
-            Rule::ident => Ok(self.bindings.get(pair.as_str())?.value().clone()),
-            Rule::member_access => {
-                let lvalue = Self::interpret_lvalue(pair, &mut self.bindings)?;
-                Ok(lvalue.value().clone())
-            }
-
-            Rule::function_call => {
-                let mut pair = pair.into_inner();
-                let subroutine = Self::interpret_lvalue(pair.next().unwrap(), &mut self.bindings)?
-                    .value()
-                    .as_subroutine()
-                    .ok_or_else(|| JabroniError::Type("Not a function".into()))?
-                    .clone();
-
-                let mut args = Vec::new();
-                for arg in pair {
-                    args.push(self.interpret_expression(arg)?);
-                }
+    /// Whether the script has finished.
+    pub fn is_complete(&self) -> bool {
+        self.evaluation.is_none()
+    }
+}
+
+/// The outcome of stepping an [`Incremental`] script.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Progress {
+    /// The script used up its budget, or yielded, and can be stepped again.
+    Pending,
+    /// The script finished, with the value of its last statement.
+    Done(Value),
+}
+
+/// A script that runs a limited number of statements at a time, started with
+/// [`Jabroni::run_incremental`].
+pub struct Incremental<'a> {
+    evaluation: Option<Evaluation<'a>>,
+    runtime: Rc<RefCell<Runtime>>,
+}
 
-                subroutine.call(self.bindings.new_context(), &mut args)
+impl Incremental<'_> {
+    /// Run the script for up to `budget` statements, or until it yields or finishes. Values
+    /// passed to `yield` are discarded.
+    pub fn step(&mut self, budget: usize) -> JabroniResult<Progress> {
+        let evaluation = self.evaluation.as_mut().ok_or_else(|| {
+            JabroniError::from_code(
+                ErrorCode::ScriptFinished,
+                "Cannot step a script that has finished".into(),
+            )
+        })?;
+        {
+            let mut runtime = self.runtime.borrow_mut();
+            runtime.yielded = None;
+            runtime.budget = Some(budget);
+        }
+        match utils::poll(evaluation.as_mut()) {
+            Poll::Ready(result) => {
+                self.evaluation = None;
+                self.runtime.borrow_mut().budget = None;
+                result.map(Progress::Done)
             }
-            Rule::ternary => {
-                let mut pair = pair.into_inner();
-                let condition = self.interpret_expression(pair.next().unwrap())?;
-                match condition {
-                    Value::Boolean(condition) => {
-                        if !condition {
-                            pair.next().unwrap();
-                        }
-                        self.interpret_expression(pair.next().unwrap())
-                    }
-                    _ => Err(JabroniError::Type(
-                        "Ternary condition must be boolean".into(),
+            Poll::Pending => {
+                let mut runtime = self.runtime.borrow_mut();
+                match (runtime.budget, runtime.yielded.take()) {
+                    (Some(0), _) | (_, Some(_)) => Ok(Progress::Pending),
+                    _ => Err(JabroniError::from_code(
+                        ErrorCode::CannotSuspend,
+                        "Cannot wait for an asynchronous function in an incremental script".into(),
                     )),
                 }
             }
-            Rule::string_literal => return Value::from_string_literal(pair.as_str()),
-            Rule::numeric_literal => return Value::from_numeric_literal(pair.as_str()),
-            Rule::boolean_literal => {
-                return Value::from_boolean_literal(pair.as_str());
-            }
-            Rule::null_literal => Ok(Value::Null),
-            Rule::expression => {
-                return self.interpret_expression(pair.into_inner().next().unwrap());
-            }
-            Rule::assignment => {
-                let mut pairs = pair.into_inner();
-                let lhs = pairs.next().unwrap();
-                let operator = pairs.next().unwrap();
-                let operator = operator.as_str();
-                let operand = self.interpret_expression(pairs.next().unwrap())?;
-                if operator == "=" {
-                    Self::interpret_lvalue(lhs, &mut self.bindings)?.set_value(operand)?;
-                } else {
-                    unimplemented!("Unimplemented assignment operator: {}", operator);
-                }
-                // Assignment return void because we don't want to accidentally assign while trying
-                // to compare
-                Ok(Value::Null)
-            }
-            Rule::comparison | Rule::inequality | Rule::sum | Rule::product => {
-                let mut pairs = pair.into_inner();
-                let mut value = self.interpret_expression(pairs.next().unwrap())?;
-                while let Some(operator) = pairs.next() {
-                    let operator = operator.as_str();
-                    let operand = self.interpret_expression(pairs.next().unwrap())?;
-                    if operator == "==" {
-                        value.compare(operand, false)?;
-                    } else if operator == "!=" {
-                        value.compare(operand, false)?;
-                        value.inverse()?;
-                    } else if operator == "===" {
-                        value.compare(operand, true)?;
-                    } else if operator == "!==" {
-                        value.compare(operand, true)?;
-                        value.inverse()?;
-                    } else if operator == ">" {
-                        value.compare_inequality(operand, &|a, b| a > b)?;
-                    } else if operator == ">=" {
-                        value.compare_inequality(operand, &|a, b| a >= b)?;
-                    } else if operator == "<" {
-                        value.compare_inequality(operand, &|a, b| a < b)?;
-                    } else if operator == "<=" {
-                        value.compare_inequality(operand, &|a, b| a <= b)?;
-                    } else if operator == "+" {
-                        value.add(operand)?;
-                    } else if operator == "-" {
-                        value.subtract(operand)?;
-                    } else if operator == "*" {
-                        value.multiply(operand)?;
-                    } else {
-                        unimplemented!("Unimplemented operator: {}", operator);
-                    }
-                }
-                Ok(value)
-            }
-            _ => {
-                unimplemented!("Unimplemented expression rule: {:?}", pair.as_rule());
-            }
         }
     }
 
-    fn interpret_statement(&mut self, pair: Pair<Rule>) -> JabroniResult<Value> {
-        match pair.as_rule() {
-            Rule::expression => {
-                self.interpret_expression(pair)?;
-            }
-            Rule::statement => return self.interpret_statement(pair.into_inner().next().unwrap()),
-            Rule::block_statement => {
-                let mut value = Value::Null;
-                for pair in pair.into_inner() {
-                    value = self.interpret_statement(pair)?;
-                }
-                return Ok(value);
-            }
-            Rule::function_statement => {
-                let mut pair = pair.into_inner();
+    /// Whether the script has finished.
+    pub fn is_complete(&self) -> bool {
+        self.evaluation.is_none()
+    }
+}
 
-                let function_name = pair.next().unwrap();
-                let mut params = Vec::new();
-                for param in pair.next().unwrap().into_inner() {
-                    params.push(param.as_str().to_string());
-                }
-                let num_args = params.len();
-
-                let body = pair.next().unwrap().as_str().to_string();
-                let callback =
-                    move |mut context: BindingMap, args: &mut [Value]| -> JabroniResult<Value> {
-                        // Copy params/args (WARN: currently pass by value only)
-                        for (param, arg) in params.iter().zip(args.iter_mut()) {
-                            context.set(param.into(), Binding::constant(arg.clone()));
-                        }
-                        let mut substate = Jabroni { bindings: context };
+impl Drop for Incremental<'_> {
+    fn drop(&mut self) {
+        self.runtime.borrow_mut().budget = None;
+    }
+}
 
-                        substate.run_script(body.as_str())
-                    };
-                let subroutine = Subroutine::new(num_args, Box::new(callback));
-                self.bindings.set(
-                    function_name.as_str().into(),
-                    Binding::constant(Value::Subroutine(subroutine)),
-                );
-            }
-            Rule::throw_statement => {
-                return Err(JabroniError::Exception(format!(
-                    "{}",
-                    self.interpret_expression(pair.into_inner().next().unwrap())?
-                )))
-            }
-            Rule::return_statement => {
-                return self.interpret_expression(pair.into_inner().next().unwrap());
-            }
-            Rule::declaration_statement => {
-                let mut pair = pair.into_inner();
-                let kind = pair.next().unwrap().as_str();
-                let ident = pair.next().unwrap().as_str();
-                let expression = pair.next().unwrap();
-                let value = self.interpret_expression(expression)?;
-                if kind == "const" {
-                    self.define_constant(ident, value)?;
-                } else {
-                    self.define_variable(ident, value)?;
-                }
-            }
-            _ => {
-                unimplemented!("Unimplemented statement rule: {:?}", pair.as_rule());
+/// Suspends evaluation once, handing a value to whoever's polling.
+struct Yield {
+    value: Option<Value>,
+    runtime: Rc<RefCell<Runtime>>,
+}
+
+impl Future for Yield {
+    type Output = JabroniResult<Value>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.value.take() {
+            Some(value) => {
+                self.runtime.borrow_mut().yielded = Some(value);
+                // Async executors should treat this like any other yield to the scheduler
+                context.waker().wake_by_ref();
+                Poll::Pending
             }
+            None => Poll::Ready(Ok(Value::Null)),
         }
-        Ok(Value::Null)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Interpreter-wide state, shared with the functions an interpreter defines.
+#[derive(Default)]
+pub(crate) struct Runtime {
+    resolver: Option<Resolver>,
+    module_loader: Option<Rc<dyn ModuleLoader>>,
+    /// Exports of modules that have been evaluated.
+    modules: HashMap<String, BindingMap>,
+    /// Modules currently being evaluated, to detect circular imports.
+    loading: HashSet<String>,
+    /// The value passed to the `yield` statement that last suspended evaluation.
+    pub(crate) yielded: Option<Value>,
+    /// How many more statements an [`Incremental`] script can run before it's suspended.
+    budget: Option<usize>,
+    permissions: Permissions,
+    /// Whether nondeterministic functions are refused.
+    deterministic: bool,
+    /// Whether scripts are kept from changing the global scope.
+    globals_frozen: bool,
+    /// Whether only pure functions can be called, while running [`Jabroni::evaluate_pure`].
+    pure: bool,
+    coercion: Coercion,
+    equality: Equality,
+    sandbox: Sandbox,
+    /// Resources used by the current or last run.
+    stats: RunStats,
+    /// Whether [`RunStats::peak_memory`] is measured, which means walking every binding after
+    /// each statement.
+    measure_memory: bool,
+    /// Warnings from the current or last run.
+    diagnostics: Vec<Diagnostic>,
+    tracer: Option<Box<dyn Tracer>>,
+    /// The debugging session, if a script is being debugged.
+    pub(crate) debug: Option<Session>,
+    profiler: Option<Profiler>,
+    coverage: Option<Collector>,
+    audit: Option<AuditLog>,
+    /// Where timers and spawned tasks run, once installed.
+    #[cfg(feature = "tokio")]
+    async_runtime: Option<AsyncRuntime>,
+    /// How many function calls are being evaluated, each inside the last.
+    depth: usize,
+    /// The most `depth` can be, if not the default.
+    max_depth: Option<usize>,
+    /// Functions compiled to native code, while the JIT is on.
+    #[cfg(feature = "jit")]
+    jit: Option<Jit>,
+}
 
-    #[test]
-    fn simple_expressions() {
-        let mut state = Jabroni::new();
-        assert_eq!(state.run_expression("4").unwrap(), 4.into());
-        assert_eq!(state.run_expression("2+2").unwrap(), 4.into());
-        assert_eq!(state.run_expression("3*10-5").unwrap(), 25.into());
-        assert_eq!(state.run_expression("1+(10-7)*3").unwrap(), 10.into());
-        assert_eq!(state.run_expression("2+2==4").unwrap(), true.into());
-        assert_eq!(state.run_expression("2+2!=4").unwrap(), false.into());
-        assert_eq!(state.run_expression("2+2==5").unwrap(), false.into());
-        assert_eq!(state.run_expression("2+2!=5").unwrap(), true.into());
-        assert_eq!(state.run_expression("2+2==4==false").unwrap(), false.into());
-        assert_eq!(state.run_expression("true==true").unwrap(), true.into());
-        assert_eq!(state.run_expression("1==1?100:200").unwrap(), 100.into());
-        assert_eq!(state.run_expression("1==2?100:200").unwrap(), 200.into());
-        assert_eq!(state.run_expression("8===8").unwrap(), true.into());
-        assert_eq!(state.run_expression("null===null").unwrap(), true.into());
-        assert_eq!(state.run_expression("null===0").unwrap(), false.into());
-        assert_eq!(state.run_expression("4>3").unwrap(), true.into());
-        assert_eq!(state.run_expression("4>=3").unwrap(), true.into());
-        assert_eq!(state.run_expression("4>=4").unwrap(), true.into());
-        assert_eq!(state.run_expression("4<=4").unwrap(), true.into());
-        assert_eq!(state.run_expression("4<4").unwrap(), false.into());
-    }
+/// How deeply function calls can nest by default. The stack grows as evaluation nests, so this
+/// only bounds how much memory runaway recursion takes before it fails.
+const DEFAULT_MAX_DEPTH: usize = 4096;
 
-    #[test]
-    fn forbid_type_mismatch() {
-        let mut state = Jabroni::new();
-        state.define_variable("x", Value::Number(4)).unwrap();
-        assert!(state.run_expression("4==false").is_err());
-        assert!(state.run_expression("true==4").is_err());
-        assert!(state.run_expression("x=true").is_err());
-        // assignments return void
-        assert!(state.run_expression("4==(x=4)").is_err());
+/// Counts a function call for as long as it's held, so runaway recursion fails instead of
+/// exhausting memory.
+struct DepthGuard(Rc<RefCell<Runtime>>);
+
+impl DepthGuard {
+    fn enter(runtime: &Rc<RefCell<Runtime>>) -> JabroniResult<Self> {
+        let mut borrowed = runtime.borrow_mut();
+        let max_depth = borrowed.max_depth.unwrap_or(DEFAULT_MAX_DEPTH);
+        if borrowed.depth >= max_depth {
+            return Err(JabroniError::from_code(
+                ErrorCode::RecursionTooDeep,
+                format!("Function calls nested more than {max_depth} levels deep"),
+            ));
+        }
+        borrowed.depth += 1;
+        Ok(Self(runtime.clone()))
     }
+}
 
-    #[test]
-    fn constants() {
-        let mut state = Jabroni::new();
-        state.define_constant("FOO", Value::Number(42)).unwrap();
-        assert_eq!(state.run_expression("FOO").unwrap(), Value::Number(42));
-        assert_eq!(state.run_expression("FOO*10").unwrap(), Value::Number(420));
-        // Constants can't be modified
-        assert!(state.run_expression("FOO=8").is_err());
-        // Cannot be defined multiple times
-        assert!(state.define_constant("FOO", Value::Number(42)).is_err());
-        assert!(state.define_variable("FOO", Value::Number(42)).is_err());
-        // Cannot be updated
-        assert!(state.update_variable("FOO", Value::Number(42)).is_err());
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        if let Ok(mut runtime) = self.0.try_borrow_mut() {
+            runtime.depth -= 1;
+        }
     }
+}
 
-    #[test]
-    fn variables() {
-        let mut state = Jabroni::new();
-        state.define_variable("foo", Value::Number(42)).unwrap();
+#[derive(Default)]
+pub struct Jabroni {
+    bindings: BindingMap,
+    runtime: Rc<RefCell<Runtime>>,
+    /// Holds the last binding produced by the resolver, so it can be borrowed like any other.
+    resolved: Option<Binding>,
+    events: Events,
+    channels: Channels,
+    /// Functions defined by each script loaded through [`Jabroni::reload_script`].
+    scripts: HashMap<String, HashSet<String>>,
+    /// The script being run, to attribute errors to.
+    source: Option<Arc<Source>>,
+    /// Name of the function being run, if it's a function body.
+    function: Option<Rc<str>>,
+    /// Where the code being run starts in its script, if it's not the whole script, such as a
+    /// function body.
+    origin: Option<Location>,
+}
 
-        assert_eq!(state.run_expression("foo").unwrap(), Value::Number(42));
+impl Jabroni {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        // Assign
-        state.run_expression("foo=420").unwrap();
-        assert_eq!(state.run_expression("foo").unwrap(), Value::Number(420));
+    /// Construct an interpreter that can use `bindings`, with a scope of its own above them for
+    /// what scripts declare.
+    #[cfg(feature = "serde")]
+    pub(crate) fn with_bindings(mut bindings: BindingMap) -> Self {
+        bindings.push_scope();
+        Self {
+            bindings,
+            ..Default::default()
+        }
+    }
 
-        // Try to define twice
-        assert!(state.define_variable("foo", Value::Number(42)).is_err());
-        assert!(state.define_constant("foo", Value::Number(42)).is_err());
+    /// Set a function to supply values for identifiers that aren't bound, instead of raising a
+    /// reference error. Resolved values are constant.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Jabroni, Value as JabroniValue};
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.set_resolver(|ident| {
+    ///     ident
+    ///         .strip_prefix("field")
+    ///         .and_then(|n| n.parse().ok())
+    ///         .map(JabroniValue::Number)
+    /// });
+    /// assert_eq!(interpreter.run_expression("field1 + field2").unwrap(), 3.into());
+    /// assert!(interpreter.run_expression("other").is_err());
+    /// ```
+    pub fn set_resolver(&mut self, resolver: impl Fn(&str) -> Option<Value> + 'static) {
+        self.runtime.borrow_mut().resolver = Some(Box::new(resolver));
+    }
 
-        // Update
-        state.update_variable("foo", Value::Number(16)).unwrap();
-        assert_eq!(state.run_expression("foo").unwrap(), Value::Number(16));
+    /// Set the loader used to resolve `import` statements.
+    pub fn set_module_loader(&mut self, loader: impl ModuleLoader + 'static) {
+        self.runtime.borrow_mut().module_loader = Some(Rc::new(loader));
     }
 
-    #[test]
-    fn strings() {
-        let mut state = Jabroni::new();
-        state
-            .define_variable("foo", Value::String("Hello World!".into()))
-            .unwrap();
+    /// Set the capabilities granted to scripts. Functions wrapped with [`Jabroni::guard`] check
+    /// them before each call.
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        self.runtime.borrow_mut().permissions = permissions;
+    }
 
-        state.run_expression("foo='Shut up!'").unwrap();
-        assert_eq!(
-            state.run_expression("foo").unwrap(),
-            Value::String("Shut up!".into())
-        );
+    /// Get the capabilities granted to scripts.
+    pub fn permissions(&self) -> Permissions {
+        self.runtime.borrow().permissions.clone()
+    }
 
-        state.run_expression("foo='\\''").unwrap();
-        assert_eq!(
-            state.run_expression("foo").unwrap(),
-            Value::String("\'".into())
-        );
+    /// Set whether runs must be reproducible, so the same scripts always give the same results.
+    /// A deterministic interpreter refuses to define functions marked
+    /// [nondeterministic](Subroutine::nondeterministic), including ones guarded by the
+    /// [`Time`](Permission::Time) or [`Random`](Permission::Random) permissions, failing with a
+    /// [`Nondeterministic`](ErrorCode::Nondeterministic) error. Turning it on fails if one is
+    /// already defined.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{errors::ErrorCode, Jabroni, Permission, Subroutine, Value as JabroniValue};
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.set_deterministic(true).unwrap();
+    /// let now = Subroutine::new(0, Box::new(|_, _| Ok(JabroniValue::Number(1234))));
+    /// let now = interpreter.guard(Permission::Time, now);
+    /// let error = interpreter
+    ///     .define_constant("now", JabroniValue::Subroutine(now))
+    ///     .unwrap_err();
+    /// assert_eq!(error.code(), Some(ErrorCode::Nondeterministic));
+    /// ```
+    pub fn set_deterministic(&mut self, deterministic: bool) -> JabroniResult {
+        if deterministic {
+            for (_, binding) in self.bindings.iter() {
+                Self::check_deterministic(binding.value())?;
+            }
+        }
+        self.runtime.borrow_mut().deterministic = deterministic;
+        Ok(())
+    }
 
-        state.run_expression("foo=\"Hello!\"").unwrap();
-        assert_eq!(
-            state.run_expression("foo").unwrap(),
-            Value::String("Hello!".into())
-        );
+    /// Check if runs must be reproducible.
+    pub fn is_deterministic(&self) -> bool {
+        self.runtime.borrow().deterministic
+    }
 
-        state.run_expression("foo='\\n\\t\\r'").unwrap();
-        assert_eq!(
-            state.run_expression("foo").unwrap(),
-            Value::String("\n\t\r".into())
-        );
+    /// Set whether scripts are kept from changing the global scope, which holds what the host
+    /// defines. While it's frozen, each script runs in a scope of its own, so what it declares at
+    /// its top level is forgotten when it finishes, and assigning to a global fails with a
+    /// [`FrozenGlobal`](ErrorCode::FrozenGlobal) error. Scripts can still call functions and
+    /// change the properties of global objects, unless the properties are read-only. The host
+    /// can still define and update globals.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{errors::ErrorCode, Jabroni};
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.define_variable("rate", 3.into()).unwrap();
+    /// interpreter.run_script("function cost(n) {return n * rate;}").unwrap();
+    /// interpreter.set_globals_frozen(true);
+    ///
+    /// assert_eq!(
+    ///     interpreter.run_script("let n = 4; return cost(n);").unwrap(),
+    ///     12.into()
+    /// );
+    /// assert!(interpreter.run_expression("n").is_err());
+    /// let error = interpreter.run_expression("rate = 0").unwrap_err();
+    /// assert_eq!(error.code(), Some(ErrorCode::FrozenGlobal));
+    /// ```
+    pub fn set_globals_frozen(&mut self, frozen: bool) {
+        self.runtime.borrow_mut().globals_frozen = frozen;
     }
 
-    #[test]
-    fn objects() {
-        let mut state = Jabroni::new();
+    /// Check if scripts are kept from changing the global scope.
+    pub fn globals_frozen(&self) -> bool {
+        self.runtime.borrow().globals_frozen
+    }
 
-        let mut object = BindingMap::default();
-        object.set("bar".into(), Binding::variable(Value::Number(8)));
-        object.set("baz".into(), Binding::constant(Value::Number(42)));
-        let object = Value::Object(object);
-        state.define_variable("foo", object.clone()).unwrap();
+    /// Fail if globals are frozen and a script is about to declare a global.
+    fn check_global_declaration(&self, ident: &str) -> JabroniResult {
+        if self.globals_frozen() && self.bindings.in_outermost_scope() {
+            return Err(JabroniError::from_code(
+                ErrorCode::FrozenGlobal,
+                format!("Cannot declare global '{ident}' because globals are frozen"),
+            ));
+        }
+        Ok(())
+    }
 
-        assert_eq!(state.run_expression("foo.bar").unwrap(), Value::Number(8));
-        assert_eq!(state.run_expression("foo.baz").unwrap(), Value::Number(42));
+    /// Fail if a value is or holds a nondeterministic function.
+    fn check_deterministic(value: &Value) -> JabroniResult {
+        match value.find_nondeterministic() {
+            Some(subroutine) => Err(JabroniError::from_code(
+                ErrorCode::Nondeterministic,
+                format!(
+                    "Cannot define nondeterministic function{} in a deterministic interpreter",
+                    subroutine
+                        .name()
+                        .map(|name| format!(" '{name}'"))
+                        .unwrap_or_default()
+                ),
+            )),
+            None => Ok(()),
+        }
+    }
 
-        state.run_expression("foo.bar=0").unwrap();
-        assert!(state.run_expression("foo.baz=1").is_err());
+    /// Set how strictly scripts treat values of the wrong type. Coercion is strict by default.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Coercion, Jabroni};
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.define_variable("name", "".into()).unwrap();
+    /// assert!(interpreter.run_expression("name ? name : 'anonymous'").is_err());
+    ///
+    /// interpreter.set_coercion(Coercion::Loose);
+    /// assert_eq!(
+    ///     interpreter.run_expression("name ? name : 'anonymous'").unwrap(),
+    ///     "anonymous".into()
+    /// );
+    /// assert_eq!(interpreter.run_expression("'5' == 5").unwrap(), true.into());
+    /// ```
+    pub fn set_coercion(&mut self, coercion: Coercion) {
+        self.runtime.borrow_mut().coercion = coercion;
+    }
 
-        assert_eq!(state.run_expression("foo.bar").unwrap(), Value::Number(0));
-        assert_eq!(state.run_expression("foo.baz").unwrap(), Value::Number(42));
+    /// Get how strictly scripts treat values of the wrong type.
+    pub fn coercion(&self) -> Coercion {
+        self.runtime.borrow().coercion
     }
 
-    #[test]
-    fn object_method() {
-        fn bar(_: BindingMap, _: &mut [Value]) -> JabroniResult<Value> {
-            Ok(Value::Number(42))
-        }
+    /// Set which equality operators scripts can use. All of them can be used by default.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{errors::ErrorCode, Equality, Jabroni};
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.set_equality(Equality::StrictOnly);
+    /// assert_eq!(interpreter.run_expression("1 === 1").unwrap(), true.into());
+    /// let error = interpreter.run_expression("1 == 1").unwrap_err();
+    /// assert_eq!(error.code(), Some(ErrorCode::LooseEquality));
+    /// ```
+    pub fn set_equality(&mut self, equality: Equality) {
+        self.runtime.borrow_mut().equality = equality;
+    }
 
-        let mut state = Jabroni::new();
-        let mut object = BindingMap::default();
-        object.set(
-            "bar".into(),
-            Binding::constant(Value::Subroutine(Subroutine::new(0, Box::new(bar)))),
-        );
+    /// Get which equality operators scripts can use.
+    pub fn equality(&self) -> Equality {
+        self.runtime.borrow().equality
+    }
 
-        let object = Value::Object(object);
-        state.define_variable("foo", object.clone()).unwrap();
+    /// Set which identifiers and constructs scripts can use. Scripts and expressions are checked
+    /// before they run, failing with a [`Sandboxed`](ErrorCode::Sandboxed) error if they use
+    /// anything the sandbox denies. Everything is allowed by default.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{errors::ErrorCode, sandbox::Sandbox, Jabroni};
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.define_constant("price", 12.into()).unwrap();
+    /// interpreter.set_sandbox(Sandbox::formula().allow_only(["price", "quantity"]));
+    /// assert_eq!(interpreter.run_expression("price * 2").unwrap(), 24.into());
+    /// let error = interpreter.run_script("price = 0;").unwrap_err();
+    /// assert_eq!(error.code(), Some(ErrorCode::Sandboxed));
+    /// ```
+    pub fn set_sandbox(&mut self, sandbox: Sandbox) {
+        self.runtime.borrow_mut().sandbox = sandbox;
+    }
 
-        assert_eq!(state.run_expression("foo.bar()").unwrap(), 42.into());
+    /// Get which identifiers and constructs scripts can use.
+    pub fn sandbox(&self) -> Sandbox {
+        self.runtime.borrow().sandbox.clone()
     }
 
-    #[test]
-    fn call_rust_function() {
-        let mut state = Jabroni::new();
-        fn foo(_: BindingMap, _: &mut [Value]) -> JabroniResult<Value> {
-            Ok(Value::Number(42))
+    /// Fail if code uses anything the sandbox denies.
+    fn check_sandbox(&self, code: &str, expression: bool) -> JabroniResult {
+        let runtime = self.runtime.borrow();
+        match (runtime.sandbox.is_open(), expression) {
+            (true, _) => Ok(()),
+            (false, true) => runtime.sandbox.check_expression(code),
+            (false, false) => runtime.sandbox.check(code),
         }
-        state
-            .define_constant("foo", Value::Subroutine(Subroutine::new(0, Box::new(foo))))
-            .unwrap();
-        assert_eq!(state.run_expression("foo()").unwrap(), 42.into());
     }
 
-    #[test]
-    fn call_jabroni_function() {
-        let mut state = Jabroni::new();
+    /// Set how deeply function calls can nest before failing with a
+    /// [`RecursionTooDeep`](ErrorCode::RecursionTooDeep) error. Each call being evaluated counts
+    /// as a level; the stack grows as needed, so the limit only bounds how much memory recursion
+    /// can take. The default is 4096.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{errors::ErrorCode, Jabroni};
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.set_max_depth(100);
+    /// interpreter
+    ///     .run_script("function forever(n) {return forever(n + 1);}")
+    ///     .unwrap();
+    /// let error = interpreter.run_expression("forever(0)").unwrap_err();
+    /// assert_eq!(error.code(), Some(ErrorCode::RecursionTooDeep));
+    /// ```
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.runtime.borrow_mut().max_depth = Some(max_depth);
+    }
+
+    /// Get how deeply function calls can nest.
+    pub fn max_depth(&self) -> usize {
+        self.runtime.borrow().max_depth.unwrap_or(DEFAULT_MAX_DEPTH)
+    }
+
+    /// Set whether runs measure [`RunStats::peak_memory`]. Measuring it means estimating the size
+    /// of every binding after each statement, which slows scripts down, so it's off by default.
+    pub fn set_measure_memory(&mut self, measure_memory: bool) {
+        self.runtime.borrow_mut().measure_memory = measure_memory;
+    }
+
+    /// Set whether functions that are called often are compiled to native code. Only functions
+    /// that work with nothing but numbers and booleans can be compiled, and calls are interpreted
+    /// while scripts are traced, debugged, profiled, audited, covered or run incrementally, or
+    /// when [`Coercion::Loose`] or [`Equality::StrictOnly`] is set. Compiled calls aren't counted
+    /// in [`RunStats::instructions`].
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::Jabroni;
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.set_jit(true);
+    /// interpreter
+    ///     .run_script("function fib(n) {return n < 2 ? n : fib(n - 1) + fib(n - 2);}")
+    ///     .unwrap();
+    /// assert_eq!(interpreter.run_expression("fib(15)").unwrap(), 610.into());
+    /// assert_eq!(interpreter.native_functions(), ["fib"]);
+    /// ```
+    #[cfg(feature = "jit")]
+    pub fn set_jit(&mut self, enabled: bool) {
+        let mut runtime = self.runtime.borrow_mut();
+        match enabled {
+            true => runtime.jit = Some(runtime.jit.take().unwrap_or_default()),
+            false => runtime.jit = None,
+        }
+    }
+
+    /// Get the names of the functions that have been compiled to native code since the JIT was
+    /// turned on, in the order they were compiled.
+    #[cfg(feature = "jit")]
+    pub fn native_functions(&self) -> Vec<String> {
+        let runtime = self.runtime.borrow();
+        runtime
+            .jit
+            .as_ref()
+            .map_or_else(Vec::new, |jit| jit.compiled().to_vec())
+    }
+
+    /// Run a function defined by a script as native code, if the JIT is on and nothing needs to
+    /// watch it run. Returns `None` if it has to be interpreted.
+    #[cfg(feature = "jit")]
+    fn call_native(
+        runtime: &Rc<RefCell<Runtime>>,
+        function: &RefCell<HotFunction>,
+        context: &BindingMap,
+        args: &[Value],
+    ) -> Option<Value> {
+        let mut runtime = runtime.borrow_mut();
+        let watched = runtime.budget.is_some()
+            || runtime.tracer.is_some()
+            || runtime.debug.is_some()
+            || runtime.profiler.is_some()
+            || runtime.coverage.is_some()
+            || runtime.audit.is_some()
+            || runtime.measure_memory;
+        if watched || runtime.coercion != Coercion::Strict || runtime.equality != Equality::Any {
+            return None;
+        }
+        let max_depth = runtime.max_depth.unwrap_or(DEFAULT_MAX_DEPTH);
+        let depth = max_depth.saturating_sub(runtime.depth);
+        let jit = runtime.jit.as_mut()?;
+        function.borrow_mut().call(jit, context, args, depth)
+    }
+
+    /// Set callbacks to follow what scripts do as they run, replacing any that were set before.
+    pub fn set_tracer(&mut self, tracer: impl Tracer + 'static) {
+        self.runtime.borrow_mut().tracer = Some(Box::new(tracer));
+    }
+
+    /// Stop tracing scripts.
+    pub fn remove_tracer(&mut self) {
+        self.runtime.borrow_mut().tracer = None;
+    }
+
+    /// Start recording how long scripts spend in each function and expression, discarding
+    /// anything recorded before. Scripts run more slowly while they're being profiled.
+    pub fn start_profiling(&mut self) {
+        self.runtime.borrow_mut().profiler = Some(Profiler::default());
+    }
+
+    /// Stop profiling, returning what was recorded.
+    pub fn stop_profiling(&mut self) -> Option<Profile> {
+        let profiler = self.runtime.borrow_mut().profiler.take();
+        profiler.map(|profiler| profiler.report())
+    }
+
+    /// Get what's been recorded since profiling started, if it has.
+    pub fn profile(&self) -> Option<Profile> {
+        let runtime = self.runtime.borrow();
+        runtime.profiler.as_ref().map(Profiler::report)
+    }
+
+    /// Start counting how many times each statement and branch runs, discarding any counts from
+    /// before.
+    pub fn start_coverage(&mut self) {
+        self.runtime.borrow_mut().coverage = Some(Collector::default());
+    }
+
+    /// Stop counting statements and branches, returning the counts.
+    pub fn stop_coverage(&mut self) -> Option<Coverage> {
+        let collector = self.runtime.borrow_mut().coverage.take();
+        collector.map(|collector| collector.report())
+    }
+
+    /// Get the counts of statements and branches since coverage started, if it has.
+    pub fn coverage(&self) -> Option<Coverage> {
+        let runtime = self.runtime.borrow();
+        runtime.coverage.as_ref().map(Collector::report)
+    }
+
+    /// Start recording what scripts do: the bindings they declare and assign, the host functions
+    /// they call, and the errors they throw or fail with. Discards anything recorded before.
+    pub fn start_audit(&mut self) {
+        self.runtime.borrow_mut().audit = Some(AuditLog::default());
+    }
+
+    /// Stop recording what scripts do, returning what they did.
+    pub fn stop_audit(&mut self) -> Option<AuditLog> {
+        self.runtime.borrow_mut().audit.take()
+    }
+
+    /// Get what scripts did since auditing started, if it has.
+    pub fn audit_log(&self) -> Option<AuditLog> {
+        self.runtime.borrow().audit.clone()
+    }
+
+    /// Record an event in the audit log, if auditing is on.
+    fn audit(&self, location: impl Into<Option<Location>>, event: impl FnOnce() -> AuditEvent) {
+        if let Some(log) = self.runtime.borrow_mut().audit.as_mut() {
+            let source = self.source.as_ref().and_then(|source| source.name());
+            log.record(source, location.into(), event());
+        }
+    }
+
+    /// Record an error in the audit log, if auditing is on. Thrown values are recorded where
+    /// they're thrown instead.
+    fn audit_failure(&self, error: &JabroniError) {
+        if error.code() == Some(ErrorCode::UncaughtException) {
+            return;
+        }
+        if let Some(log) = self.runtime.borrow_mut().audit.as_mut() {
+            let event = AuditEvent::Failed {
+                kind: error.kind(),
+                code: error.code(),
+                message: error.message().into(),
+            };
+            log.record(error.source_name(), error.location(), event);
+        }
+    }
+
+    /// Call the coverage collector with the region of `span`, if coverage is on.
+    fn cover(&self, span: &Span, callback: impl FnOnce(&mut Collector, Region)) {
+        if let Some(collector) = self.runtime.borrow_mut().coverage.as_mut() {
+            let region = Region {
+                source: self
+                    .source
+                    .as_ref()
+                    .and_then(|source| source.name().map(Into::into)),
+                start: locate(span, self.origin),
+                end: locate_position(span.end_pos(), self.origin),
+            };
+            callback(collector, region);
+        }
+    }
+
+    /// Call the profiler, if profiling is on.
+    fn profile_with(runtime: &RefCell<Runtime>, callback: impl FnOnce(&mut Profiler)) {
+        if let Some(profiler) = runtime.borrow_mut().profiler.as_mut() {
+            callback(profiler);
+        }
+    }
+
+    /// Keep track of the script functions being called from `call_site`, for stepping and
+    /// backtraces in the debugger.
+    fn enter_call(&self, scripted: bool, entering: bool, call_site: Location) {
+        if let (true, Some(session)) = (scripted, self.runtime.borrow_mut().debug.as_mut()) {
+            match entering {
+                true => session.calls.push(Frame::new(
+                    self.function.as_deref(),
+                    self.source.as_ref(),
+                    Some(call_site),
+                )),
+                false => {
+                    session.calls.pop();
+                }
+            }
+        }
+    }
+
+    /// Call the tracer, if there is one.
+    fn trace(runtime: &RefCell<Runtime>, callback: impl FnOnce(&mut dyn Tracer)) {
+        if let Some(tracer) = runtime.borrow_mut().tracer.as_mut() {
+            callback(tracer.as_mut());
+        }
+    }
+
+    /// Wrap a function so it fails with a permission error unless this interpreter grants
+    /// `permission` at the time it's called. Functions guarded by the [`Time`](Permission::Time)
+    /// or [`Random`](Permission::Random) permissions are marked
+    /// [nondeterministic](Subroutine::nondeterministic).
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Jabroni, Permission, Permissions, Subroutine, Value as JabroniValue};
+    ///
+    /// fn install_stdlib(interpreter: &mut Jabroni) {
+    ///     let now = Subroutine::new(0, Box::new(|_, _| Ok(JabroniValue::Number(1234))));
+    ///     let now = interpreter.guard(Permission::Time, now);
+    ///     interpreter
+    ///         .define_constant("now", JabroniValue::Subroutine(now))
+    ///         .unwrap();
+    /// }
+    ///
+    /// let mut trusted = Jabroni::new();
+    /// install_stdlib(&mut trusted);
+    /// assert_eq!(trusted.run_expression("now()").unwrap(), 1234.into());
+    ///
+    /// let mut untrusted = Jabroni::new();
+    /// untrusted.set_permissions(Permissions::none());
+    /// install_stdlib(&mut untrusted);
+    /// assert!(untrusted.run_expression("now()").is_err());
+    /// ```
+    pub fn guard(&self, permission: Permission, subroutine: Subroutine) -> Subroutine {
+        let runtime = self.runtime.clone();
+        let subroutine = subroutine.guarded(move || runtime.borrow().permissions.check(permission));
+        match permission.is_nondeterministic() {
+            true => subroutine.nondeterministic(),
+            false => subroutine,
+        }
+    }
+
+    /// Define the `on(event, handler)` and `emit(event, ...args)` functions, so scripts can
+    /// register handlers for and emit events. The host can do the same with [`Jabroni::on`] and
+    /// [`Jabroni::emit`].
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Jabroni, Subroutine, Value as JabroniValue};
+    /// use std::{cell::Cell, rc::Rc};
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.enable_events().unwrap();
+    /// interpreter
+    ///     .run_script("function tick(n) {emit('tock', n * 2);} on('tick', tick);")
+    ///     .unwrap();
+    ///
+    /// let tocks = Rc::new(Cell::new(0));
+    /// let counter = tocks.clone();
+    /// interpreter.on(
+    ///     "tock",
+    ///     Subroutine::new(
+    ///         1,
+    ///         Box::new(move |_, args| {
+    ///             counter.set(counter.get() + args[0].as_number().unwrap());
+    ///             Ok(JabroniValue::Null)
+    ///         }),
+    ///     ),
+    /// );
+    /// interpreter.emit("tick", &[JabroniValue::Number(5)]).unwrap();
+    /// assert_eq!(tocks.get(), 10);
+    /// ```
+    pub fn enable_events(&mut self) -> JabroniResult {
+        self.define_constant("on", Value::Subroutine(self.events.on_builtin()))?;
+        self.define_constant("emit", Value::Subroutine(self.events.emit_builtin()))
+    }
+
+    /// Register a handler for an event.
+    pub fn on(&mut self, event: &str, handler: Subroutine) {
+        self.events.on(event, handler);
+    }
+
+    /// Call the handlers registered for an event.
+    pub fn emit(&mut self, event: &str, args: &[Value]) -> JabroniResult {
+        self.events.emit(event, &self.bindings, args)
+    }
+
+    /// Define the `channel(name)`, `send(channel, value)` and `receive(channel)` functions, so
+    /// scripts can exchange messages with channels connected by [`Jabroni::add_channel`].
+    /// `receive` blocks until a message arrives, and returns null once the channel is closed.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Jabroni, Message};
+    /// use std::{sync::mpsc, thread};
+    ///
+    /// let (to_script, from_host) = mpsc::channel();
+    /// let (to_host, from_script) = mpsc::channel();
+    /// let worker = thread::spawn(move || {
+    ///     let mut interpreter = Jabroni::new();
+    ///     interpreter.enable_channels().unwrap();
+    ///     interpreter.add_channel("jobs", to_host, from_host);
+    ///     interpreter
+    ///         .run_script("const jobs = channel('jobs'); send(jobs, receive(jobs) * 2);")
+    ///         .unwrap();
+    /// });
+    /// to_script.send(Message::Number(21)).unwrap();
+    /// assert_eq!(from_script.recv().unwrap(), Message::Number(42));
+    /// worker.join().unwrap();
+    /// ```
+    pub fn enable_channels(&mut self) -> JabroniResult {
+        self.define_constant(
+            "channel",
+            Value::Subroutine(self.channels.channel_builtin()),
+        )?;
+        self.define_constant("send", Value::Subroutine(self.channels.send_builtin()))?;
+        self.define_constant(
+            "receive",
+            Value::Subroutine(self.channels.receive_builtin()),
+        )
+    }
+
+    /// Connect a channel that scripts can open with `channel(name)`. Scripts send messages to
+    /// `sender` and receive them from `receiver`.
+    pub fn add_channel(
+        &mut self,
+        name: &str,
+        sender: Sender<Message>,
+        receiver: Receiver<Message>,
+    ) {
+        self.channels.add(name, sender, receiver);
+    }
+
+    /// Define the `setTimeout(callback, milliseconds, ...args)`, `clearTimeout(id)` and
+    /// `sleep(milliseconds)` functions, which run on a Tokio runtime.
+    /// [`Jabroni::run_script_async`] waits for pending timers before returning, so it has to be
+    /// awaited from a task on the same runtime. Use [`Subroutine::new_spawned`] to run host
+    /// functions on the runtime, such as a `fetch` backed by the host's HTTP client, or
+    /// [`Jabroni::enable_fetch`] for a basic one.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Binding, BindingMap, Jabroni};
+    ///
+    /// let runtime = tokio::runtime::Builder::new_current_thread()
+    ///     .enable_all()
+    ///     .build()
+    ///     .unwrap();
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.install_async_runtime(runtime.handle().clone()).unwrap();
+    /// let mut log = BindingMap::default();
+    /// log.set("digits".into(), Binding::variable(0.into()));
+    /// interpreter.define_constant("log", log.into()).unwrap();
+    /// let code = "
+    ///     function write(digit) {log.digits = log.digits * 10 + digit;}
+    ///     setTimeout(write, 20, 2);
+    ///     setTimeout(write, 10, 1);
+    /// ";
+    /// runtime.block_on(interpreter.run_script_async(code)).unwrap();
+    /// assert_eq!(interpreter.run_expression("log.digits").unwrap(), 12.into());
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub fn install_async_runtime(&mut self, handle: tokio::runtime::Handle) -> JabroniResult {
+        let runtime = AsyncRuntime::new(handle);
+        self.define_constant("setTimeout", runtime.set_timeout_builtin().into())?;
+        self.define_constant("clearTimeout", runtime.clear_timeout_builtin().into())?;
+        self.define_constant("sleep", runtime.sleep_builtin().into())?;
+        self.runtime.borrow_mut().async_runtime = Some(runtime);
+        Ok(())
+    }
+
+    /// Define `fetch(url)`, which gets an `http://` URL on the runtime installed by
+    /// [`Jabroni::install_async_runtime`] and needs [`Permission::Net`]. It's a minimal HTTP/1.0
+    /// client without TLS or redirects, which reads responses up to 4 MiB and gives up on servers
+    /// that take more than 30 seconds; hosts with their own HTTP client should define `fetch`
+    /// with it instead.
+    #[cfg(feature = "tokio")]
+    pub fn enable_fetch(&mut self) -> JabroniResult {
+        let runtime = self.runtime.borrow().async_runtime.clone().ok_or_else(|| {
+            JabroniError::from_code(
+                ErrorCode::CannotSuspend,
+                "Install an async runtime before enabling fetch".into(),
+            )
+        })?;
+        let fetch = self.guard(Permission::Net, runtime.fetch_builtin());
+        self.define_constant("fetch", fetch.into())
+    }
+
+    /// Run timers as they come due, until none are pending.
+    #[cfg(feature = "tokio")]
+    async fn run_timers(&mut self) -> JabroniResult {
+        let runtime = match self.runtime.borrow().async_runtime.clone() {
+            Some(runtime) => runtime,
+            None => return Ok(()),
+        };
+        while let Some((callback, args)) = runtime.next_timer().await? {
+            callback
+                .call_inline(self.bindings.new_context(), Args::from_vec(args))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Define the `bytes` object, with functions for working with [`Value::Bytes`]:
+    /// `bytes.length(b)`, `bytes.at(b, index)`, `bytes.slice(b, start, end)`, `bytes.toHex(b)`,
+    /// `bytes.fromHex(string)`, `bytes.toBase64(b)` and `bytes.fromBase64(string)`. Negative
+    /// indices count back from the end, and `at` returns null past either end.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Jabroni, Value as JabroniValue};
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.enable_bytes().unwrap();
+    /// interpreter
+    ///     .define_constant("frame", vec![0x02, 0xca, 0xfe, 0x03].into())
+    ///     .unwrap();
+    /// interpreter
+    ///     .run_script("const body = bytes.slice(frame, 1, bytes.length(frame) - 1);")
+    ///     .unwrap();
+    /// assert_eq!(
+    ///     interpreter.run_expression("bytes.toHex(body)").unwrap(),
+    ///     "cafe".into()
+    /// );
+    /// assert_eq!(
+    ///     interpreter.run_expression("bytes.at(frame, 3)").unwrap(),
+    ///     JabroniValue::Number(3)
+    /// );
+    /// assert_eq!(
+    ///     interpreter.run_expression("bytes.fromBase64('yv4=')").unwrap(),
+    ///     JabroniValue::Bytes(vec![0xca, 0xfe])
+    /// );
+    /// ```
+    pub fn enable_bytes(&mut self) -> JabroniResult {
+        self.define_constant("bytes", bytes::namespace().into())
+    }
+
+    /// Define the `strings` object, with functions for comparing and normalizing Unicode text:
+    /// `strings.localeCompare(a, b)`, `strings.compareIgnoreCase(a, b)`,
+    /// `strings.equalsIgnoreCase(a, b)` and `strings.normalize(string, form)`. Comparisons
+    /// return -1, 0 or 1, and treat composed and decomposed accents the same. `localeCompare`
+    /// sorts by letters first, then accents, then case, with lowercase first.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Jabroni, Value as JabroniValue};
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.enable_strings().unwrap();
+    /// assert_eq!(
+    ///     interpreter.run_expression("strings.localeCompare('été', 'Ete')").unwrap(),
+    ///     JabroniValue::Number(1)
+    /// );
+    /// assert_eq!(
+    ///     interpreter.run_expression("strings.localeCompare('émile', 'eve')").unwrap(),
+    ///     JabroniValue::Number(-1)
+    /// );
+    /// assert_eq!(
+    ///     interpreter.run_expression("strings.equalsIgnoreCase('CAFÉ', 'café')").unwrap(),
+    ///     JabroniValue::Boolean(true)
+    /// );
+    /// ```
+    #[cfg(feature = "unicode-normalization")]
+    pub fn enable_strings(&mut self) -> JabroniResult {
+        self.define_constant("strings", crate::strings::namespace().into())
+    }
+
+    pub fn define_constant(&mut self, ident: &str, value: Value) -> JabroniResult {
+        if self.is_deterministic() {
+            Self::check_deterministic(&value)?;
+        }
+        self.define_binding(ident, value, false)
+    }
+
+    pub fn define_variable(&mut self, ident: &str, value: Value) -> JabroniResult {
+        if self.is_deterministic() {
+            Self::check_deterministic(&value)?;
+        }
+        self.define_binding(ident, value, true)
+    }
+
+    pub fn update_variable(&mut self, ident: &str, value: Value) -> JabroniResult {
+        if self.is_deterministic() {
+            Self::check_deterministic(&value)?;
+        }
+        self.bindings.get_mut(ident)?.set_value(value)?;
+
+        Ok(())
+    }
+
+    /// Get the bindings scripts can see, such as to list or complete their names.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::Jabroni;
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.run_script("const width = 3; function area(h) {return width * h;}").unwrap();
+    /// let mut names: Vec<_> = interpreter.bindings().iter().map(|(ident, _)| ident).collect();
+    /// names.sort_unstable();
+    /// assert_eq!(names, ["area", "width"]);
+    /// ```
+    pub fn bindings(&self) -> &BindingMap {
+        &self.bindings
+    }
+
+    /// Describe the function at `path`, such as `console.log`, for tools that show people the
+    /// functions scripts can call. Nothing is run to find it.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::Jabroni;
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.enable_bytes().unwrap();
+    /// interpreter.run_script("function greet(name) {return name;}").unwrap();
+    ///
+    /// let slice = interpreter.describe("bytes.slice").unwrap();
+    /// assert_eq!(slice.arity, Some(3));
+    /// assert!(slice.doc.is_some());
+    /// let greet = interpreter.describe("greet").unwrap();
+    /// assert_eq!(greet.signature.as_deref(), Some("greet(name)"));
+    /// assert_eq!(greet.parameters.unwrap()[0].name, "name");
+    /// ```
+    pub fn describe(&self, path: &str) -> JabroniResult<Description> {
+        reflect::describe(&self.bindings, path)
+    }
+
+    /// Describe every function scripts can reach through bindings and the objects they hold,
+    /// sorted by path. Hidden bindings are left out.
+    pub fn describe_all(&self) -> Vec<Description> {
+        reflect::describe_all(&self.bindings)
+    }
+
+    fn define_binding(&mut self, ident: &str, value: Value, mutable: bool) -> JabroniResult {
+        if self.bindings.has_on_top(ident) {
+            return Err(JabroniError::from_code(
+                ErrorCode::DoubleDefinition,
+                format!("Cannot define '{ident}' because it has already been defined"),
+            ));
+        }
+        self.bindings
+            .set_interned(utils::intern(ident), Binding::new(value, mutable));
+        Ok(())
+    }
+
+    pub fn run_expression(&mut self, code: &str) -> JabroniResult<Value> {
+        utils::block_on(self.run_expression_async(code))
+    }
+
+    /// Run an expression, attributing errors to `name`, as [`Jabroni::run_script_named`] does
+    /// for scripts.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::Jabroni;
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// let error = interpreter.run_expression_named("<eval>", "1 + missing").unwrap_err();
+    /// assert_eq!(error.source_name(), Some("<eval>"));
+    /// ```
+    pub fn run_expression_named(&mut self, name: &str, code: &str) -> JabroniResult<Value> {
+        utils::block_on(self.run_expression_source(Some(name), code))
+    }
+
+    /// Run an expression, awaiting any async functions it calls.
+    pub async fn run_expression_async(&mut self, code: &str) -> JabroniResult<Value> {
+        self.run_expression_source(None, code).await
+    }
+
+    async fn run_expression_source(
+        &mut self,
+        name: Option<&str>,
+        code: &str,
+    ) -> JabroniResult<Value> {
+        let start = self.begin_run();
+        let outer = self.source.replace(Arc::new(Source::new(name, code)));
+        let result = self.evaluate_expression(code).await;
+        let result = result.map_err(|e| {
+            e.in_source(self.source.as_ref())
+                .raised_in(self.function.as_deref(), self.source.as_ref())
+        });
+        if let Err(error) = &result {
+            self.audit_failure(error);
+        }
+        self.source = outer;
+        self.end_run(start);
+        result
+    }
+
+    /// Run an expression that's guaranteed to have no side effects, such as a filter or sort key
+    /// supplied by a user. It can't assign to anything, and can only call functions marked
+    /// [pure](Subroutine::pure), failing with an [`ImpureCall`](ErrorCode::ImpureCall) error
+    /// otherwise. Functions defined by scripts aren't pure, since they can assign.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{errors::ErrorCode, Jabroni, Subroutine, Value as JabroniValue};
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// let abs = Subroutine::new(
+    ///     1,
+    ///     Box::new(|_, args| Ok(JabroniValue::Number(args[0].as_number().unwrap().abs()))),
+    /// );
+    /// let log = Subroutine::new_variadic(Box::new(|_, _| Ok(JabroniValue::Null)));
+    /// interpreter.define_constant("abs", abs.pure().into()).unwrap();
+    /// interpreter.define_constant("log", log.into()).unwrap();
+    /// interpreter.define_variable("score", JabroniValue::Number(-7)).unwrap();
+    ///
+    /// assert_eq!(
+    ///     interpreter.evaluate_pure("abs(score) > 5").unwrap(),
+    ///     true.into()
+    /// );
+    /// assert!(interpreter.evaluate_pure("score = 0").is_err());
+    /// let error = interpreter.evaluate_pure("log(score)").unwrap_err();
+    /// assert_eq!(error.code(), Some(ErrorCode::ImpureCall));
+    /// ```
+    pub fn evaluate_pure(&mut self, code: &str) -> JabroniResult<Value> {
+        Sandbox::new()
+            .without(Construct::Assignments)
+            .check_expression(code)?;
+        let outer = std::mem::replace(&mut self.runtime.borrow_mut().pure, true);
+        let result = self.run_expression(code);
+        self.runtime.borrow_mut().pure = outer;
+        result
+    }
+
+    /// Render text with expressions embedded in it between `${` and `}`, replacing each with its
+    /// value. Use a [`Template`](crate::template::Template) to change the delimiters or escape
+    /// values.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::Jabroni;
+    ///
+    /// let mut jabroni = Jabroni::new();
+    /// jabroni.run_script("const name = 'Ada'; const count = 2;").unwrap();
+    /// assert_eq!(
+    ///     jabroni.render_template("Hello ${name}, you have ${count} items").unwrap(),
+    ///     "Hello Ada, you have 2 items"
+    /// );
+    /// ```
+    pub fn render_template(&mut self, text: &str) -> JabroniResult<String> {
+        Template::new().render(self, text)
+    }
+
+    async fn evaluate_expression(&mut self, code: &str) -> JabroniResult<Value> {
+        let mut pairs = self.parse(Rule::jabroni_expression, code)?;
+        self.check_sandbox(code, true)?;
+
+        let value = self.interpret_expression(pairs.next().unwrap()).await?;
+        self.record_memory();
+        Ok(value)
+    }
+
+    /// Run an expression with `locals` defined as constants in a scope that only lasts for the
+    /// duration of the call.
+    pub fn run_expression_with(
+        &mut self,
+        code: &str,
+        locals: &[(&str, Value)],
+    ) -> JabroniResult<Value> {
+        self.bindings.push_scope();
+        for (ident, value) in locals {
+            self.bindings
+                .set(ident.to_string(), Binding::constant(value.clone()));
+        }
+        let result = self.run_expression(code);
+        self.bindings.pop_scope();
+        result
+    }
+
+    /// Run an expression and convert the result into a Rust type.
+    #[cfg(feature = "serde")]
+    pub fn eval_typed<T: serde::de::DeserializeOwned>(&mut self, code: &str) -> JabroniResult<T> {
+        crate::de::from_value(&self.run_expression(code)?)
+    }
+
+    /// Save the bindings in every scope, such as to restore a session after the host restarts.
+    /// See [`Snapshot`](crate::snapshot::Snapshot) for an example.
+    #[cfg(feature = "serde")]
+    pub fn snapshot(&self) -> JabroniResult<crate::snapshot::Snapshot> {
+        crate::snapshot::Snapshot::take(&self.bindings)
+    }
+
+    /// Restore the bindings saved in a snapshot, replacing bindings with the same names. Scopes
+    /// beyond the outermost are added above the current scope. Functions defined by the host are
+    /// linked to the functions in `natives` with the same names.
+    #[cfg(feature = "serde")]
+    pub fn restore(
+        &mut self,
+        snapshot: &crate::snapshot::Snapshot,
+        natives: &[Subroutine],
+    ) -> JabroniResult {
+        let scopes = snapshot.bindings(natives, |definition| self.define_saved(definition))?;
+        for (index, scope) in scopes.into_iter().enumerate() {
+            if index > 0 {
+                self.bindings.push_scope();
+            }
+            for (ident, binding) in scope {
+                if self.is_deterministic() {
+                    Self::check_deterministic(binding.value())?;
+                }
+                self.bindings.set(ident, binding);
+            }
+        }
+        Ok(())
+    }
+
+    /// Define a function again from the `function` statement saved in a snapshot.
+    #[cfg(feature = "serde")]
+    fn define_saved(&self, definition: &str) -> JabroniResult<Subroutine> {
+        let mut substate = Jabroni {
+            runtime: self.runtime.clone(),
+            source: Some(Arc::new(Source::new(None, definition))),
+            ..Default::default()
+        };
+        // Not in the outermost scope, so it can be defined while globals are frozen
+        substate.bindings.push_scope();
+        for pair in parse_pairs(Rule::jabroni_script, definition)? {
+            if pair.as_rule() == Rule::statement {
+                let statement = pair.into_inner().next().unwrap();
+                if statement.as_rule() == Rule::function_statement {
+                    substate.define_function(statement)?;
+                }
+            }
+        }
+        let function = substate.bindings.iter_innermost().next();
+        match function.map(|(_, binding)| binding.value()) {
+            Some(Value::Subroutine(subroutine)) => Ok(subroutine.clone()),
+            _ => Err(JabroniError::from_code(
+                ErrorCode::Snapshot,
+                format!("Snapshot has a function that isn't defined by a function statement: {definition}"),
+            )),
+        }
+    }
+
+    /// Run a script. Async functions must complete without waiting; use
+    /// [`Jabroni::run_script_async`] for functions that need to wait.
+    pub fn run_script(&mut self, code: &str) -> JabroniResult<Value> {
+        utils::block_on(self.run_source(None, code))
+    }
+
+    /// Run a script, awaiting any async functions it calls, and the timers it sets if an async
+    /// runtime is installed.
+    pub async fn run_script_async(&mut self, code: &str) -> JabroniResult<Value> {
+        let result = self.run_source(None, code).await?;
+        #[cfg(feature = "tokio")]
+        self.run_timers().await?;
+        Ok(result)
+    }
+
+    /// Run a script, attributing errors to `name`. Errors from functions the script defines are
+    /// attributed to it too, wherever they're called from.
+    pub fn run_script_named(&mut self, name: &str, code: &str) -> JabroniResult<Value> {
+        utils::block_on(self.run_source(Some(name), code))
+    }
+
+    /// Run a script written in a subset of JavaScript, translated into Jabroni by
+    /// [`compat::translate`](crate::compat::translate). Errors point at the JavaScript.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::Jabroni;
+    ///
+    /// let mut jabroni = Jabroni::new();
+    /// let js = "var count = 2\nconst greet = (name) => `Hello ${name}, you have ${count} items`\n\
+    ///           return greet('Ada')";
+    /// assert_eq!(
+    ///     jabroni.run_js(js).unwrap(),
+    ///     "Hello Ada, you have 2 items".into()
+    /// );
+    /// ```
+    pub fn run_js(&mut self, code: &str) -> JabroniResult<Value> {
+        let (translation, map) = compat::translate_mapped(code)?;
+        self.define_template_helper();
+        self.run_script(&translation).map_err(|e| e.mapped(&map))
+    }
+
+    fn define_template_helper(&mut self) {
+        if !self.bindings.contains(compat::TEMPLATE_HELPER) {
+            let join = Subroutine::new_variadic(Box::new(|_, args| {
+                Ok(args.iter().map(Value::to_string).collect::<String>().into())
+            }))
+            .named(compat::TEMPLATE_HELPER);
+            self.bindings.set(
+                compat::TEMPLATE_HELPER.into(),
+                Binding::constant(join.into()).hidden(),
+            );
+        }
+    }
+
+    async fn run_source(&mut self, name: Option<&str>, code: &str) -> JabroniResult<Value> {
+        self.run_in(Arc::new(Source::new(name, code)), code).await
+    }
+
+    async fn run_in(&mut self, source: Arc<Source>, code: &str) -> JabroniResult<Value> {
+        let start = self.begin_run();
+        let outer = self.source.replace(source);
+        let scoped = self.globals_frozen();
+        if scoped {
+            self.bindings.push_scope();
+        }
+        let result = self.evaluate_script(code).await;
+        if scoped {
+            self.bindings.pop_scope();
+        }
+        if let Err(error) = &result {
+            self.audit_failure(error);
+        }
+        self.source = outer;
+        self.end_run(start);
+        result
+    }
+
+    /// Get the resources used by the last run, or so far by the current one.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::Jabroni;
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.set_measure_memory(true);
+    /// interpreter.run_script("const greeting = 'hello';").unwrap();
+    /// let stats = interpreter.last_run_stats();
+    /// assert_eq!(stats.allocations, 1);
+    /// assert!(stats.instructions > 0);
+    /// assert!(stats.peak_memory >= "greeting".len() + "hello".len());
+    /// ```
+    pub fn last_run_stats(&self) -> RunStats {
+        self.runtime.borrow().stats.clone()
+    }
+
+    /// Get the warnings from the last run, or so far from the current one.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::Jabroni;
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter
+    ///     .run_script("function area(w, h) {let unused = 0; return w * h;}")
+    ///     .unwrap();
+    /// let diagnostics = interpreter.diagnostics();
+    /// assert_eq!(diagnostics.len(), 1);
+    /// assert_eq!(diagnostics[0].message, "'unused' is declared but never used");
+    /// ```
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.runtime.borrow().diagnostics.clone()
+    }
+
+    /// Update the peak memory held by bindings, if it's being measured.
+    fn record_memory(&self) {
+        let mut runtime = self.runtime.borrow_mut();
+        if runtime.measure_memory {
+            runtime.stats.record_memory(&self.bindings);
+        }
+    }
+
+    fn begin_run(&self) -> Instant {
+        let mut runtime = self.runtime.borrow_mut();
+        runtime.stats = RunStats::default();
+        runtime.diagnostics.clear();
+        Instant::now()
+    }
+
+    /// Record a warning about the code at `span`, unless it's already been recorded, such as by
+    /// an earlier call to the same function.
+    fn warn(&self, span: &Span, message: String) {
+        let diagnostic = Diagnostic {
+            severity: Severity::Warning,
+            message,
+            code: None,
+            location: locate(span, self.origin),
+            error_code: None,
+        };
+        let diagnostics = &mut self.runtime.borrow_mut().diagnostics;
+        if !diagnostics.contains(&diagnostic) {
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Parse `code`, counting the time taken towards the run's parse time.
+    fn parse<'a>(&self, rule: Rule, code: &'a str) -> JabroniResult<Pairs<'a, Rule>> {
+        let start = Instant::now();
+        let pairs = parse_pairs(rule, code);
+        self.runtime
+            .borrow_mut()
+            .stats
+            .record_parse(start.elapsed());
+        pairs
+    }
+
+    fn end_run(&self, start: Instant) {
+        self.runtime.borrow_mut().stats.elapsed = start.elapsed();
+    }
+
+    /// Run a script without starting a new run, for function bodies and modules.
+    async fn evaluate_script(&mut self, code: &str) -> JabroniResult<Value> {
+        let (source, function) = (self.source.clone(), self.function.clone());
+        self.evaluate_statements(code).await.map_err(|e| {
+            e.in_source(source.as_ref())
+                .raised_in(function.as_deref(), source.as_ref())
+        })
+    }
+
+    /// Run the body of a function defined by a script.
+    async fn evaluate_body(&mut self, body: &FunctionBody) -> JabroniResult<Value> {
+        let (source, function) = (self.source.clone(), self.function.clone());
+        self.run_statements(body.pairs()).await.map_err(|e| {
+            e.in_source(source.as_ref())
+                .raised_in(function.as_deref(), source.as_ref())
+        })
+    }
+
+    async fn evaluate_statements(&mut self, code: &str) -> JabroniResult<Value> {
+        let pairs = self.parse(Rule::jabroni_script, code)?;
+        // Function bodies were already checked with the rest of their script
+        if self.function.is_none() {
+            self.check_sandbox(code, false)?;
+            for span in warnings::unused_locals(pairs.clone()) {
+                self.warn(
+                    &span,
+                    format!("'{}' is declared but never used", span.as_str()),
+                );
+            }
+            for span in warnings::assignments_in_conditions(pairs.clone()) {
+                self.warn(
+                    &span,
+                    "Assignment used as a condition; did you mean '=='?".into(),
+                );
+            }
+            if self.runtime.borrow().coverage.is_some() {
+                for pair in pairs.clone().flatten() {
+                    match pair.as_rule() {
+                        Rule::statement => {
+                            let statement = pair.into_inner().next().unwrap();
+                            if statement.as_rule() != Rule::block_statement {
+                                self.cover(&statement.as_span(), Collector::add_statement);
+                            }
+                        }
+                        Rule::ternary if pair.clone().into_inner().count() > 1 => {
+                            let condition = collapse(pair.into_inner().next().unwrap());
+                            self.cover(&condition.as_span(), Collector::add_branch);
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        }
+        // Define functions first, so they can be called from above where they're written
+        for pair in pairs.clone() {
+            if pair.as_rule() == Rule::statement {
+                let statement = pair.into_inner().next().unwrap();
+                if statement.as_rule() == Rule::function_statement {
+                    self.define_function(statement)?;
+                }
+            }
+        }
+        self.run_statements(pairs).await
+    }
+
+    async fn run_statements<'a>(&'a mut self, pairs: Pairs<'a, Rule>) -> JabroniResult<Value> {
+        let mut value = Value::Null;
+        for pair in pairs {
+            match pair.as_rule() {
+                Rule::statement => {
+                    value = self.interpret_statement(pair).await?;
+                    self.record_memory();
+                }
+                Rule::shebang | Rule::EOI => (),
+                rule => {
+                    return Err(JabroniError::from_code(
+                        ErrorCode::UnsupportedSyntax,
+                        format!("Unexpected {rule:?} found while running script"),
+                    ))
+                }
+            }
+        }
+        Ok(value)
+    }
+
+    /// Find every syntax error in a script without running it. After an error, checking resumes
+    /// at the next top-level statement.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::Jabroni;
+    ///
+    /// let diagnostics = Jabroni::check("const x = ;\nlet y = 1;\nfunction f( {}");
+    /// assert_eq!(diagnostics.len(), 2);
+    /// assert_eq!(diagnostics[0].location.line, 1);
+    /// assert_eq!(diagnostics[1].location.line, 3);
+    /// ```
+    pub fn check(code: &str) -> Vec<Diagnostic> {
+        // Statements are parsed one at a time, so check the whole script can be parsed safely
+        if let Some(offset) = utils::nesting_overflow(code) {
+            let (line, column) = Position::new(code, offset).unwrap().line_col();
+            return vec![Diagnostic {
+                severity: Severity::Error,
+                message: nesting_error().message().into(),
+                code: None,
+                location: Location { line, column },
+                error_code: Some(ErrorCode::NestingTooDeep),
+            }];
+        }
+        let mut diagnostics = Vec::new();
+        let mut start = utils::shebang_len(code);
+        loop {
+            let rest = &code[start..];
+            start += rest.len() - rest.trim_start_matches(&[' ', '\t', '\n'][..]).len();
+            if start == code.len() {
+                return diagnostics;
+            }
+            match IdentParser::parse(Rule::statement, &code[start..]) {
+                Ok(mut pairs) => start += pairs.next().unwrap().as_span().end(),
+                Err(error) => {
+                    let offset = match error.location {
+                        InputLocation::Pos(offset) => offset,
+                        InputLocation::Span((offset, _)) => offset,
+                    };
+                    let (line, column) = Position::new(code, start + offset).unwrap().line_col();
+                    diagnostics.push(Diagnostic {
+                        severity: Severity::Error,
+                        message: parse_error_message(&error),
+                        code: None,
+                        location: Location { line, column },
+                        error_code: Some(ErrorCode::Syntax),
+                    });
+                    start += utils::statement_end(&code[start..]);
+                }
+            }
+        }
+    }
+
+    /// Find type errors in a script without running it, using the types of the bindings it would
+    /// run with. Only errors that are certain are reported, so code involving values whose types
+    /// can't be known in advance, like function parameters, isn't checked. Syntax errors are
+    /// reported instead if there are any.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Jabroni, Subroutine, Value as JabroniValue};
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// let clamp = Subroutine::new(3, Box::new(|_, _| Ok(JabroniValue::Null)));
+    /// interpreter
+    ///     .define_constant("clamp", JabroniValue::Subroutine(clamp))
+    ///     .unwrap();
+    /// let diagnostics = interpreter.typecheck("const x = 'a' * 3;\nclamp(x, 1);");
+    /// let messages: Vec<_> = diagnostics.iter().map(ToString::to_string).collect();
+    /// assert_eq!(
+    ///     messages,
+    ///     [
+    ///         "1:11: error: '*' expects numbers, found string",
+    ///         "2:1: error: 'clamp' takes 3 arguments, but 2 were given"
+    ///     ]
+    /// );
+    /// ```
+    pub fn typecheck(&self, code: &str) -> Vec<Diagnostic> {
+        match ast::parse(code) {
+            Ok(script) => typecheck::typecheck(
+                &script,
+                code,
+                &self.bindings,
+                self.coercion(),
+                self.equality(),
+            ),
+            Err(error) => {
+                let diagnostics = Self::check(code);
+                match diagnostics.is_empty() {
+                    true => vec![Diagnostic {
+                        severity: Severity::Error,
+                        message: error.message().into(),
+                        code: None,
+                        location: error.location().unwrap_or(Location { line: 1, column: 1 }),
+                        error_code: error.code(),
+                    }],
+                    false => diagnostics,
+                }
+            }
+        }
+    }
+
+    /// Start a script that can suspend itself with `yield` statements. Nothing runs until the
+    /// first call to [`Resumable::resume`].
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Jabroni, Step, Value as JabroniValue};
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter
+    ///     .run_script("function pan(x) {yield x; yield x + 1;}")
+    ///     .unwrap();
+    /// let mut cutscene = interpreter.run_resumable("pan(10); yield 'fade';");
+    /// assert_eq!(cutscene.resume().unwrap(), Step::Yielded(10.into()));
+    /// assert_eq!(cutscene.resume().unwrap(), Step::Yielded(11.into()));
+    /// assert_eq!(cutscene.resume().unwrap(), Step::Yielded(JabroniValue::String("fade".into())));
+    /// assert_eq!(cutscene.resume().unwrap(), Step::Complete(JabroniValue::Null));
+    /// assert!(cutscene.is_complete());
+    /// ```
+    pub fn run_resumable<'a>(&'a mut self, code: &'a str) -> Resumable<'a> {
+        Resumable {
+            runtime: self.runtime.clone(),
+            evaluation: Some(Box::pin(self.run_source(None, code))),
+        }
+    }
+
+    /// Start running a script a few statements at a time, such as to spread it across the
+    /// frames of a game without threads. The script doesn't run until it's stepped.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Jabroni, Progress, Value as JabroniValue};
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// let mut script = interpreter.run_incremental(
+    ///     "let total = 0;\n\
+    ///      function add(n) {return total + n;}\n\
+    ///      total = add(1);\n\
+    ///      total = add(2);\n\
+    ///      total = add(3);\n\
+    ///      return total;",
+    /// );
+    /// let mut frames = 0;
+    /// let total = loop {
+    ///     frames += 1;
+    ///     match script.step(2).unwrap() {
+    ///         Progress::Pending => continue,
+    ///         Progress::Done(total) => break total,
+    ///     }
+    /// };
+    /// assert_eq!(total, JabroniValue::Number(6));
+    /// assert!(frames > 1);
+    /// ```
+    pub fn run_incremental<'a>(&'a mut self, code: &'a str) -> Incremental<'a> {
+        Incremental {
+            runtime: self.runtime.clone(),
+            evaluation: Some(Box::pin(self.run_source(None, code))),
+        }
+    }
+
+    /// Start debugging a script. See [`Debugger`] for an example.
+    pub fn debug<'a>(&'a mut self, code: &'a str) -> Debugger<'a> {
+        let source = Arc::new(Source::new(None, code));
+        self.runtime.borrow_mut().debug = Some(Session::new(source.clone()));
+        let runtime = self.runtime.clone();
+        Debugger::new(Box::pin(self.run_in(source, code)), runtime)
+    }
+
+    /// Start debugging a script written in a subset of JavaScript, like [`Jabroni::run_js`].
+    /// Translating keeps code on the same lines, so breakpoints are set on the JavaScript's
+    /// lines, and where the script pauses and errors point at the JavaScript.
+    pub fn debug_js(&mut self, code: &str) -> JabroniResult<Debugger<'_>> {
+        let (translation, map) = compat::translate_mapped(code)?;
+        self.define_template_helper();
+        let source = Arc::new(Source::new(None, &translation));
+        self.runtime.borrow_mut().debug = Some(Session::new(source.clone()));
+        let runtime = self.runtime.clone();
+        let evaluation = Box::pin(async move { self.run_in(source, &translation).await });
+        Ok(Debugger::new(evaluation, runtime).mapped(map))
+    }
+
+    /// Count a statement against an incremental script's budget, returning whether it's been
+    /// used up, and the script should be suspended until it's stepped again.
+    fn spend_budget(&self) -> bool {
+        match &mut self.runtime.borrow_mut().budget {
+            Some(0) => true,
+            Some(budget) => {
+                *budget -= 1;
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Check if the statement at `span` should pause for the debugger, and if so, record where
+    /// it's paused.
+    fn pause_point(&self, span: &Span) -> bool {
+        let mut runtime = self.runtime.borrow_mut();
+        let session = match runtime.debug.as_mut() {
+            Some(session) => session,
+            None => return false,
+        };
+        let in_session =
+            matches!(&self.source, Some(source) if Arc::ptr_eq(source, &session.source));
+        let location = locate(span, self.origin);
+        if !in_session || !session.should_pause(location) {
+            return false;
+        }
+        let scopes = self
+            .bindings
+            .scopes()
+            .into_iter()
+            .map(|scope| {
+                let mut scope: Vec<_> = scope
+                    .into_iter()
+                    .map(|(ident, binding)| (ident.to_string(), binding.value().clone()))
+                    .collect();
+                scope.sort_by(|a, b| a.0.cmp(&b.0));
+                scope
+            })
+            .collect();
+        let current = Frame::new(
+            self.function.as_deref(),
+            self.source.as_ref(),
+            Some(location),
+        );
+        let stack = iter::once(current)
+            .chain(session.calls.iter().rev().cloned())
+            .collect();
+        session.pause(Pause {
+            location,
+            code: span.as_str().into(),
+            function: self.function.as_deref().map(Into::into),
+            scopes,
+            stack,
+        });
+        true
+    }
+
+    /// Load a script, or reload it if a script called `name` has already been loaded.
+    ///
+    /// When reloading, only definitions are applied: functions are redefined (or removed if
+    /// they're no longer in the script), new declarations are defined, and existing variables
+    /// keep their values. Other statements aren't run. Definitions that couldn't be applied are
+    /// returned.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::Jabroni;
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter
+    ///     .reload_script("game", "let score = 0; function bonus() {return 1;} score = 5;")
+    ///     .unwrap();
+    /// let conflicts = interpreter
+    ///     .reload_script("game", "let score = 0; function bonus() {return 2;}")
+    ///     .unwrap();
+    /// assert!(conflicts.is_empty());
+    /// assert_eq!(interpreter.run_expression("score + bonus()").unwrap(), 7.into());
+    /// ```
+    pub fn reload_script(&mut self, name: &str, code: &str) -> JabroniResult<Vec<ReloadConflict>> {
+        let start = self.begin_run();
+        let result = utils::block_on(self.reload_script_async(name, code));
+        self.end_run(start);
+        result
+    }
+
+    async fn reload_script_async(
+        &mut self,
+        name: &str,
+        code: &str,
+    ) -> JabroniResult<Vec<ReloadConflict>> {
+        let pairs = self.parse(Rule::jabroni_script, code)?;
+        self.check_sandbox(code, false)?;
+        let statements: Vec<_> = pairs
+            .filter(|pair| pair.as_rule() == Rule::statement)
+            .map(|pair| pair.into_inner().next().unwrap())
+            .collect();
+        let functions: HashSet<String> = statements
+            .iter()
+            .filter(|pair| pair.as_rule() == Rule::function_statement)
+            .map(|pair| pair.clone().into_inner().next().unwrap().as_str().into())
+            .collect();
+
+        let mut conflicts = Vec::new();
+        let old_functions = match self.scripts.insert(name.into(), functions.clone()) {
+            Some(old_functions) => old_functions,
+            None => {
+                for statement in statements {
+                    self.interpret_statement(statement).await?;
+                }
+                return Ok(conflicts);
+            }
+        };
+
+        for removed in old_functions.difference(&functions) {
+            self.bindings.remove(removed);
+        }
+        for statement in statements {
+            match statement.as_rule() {
+                Rule::function_statement => {
+                    let ident = statement.clone().into_inner().next().unwrap().as_str();
+                    match self.bindings.get(ident) {
+                        Ok(binding) if binding.value().as_subroutine().is_none() => {
+                            conflicts.push(ReloadConflict::NotAFunction(ident.into()));
+                        }
+                        _ => {
+                            self.interpret_statement(statement).await?;
+                        }
+                    }
+                }
+                Rule::declaration_statement => {
+                    let mut pair = statement.clone().into_inner();
+                    let mutable = pair.next().unwrap().as_str() == "let";
+                    let ident = pair.next().unwrap().as_str();
+                    if !self.bindings.has_on_top(ident) {
+                        self.interpret_statement(statement).await?;
+                        continue;
+                    }
+                    let value = self.interpret_expression(pair.next().unwrap()).await?;
+                    let binding = self.bindings.get(ident)?;
+                    if binding.mutable() != mutable {
+                        conflicts.push(ReloadConflict::DeclarationKind(ident.into()));
+                    } else if std::mem::discriminant(binding.value())
+                        != std::mem::discriminant(&value)
+                    {
+                        conflicts.push(ReloadConflict::Type(ident.into()));
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(conflicts)
+    }
+
+    /// Get the exports of a module, evaluating it if that hasn't been done yet. A module's exports
+    /// are the bindings defined at its top level.
+    async fn import_module(&mut self, specifier: &str) -> JabroniResult<BindingMap> {
+        let loader = {
+            let mut runtime = self.runtime.borrow_mut();
+            if let Some(exports) = runtime.modules.get(specifier) {
+                return Ok(exports.clone());
+            }
+            if !runtime.loading.insert(specifier.into()) {
+                return Err(JabroniError::from_code(
+                    ErrorCode::CircularImport,
+                    format!("Circular import of '{specifier}'"),
+                ));
+            }
+            runtime.module_loader.clone()
+        };
+
+        let result = match loader {
+            Some(loader) => self.evaluate_module(&*loader, specifier).await,
+            None => Err(JabroniError::from_code(
+                ErrorCode::ModuleNotFound,
+                format!("Cannot import '{specifier}' without a module loader"),
+            )),
+        };
+
+        let mut runtime = self.runtime.borrow_mut();
+        runtime.loading.remove(specifier);
+        let exports = result?;
+        runtime.modules.insert(specifier.into(), exports.clone());
+        Ok(exports)
+    }
+
+    async fn evaluate_module(
+        &self,
+        loader: &dyn ModuleLoader,
+        specifier: &str,
+    ) -> JabroniResult<BindingMap> {
+        let code = loader.load(specifier)?;
+        let mut module = Jabroni {
+            bindings: self.bindings.new_root_context(),
+            runtime: self.runtime.clone(),
+            source: Some(Arc::new(Source::new(Some(specifier), &code))),
+            ..Default::default()
+        };
+        module.evaluate_script(&code).await?;
+        // Functions from the module need to see the module's bindings, not the importer's
+        let mut exports = BindingMap::default();
+        for (ident, binding) in module.bindings.iter_innermost() {
+            let binding = match binding.value() {
+                Value::Subroutine(subroutine) => Binding::constant(Value::Subroutine(
+                    subroutine.with_context(module.bindings.clone()),
+                )),
+                _ => binding.clone(),
+            };
+            exports.set(ident.into(), binding);
+        }
+        Ok(exports)
+    }
+
+    fn get_binding_mut(&mut self, ident: &str) -> JabroniResult<&mut Binding> {
+        if !self.bindings.contains(ident) {
+            let runtime = self.runtime.borrow();
+            if let Some(value) = runtime
+                .resolver
+                .as_ref()
+                .and_then(|resolver| resolver(ident))
+            {
+                if runtime.deterministic {
+                    Self::check_deterministic(&value)?;
+                }
+                return Ok(self.resolved.insert(Binding::constant(value)));
+            }
+        }
+        self.bindings.get_mut(ident)
+    }
+
+    /// Run `f` with the binding an lvalue expression refers to.
+    async fn with_lvalue<T>(
+        &mut self,
+        pair: Pair<'_, Rule>,
+        f: impl FnOnce(&mut Binding) -> JabroniResult<T>,
+    ) -> JabroniResult<T> {
+        let pair = collapse(pair);
+        let assignable = match pair.as_rule() {
+            Rule::ident => true,
+            Rule::postfix => pair.clone().into_inner().last().unwrap().as_rule() == Rule::property,
+            _ => false,
+        };
+        if !assignable {
+            return Err(JabroniError::from_code(
+                ErrorCode::Syntax,
+                format!("Cannot make out lvalue expression: {}", pair.as_str()),
+            ));
+        }
+        if pair.as_rule() == Rule::ident
+            && self.globals_frozen()
+            && self.bindings.is_outermost(pair.as_str())
+        {
+            return Err(JabroniError::from_code(
+                ErrorCode::FrozenGlobal,
+                format!(
+                    "Cannot assign to global '{}' because globals are frozen",
+                    pair.as_str()
+                ),
+            ));
+        }
+        let place = self.interpret_place(pair).await?;
+        self.with_place(place, f)
+    }
+
+    /// Run `f` with the binding at a place, or a constant binding holding its value if it's not
+    /// stored anywhere.
+    fn with_place<T>(
+        &mut self,
+        place: Place,
+        f: impl FnOnce(&mut Binding) -> JabroniResult<T>,
+    ) -> JabroniResult<T> {
+        match place {
+            Place::Ident(ident) => f(self.get_binding_mut(ident.as_str())?),
+            Place::Member(object, member) => {
+                let mut properties = object.borrow_mut();
+                f(Self::interpret_member(member, &mut properties)?)
+            }
+            Place::Value(value) => f(&mut Binding::constant(value)),
+        }
+    }
+
+    /// Follow an expression's property accesses and calls, left to right, to the place it refers
+    /// to. Bindings are looked up rather than read along the way, so they can be assigned to.
+    fn interpret_place<'a, 'p: 'a>(
+        &'a mut self,
+        pair: Pair<'p, Rule>,
+    ) -> Pin<Box<dyn Future<Output = JabroniResult<Place<'p>>> + 'a>> {
+        Box::pin(async move {
+            let pair = collapse(pair);
+            match pair.as_rule() {
+                Rule::ident => return Ok(Place::Ident(pair)),
+                Rule::postfix => (),
+                _ => return Ok(Place::Value(self.interpret_expression(pair).await?)),
+            }
+            let (code, start) = (pair.as_str(), pair.as_span().start());
+            let call_site = locate(&pair.as_span(), self.origin);
+            let mut pairs = pair.into_inner();
+            let mut place = self.interpret_place(pairs.next().unwrap()).await?;
+            for suffix in pairs {
+                place = match suffix.as_rule() {
+                    Rule::property => {
+                        let object = self.with_place(place, |binding| {
+                            binding.value().as_object().cloned().ok_or_else(|| {
+                                JabroniError::from_code(
+                                    ErrorCode::NotAnObject,
+                                    "Not an object".into(),
+                                )
+                            })
+                        })?;
+                        Place::Member(object, suffix.into_inner().next().unwrap())
+                    }
+                    _ => {
+                        let callee_code = &code[..suffix.as_span().start() - start];
+                        let subroutine = self.interpret_callee(place)?;
+                        let value = self
+                            .interpret_call(subroutine, call_site, callee_code, suffix)
+                            .await?;
+                        Place::Value(value)
+                    }
+                };
+            }
+            Ok(place)
+        })
+    }
+
+    fn interpret_member<'a>(
+        pair: Pair<Rule>,
+        object: &'a mut BindingMap,
+    ) -> JabroniResult<&'a mut Binding> {
+        match pair.as_rule() {
+            Rule::ident => object.get_mut(pair.as_str()),
+            _ => Err(JabroniError::from_code(
+                ErrorCode::Syntax,
+                format!("Cannot make out member expression: {}", pair.as_str()),
+            )),
+        }
+    }
+
+    fn interpret_callee(&mut self, place: Place) -> JabroniResult<Subroutine> {
+        if let Place::Member(object, member) = &place {
+            let properties = object.borrow();
+            if let Some(handler) = properties.handler() {
+                let method = member.as_str();
+                if !properties.contains(method) && handler.has(method) {
+                    let (handler, method) = (handler.clone(), method.to_string());
+                    return Ok(Subroutine::new_variadic(Box::new(move |context, args| {
+                        handler.call(&method, context, args)
+                    })));
+                }
+            }
+        }
+        self.with_place(place, |binding| {
+            binding.value().as_subroutine().cloned().ok_or_else(|| {
+                JabroniError::from_code(ErrorCode::NotAFunction, "Not a function".into())
+            })
+        })
+    }
+
+    fn interpret_expression<'a>(&'a mut self, pair: Pair<'a, Rule>) -> Evaluation<'a> {
+        Box::pin(utils::GrowStack::new(async move {
+            let pair = collapse(pair);
+            self.runtime.borrow_mut().stats.record_instruction();
+            let (span, origin, rule) = (pair.as_span(), self.origin, pair.as_rule());
+            let runtime = self.runtime.clone();
+            let profiling = rule != Rule::expression && runtime.borrow().profiler.is_some();
+            let start = profiling.then(Instant::now);
+            let result = async move {
+                match pair.as_rule() {
+                    // Values are reference counted, so this doesn't copy strings or objects
+                    Rule::ident | Rule::postfix => {
+                        let place = self.interpret_place(pair).await?;
+                        self.with_place(place, |binding| Ok(binding.value().clone()))
+                    }
+
+                    // Each kind of operation is evaluated by its own future, so the futures of
+                    // deeply nested expressions take as little stack as they can when polled
+                    Rule::ternary => self.interpret_ternary(pair).await,
+                    Rule::string_literal => {
+                        let value = Value::from_string_literal(pair.as_str())?;
+                        self.runtime.borrow_mut().stats.record_value(&value);
+                        Ok(value)
+                    }
+                    Rule::numeric_literal => Value::from_numeric_literal(pair.as_str()),
+                    Rule::boolean_literal => Value::from_boolean_literal(pair.as_str()),
+                    Rule::null_literal => Ok(Value::Null),
+                    Rule::expression => {
+                        self.interpret_expression(pair.into_inner().next().unwrap())
+                            .await
+                    }
+                    Rule::assignment => self.interpret_assignment(pair).await,
+                    Rule::comparison | Rule::inequality | Rule::sum | Rule::product => {
+                        self.interpret_operators(pair).await
+                    }
+                    rule => Err(JabroniError::from_code(
+                        ErrorCode::UnsupportedSyntax,
+                        format!("Expressions of type {rule:?} are not supported"),
+                    )),
+                }
+            }
+            .await
+            .map_err(|e| e.located(|| locate(&span, origin), span.as_str().len()));
+            if let (Ok(value), false) = (&result, rule == Rule::expression) {
+                Self::trace(&runtime, |tracer| {
+                    tracer.expression_evaluated(locate(&span, origin), span.as_str(), value)
+                });
+            }
+            if let Some(start) = start {
+                Self::profile_with(&runtime, |profiler| {
+                    profiler.record_expression(
+                        locate(&span, origin),
+                        span.as_str(),
+                        start.elapsed(),
+                    )
+                });
+            }
+            result
+        }))
+    }
+
+    /// Call a function with the values of its arguments.
+    fn interpret_call<'a>(
+        &'a mut self,
+        subroutine: Subroutine,
+        call_site: Location,
+        callee_code: &'a str,
+        arguments: Pair<'a, Rule>,
+    ) -> Evaluation<'a> {
+        Box::pin(async move {
+            if self.runtime.borrow().pure && !subroutine.is_pure() {
+                return Err(JabroniError::from_code(
+                    ErrorCode::ImpureCall,
+                    format!("Cannot call '{callee_code}' because it isn't pure"),
+                ));
+            }
+            let _depth = DepthGuard::enter(&self.runtime)?;
+            let mut args = Args::new();
+            for arg in arguments.into_inner() {
+                args.push(self.interpret_expression(arg).await?);
+            }
+            Self::trace(&self.runtime, |tracer| {
+                tracer.function_call(call_site, callee_code, &args)
+            });
+
+            let scripted = subroutine.is_scripted();
+            if !scripted {
+                self.audit(call_site, || AuditEvent::HostCall {
+                    callee: callee_code.into(),
+                    args: args.to_vec(),
+                });
+            }
+            self.enter_call(scripted, true, call_site);
+            Self::profile_with(&self.runtime, |profiler| profiler.enter(callee_code));
+            let result = subroutine
+                .call_inline(self.bindings.new_context(), args)
+                .await;
+            Self::profile_with(&self.runtime, Profiler::exit);
+            self.enter_call(scripted, false, call_site);
+            let value = result.map_err(|e| match subroutine.is_scripted() {
+                true => e.called_from(self.function.as_deref(), self.source.as_ref(), call_site),
+                false => e,
+            })?;
+            if !subroutine.is_scripted() {
+                let mut runtime = self.runtime.borrow_mut();
+                runtime.stats.record_host_call();
+                runtime.stats.record_value(&value);
+            }
+            Ok(value)
+        })
+    }
+
+    /// Evaluate the branch of a ternary its condition picks.
+    fn interpret_ternary<'a>(&'a mut self, pair: Pair<'a, Rule>) -> Evaluation<'a> {
+        Box::pin(async move {
+            let mut pair = pair.into_inner();
+            let condition_pair = collapse(pair.next().unwrap());
+            let condition_span = condition_pair.as_span();
+            let mut condition = self.interpret_expression(condition_pair).await?;
+            if self.coercion() == Coercion::Loose {
+                condition = condition.is_truthy().into();
+            }
+            match condition {
+                Value::Boolean(condition) => {
+                    self.cover(&condition_span, |collector, region| {
+                        collector.hit_branch(region, condition)
+                    });
+                    if !condition {
+                        pair.next().unwrap();
+                    }
+                    self.interpret_expression(pair.next().unwrap()).await
+                }
+                _ => Err(JabroniError::from_code(
+                    ErrorCode::NonBooleanCondition,
+                    "Ternary condition must be boolean".into(),
+                )),
+            }
+        })
+    }
+
+    /// Assign the value on the right of an assignment to the lvalue on its left.
+    fn interpret_assignment<'a>(&'a mut self, pair: Pair<'a, Rule>) -> Evaluation<'a> {
+        Box::pin(async move {
+            let mut pairs = pair.into_inner();
+            let lhs = collapse(pairs.next().unwrap());
+            let operator = pairs.next().unwrap();
+            let operator = operator.as_str();
+            let operand = self.interpret_expression(pairs.next().unwrap()).await?;
+            if operator == "=" {
+                let target = (locate(&lhs.as_span(), self.origin), lhs.as_str());
+                self.with_lvalue(lhs, |binding| binding.assign(operand.clone()))
+                    .await?;
+                Self::trace(&self.runtime, |tracer| {
+                    tracer.binding_mutated(target.0, target.1, &operand)
+                });
+                self.audit(target.0, || AuditEvent::BindingMutated {
+                    target: target.1.into(),
+                    value: operand,
+                });
+            } else {
+                return Err(JabroniError::from_code(
+                    ErrorCode::UnsupportedSyntax,
+                    format!("Assignment operator '{operator}' is not supported"),
+                ));
+            }
+            // Assignment return void because we don't want to accidentally assign while trying
+            // to compare
+            Ok(Value::Null)
+        })
+    }
+
+    /// Apply a chain of binary operators of the same precedence, from left to right.
+    fn interpret_operators<'a>(&'a mut self, pair: Pair<'a, Rule>) -> Evaluation<'a> {
+        Box::pin(async move {
+            let mut pairs = pair.into_inner();
+            let mut value = self.interpret_expression(pairs.next().unwrap()).await?;
+            while let Some(operator) = pairs.next() {
+                let operand = self.interpret_expression(pairs.next().unwrap()).await?;
+                self.apply_operator(&mut value, operator.as_str(), operand)?;
+            }
+            Ok(value)
+        })
+    }
+
+    /// Apply a binary operator to the value on its left, replacing it with the result.
+    fn apply_operator(&self, value: &mut Value, operator: &str, operand: Value) -> JabroniResult {
+        if let Some(operator) = Operator::from_symbol(operator) {
+            if let Some(result) = Value::apply_overload(operator, value, &operand)? {
+                *value = result;
+                return Ok(());
+            }
+        }
+        if (operator == "==" || operator == "!=") && self.equality() == Equality::StrictOnly {
+            return Err(JabroniError::from_code(
+                ErrorCode::LooseEquality,
+                format!("'{operator}' isn't allowed. Use '{operator}=' instead"),
+            ));
+        }
+        let loose = self.coercion() == Coercion::Loose;
+        if loose && (operator == "==" || operator == "!=") {
+            *value = (value.loose_eq(&operand) == (operator == "==")).into();
+        } else if operator == "==" {
+            value.compare(operand, false)?;
+        } else if operator == "!=" {
+            value.compare(operand, false)?;
+            value.inverse()?;
+        } else if operator == "===" {
+            value.compare(operand, true)?;
+        } else if operator == "!==" {
+            value.compare(operand, true)?;
+            value.inverse()?;
+        } else if operator == ">" {
+            value.compare_inequality(operand, &|a, b| a > b)?;
+        } else if operator == ">=" {
+            value.compare_inequality(operand, &|a, b| a >= b)?;
+        } else if operator == "<" {
+            value.compare_inequality(operand, &|a, b| a < b)?;
+        } else if operator == "<=" {
+            value.compare_inequality(operand, &|a, b| a <= b)?;
+        } else if operator == "+" {
+            value.add(operand)?;
+        } else if operator == "-" {
+            value.subtract(operand)?;
+        } else if operator == "*" {
+            value.multiply(operand)?;
+        } else {
+            return Err(JabroniError::from_code(
+                ErrorCode::UnsupportedSyntax,
+                format!("Operator '{operator}' is not supported"),
+            ));
+        }
+        Ok(())
+    }
+
+    fn interpret_statement<'a>(&'a mut self, pair: Pair<'a, Rule>) -> Evaluation<'a> {
+        Box::pin(utils::GrowStack::new(async move {
+            self.runtime.borrow_mut().stats.record_instruction();
+            let (span, origin) = (pair.as_span(), self.origin);
+            if pair.as_rule() != Rule::statement {
+                Self::trace(&self.runtime, |tracer| {
+                    tracer.statement_enter(locate(&span, origin), span.as_str())
+                });
+            }
+            // Blocks are skipped, since the debugger pauses at the statements in them
+            let pausable = !matches!(pair.as_rule(), Rule::statement | Rule::block_statement);
+            if pausable {
+                self.cover(&span, Collector::hit_statement);
+                if self.pause_point(&span) {
+                    Suspend::default().await;
+                }
+                if self.spend_budget() {
+                    Suspend::default().await;
+                    self.spend_budget();
+                }
+            }
+            async move {
+                match pair.as_rule() {
+                    Rule::expression => {
+                        self.interpret_expression(pair).await?;
+                    }
+                    Rule::statement => {
+                        return self
+                            .interpret_statement(pair.into_inner().next().unwrap())
+                            .await
+                    }
+                    Rule::block_statement => {
+                        // A function body is the whole of its code, and shares a scope with the
+                        // parameters
+                        let scoped = self.function.is_none() || pair.as_span().start() != 0;
+                        if scoped {
+                            self.bindings.push_scope();
+                        }
+                        let mut result = Ok(Value::Null);
+                        for pair in pair.into_inner() {
+                            result = self.interpret_statement(pair).await;
+                            if result.is_err() {
+                                break;
+                            }
+                        }
+                        if scoped {
+                            self.bindings.pop_scope();
+                        }
+                        return result;
+                    }
+                    Rule::function_statement => self.define_function(pair)?,
+                    Rule::throw_statement => {
+                        let location = locate(&pair.as_span(), self.origin);
+                        let value = self
+                            .interpret_expression(pair.into_inner().next().unwrap())
+                            .await?;
+                        self.audit(location, || AuditEvent::Thrown {
+                            value: value.clone(),
+                        });
+                        return Err(JabroniError::throw(value));
+                    }
+                    Rule::try_statement => return self.interpret_try(pair).await,
+                    Rule::import_statement => return self.interpret_import(pair).await,
+                    Rule::yield_statement => {
+                        let value = match pair.into_inner().next() {
+                            Some(expression) => self.interpret_expression(expression).await?,
+                            None => Value::Null,
+                        };
+                        let runtime = self.runtime.clone();
+                        Yield {
+                            value: Some(value),
+                            runtime,
+                        }
+                        .await?;
+                    }
+                    Rule::return_statement => {
+                        return self
+                            .interpret_expression(pair.into_inner().next().unwrap())
+                            .await;
+                    }
+                    Rule::declaration_statement => return self.interpret_declaration(pair).await,
+                    rule => {
+                        return Err(JabroniError::from_code(
+                            ErrorCode::UnsupportedSyntax,
+                            format!("Statements of type {rule:?} are not supported"),
+                        ))
+                    }
+                }
+                Ok(Value::Null)
+            }
+            .await
+            .map_err(|e| e.located(|| locate(&span, origin), span.as_str().len()))
+        }))
+    }
+
+    /// Define a function from its statement, parsing its body once so calls don't parse it again.
+    fn define_function(&mut self, pair: Pair<Rule>) -> JabroniResult {
+        let definition = pair.as_str();
+        let mut pair = pair.into_inner();
+
+        let function_name = pair.next().unwrap();
+        self.check_global_declaration(function_name.as_str())?;
+        let function: Rc<str> = function_name.as_str().into();
+        let mut params = Vec::new();
+        for param in pair.next().unwrap().into_inner() {
+            params.push(utils::intern(param.as_str()));
+        }
+        let num_args = params.len();
+        let signature = format!("{}({})", function, params.join(", "));
+        let parameters = params.iter().map(|param| Parameter::new(param)).collect();
+
+        let body = pair.next().unwrap();
+        let origin = Some(locate(&body.as_span(), self.origin));
+        let body = Rc::new(FunctionBody::parse(body.as_str())?);
+        let params = Rc::new(params);
+        let runtime = self.runtime.clone();
+        let source = self.source.clone();
+        #[cfg(feature = "jit")]
+        let hot = Rc::new(RefCell::new(HotFunction::new(definition)));
+        let callback = move |mut context: BindingMap, args: Args| {
+            let (body, params, runtime, source, function) = (
+                body.clone(),
+                params.clone(),
+                runtime.clone(),
+                source.clone(),
+                function.clone(),
+            );
+            #[cfg(feature = "jit")]
+            let hot = hot.clone();
+            async move {
+                #[cfg(feature = "jit")]
+                if let Some(value) = Self::call_native(&runtime, &hot, &context, &args) {
+                    return Ok(value);
+                }
+                // Bind params to args. Objects are shared with the caller
+                for (param, arg) in params.iter().zip(args) {
+                    context.set_interned(param.clone(), Binding::constant(arg));
+                }
+                let mut substate = Jabroni {
+                    bindings: context,
+                    runtime,
+                    source,
+                    function: Some(function),
+                    origin,
+                    ..Default::default()
+                };
+
+                substate.evaluate_body(&body).await
+            }
+        };
+        let subroutine = Subroutine::new_async_inline(Some(num_args), callback)
+            .named(function_name.as_str())
+            .with_signature(signature)
+            .with_parameters(parameters)
+            .scripted(definition);
+        self.bindings.set_interned(
+            utils::intern(function_name.as_str()),
+            Binding::constant(Value::Subroutine(subroutine)),
+        );
+        Ok(())
+    }
+
+    /// Run the block of a `try` statement, and its `catch` block if it fails.
+    fn interpret_try<'a>(&'a mut self, pair: Pair<'a, Rule>) -> Evaluation<'a> {
+        Box::pin(async move {
+            let mut pair = pair.into_inner();
+            let block = pair.next().unwrap();
+            let error = match self.interpret_statement(block).await {
+                Ok(value) => return Ok(value),
+                Err(error) => error.raised_in(self.function.as_deref(), self.source.as_ref()),
+            };
+            self.audit_failure(&error);
+            let ident = pair.next().unwrap().as_str();
+            self.bindings.push_scope();
+            self.bindings
+                .set(ident.into(), Binding::constant(error_object(&error)));
+            let result = self.interpret_statement(pair.next().unwrap()).await;
+            self.bindings.pop_scope();
+            result
+        })
+    }
+
+    /// Bind the names an `import` statement lists to the exports of its module.
+    fn interpret_import<'a>(&'a mut self, pair: Pair<'a, Rule>) -> Evaluation<'a> {
+        Box::pin(async move {
+            let mut pair = pair.into_inner();
+            let idents = pair.next().unwrap();
+            let specifier = utils::unquote(pair.next().unwrap().as_str())?;
+            let exports = self.import_module(&specifier).await?;
+            for ident in idents.into_inner() {
+                let ident = ident.as_str();
+                let binding = exports.get(ident).map_err(|_| {
+                    JabroniError::from_code(
+                        ErrorCode::MissingExport,
+                        format!("'{ident}' is not exported by '{specifier}'"),
+                    )
+                })?;
+                self.check_global_declaration(ident)?;
+                self.define_constant(ident, binding.value().clone())?;
+            }
+            Ok(Value::Null)
+        })
+    }
+
+    /// Bind the value of a `const` or `let` declaration.
+    fn interpret_declaration<'a>(&'a mut self, pair: Pair<'a, Rule>) -> Evaluation<'a> {
+        Box::pin(async move {
+            let location = locate(&pair.as_span(), self.origin);
+            let mut pair = pair.into_inner();
+            let kind = pair.next().unwrap().as_str();
+            let ident = pair.next().unwrap();
+            if self.bindings.contains(ident.as_str()) && !self.bindings.has_on_top(ident.as_str()) {
+                self.warn(
+                    &ident.as_span(),
+                    format!("'{}' shadows a binding in an outer scope", ident.as_str()),
+                );
+            }
+            let ident = ident.as_str();
+            self.check_global_declaration(ident)?;
+            let expression = pair.next().unwrap();
+            let value = self.interpret_expression(expression).await?;
+            Self::trace(&self.runtime, |tracer| {
+                tracer.binding_mutated(location, ident, &value)
+            });
+            self.audit(location, || AuditEvent::BindingMutated {
+                target: ident.into(),
+                value: value.clone(),
+            });
+            // Scripts can only bind values that were already defined, so they aren't checked for
+            // determinism again
+            self.define_binding(ident, value, kind == "let")?;
+            Ok(Value::Null)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{errors::ErrorKind, value::Number};
+    use std::time::Duration;
+
+    #[test]
+    fn simple_expressions() {
+        let mut state = Jabroni::new();
+        assert_eq!(state.run_expression("4").unwrap(), 4.into());
+        assert_eq!(state.run_expression("2+2").unwrap(), 4.into());
+        assert_eq!(state.run_expression("3*10-5").unwrap(), 25.into());
+        assert_eq!(state.run_expression("1+(10-7)*3").unwrap(), 10.into());
+        assert_eq!(state.run_expression("2+2==4").unwrap(), true.into());
+        assert_eq!(state.run_expression("2+2!=4").unwrap(), false.into());
+        assert_eq!(state.run_expression("2+2==5").unwrap(), false.into());
+        assert_eq!(state.run_expression("2+2!=5").unwrap(), true.into());
+        assert_eq!(state.run_expression("2+2==4==false").unwrap(), false.into());
+        assert_eq!(state.run_expression("true==true").unwrap(), true.into());
+        assert_eq!(state.run_expression("1==1?100:200").unwrap(), 100.into());
+        assert_eq!(state.run_expression("1==2?100:200").unwrap(), 200.into());
+        assert_eq!(state.run_expression("8===8").unwrap(), true.into());
+        assert_eq!(state.run_expression("null===null").unwrap(), true.into());
+        assert_eq!(state.run_expression("null===0").unwrap(), false.into());
+        assert_eq!(state.run_expression("4>3").unwrap(), true.into());
+        assert_eq!(state.run_expression("4>=3").unwrap(), true.into());
+        assert_eq!(state.run_expression("4>=4").unwrap(), true.into());
+        assert_eq!(state.run_expression("4<=4").unwrap(), true.into());
+        assert_eq!(state.run_expression("4<4").unwrap(), false.into());
+    }
+
+    #[test]
+    fn expression_with_locals() {
+        let mut state = Jabroni::new();
+        state.define_constant("tax", Value::Number(2)).unwrap();
+        for (price, qty) in [(3, 4), (5, 6)] {
+            assert_eq!(
+                state
+                    .run_expression_with(
+                        "price * qty + tax",
+                        &[("price", price.into()), ("qty", qty.into())]
+                    )
+                    .unwrap(),
+                (price * qty + 2).into()
+            );
+        }
+        // Locals don't outlive the call, even when it fails
+        assert!(state.run_expression("price").is_err());
+        assert!(state
+            .run_expression_with("price = 1", &[("price", 0.into())])
+            .is_err());
+        assert!(state.run_expression("price").is_err());
+        // Locals shadow globals
+        assert_eq!(
+            state
+                .run_expression_with("tax", &[("tax", 8.into())])
+                .unwrap(),
+            8.into()
+        );
+    }
+
+    #[test]
+    fn forbid_type_mismatch() {
+        let mut state = Jabroni::new();
+        state.define_variable("x", Value::Number(4)).unwrap();
+        assert!(state.run_expression("4==false").is_err());
+        assert!(state.run_expression("true==4").is_err());
+        assert!(state.run_expression("x=true").is_err());
+        // assignments return void
+        assert!(state.run_expression("4==(x=4)").is_err());
+    }
+
+    #[test]
+    fn constants() {
+        let mut state = Jabroni::new();
+        state.define_constant("FOO", Value::Number(42)).unwrap();
+        assert_eq!(state.run_expression("FOO").unwrap(), Value::Number(42));
+        assert_eq!(state.run_expression("FOO*10").unwrap(), Value::Number(420));
+        // Constants can't be modified
+        assert!(state.run_expression("FOO=8").is_err());
+        // Cannot be defined multiple times
+        assert!(state.define_constant("FOO", Value::Number(42)).is_err());
+        assert!(state.define_variable("FOO", Value::Number(42)).is_err());
+        // Cannot be updated
+        assert!(state.update_variable("FOO", Value::Number(42)).is_err());
+    }
+
+    #[test]
+    fn variables() {
+        let mut state = Jabroni::new();
+        state.define_variable("foo", Value::Number(42)).unwrap();
+
+        assert_eq!(state.run_expression("foo").unwrap(), Value::Number(42));
+
+        // Assign
+        state.run_expression("foo=420").unwrap();
+        assert_eq!(state.run_expression("foo").unwrap(), Value::Number(420));
+
+        // Try to define twice
+        assert!(state.define_variable("foo", Value::Number(42)).is_err());
+        assert!(state.define_constant("foo", Value::Number(42)).is_err());
+
+        // Update
+        state.update_variable("foo", Value::Number(16)).unwrap();
+        assert_eq!(state.run_expression("foo").unwrap(), Value::Number(16));
+    }
+
+    #[test]
+    fn strings() {
+        let mut state = Jabroni::new();
+        state
+            .define_variable("foo", Value::String("Hello World!".into()))
+            .unwrap();
+
+        state.run_expression("foo='Shut up!'").unwrap();
+        assert_eq!(
+            state.run_expression("foo").unwrap(),
+            Value::String("Shut up!".into())
+        );
+
+        state.run_expression("foo='\\''").unwrap();
+        assert_eq!(
+            state.run_expression("foo").unwrap(),
+            Value::String("\'".into())
+        );
+
+        state.run_expression("foo=\"Hello!\"").unwrap();
+        assert_eq!(
+            state.run_expression("foo").unwrap(),
+            Value::String("Hello!".into())
+        );
+
+        state.run_expression("foo='\\n\\t\\r'").unwrap();
+        assert_eq!(
+            state.run_expression("foo").unwrap(),
+            Value::String("\n\t\r".into())
+        );
+
+        state.run_expression("foo='\\\\'").unwrap();
+        assert_eq!(
+            state.run_expression("foo").unwrap(),
+            Value::String("\\".into())
+        );
+    }
+
+    #[test]
+    fn objects() {
+        let mut state = Jabroni::new();
+
+        let mut object = BindingMap::default();
+        object.set("bar".into(), Binding::variable(Value::Number(8)));
+        object.set("baz".into(), Binding::constant(Value::Number(42)));
+        let object = Value::Object(object.into());
+        state.define_variable("foo", object.clone()).unwrap();
+
+        assert_eq!(state.run_expression("foo.bar").unwrap(), Value::Number(8));
+        assert_eq!(state.run_expression("foo.baz").unwrap(), Value::Number(42));
+
+        state.run_expression("foo.bar=0").unwrap();
+        assert!(state.run_expression("foo.baz=1").is_err());
+
+        assert_eq!(state.run_expression("foo.bar").unwrap(), Value::Number(0));
+        assert_eq!(state.run_expression("foo.baz").unwrap(), Value::Number(42));
+
+        // Objects are shared, not copied
+        state
+            .run_script(
+                "
+let alias = foo;
+alias.bar = 5;
+function set(object) {
+    object.bar = 6;
+}
+set(alias);",
+            )
+            .unwrap();
+        assert_eq!(state.run_expression("foo.bar").unwrap(), Value::Number(6));
+        assert_eq!(
+            object.as_object().unwrap().get("bar"),
+            Some(Value::Number(6))
+        );
+
+        // Unless they're copied
+        let copy = object.as_object().unwrap().copy();
+        state.run_expression("foo.bar = 7").unwrap();
+        assert_eq!(copy.get("bar"), Some(Value::Number(6)));
+
+        // `==` compares properties, and `===` compares identity
+        state
+            .define_constant("copy", Value::Object(copy.clone()))
+            .unwrap();
+        assert_eq!(state.run_expression("copy == foo").unwrap(), false.into());
+        state.run_expression("foo.bar = 6").unwrap();
+        assert_eq!(state.run_expression("copy == foo").unwrap(), true.into());
+        assert_eq!(state.run_expression("copy != foo").unwrap(), false.into());
+        assert_eq!(state.run_expression("copy === foo").unwrap(), false.into());
+        assert_eq!(state.run_expression("alias === foo").unwrap(), true.into());
+        assert_eq!(state.run_expression("copy !== foo").unwrap(), true.into());
+        copy.borrow_mut()
+            .set("extra".into(), Binding::constant(Value::Null));
+        assert_eq!(state.run_expression("copy == foo").unwrap(), false.into());
+        assert_eq!(state.run_expression("foo == copy").unwrap(), false.into());
+    }
+
+    #[test]
+    fn lazy_bindings() {
+        use std::{cell::Cell, rc::Rc};
+
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        let mut state = Jabroni::new();
+        state.bindings.set(
+            "unused".into(),
+            Binding::lazy(Box::new(|| panic!("Forced"))),
+        );
+        state.bindings.set(
+            "expensive".into(),
+            Binding::lazy(Box::new(move || {
+                counter.set(counter.get() + 1);
+                Ok(Value::Number(42))
+            })),
+        );
+        state.bindings.set(
+            "broken".into(),
+            Binding::lazy(Box::new(|| {
+                Err(JabroniError::new(ErrorKind::Exception, "oops".into()))
+            })),
+        );
+        let attempts = Rc::new(Cell::new(0));
+        let attempt = attempts.clone();
+        state.bindings.set(
+            "flaky".into(),
+            Binding::lazy(Box::new(move || {
+                attempt.set(attempt.get() + 1);
+                match attempt.get() {
+                    1 => Err(JabroniError::new(ErrorKind::Exception, "busy".into())),
+                    _ => Ok(Value::Number(7)),
+                }
+            })),
+        );
+        assert_eq!(calls.get(), 0);
+
+        state
+            .run_script("function get() {return expensive;}")
+            .unwrap();
+        assert_eq!(state.run_expression("get()").unwrap(), 42.into());
+        assert_eq!(state.run_expression("expensive+1").unwrap(), 43.into());
+        assert_eq!(state.run_expression("get()").unwrap(), 42.into());
+        assert_eq!(calls.get(), 1);
+
+        // Lazy bindings are constant
+        assert!(state.run_expression("expensive=1").is_err());
+
+        // Failures are reported on every access, not turned into null
+        for _ in 0..2 {
+            assert!(matches!(
+                state.run_expression("broken"),
+                Err(e) if e.kind() == ErrorKind::Exception
+            ));
+        }
+        let error = state.bindings.get("broken").unwrap_err();
+        assert_eq!(error.message(), "oops");
+
+        // A provider that failed is tried again, until it succeeds
+        assert!(state.run_expression("flaky").is_err());
+        assert_eq!(state.run_expression("flaky").unwrap(), 7.into());
+        assert_eq!(state.run_expression("flaky").unwrap(), 7.into());
+        assert_eq!(attempts.get(), 2);
+    }
+
+    #[test]
+    fn computed_bindings() {
+        use std::{cell::Cell, rc::Rc};
+
+        let ticks = Rc::new(Cell::new(0));
+        let health = Rc::new(Cell::new(10));
+        let (getter, setter) = (health.clone(), health.clone());
+        let counter = ticks.clone();
+        let mut player = BindingMap::default();
+        player.set(
+            "health".into(),
+            Binding::computed(
+                Box::new(move || Ok(Value::Number(getter.get()))),
+                Some(Box::new(move |value| {
+                    setter.set(value.into_number().unwrap());
+                    Ok(())
+                })),
+            ),
+        );
+        player.set(
+            "ticks".into(),
+            Binding::computed(
+                Box::new(move || {
+                    counter.set(counter.get() + 1);
+                    Ok(Value::Number(counter.get()))
+                }),
+                None,
+            ),
+        );
+        let mut state = Jabroni::new();
+        state
+            .define_constant("player", Value::Object(player.into()))
+            .unwrap();
+
+        state
+            .run_script("function heal() {player.health = player.health + 5;}")
+            .unwrap();
+        state.run_expression("heal()").unwrap();
+        assert_eq!(health.get(), 15);
+        health.set(1);
+        state.run_expression("heal()").unwrap();
+        assert_eq!(health.get(), 6);
+
+        assert_eq!(state.run_expression("player.ticks").unwrap(), 1.into());
+        assert_eq!(state.run_expression("player.ticks").unwrap(), 2.into());
+        assert!(state.run_expression("player.ticks = 0").is_err());
+        assert!(state.run_expression("player.health = 'full'").is_err());
+        assert_eq!(health.get(), 6);
+    }
+
+    #[test]
+    fn bindings_are_read_when_listed() {
+        use std::{cell::Cell, collections::HashMap, rc::Rc};
+
+        let health = Rc::new(Cell::new(10));
+        let getter = health.clone();
+        let mut player = BindingMap::default();
+        player.set(
+            "health".into(),
+            Binding::computed(Box::new(move || Ok(Value::Number(getter.get()))), None),
+        );
+        player.set(
+            "level".into(),
+            Binding::lazy(Box::new(|| Ok(Value::Number(3)))),
+        );
+        let player = Object::new(player);
+
+        assert_eq!(player.to_string(), "{health: 10, level: 3}");
+        health.set(7);
+        assert_eq!(player.to_string(), "{health: 7, level: 3}");
+        assert_eq!(player.get("health"), Some(Value::Number(7)));
+        let properties: HashMap<String, Number> = Value::Object(player.clone()).try_into().unwrap();
+        assert_eq!(properties["health"], 7);
+        assert_eq!(properties["level"], 3);
+        for (_, binding) in player.borrow().iter() {
+            assert_ne!(binding.read().unwrap(), Value::Null);
+        }
+    }
+
+    #[test]
+    fn resolver() {
+        let mut state = Jabroni::new();
+        state.define_constant("x", Value::Number(1)).unwrap();
+        state.run_script("function get_y() {return y;}").unwrap();
+        assert!(matches!(
+            state.run_expression("y"),
+            Err(e) if e.kind() == ErrorKind::Reference
+        ));
+
+        state.set_resolver(|ident| {
+            let mut object = BindingMap::default();
+            object.set(
+                "len".into(),
+                Binding::constant(Value::Number(ident.len() as i64)),
+            );
+            match ident {
+                "x" => Some(Value::Number(100)),
+                "y" => Some(Value::Number(2)),
+                "row" => Some(Value::Object(object.into())),
+                _ => None,
+            }
+        });
+        // Bound identifiers take precedence
+        assert_eq!(state.run_expression("x+y").unwrap(), 3.into());
+        assert_eq!(state.run_expression("get_y()").unwrap(), 2.into());
+        assert_eq!(state.run_expression("row.len").unwrap(), 3.into());
+        assert!(state.run_expression("y=3").is_err());
+        assert!(matches!(
+            state.run_expression("z"),
+            Err(e) if e.kind() == ErrorKind::Reference
+        ));
+    }
+
+    #[test]
+    fn object_method() {
+        fn bar(_: BindingMap, _: &mut [Value]) -> JabroniResult<Value> {
+            Ok(Value::Number(42))
+        }
+
+        let mut state = Jabroni::new();
+        let mut object = BindingMap::default();
+        object.set(
+            "bar".into(),
+            Binding::constant(Value::Subroutine(Subroutine::new(0, Box::new(bar)))),
+        );
+
+        let object = Value::Object(object.into());
+        state.define_variable("foo", object.clone()).unwrap();
+
+        assert_eq!(state.run_expression("foo.bar()").unwrap(), 42.into());
+    }
+
+    #[test]
+    fn object_handler() {
+        use crate::ObjectHandler;
+        use std::cell::RefCell;
+
+        #[derive(Default)]
+        struct Tables(RefCell<Vec<(String, i64)>>);
+
+        impl ObjectHandler for Rc<Tables> {
+            fn has(&self, table: &str) -> bool {
+                table != "missing"
+            }
+
+            fn get(&self, table: &str) -> JabroniResult<Value> {
+                let tables = self.0.borrow();
+                let rows = tables.iter().find(|(name, _)| name == table);
+                Ok(Value::Number(rows.map(|(_, rows)| *rows).unwrap_or(0)))
+            }
+
+            fn set(&self, table: &str, value: Value) -> JabroniResult {
+                let rows = value.into_number().unwrap();
+                self.0.borrow_mut().push((table.into(), rows));
+                Ok(())
+            }
+
+            fn call(&self, table: &str, _: BindingMap, args: &mut [Value]) -> JabroniResult<Value> {
+                Ok(format!("{table}:{}", args.len()).into())
+            }
+        }
+
+        let tables = Rc::new(Tables::default());
+        let mut db = BindingMap::with_handler(tables.clone());
+        db.set("version".into(), Binding::constant(Value::Number(3)));
+        let mut state = Jabroni::new();
+        state
+            .define_constant("db", Value::Object(db.into()))
+            .unwrap();
+
+        assert_eq!(state.run_expression("db.users").unwrap(), 0.into());
+        state.run_expression("db.users = 4").unwrap();
+        assert_eq!(state.run_expression("db.users").unwrap(), 4.into());
+        assert_eq!(tables.0.borrow().len(), 1);
+        assert_eq!(
+            state.run_expression("db.orders(1, 2)").unwrap(),
+            Value::String("orders:2".into())
+        );
+        // Bound properties aren't intercepted
+        assert_eq!(state.run_expression("db.version").unwrap(), 3.into());
+        assert!(state.run_expression("db.version = 4").is_err());
+        assert!(matches!(
+            state.run_expression("db.missing"),
+            Err(e) if e.kind() == ErrorKind::Reference
+        ));
+    }
+
+    #[test]
+    fn operator_overloading() {
+        use crate::ObjectHandler;
+
+        struct Money;
+
+        impl ObjectHandler for Money {
+            fn operate(
+                &self,
+                operator: Operator,
+                lhs: &Value,
+                rhs: &Value,
+            ) -> JabroniResult<Option<Value>> {
+                let cents = |value: &Value| match value {
+                    Value::Object(object) => *object.get("cents").unwrap().as_number().unwrap(),
+                    Value::Number(number) => *number,
+                    _ => 0,
+                };
+                Ok(match operator {
+                    Operator::Multiply => Some(money(cents(lhs) * cents(rhs))),
+                    Operator::Equal => Some(Value::Boolean(cents(lhs) == cents(rhs))),
+                    Operator::Less => Some(Value::Boolean(cents(lhs) < cents(rhs))),
+                    _ => None,
+                })
+            }
+        }
+
+        fn money(cents: i64) -> Value {
+            let mut object = BindingMap::with_handler(Money);
+            object.set("cents".into(), Binding::constant(cents.into()));
+            Value::Object(object.into())
+        }
+
+        let mut state = Jabroni::new();
+        state.define_constant("price", money(150)).unwrap();
+        state.run_script("const total = 3 * price;").unwrap();
+        assert_eq!(state.run_expression("total.cents").unwrap(), 450.into());
+        assert_eq!(state.run_expression("price == 150").unwrap(), true.into());
+        assert_eq!(state.run_expression("price != 150").unwrap(), false.into());
+        assert_eq!(state.run_expression("price < total").unwrap(), true.into());
+        assert!(state.run_expression("price + 1").is_err());
+    }
+
+    #[test]
+    fn events() {
+        use std::cell::Cell;
+
+        let saves = Rc::new(Cell::new(0));
+        let counter = saves.clone();
+        let mut state = Jabroni::new();
+        state.enable_events().unwrap();
+        state.on(
+            "save",
+            Subroutine::new(
+                1,
+                Box::new(move |_, args| {
+                    counter.set(counter.get() + args[0].as_number().unwrap());
+                    Ok(Value::Null)
+                }),
+            ),
+        );
+        state
+            .run_script(
+                "
+            function once(n) {emit('save', n);}
+            function twice(n) {emit('save', n * 2);}
+            on('tick', once);
+            on('tick', twice);
+            emit('save', 10);
+        ",
+            )
+            .unwrap();
+        assert_eq!(saves.get(), 10);
+
+        state.emit("tick", &[3.into()]).unwrap();
+        assert_eq!(saves.get(), 19);
+
+        // Events without handlers are fine
+        state.emit("nothing", &[]).unwrap();
+        assert!(state.run_expression("on('tick', 4)").is_err());
+        assert!(state.run_expression("emit()").is_err());
+    }
+
+    #[test]
+    fn channels() {
+        use std::{collections::HashMap, sync::mpsc};
+
+        let (to_script, from_host) = mpsc::channel();
+        let (to_host, from_script) = mpsc::channel();
+        let mut state = Jabroni::new();
+        state.enable_channels().unwrap();
+        state.add_channel("jobs", to_host, from_host);
+
+        let mut job = HashMap::new();
+        job.insert("id".into(), Message::Number(3));
+        job.insert("kind".into(), Message::String("resize".into()));
+        to_script.send(Message::Object(job)).unwrap();
+        state
+            .run_script(
+                "
+            const jobs = channel('jobs');
+            const job = receive(jobs);
+            send(jobs, job.kind);
+            send(jobs, job);
+        ",
+            )
+            .unwrap();
+        assert_eq!(
+            from_script.recv().unwrap(),
+            Message::String("resize".into())
+        );
+        assert!(matches!(from_script.recv().unwrap(), Message::Object(job) if job.len() == 2));
+
+        assert!(matches!(
+            state.run_expression("send(jobs, channel)"),
+            Err(e) if e.kind() == ErrorKind::Type
+        ));
+        assert!(matches!(
+            state.run_expression("channel('missing')"),
+            Err(e) if e.kind() == ErrorKind::Reference
+        ));
+        assert!(state.run_expression("send('jobs', 1)").is_err());
+
+        // Closed channels
+        drop(to_script);
+        assert_eq!(state.run_expression("receive(jobs)").unwrap(), Value::Null);
+        drop(from_script);
+        assert!(matches!(
+            state.run_expression("send(jobs, 1)"),
+            Err(e) if e.kind() == ErrorKind::Exception
+        ));
+    }
+
+    #[test]
+    fn permissions() {
+        let mut state = Jabroni::new();
+        let env = state.guard(
+            Permission::Env,
+            Subroutine::new(1, Box::new(|_, args| Ok(format!("${}", args[0]).into()))),
+        );
+        let fetch = state.guard(
+            Permission::Net,
+            Subroutine::new_async(Some(0), |_, _| async { Ok(Value::Number(200)) }),
+        );
+        state
+            .define_constant("env", Value::Subroutine(env))
+            .unwrap();
+        state
+            .define_constant("fetch", Value::Subroutine(fetch))
+            .unwrap();
+        state
+            .run_script("function home() {return env('HOME');}")
+            .unwrap();
+
+        assert_eq!(
+            state.run_expression("home()").unwrap(),
+            Value::String("$HOME".into())
+        );
+        assert_eq!(state.run_expression("fetch()").unwrap(), 200.into());
+
+        state.set_permissions(Permissions::all().without(Permission::Env));
+        assert!(matches!(
+            state.run_expression("home()"),
+            Err(e) if e.kind() == ErrorKind::Permission
+        ));
+        assert_eq!(state.run_expression("fetch()").unwrap(), 200.into());
+
+        state.set_permissions(Permissions::none().with(Permission::Env));
+        assert!(state.permissions().allows(Permission::Env));
+        assert!(state.run_expression("home()").is_ok());
+        assert!(matches!(
+            state.run_expression("fetch()"),
+            Err(e) if e.kind() == ErrorKind::Permission
+        ));
+        // Arguments are still checked
+        assert!(matches!(
+            state.run_expression("env()"),
+            Err(e) if e.kind() == ErrorKind::InvalidArguments
+        ));
+    }
+
+    #[test]
+    fn deterministic() {
+        let random = |state: &Jabroni| {
+            state.guard(
+                Permission::Random,
+                Subroutine::new(0, Box::new(|_, _| Ok(Value::Number(4)))).named("random"),
+            )
+        };
+        let nondeterministic =
+            |error: JabroniError| error.code() == Some(ErrorCode::Nondeterministic);
+
+        let mut state = Jabroni::new();
+        assert!(!state.is_deterministic());
+        state.set_deterministic(true).unwrap();
+        assert!(state.is_deterministic());
+        let error = state
+            .define_constant("random", Value::Subroutine(random(&state)))
+            .unwrap_err();
+        assert!(nondeterministic(error));
+        // Including when it's nested in an object
+        let mut math = BindingMap::default();
+        math.set("random".into(), Binding::constant(random(&state).into()));
+        assert!(nondeterministic(
+            state.define_constant("math", math.into()).unwrap_err()
+        ));
+        state.define_variable("f", Value::Null).unwrap();
+        assert!(nondeterministic(
+            state
+                .update_variable("f", random(&state).into())
+                .unwrap_err()
+        ));
+        state.set_resolver(move |_| {
+            Some(Value::Subroutine(
+                Subroutine::new_variadic(Box::new(|_, _| Ok(Value::Null))).nondeterministic(),
+            ))
+        });
+        assert!(nondeterministic(state.run_expression("g").unwrap_err()));
+        // Other permissions don't make functions nondeterministic
+        let env = state.guard(
+            Permission::Env,
+            Subroutine::new(0, Box::new(|_, _| Ok(Value::Null))),
+        );
+        state.define_constant("env", env.into()).unwrap();
+
+        let mut state = Jabroni::new();
+        state
+            .define_constant("random", Value::Subroutine(random(&state)))
+            .unwrap();
+        let error = state.set_deterministic(true).unwrap_err();
+        assert!(error.to_string().contains("'random'"));
+        assert!(!state.is_deterministic());
+
+        // Objects from hash maps are always in the same order
+        let scores: HashMap<_, _> = (0..20)
+            .map(|i| (format!("p{i}"), Value::Number(i)))
+            .collect();
+        let object = Value::from(scores);
+        let object = object.as_object().unwrap().borrow();
+        let names: Vec<_> = object.iter().map(|(ident, _)| ident).collect();
+        let mut sorted = names.clone();
+        sorted.sort_unstable();
+        assert_eq!(names, sorted);
+    }
+
+    #[test]
+    fn frozen_globals() {
+        let frozen = |error: JabroniError| error.code() == Some(ErrorCode::FrozenGlobal);
+        let mut state = Jabroni::new();
+        state.define_variable("total", Value::Number(0)).unwrap();
+        let mut config = BindingMap::default();
+        config.set("limit".into(), Binding::variable(Value::Number(5)));
+        state.define_constant("config", config.into()).unwrap();
+        state
+            .run_script("function add(n) {total = total + n; return total;}")
+            .unwrap();
+        state.set_globals_frozen(true);
+        assert!(state.globals_frozen());
+
+        // Locals can be declared and assigned, even where they shadow globals
+        assert_eq!(
+            state
+                .run_script(
+                    "let total = 1; function twice(n) {let m = n * 2; m = m + total; return m;} \
+                     {const x = 3; total = twice(x);} return total;"
+                )
+                .unwrap(),
+            Value::Number(7)
+        );
+        assert!(state.run_expression("twice").is_err());
+        assert!(frozen(state.run_expression("add(1)").unwrap_err()));
+        assert!(frozen(state.run_expression("total = 1").unwrap_err()));
+        assert!(frozen(
+            state.reload_script("plugin", "const x = 1;").unwrap_err()
+        ));
+        assert_eq!(state.run_expression("total").unwrap(), Value::Number(0));
+        // Properties of global objects aren't frozen
+        state.run_expression("config.limit = 10").unwrap();
+        // The host can still change globals
+        state.update_variable("total", Value::Number(2)).unwrap();
+        state.define_constant("extra", Value::Null).unwrap();
+
+        state.set_globals_frozen(false);
+        assert_eq!(state.run_expression("add(1)").unwrap(), Value::Number(3));
+        state.run_script("let n = 1;").unwrap();
+        assert_eq!(state.run_expression("n").unwrap(), Value::Number(1));
+    }
+
+    #[test]
+    fn sandbox() {
+        let mut state = Jabroni::new();
+        state.define_variable("count", Value::Number(0)).unwrap();
+        state.set_sandbox(Sandbox::new().without(Construct::Functions).deny("secret"));
+        assert!(!state.sandbox().allows(Construct::Functions));
+
+        // Scripts are checked before any of them runs
+        let error = state
+            .run_script("count = 1;\nfunction f() {return 1;}")
+            .unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::Sandboxed));
+        assert_eq!(error.location().unwrap().line, 2);
+        assert_eq!(state.run_expression("count").unwrap(), Value::Number(0));
+        assert!(state.run_expression("secret").is_err());
+        assert!(state.render_template("${secret}").is_err());
+        assert!(state.reload_script("plugin", "function f() {}").is_err());
+        assert!(state.run_script("count = count + 1;").is_ok());
+
+        state.set_sandbox(Sandbox::new());
+        assert!(state.run_script("function f() {return 1;}").is_ok());
+    }
+
+    #[test]
+    fn evaluate_pure() {
+        let mut state = Jabroni::new();
+        let double = Subroutine::new(
+            1,
+            Box::new(|_, args| Ok(Value::Number(args[0].as_number().unwrap() * 2))),
+        );
+        state
+            .define_constant("double", double.pure().into())
+            .unwrap();
+        state
+            .run_script("let calls = 0; function count(n) {calls = calls + 1; return n;}")
+            .unwrap();
+
+        assert_eq!(
+            state.evaluate_pure("double(double(3)) === 12").unwrap(),
+            Value::Boolean(true)
+        );
+        let error = state.evaluate_pure("double(count(1))").unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::ImpureCall));
+        let error = state.evaluate_pure("1 + (calls = 5)").unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::Sandboxed));
+        assert_eq!(state.run_expression("calls").unwrap(), Value::Number(0));
+        // Other entry points can call impure functions again
+        assert_eq!(state.run_expression("count(2)").unwrap(), Value::Number(2));
+    }
+
+    #[test]
+    fn audit() {
+        use crate::audit::AuditEvent;
+
+        let mut state = Jabroni::new();
+        let mut player = BindingMap::default();
+        player.set("hp".into(), Binding::variable(Value::Number(10)));
+        state.define_constant("player", player.into()).unwrap();
+        let notify = Subroutine::new(2, Box::new(|_, _| Ok(Value::Null)));
+        state.define_constant("notify", notify.into()).unwrap();
+        assert!(state.audit_log().is_none());
+        state.start_audit();
+        state
+            .run_script(
+                "function hit(n) {player.hp = player.hp - n; notify('hit', player);}\n\
+                 hit(3);\n\
+                 try {throw 'oops';} catch (e) {}\n\
+                 try {const x = 1 + 'a';} catch (e) {}",
+            )
+            .unwrap();
+        assert!(state.run_expression("player.hp = 'x' + 1").is_err());
+        let log = state.stop_audit().unwrap();
+        assert!(state.audit_log().is_none());
+
+        let events: Vec<_> = log
+            .entries
+            .iter()
+            .map(|entry| (entry.location.map(|location| location.line), &entry.event))
+            .collect();
+        assert_eq!(events.len(), 5);
+        assert!(matches!(
+            events[0],
+            (Some(1), AuditEvent::BindingMutated { target, value })
+                if target == "player.hp" && *value == Value::Number(7)
+        ));
+        match events[1] {
+            (Some(1), AuditEvent::HostCall { callee, args }) => {
+                assert_eq!(callee, "notify");
+                // Objects are copied, so they're as they were when recorded
+                let player = args[1].as_object().unwrap();
+                assert_eq!(player.get("hp"), Some(Value::Number(7)));
+            }
+            event => panic!("Unexpected event {event:?}"),
+        }
+        assert!(matches!(
+            events[2],
+            (Some(3), AuditEvent::Thrown { value }) if *value == Value::String("oops".into())
+        ));
+        assert!(matches!(
+            events[3],
+            (
+                Some(4),
+                AuditEvent::Failed {
+                    kind: ErrorKind::Type,
+                    ..
+                }
+            )
+        ));
+        assert!(matches!(
+            events[4],
+            (
+                Some(1),
+                AuditEvent::Failed {
+                    kind: ErrorKind::Type,
+                    ..
+                }
+            )
+        ));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_runtime() {
+        use crate::channels::Message;
+        use std::io::{Read, Write};
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let mut state = Jabroni::new();
+        state
+            .install_async_runtime(runtime.handle().clone())
+            .unwrap();
+        let mut log = BindingMap::default();
+        log.set("digits".into(), Binding::variable(Value::Number(0)));
+        state.define_constant("log", log.into()).unwrap();
+        let double = Subroutine::new_spawned(runtime.handle(), Some(1), |args| async move {
+            match &args[0] {
+                Message::Number(n) => Ok(Message::Number(n * 2)),
+                _ => Ok(Message::Null),
+            }
+        });
+        state.define_constant("double", double.into()).unwrap();
+
+        // Timers run in order of their deadlines, after the script, unless they're cleared
+        let code = "function write(digit) {log.digits = log.digits * 10 + digit;}\n\
+                    setTimeout(write, 30, 3);\n\
+                    const cleared = setTimeout(write, 20, 9);\n\
+                    setTimeout(write, 10, 2);\n\
+                    setTimeout(write, 10, double(2));\n\
+                    clearTimeout(cleared);\n\
+                    sleep(5);\n\
+                    write(1);\n\
+                    return log.digits;";
+        let result = runtime.block_on(state.run_script_async(code)).unwrap();
+        assert_eq!(result, 1.into());
+        assert_eq!(state.run_expression("log.digits").unwrap(), 1243.into());
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let read = stream.read(&mut request).unwrap();
+            assert!(String::from_utf8_lossy(&request[..read]).starts_with("GET /status HTTP/1.0"));
+            stream
+                .write_all(b"HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\nup")
+                .unwrap();
+        });
+        let code = format!("return fetch('http://{address}/status');");
+        // Hosts choose whether scripts can fetch
+        assert!(runtime.block_on(state.run_script_async(&code)).is_err());
+        state.enable_fetch().unwrap();
+        let response = runtime.block_on(state.run_script_async(&code)).unwrap();
+        let response = response.as_object().unwrap();
+        assert_eq!(response.get("status"), Some(Value::Number(200)));
+        assert_eq!(response.get("body"), Some(Value::String("up".into())));
+        server.join().unwrap();
+
+        let error = runtime
+            .block_on(state.run_script_async("fetch('https://example.com');"))
+            .unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::InvalidArgument));
+        assert!(error.to_string().contains("'https' URLs aren't supported"));
+        // Nothing can be added to the request, not even to an unreachable server
+        let error = runtime
+            .block_on(state.run_script_async("fetch('http://192.0.2.1/\\r\\nX-Injected: 1');"))
+            .unwrap_err();
+        assert!(error.to_string().contains("control characters"));
+
+        // Responses are only read up to a limit
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // Read the request, so closing the connection doesn't reset it
+            assert!(stream.read(&mut [0; 1024]).unwrap() > 0);
+            let _ = stream.write_all(b"HTTP/1.0 200 OK\r\n\r\n");
+            let chunk = [b'x'; 1024];
+            for _ in 0..5 * 1024 {
+                if stream.write_all(&chunk).is_err() {
+                    break;
+                }
+            }
+        });
+        let code = format!("return fetch('http://{address}/');");
+        let error = runtime.block_on(state.run_script_async(&code)).unwrap_err();
+        assert!(error.to_string().contains("larger than"), "{error}");
+        server.join().unwrap();
+
+        state.set_permissions(Permissions::none());
+        let error = runtime.block_on(state.run_script_async(&code)).unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::PermissionDenied));
+    }
+    #[test]
+    fn reload_script() {
+        let mut state = Jabroni::new();
+        state.define_variable("calls", Value::Number(0)).unwrap();
+        let conflicts = state
+            .reload_script(
+                "plugin",
+                "
+            const name = 'plugin';
+            let lives = 3;
+            const speed = 1;
+            function old() {return 0;}
+            function jump() {return 1;}
+            calls = calls + 1;
+            lives = 2;
+        ",
+            )
+            .unwrap();
+        assert!(conflicts.is_empty());
+        assert_eq!(state.run_expression("calls").unwrap(), 1.into());
+
+        let conflicts = state
+            .reload_script(
+                "plugin",
+                "
+            const name = 'plugin';
+            let lives = 'three';
+            let speed = 1;
+            const power = 9;
+            function jump() {return lives * 10;}
+            function calls() {return 0;}
+            calls = calls + 1;
+        ",
+            )
+            .unwrap();
+        assert_eq!(
+            conflicts,
+            vec![
+                ReloadConflict::Type("lives".into()),
+                ReloadConflict::DeclarationKind("speed".into()),
+                ReloadConflict::NotAFunction("calls".into()),
+            ]
+        );
+        // State is kept and statements aren't rerun
+        assert_eq!(state.run_expression("calls").unwrap(), 1.into());
+        assert_eq!(state.run_expression("jump()").unwrap(), 20.into());
+        assert_eq!(state.run_expression("power").unwrap(), 9.into());
+        assert!(state.run_expression("old()").is_err());
+
+        // Errors in a reload are reported
+        assert!(state.reload_script("plugin", "function (").is_err());
+    }
+
+    #[test]
+    fn modules() {
+        use crate::ModuleLoader;
+        use std::cell::Cell;
+
+        #[derive(Default)]
+        struct Loader(Rc<Cell<i32>>);
+
+        impl ModuleLoader for Loader {
+            fn load(&self, specifier: &str) -> JabroniResult<String> {
+                self.0.set(self.0.get() + 1);
+                Ok(match specifier {
+                    "utils" => "const base = 10; function helper(x) {return x + base;}",
+                    "derived" => "import { helper } from 'utils'; const value = helper(host);",
+                    "ouroboros" => "import { x } from 'ouroboros';",
+                    "broken" => "throw 'broken';",
+                    _ => {
+                        return Err(JabroniError::new(
+                            ErrorKind::Reference,
+                            format!("No module '{specifier}'"),
+                        ))
+                    }
+                }
+                .into())
+            }
+        }
+
+        let mut state = Jabroni::new();
+        assert!(state.run_script("import { helper } from 'utils';").is_err());
+
+        let loads = Rc::new(Cell::new(0));
+        state.set_module_loader(Loader(loads.clone()));
+        state.define_constant("host", Value::Number(5)).unwrap();
+        state
+            .run_script("import { helper } from 'utils'; import { value } from 'derived';")
+            .unwrap();
+        assert_eq!(state.run_expression("helper(1)").unwrap(), 11.into());
+        assert_eq!(state.run_expression("value").unwrap(), 15.into());
+        // Only the imported names are defined
+        assert!(state.run_expression("base").is_err());
+
+        // Modules are only evaluated once
+        assert_eq!(loads.get(), 2);
+        state
+            .run_script("function f() {import { base } from 'utils'; return base;}")
+            .unwrap();
+        assert_eq!(state.run_expression("f()").unwrap(), 10.into());
+        assert_eq!(loads.get(), 2);
+
+        assert!(matches!(
+            state.run_script("import { nothing } from 'utils';"),
+            Err(e) if e.kind() == ErrorKind::Reference
+        ));
+        assert!(matches!(
+            state.run_script("import { helper } from 'utils';"),
+            Err(e) if e.kind() == ErrorKind::DoubleDefinition
+        ));
+        assert!(state.run_script("import { x } from 'ouroboros';").is_err());
+        assert!(state.run_script("import { x } from 'missing';").is_err());
+        assert!(matches!(
+            state.run_script("import { x } from 'broken';"),
+            Err(e) if e.kind() == ErrorKind::Exception
+        ));
+    }
+
+    #[test]
+    fn call_rust_function() {
+        let mut state = Jabroni::new();
+        fn foo(_: BindingMap, _: &mut [Value]) -> JabroniResult<Value> {
+            Ok(Value::Number(42))
+        }
+        state
+            .define_constant("foo", Value::Subroutine(Subroutine::new(0, Box::new(foo))))
+            .unwrap();
+        assert_eq!(state.run_expression("foo()").unwrap(), 42.into());
+    }
+
+    #[test]
+    fn async_functions() {
+        use std::{
+            future::{self, Future},
+            pin::Pin,
+            sync::Arc,
+            task::{Context, Poll, Wake, Waker},
+        };
+
+        /// Pending on the first poll, like a request that hasn't come back yet.
+        struct Request(bool, Value);
+
+        impl Future for Request {
+            type Output = JabroniResult<Value>;
+
+            fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+                if self.0 {
+                    return Poll::Ready(Ok(self.1.clone()));
+                }
+                self.0 = true;
+                context.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+
+        struct NoopWaker;
+
+        impl Wake for NoopWaker {
+            fn wake(self: Arc<Self>) {}
+        }
+
+        fn run<T>(future: impl Future<Output = T>) -> T {
+            let waker = Waker::from(Arc::new(NoopWaker));
+            let mut future = Box::pin(future);
+            loop {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut Context::from_waker(&waker))
+                {
+                    return output;
+                }
+            }
+        }
+
+        let mut state = Jabroni::new();
+        state
+            .define_constant(
+                "fetch",
+                Value::Subroutine(Subroutine::new_async(Some(1), |_, args| {
+                    Request(false, args[0].clone())
+                })),
+            )
+            .unwrap();
+        state
+            .define_constant(
+                "cached",
+                Value::Subroutine(Subroutine::new_async(None, |_, args| {
+                    future::ready(Ok(Value::Number(args.len() as i64)))
+                })),
+            )
+            .unwrap();
+        state
+            .run_script("function double(x) {return fetch(x) * 2;}")
+            .unwrap();
+
+        run(state.run_script_async("const y = double(4) + fetch(1);")).unwrap();
+        assert_eq!(state.run_expression("y").unwrap(), 9.into());
+        assert_eq!(
+            run(state.run_expression_async("fetch('done')")).unwrap(),
+            Value::String("done".into())
+        );
+
+        // Synchronous entry points can only call async functions that don't wait
+        assert_eq!(state.run_expression("cached(1, 2)").unwrap(), 2.into());
+        assert!(matches!(
+            state.run_expression("double(1)"),
+            Err(e) if e.kind() == ErrorKind::Async
+        ));
+        assert!(matches!(
+            run(state.run_expression_async("fetch()")),
+            Err(e) if e.kind() == ErrorKind::InvalidArguments
+        ));
+    }
+
+    #[test]
+    fn resumable_scripts() {
+        use std::cell::Cell;
+
+        let tick = Rc::new(Cell::new(0));
+        let clock = tick.clone();
+        let mut state = Jabroni::new();
+        state
+            .define_constant(
+                "tick",
+                Value::Subroutine(Subroutine::new(
+                    0,
+                    Box::new(move |_, _| Ok(Value::Number(clock.get()))),
+                )),
+            )
+            .unwrap();
+        state
+            .run_script("function wait() {yield; return tick();}")
+            .unwrap();
+
+        let mut script = state.run_resumable("let t = tick(); yield t; t = wait(); yield t * 10;");
+        assert_eq!(script.resume().unwrap(), Step::Yielded(0.into()));
+        tick.set(1);
+        assert_eq!(script.resume().unwrap(), Step::Yielded(Value::Null));
+        tick.set(2);
+        assert_eq!(script.resume().unwrap(), Step::Yielded(20.into()));
+        assert!(!script.is_complete());
+        assert_eq!(script.resume().unwrap(), Step::Complete(Value::Null));
+        assert!(script.is_complete());
+        assert!(matches!(script.resume(), Err(e) if e.kind() == ErrorKind::Async));
+        drop(script);
+        assert_eq!(state.run_expression("t").unwrap(), 2.into());
+
+        // Errors are reported when resuming
+        let mut script = state.run_resumable("yield 1; throw 'oops';");
+        assert_eq!(script.resume().unwrap(), Step::Yielded(1.into()));
+        assert!(matches!(script.resume(), Err(e) if e.kind() == ErrorKind::Exception));
+        drop(script);
+
+        // Scripts can only yield when resumable
+        assert!(matches!(
+            state.run_script("yield 1;"),
+            Err(e) if e.kind() == ErrorKind::Async
+        ));
+        assert!(matches!(
+            state.run_expression("wait()"),
+            Err(e) if e.kind() == ErrorKind::Async
+        ));
+    }
+
+    #[test]
+    fn incremental_scripts() {
+        use std::cell::Cell;
+
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        let mut state = Jabroni::new();
+        let count = Subroutine::new(
+            0,
+            Box::new(move |_, _| {
+                counter.set(counter.get() + 1);
+                Ok(Value::Number(counter.get()))
+            }),
+        );
+        state.define_constant("count", count.into()).unwrap();
+        state
+            .run_script("function twice() {count(); return count();}")
+            .unwrap();
+
+        let mut script = state.run_incremental("count(); twice(); yield 5; return count();");
+        assert_eq!(calls.get(), 0);
+        assert_eq!(script.step(1).unwrap(), Progress::Pending);
+        assert_eq!(calls.get(), 1);
+        // Statements in functions count against the budget
+        assert_eq!(script.step(2).unwrap(), Progress::Pending);
+        assert_eq!(calls.get(), 2);
+        // Yielding ends a step early
+        assert_eq!(script.step(100).unwrap(), Progress::Pending);
+        assert_eq!(calls.get(), 3);
+        assert!(!script.is_complete());
+        assert_eq!(script.step(100).unwrap(), Progress::Done(4.into()));
+        assert!(script.is_complete());
+        assert!(matches!(script.step(1), Err(e) if e.kind() == ErrorKind::Async));
+        drop(script);
+
+        // Errors are reported when stepping
+        let mut script = state.run_incremental("count(); throw 'oops';");
+        assert_eq!(script.step(1).unwrap(), Progress::Pending);
+        assert!(matches!(script.step(1), Err(e) if e.kind() == ErrorKind::Exception));
+        drop(script);
+
+        // Other runs aren't limited once the script is dropped
+        let mut script = state.run_incremental("count(); count();");
+        assert_eq!(script.step(1).unwrap(), Progress::Pending);
+        drop(script);
+        assert_eq!(
+            state.run_script("count(); return count();").unwrap(),
+            8.into()
+        );
+    }
+
+    #[test]
+    fn identifiers_starting_with_keywords() {
+        let mut state = Jabroni::new();
+        let code = "let yield_count = 1; let yielded = 2; yield_count = yield_count + yielded; \
+                    return yield_count;";
+        assert_eq!(state.run_script(code).unwrap(), Value::Number(3));
+        let mut script = state.run_resumable("yield yielded;");
+        assert_eq!(script.resume().unwrap(), Step::Yielded(Value::Number(2)));
+    }
+
+    #[test]
+    fn run_stats() {
+        let mut state = Jabroni::new();
+        assert_eq!(state.last_run_stats(), RunStats::default());
+        state
+            .define_constant(
+                "host",
+                Value::Subroutine(Subroutine::new(0, Box::new(|_, _| Ok(Value::Number(1))))),
+            )
+            .unwrap();
+        state
+            .run_script("function twice() {return host() + host();}")
+            .unwrap();
+
+        state
+            .run_script("const a = twice(); const b = twice();")
+            .unwrap();
+        let stats = state.last_run_stats();
+        assert_eq!(stats.host_calls, 4);
+        assert_eq!(stats.allocations, 0);
+        // Memory is only measured when asked for
+        assert_eq!(stats.peak_memory, 0);
+        assert!(stats.parse_time > Duration::ZERO);
+        assert!(stats.parse_time <= stats.elapsed);
+        let instructions = stats.instructions;
+
+        // Stats only cover the last run
+        state.run_expression("twice()").unwrap();
+        let stats = state.last_run_stats();
+        assert_eq!(stats.host_calls, 2);
+        assert!(stats.instructions < instructions);
+
+        state.set_measure_memory(true);
+        state
+            .run_script("let s = 'a long string that takes up memory'; s = '';")
+            .unwrap();
+        let stats = state.last_run_stats();
+        assert_eq!(stats.allocations, 2);
+        assert!(stats.peak_memory >= 32);
+        // The long string was replaced, so less memory is held now
+        state.run_expression("s").unwrap();
+        assert!(state.last_run_stats().peak_memory < stats.peak_memory);
+
+        // Failed runs are measured too
+        assert!(state.run_expression("host() + missing").is_err());
+        assert_eq!(state.last_run_stats().host_calls, 1);
+    }
+
+    #[test]
+    fn named_sources() {
+        use crate::ModuleLoader;
+
+        struct Loader;
+
+        impl ModuleLoader for Loader {
+            fn load(&self, _: &str) -> JabroniResult<String> {
+                Ok("function fail() {throw 'from module';}".into())
+            }
+        }
+
+        let mut state = Jabroni::new();
+        state.set_module_loader(Loader);
+        state
+            .run_script_named("lib.jab", "function broken() {return missing;}")
+            .unwrap();
+
+        let error = state
+            .run_script_named("main.jab", "const x = ;")
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Parse);
+        assert_eq!(error.source_name(), Some("main.jab"));
+
+        // Errors are attributed to the script that defined the function
+        let error = state.run_script_named("main.jab", "broken();").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Reference);
+        assert_eq!(error.source_name(), Some("lib.jab"));
+        assert!(error.to_string().starts_with("lib.jab: ReferenceError: "));
+        let error = state.run_expression("broken()").unwrap_err();
+        assert_eq!(error.source_name(), Some("lib.jab"));
+        let error = state
+            .run_expression_named("<eval>", "broken()")
+            .unwrap_err();
+        assert_eq!(error.source_name(), Some("lib.jab"));
+        let error = state.run_expression_named("<eval>", "missing").unwrap_err();
+        assert_eq!(error.source_name(), Some("<eval>"));
+
+        let error = state
+            .run_script_named("main.jab", "import { fail } from 'util'; fail();")
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Exception);
+        assert_eq!(error.source_name(), Some("util"));
+
+        // Unnamed scripts don't have a source
+        let error = state.run_script("throw 1;").unwrap_err();
+        assert_eq!(error.source_name(), None);
+        assert_eq!(error.to_string(), "Uncaught exception: 1");
+    }
+
+    #[test]
+    fn error_locations() {
+        let location = |line, column| Some(Location { line, column });
+        let mut state = Jabroni::new();
+        state.define_variable("n", Value::Number(0)).unwrap();
+
+        let error = state.run_script("n = 1;\nn = 'one';").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Type);
+        assert_eq!(error.location(), location(2, 1));
+        assert_eq!(
+            state
+                .run_expression("1 + (2 + true)")
+                .unwrap_err()
+                .location(),
+            location(1, 6)
+        );
+        let error = state.run_script("const x = 1;\n  const y = ;").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Parse);
+        assert_eq!(error.location(), location(2, 13));
+
+        // Errors in functions point into the function body
+        state
+            .run_script(
+                "
+function outer() {
+    return inner();
+}
+function inner() {return 1 + missing;}
+",
+            )
+            .unwrap();
+        let error = state.run_script("\nouter();").unwrap_err();
+        assert_eq!(error.location(), location(5, 30));
+        assert_eq!(error.message(), "'missing' does not exist");
+
+        // Errors from host functions point at the call
+        state
+            .define_constant(
+                "fail",
+                Value::Subroutine(Subroutine::new(
+                    0,
+                    Box::new(|_, _| Err(JabroniError::new(ErrorKind::Type, "oops".into()))),
+                )),
+            )
+            .unwrap();
+        assert_eq!(
+            state.run_script("n = 2;   fail();").unwrap_err().location(),
+            location(1, 10)
+        );
+    }
+
+    #[test]
+    fn check_syntax() {
+        assert!(Jabroni::check("const x = 1;\nfunction f(a) {\n    return a;\n}\n").is_empty());
+
+        let diagnostics = Jabroni::check(
+            "
+const a = ;
+function f() {
+    const s = '};';
+    return 1 +;
+}
+let b = 2;
+b = = 3;
+const c = 4",
+        );
+        let locations: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.location.line, diagnostic.location.column))
+            .collect();
+        assert_eq!(locations, [(2, 11), (5, 15), (8, 5), (9, 12)]);
+        assert!(diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.severity == Severity::Error));
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "2:11: error: Expected expression"
+        );
+        assert!(diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.error_code == Some(ErrorCode::Syntax)));
+    }
+
+    #[test]
+    fn warnings() {
+        let mut state = Jabroni::new();
+        state.define_variable("total", Value::Number(0)).unwrap();
+        state
+            .run_script(
+                "
+function add(n) {
+    let total = n;
+    const unused = 1;
+    const doubled = n * 2;
+    return doubled;
+}
+const flag = (total = 1) ? 1 : 2;",
+            )
+            .unwrap_err();
+        state.run_script("add(1); add(2);").unwrap();
+        let messages: Vec<_> = state
+            .diagnostics()
+            .into_iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        // Only warnings from the last run are kept, and each only once
+        assert_eq!(
+            messages,
+            ["3:9: warning: 'total' shadows a binding in an outer scope"]
+        );
+
+        state
+            .run_script("function f() {let x = 1; return x;} const y = (total = 1) ? 1 : 2;")
+            .unwrap_err();
+        let messages: Vec<_> = state
+            .diagnostics()
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect();
+        assert_eq!(
+            messages,
+            ["Assignment used as a condition; did you mean '=='?"]
+        );
+
+        state
+            .run_script("function g() {const unused = 1; const z = 2; return z;}")
+            .unwrap();
+        let diagnostics = state.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "1:21: warning: 'unused' is declared but never used"
+        );
+    }
+
+    #[test]
+    fn typecheck() {
+        let mut state = Jabroni::new();
+        state.define_variable("count", Value::Number(0)).unwrap();
+        state
+            .define_constant(
+                "pair",
+                Value::Subroutine(Subroutine::new(2, Box::new(|_, _| Ok(Value::Null)))),
+            )
+            .unwrap();
+        let code = "
+function add(a, b) { return a + b; }
+const greeting = 'hi';
+const product = greeting * 3;
+add(1);
+pair(1, 2, 3);
+greeting();
+count = 'many';
+greeting = 'hello';
+const truthy = count ? 1 : 2;
+const same = greeting == 1;
+const length = count.length;";
+        let messages: Vec<_> = state
+            .typecheck(code)
+            .into_iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "4:17: error: '*' expects numbers, found string",
+                "5:1: error: 'add' takes 2 arguments, but 1 were given",
+                "6:1: error: 'pair' takes 2 arguments, but 3 were given",
+                "7:1: error: 'greeting' is string, not a function",
+                "8:1: error: Cannot assign string to 'count', which holds number",
+                "9:1: error: Cannot assign to constant 'greeting'",
+                "10:16: error: Condition must be a boolean, found number",
+                "11:14: error: Cannot compare string with number using '=='",
+                "12:16: error: Cannot access 'length' on number",
+            ]
+        );
+        let codes: Vec<_> = state
+            .typecheck(code)
+            .into_iter()
+            .map(|diagnostic| diagnostic.error_code.unwrap())
+            .collect();
+        assert_eq!(
+            codes,
+            [
+                ErrorCode::OperandTypeMismatch,
+                ErrorCode::ArgumentCount,
+                ErrorCode::ArgumentCount,
+                ErrorCode::NotAFunction,
+                ErrorCode::AssignmentTypeMismatch,
+                ErrorCode::ConstantAssignment,
+                ErrorCode::NonBooleanCondition,
+                ErrorCode::OperandTypeMismatch,
+                ErrorCode::NotAnObject,
+            ]
+        );
+        // Nothing ran
+        assert_eq!(state.run_expression("count").unwrap(), Value::Number(0));
+
+        // Parameters could be anything
+        assert!(state
+            .typecheck("function f(x) { return x * 2 + x.y; } const y = f(1) + 2;")
+            .is_empty());
+
+        let diagnostics = state.typecheck("const x = ;");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn tracing() {
+        #[derive(Clone, Default)]
+        struct Log(Rc<RefCell<Vec<String>>>);
+
+        impl Tracer for Log {
+            fn statement_enter(&mut self, location: Location, code: &str) {
+                self.0.borrow_mut().push(format!("{location} enter {code}"));
+            }
+
+            fn expression_evaluated(&mut self, location: Location, code: &str, value: &Value) {
+                self.0
+                    .borrow_mut()
+                    .push(format!("{location} {code} => {value}"));
+            }
+
+            fn function_call(&mut self, location: Location, callee: &str, args: &[Value]) {
+                let args: Vec<_> = args.iter().map(ToString::to_string).collect();
+                self.0
+                    .borrow_mut()
+                    .push(format!("{location} call {callee}({})", args.join(", ")));
+            }
+
+            fn binding_mutated(&mut self, location: Location, ident: &str, value: &Value) {
+                self.0
+                    .borrow_mut()
+                    .push(format!("{location} set {ident} = {value}"));
+            }
+        }
+
+        let log = Log::default();
+        let mut state = Jabroni::new();
+        state.set_tracer(log.clone());
+        state
+            .run_script("function double(n) {\n    return n * 2;\n}\nlet x = double(4);")
+            .unwrap();
+        assert_eq!(
+            *log.0.borrow(),
+            [
+                "1:1 enter function double(n) {\n    return n * 2;\n}",
+                "4:1 enter let x = double(4);",
+                "4:16 4 => 4",
+                "4:9 call double(4)",
+                "1:20 enter {\n    return n * 2;\n}",
+                "2:5 enter return n * 2;",
+                "2:12 n => 4",
+                "2:16 2 => 2",
+                "2:12 n * 2 => 8",
+                "4:9 double(4) => 8",
+                "4:1 set x = 8",
+            ]
+        );
+
+        log.0.borrow_mut().clear();
+        state.remove_tracer();
+        state.run_script("x = 1;").unwrap();
+        assert!(log.0.borrow().is_empty());
+    }
+
+    #[test]
+    fn debugging() {
+        use crate::debug::DebugEvent;
+
+        #[track_caller]
+        fn paused_at(event: JabroniResult<DebugEvent>) -> (usize, Pause) {
+            match event.unwrap() {
+                DebugEvent::Paused(pause) => (pause.location.line, pause),
+                event => panic!("Expected a pause, got {:?}", event),
+            }
+        }
+
+        let mut state = Jabroni::new();
+        state.define_constant("limit", Value::Number(10)).unwrap();
+        let code = "
+function inner(n) {
+    const doubled = n * 2;
+    return doubled;
+}
+function outer(n) {
+    const result = inner(n);
+    return result + 1;
+}
+let total = outer(1);
+yield total;
+total = outer(total);";
+        let mut debugger = state.debug(code);
+        let (line, _) = paused_at(debugger.step_into());
+        assert_eq!(line, 2);
+        debugger.set_breakpoint(10);
+        let (line, _) = paused_at(debugger.resume());
+        assert_eq!(line, 10);
+        let (line, pause) = paused_at(debugger.step_into());
+        assert_eq!(line, 7);
+        assert_eq!(pause.function.as_deref(), Some("outer"));
+        let (line, pause) = paused_at(debugger.step_into());
+        assert_eq!(line, 3);
+        assert_eq!(pause.function.as_deref(), Some("inner"));
+        // Parameters are in the innermost scope, and globals in the outermost
+        assert_eq!(pause.scopes[0], [("n".to_string(), Value::Number(1))]);
+        assert!(pause
+            .scopes
+            .last()
+            .unwrap()
+            .contains(&("limit".to_string(), Value::Number(10))));
+        let (line, _) = paused_at(debugger.step_out());
+        assert_eq!(line, 8);
+        assert_eq!(
+            debugger.paused().unwrap().get("result"),
+            Some(&Value::Number(2))
+        );
+        let (line, _) = paused_at(debugger.step_over());
+        assert_eq!(line, 11);
+        assert_eq!(debugger.step_over().unwrap(), DebugEvent::Yielded(3.into()));
+
+        debugger.clear_breakpoint(10);
+        debugger.set_breakpoint(4);
+        assert_eq!(debugger.breakpoints(), [4]);
+        let (line, pause) = paused_at(debugger.resume());
+        assert_eq!(line, 4);
+        assert_eq!(pause.code, "return doubled;");
+        let stack: Vec<_> = pause.stack.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            stack,
+            ["at inner (4:5)", "at outer (7:20)", "at <script> (12:9)"]
+        );
+        assert_eq!(
+            debugger.resume().unwrap(),
+            DebugEvent::Finished(Value::Null)
+        );
+        assert!(debugger.is_complete());
+        assert!(debugger.resume().is_err());
+        drop(debugger);
+
+        // Scripts run normally once the debugger is gone
+        assert_eq!(state.run_expression("total").unwrap(), Value::Number(7));
+        state.run_script("total = outer(1);").unwrap();
+    }
+
+    #[test]
+    fn profiling() {
+        let mut state = Jabroni::new();
+        state
+            .run_script(
+                "
+function fact(n) { return n == 0 ? 1 : n * fact(n - 1); }
+function twice(n) { return fact(n) + fact(n); }",
+            )
+            .unwrap();
+        state.run_script("twice(3);").unwrap();
+        assert!(state.profile().is_none());
+
+        state.start_profiling();
+        state.run_script("twice(3);").unwrap();
+        state.run_expression("fact(1)").unwrap();
+        let profile = state.stop_profiling().unwrap();
+        assert!(state.profile().is_none());
+
+        let mut calls: Vec<_> = profile
+            .functions
+            .iter()
+            .map(|function| (function.name.as_str(), function.calls))
+            .collect();
+        calls.sort();
+        assert_eq!(calls, [("fact", 10), ("twice", 1)]);
+        let twice = profile
+            .functions
+            .iter()
+            .find(|function| function.name == "twice")
+            .unwrap();
+        assert!(twice.inclusive >= twice.exclusive);
+
+        let expression = profile
+            .expressions
+            .iter()
+            .find(|expression| expression.code == "n * fact(n - 1)")
+            .unwrap();
+        assert_eq!(
+            expression.location,
+            Location {
+                line: 2,
+                column: 40
+            }
+        );
+        assert_eq!(expression.evaluations, 7);
+
+        let stacks: Vec<_> = profile
+            .folded_stacks()
+            .lines()
+            .map(|line| line.rsplit_once(' ').unwrap().0.to_string())
+            .collect();
+        assert_eq!(
+            stacks,
+            [
+                "fact",
+                "fact;fact",
+                "twice",
+                "twice;fact",
+                "twice;fact;fact",
+                "twice;fact;fact;fact",
+                "twice;fact;fact;fact;fact",
+            ]
+        );
+    }
+
+    #[test]
+    fn coverage() {
+        let mut state = Jabroni::new();
+        state.start_coverage();
+        state
+            .run_script_named(
+                "grade.jab",
+                "
+function grade(score) {
+    return score > 50 ? 'pass' : 'fail';
+}
+try {
+    grade(80);
+    grade(90);
+} catch (e) {
+    throw e;
+}",
+            )
+            .unwrap();
+        let coverage = state.stop_coverage().unwrap();
+        assert!(state.coverage().is_none());
+
+        let hits: Vec<_> = coverage
+            .statements
+            .iter()
+            .map(|(region, hits)| (region.start.line, region.start.column, *hits))
+            .collect();
+        assert_eq!(
+            hits,
+            [
+                (2, 1, 1),
+                (3, 5, 2),
+                (5, 1, 1),
+                (6, 5, 1),
+                (7, 5, 1),
+                (9, 5, 0)
+            ]
+        );
+        let (region, _) = &coverage.statements[1];
+        assert_eq!(region.source.as_deref(), Some("grade.jab"));
+        assert_eq!(
+            region.end,
+            Location {
+                line: 3,
+                column: 41
+            }
+        );
+        assert_eq!(coverage.statement_rate(), 5.0 / 6.0);
+
+        assert_eq!(coverage.branches.len(), 1);
+        let branch = &coverage.branches[0];
+        assert_eq!(
+            branch.condition.start,
+            Location {
+                line: 3,
+                column: 12
+            }
+        );
+        assert_eq!((branch.taken, branch.not_taken), (2, 0));
+    }
+
+    #[test]
+    fn error_codes() {
+        let mut state = Jabroni::new();
+        state.define_constant("c", Value::Number(1)).unwrap();
+        state.define_variable("v", Value::Number(1)).unwrap();
+        for (code, expected) in [
+            ("missing", ErrorCode::UndefinedReference),
+            ("c = 2", ErrorCode::ConstantAssignment),
+            ("v = true", ErrorCode::AssignmentTypeMismatch),
+            ("v()", ErrorCode::NotAFunction),
+            ("v.x", ErrorCode::NotAnObject),
+            ("1 + true", ErrorCode::OperandTypeMismatch),
+            ("null == null", ErrorCode::NullComparison),
+            ("v ? 1 : 2", ErrorCode::NonBooleanCondition),
+            ("1 +", ErrorCode::Syntax),
+            ("8 / 2", ErrorCode::UnsupportedSyntax),
+        ] {
+            let error = state.run_expression(code).unwrap_err();
+            assert_eq!(error.code(), Some(expected), "{}", code);
+            assert_eq!(error.kind(), expected.kind());
+        }
+        let error = state.run_script("let c = 3;").unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::DoubleDefinition));
+        assert_eq!(error.code().unwrap().to_string(), "E0001");
+        let error = state.run_script("throw 1;").unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::UncaughtException));
+
+        // Errors from the host don't have to have a code
+        assert_eq!(
+            JabroniError::new(ErrorKind::Type, "Bad".into()).code(),
+            None
+        );
+    }
+
+    #[test]
+    fn reference_suggestions() {
+        let mut state = Jabroni::new();
+        let mut console = BindingMap::default();
+        console.set("log".into(), Binding::constant(Value::Null));
+        console.set("level".into(), Binding::constant(Value::Number(1)));
+        state
+            .define_constant("console", Value::Object(console.into()))
+            .unwrap();
+        state.define_variable("counter", Value::Number(0)).unwrap();
+
+        let mut message = |code| {
+            state
+                .run_expression(code)
+                .unwrap_err()
+                .message()
+                .to_string()
+        };
+        assert_eq!(
+            message("consle"),
+            "'consle' does not exist; did you mean 'console'?"
+        );
+        assert_eq!(
+            message("console.levl"),
+            "'levl' does not exist; did you mean 'level'?"
+        );
+        assert_eq!(
+            message("countr = 1"),
+            "'countr' does not exist; did you mean 'counter'?"
+        );
+        assert_eq!(message("banana"), "'banana' does not exist");
+    }
+
+    #[test]
+    fn unsupported_syntax() {
+        let mut state = Jabroni::new();
+        let error = state
+            .run_script("const x = 1;\nconst y = 8 / 2;")
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+        assert_eq!(error.message(), "Operator '/' is not supported");
+        assert_eq!(
+            error.location(),
+            Some(Location {
+                line: 2,
+                column: 11
+            })
+        );
+        // The interpreter is still usable afterwards
+        assert_eq!(state.run_expression("x").unwrap(), 1.into());
+    }
+
+    #[test]
+    fn stack_traces() {
+        let mut state = Jabroni::new();
+        let code = "
+function inner() {
+    throw 'oops';
+}
+function outer() {
+    return inner();
+}
+outer();";
+        let error = state.run_script_named("main.jab", code).unwrap_err();
+        let stack: Vec<_> = error
+            .stack()
+            .iter()
+            .map(|frame| frame.to_string())
+            .collect();
+        assert_eq!(
+            stack,
+            [
+                "at inner (main.jab:3:5)",
+                "at outer (main.jab:6:12)",
+                "at <script> (main.jab:8:1)"
+            ]
+        );
+        assert_eq!(error.stack()[0].function.as_deref(), Some("inner"));
+
+        // Errors raised at the top level only have its frame
+        let error = state.run_expression("1 + true").unwrap_err();
+        assert_eq!(error.stack().len(), 1);
+        assert_eq!(error.stack()[0].to_string(), "at <script> (1:1)");
+
+        // Catch blocks see the error as an object
+        state
+            .run_script(
+                "
+function check() {
+    try {
+        outer();
+    } catch (e) {
+        return e;
+    }
+}",
+            )
+            .unwrap();
+        let caught = state.run_expression("check()").unwrap();
+        let caught = caught.as_object().unwrap();
+        let field = |name| caught.get(name).unwrap();
+        assert_eq!(field("name"), Value::String("Exception".into()));
+        assert_eq!(field("message"), Value::String("oops".into()));
+        assert_eq!(
+            field("stack"),
+            Value::String(
+                "at inner (main.jab:3:5)\nat outer (main.jab:6:12)\nat check (4:9)".into()
+            )
+        );
+        assert_eq!(
+            state
+                .run_script("try { missing; } catch (e) { return e.name; }")
+                .unwrap(),
+            Value::String("ReferenceError".into())
+        );
+        // Thrown values are kept
+        assert_eq!(
+            state
+                .run_script("try { throw 7; } catch (e) { return e.value; }")
+                .unwrap(),
+            7.into()
+        );
+        assert_eq!(
+            state.run_script("throw 'bad';").unwrap_err().payload(),
+            Some(Value::String("bad".into()))
+        );
+        assert_eq!(state.run_script("missing;").unwrap_err().payload(), None);
+        let error = JabroniError::throw(Value::Subroutine(Subroutine::new_variadic(Box::new(
+            |_, _| Ok(Value::Null),
+        ))));
+        assert_eq!(error.payload(), None);
+        assert_eq!(error.kind(), ErrorKind::Exception);
+        // The error is only bound in the catch block
+        assert!(state
+            .run_script("try { throw 1; } catch (e) { 1; } e;")
+            .is_err());
+        assert_eq!(
+            state
+                .run_script("try { return 1; } catch (e) { return 2; }")
+                .unwrap(),
+            1.into()
+        );
+    }
+
+    #[cfg(feature = "miette")]
+    #[test]
+    fn diagnostics() {
+        use miette::Diagnostic;
+
+        let mut state = Jabroni::new();
+        let code = "const a = 1;\nconst b = a + missing;";
+        let error = state.run_script_named("main.jab", code).unwrap_err();
+        assert_eq!(error.source_code(), Some(code));
+        assert_eq!(Diagnostic::code(&error).unwrap().to_string(), "E0002");
+        let labels: Vec<_> = error.labels().unwrap().collect();
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels[0].offset(), code.find("missing").unwrap());
+        assert_eq!(labels[0].len(), "missing".len());
+        assert_eq!(labels[0].label(), Some("'missing' does not exist"));
+
+        // Errors without a location don't have labels, and ones without a code fall back to
+        // their kind
+        let error = JabroniError::new(ErrorKind::Type, "Bad".into());
+        assert!(error.labels().is_none());
+        assert_eq!(Diagnostic::code(&error).unwrap().to_string(), "TypeError");
+    }
+
+    #[test]
+    fn call_jabroni_function() {
+        let mut state = Jabroni::new();
 
         state.run_script("function foo() {return 42;}").unwrap();
         assert_eq!(state.run_expression("foo()").unwrap(), 42.into());
 
         state
-            .run_script("function add_one(x) {return x+1;}")
+            .run_script("function add_one(x) {return x+1;}")
+            .unwrap();
+        assert_eq!(state.run_expression("add_one(41)").unwrap(), 42.into());
+
+        state
+            .run_script("function add_together(x, y) {return x+y;}")
+            .unwrap();
+        assert_eq!(
+            state.run_expression("add_together(9,10)").unwrap(),
+            19.into()
+        );
+    }
+
+    #[test]
+    fn statements() {
+        let mut state = Jabroni::new();
+        state.define_variable("x", Value::Number(0)).unwrap();
+        state.define_variable("y", Value::Number(0)).unwrap();
+        state.run_script("x=0;y=1;x=2;\n").unwrap();
+        assert_eq!(state.run_expression("x").unwrap(), 2.into());
+        assert_eq!(state.run_expression("y").unwrap(), 1.into());
+    }
+
+    #[test]
+    fn exceptions() {
+        let mut state = Jabroni::new();
+        if let Err(err) = state.run_script("throw 'error';") {
+            assert!(matches!(err.kind(), ErrorKind::Exception));
+        } else {
+            panic!("Exception not thrown!");
+        }
+    }
+
+    #[test]
+    fn declarations() {
+        let mut state = Jabroni::new();
+        state.run_script("const x=4;").unwrap();
+        assert_eq!(state.run_expression("x").unwrap(), 4.into());
+        state.run_script("let y = 0;y=3;").unwrap();
+        assert_eq!(state.run_expression("y").unwrap(), 3.into());
+
+        // Make sure functions don't leak names
+        let mut state = Jabroni::new();
+        state
+            .run_script(
+                "\
+            function inner() {
+                const z = 4;
+                return 4;
+            }
+        ",
+            )
+            .unwrap();
+        assert!(state.run_expression("z").is_err());
+        assert_eq!(state.run_expression("inner()").unwrap(), 4.into());
+
+        // Make sure we can shadow
+        let mut state = Jabroni::new();
+        state
+            .run_script(
+                "\
+            const a = 3;
+            const b = 7;
+            function inner() {
+                const b = 9;
+                return a + b;
+            }
+        ",
+            )
+            .unwrap();
+        assert_eq!(state.run_expression("inner()").unwrap(), 12.into());
+    }
+
+    #[test]
+    fn walking_values() {
+        use crate::value::ValueVisitor;
+
+        // Render values as JSON-like text
+        #[derive(Default)]
+        struct Render(String);
+        impl ValueVisitor for Render {
+            fn visit(&mut self, path: &[&str], value: &Value) -> bool {
+                if let Some(name) = path.last() {
+                    self.0.push_str(&format!("{name}:"));
+                }
+                match value {
+                    Value::Object(_) => self.0.push('{'),
+                    value => self.0.push_str(&format!("{value},")),
+                }
+                true
+            }
+
+            fn leave_object(&mut self, _path: &[&str], _object: &BindingMap) {
+                self.0.push('}');
+            }
+        }
+
+        let mut inner = BindingMap::default();
+        inner.set("b".into(), Binding::constant(Value::Boolean(true)));
+        let mut outer = BindingMap::default();
+        outer.set("z".into(), Binding::constant(Value::Null));
+        outer.set(
+            "inner".into(),
+            Binding::constant(Value::Object(inner.into())),
+        );
+        outer.set("a".into(), Binding::constant(Value::Number(1)));
+        let mut state = Jabroni::new();
+        state
+            .define_constant("outer", Value::Object(outer.into()))
+            .unwrap();
+
+        let mut render = Render::default();
+        state.run_expression("outer").unwrap().accept(&mut render);
+        assert_eq!(render.0, "{z:null,inner:{b:true,}a:1,}");
+
+        let mut count = 0;
+        Value::Number(5).walk(|path, _| {
+            assert!(path.is_empty());
+            count += 1;
+        });
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn cyclic_objects() {
+        use crate::{Message, Object};
+        use std::convert::TryFrom;
+
+        let node = || {
+            let mut node = BindingMap::default();
+            node.set(
+                "next".into(),
+                Binding::variable(Value::Object(Object::default())),
+            );
+            node.set("id".into(), Binding::constant(Value::Number(1)));
+            Value::Object(node.into())
+        };
+        let mut state = Jabroni::new();
+        state.define_variable("a", node()).unwrap();
+        state.define_variable("b", node()).unwrap();
+        state
+            .run_script("a.next = a; b.next = b; const c = a;")
+            .unwrap();
+
+        let a = state.run_expression("a").unwrap();
+        assert!(format!("{a:?}").contains("[circular]"));
+        assert_eq!(a.to_string(), "{next: [circular], id: 1}");
+        assert_eq!(state.run_expression("a == b").unwrap(), true.into());
+        assert_eq!(state.run_expression("a === b").unwrap(), false.into());
+        assert!(Message::try_from(&a).is_err());
+        // The object in its own property isn't visited again
+        let mut count = 0;
+        a.walk(|_, _| count += 1);
+        assert_eq!(count, 2);
+
+        // Freed once nothing outside the cycle uses them
+        assert_eq!(Object::collect_cycles(), 0);
+        drop(a);
+        drop(state);
+        assert_eq!(Object::collect_cycles(), 2);
+
+        // Copies share properties until they change, so a cycle held by a copy is still in use
+        let mut node = BindingMap::default();
+        node.set(
+            "next".into(),
+            Binding::variable(Value::Object(Object::default())),
+        );
+        let node = Object::from(node);
+        node.borrow_mut()
+            .get_mut("next")
+            .unwrap()
+            .set_value(Value::Object(node.clone()))
+            .unwrap();
+        let copy = node.copy();
+        drop(node);
+        assert_eq!(Object::collect_cycles(), 0);
+        let original = copy.get("next").unwrap();
+        assert_eq!(
+            original.as_object().unwrap().get("next"),
+            Some(original.clone())
+        );
+        drop((original, copy));
+        assert_eq!(Object::collect_cycles(), 1);
+    }
+
+    #[test]
+    fn property_order() {
+        let mut object = BindingMap::default();
+        for ident in ["c", "a", "b", "d"] {
+            object.set(ident.into(), Binding::variable(Value::Number(0)));
+        }
+        // Setting a property again keeps its place, and removing one closes the gap
+        object.set("a".into(), Binding::variable(Value::Number(1)));
+        object.remove("c");
+        object.set("c".into(), Binding::variable(Value::Number(2)));
+        let idents: Vec<_> = object.iter().map(|(ident, _)| ident).collect();
+        assert_eq!(idents, ["a", "b", "d", "c"]);
+        assert_eq!(object.get("d").unwrap().value(), &Value::Number(0));
+
+        let mut state = Jabroni::new();
+        state
+            .define_constant("object", Value::Object(object.into()))
+            .unwrap();
+        state.run_script("object.b = 3;").unwrap();
+        let mut values = Vec::new();
+        state
+            .run_expression("object")
+            .unwrap()
+            .walk(|_, value| values.push(value.clone()));
+        assert_eq!(&values[1..], [1.into(), 3.into(), 0.into(), 2.into()]);
+        // Displaying goes in the same order as walking
+        assert_eq!(
+            state.run_expression("object").unwrap().to_string(),
+            "{a: 1, b: 3, d: 0, c: 2}"
+        );
+    }
+
+    #[test]
+    fn display() {
+        let mut state = Jabroni::new();
+        state
+            .run_script("function add(a, b) {return a + b;}")
+            .unwrap();
+        let add = state.run_expression("add").unwrap();
+        assert_eq!(add.to_string(), "[function add(2)]");
+
+        let mut inner = BindingMap::default();
+        inner.set(
+            "quote".into(),
+            Binding::constant(Value::String("it's".into())),
+        );
+        let mut object = BindingMap::default();
+        object.set(
+            "empty".into(),
+            Binding::constant(Value::Object(Object::default())),
+        );
+        object.set(
+            "inner".into(),
+            Binding::constant(Value::Object(inner.into())),
+        );
+        object.set("add".into(), Binding::constant(add));
+        object.set("none".into(), Binding::constant(Value::Null));
+        let object = Value::Object(object.into());
+        assert_eq!(
+            object.to_string(),
+            "{empty: {}, inner: {quote: 'it\\'s'}, add: [function add(2)], none: null}"
+        );
+        assert_eq!(
+            format!("{object:#}"),
+            "{
+    empty: {},
+    inner: {
+        quote: 'it\\'s'
+    },
+    add: [function add(2)],
+    none: null
+}"
+        );
+    }
+
+    #[test]
+    fn converting_values() {
+        use std::{collections::HashMap, convert::TryFrom};
+
+        let mut state = Jabroni::new();
+        let mut player = HashMap::new();
+        player.insert("name", "dave".into());
+        player.insert("hp", 10.into());
+        player.insert("title", None::<String>.into());
+        state.define_constant("player", player.into()).unwrap();
+
+        let name = String::try_from(state.run_expression("player.name").unwrap()).unwrap();
+        assert_eq!(name, "dave");
+        let hp = i32::try_from(state.run_expression("player.hp").unwrap()).unwrap();
+        assert_eq!(hp, 10);
+        assert!(bool::try_from(state.run_expression("player.hp > 5").unwrap()).unwrap());
+        assert_eq!(state.run_expression("player.title").unwrap(), ().into());
+
+        let error = bool::try_from(state.run_expression("player.name").unwrap()).unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::UnexpectedType));
+        assert_eq!(error.kind(), ErrorKind::Type);
+
+        let player = state.run_expression("player").unwrap();
+        let properties = HashMap::<String, Value>::try_from(player.clone()).unwrap();
+        assert_eq!(properties.len(), 3);
+        // Every property has to convert
+        assert!(HashMap::<String, i32>::try_from(player).is_err());
+        assert!(HashMap::<String, i32>::try_from(Value::Number(1)).is_err());
+    }
+
+    #[test]
+    fn bytes() {
+        let mut state = Jabroni::new();
+        state.enable_bytes().unwrap();
+        state
+            .define_constant("frame", Value::Bytes(vec![1, 2, 3, 255]))
+            .unwrap();
+        assert_eq!(
+            state.run_expression("bytes.length(frame)").unwrap(),
+            4.into()
+        );
+        assert_eq!(
+            state.run_expression("bytes.at(frame, 0 - 1)").unwrap(),
+            255.into()
+        );
+        assert_eq!(
+            state.run_expression("bytes.at(frame, 4)").unwrap(),
+            Value::Null
+        );
+        assert_eq!(
+            state
+                .run_expression("bytes.slice(frame, 0 - 2, 10)")
+                .unwrap(),
+            Value::Bytes(vec![3, 255])
+        );
+        assert_eq!(
+            state.run_expression("bytes.slice(frame, 3, 1)").unwrap(),
+            Value::Bytes(vec![])
+        );
+        assert_eq!(
+            state
+                .run_expression("bytes.fromHex(bytes.toHex(frame)) == frame")
+                .unwrap(),
+            true.into()
+        );
+        assert_eq!(
+            state.run_expression("bytes.toBase64(frame)").unwrap(),
+            "AQID/w==".into()
+        );
+        assert_eq!(
+            state.run_expression("frame").unwrap().to_string(),
+            "[bytes 010203ff]"
+        );
+
+        for code in [
+            "bytes.fromHex('abc')",
+            "bytes.fromHex('zz')",
+            "bytes.length('abc')",
+        ] {
+            let error = state.run_expression(code).unwrap_err();
+            assert_eq!(error.code(), Some(ErrorCode::InvalidArgument), "{code}");
+        }
+    }
+
+    #[test]
+    fn loose_coercion() {
+        let mut state = Jabroni::new();
+        state.set_coercion(Coercion::Loose);
+        state
+            .run_script("function describe(x) {return x ? 'truthy' : 'falsy';}")
             .unwrap();
-        assert_eq!(state.run_expression("add_one(41)").unwrap(), 42.into());
+        for (code, expected) in [
+            ("0", "falsy"),
+            ("''", "falsy"),
+            ("null", "falsy"),
+            ("false", "falsy"),
+            ("2", "truthy"),
+            ("' '", "truthy"),
+            ("describe", "truthy"),
+        ] {
+            assert_eq!(
+                state.run_expression(&format!("describe({code})")).unwrap(),
+                expected.into(),
+                "{code}"
+            );
+        }
+        for (code, expected) in [
+            ("null == null", true),
+            ("null == 0", false),
+            ("true == 1", true),
+            ("'' != 0", false),
+            ("'2' == true", false),
+            ("'x' == 0", false),
+            ("describe == describe", true),
+        ] {
+            assert_eq!(
+                state.run_expression(code).unwrap(),
+                expected.into(),
+                "{code}"
+            );
+        }
+        // Strict equality and ordering are unaffected
+        assert_eq!(state.run_expression("'1' === 1").unwrap(), false.into());
+        assert!(state.run_expression("'1' < 2").is_err());
+        assert!(state.typecheck("const x = 1 == 'a' ? 1 : 2;").is_empty());
+
+        state.set_coercion(Coercion::Strict);
+        assert_eq!(state.typecheck("const x = 1 == 'a' ? 1 : 2;").len(), 1);
+        assert!(state.run_expression("describe(1)").is_err());
+    }
+
+    #[test]
+    fn interning() {
+        let ident = utils::intern("score");
+        assert!(Rc::ptr_eq(&ident, &utils::intern("score")));
+        // Identifiers nothing holds onto are eventually dropped
+        let weak = Rc::downgrade(&utils::intern("unused"));
+        for i in 0..1000 {
+            utils::intern(&format!("ident{i}"));
+        }
+        assert!(weak.upgrade().is_none());
+        assert!(Rc::ptr_eq(&ident, &utils::intern("score")));
+
+        // Strings are shared rather than copied
+        let mut state = Jabroni::new();
+        state.run_script("const a = 'hello'; const b = a;").unwrap();
+        let (a, b) = (
+            state.run_expression("a").unwrap(),
+            state.run_expression("b").unwrap(),
+        );
+        assert!(Rc::ptr_eq(a.as_string().unwrap(), b.as_string().unwrap()));
+    }
 
+    #[test]
+    fn deep_nesting() {
+        let mut state = Jabroni::new();
+        let nested = |depth| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(state.run_expression(&nested(100)).unwrap(), 1.into());
+        for code in [nested(10000), format!("{}1", "true ? 1 : ".repeat(10000))] {
+            let error = state.run_expression(&code).unwrap_err();
+            assert_eq!(error.code(), Some(ErrorCode::NestingTooDeep));
+        }
+        let diagnostics = Jabroni::check(&format!("const x = 1;\nconst y = {};", nested(200)));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].location.line, 2);
+        assert_eq!(
+            state.run_expression("1 = 2").unwrap_err().code(),
+            Some(ErrorCode::Syntax)
+        );
+
+        state.set_max_depth(50);
         state
-            .run_script("function add_together(x, y) {return x+y;}")
+            .run_script(
+                "
+function forever(n) {return forever(n + 1);}
+function count(n) {return n == 0 ? 0 : 1 + count(n - 1);}
+let caught = '';
+try {forever(0);} catch (e) {caught = e.name;}",
+            )
             .unwrap();
+        assert_eq!(state.run_expression("caught").unwrap(), "LimitError".into());
+        // Levels are freed as evaluation returns, even when it fails
+        assert_eq!(state.run_expression("count(3)").unwrap(), 3.into());
         assert_eq!(
-            state.run_expression("add_together(9,10)").unwrap(),
-            19.into()
+            state.run_expression("forever(0)").unwrap_err().code(),
+            Some(ErrorCode::RecursionTooDeep)
         );
+        assert_eq!(state.run_expression("count(3)").unwrap(), 3.into());
     }
 
     #[test]
-    fn statements() {
+    fn deep_recursion() {
+        // Only calls count toward the limit, and the stack grows to fit them
         let mut state = Jabroni::new();
-        state.define_variable("x", Value::Number(0)).unwrap();
-        state.define_variable("y", Value::Number(0)).unwrap();
-        state.run_script("x=0;y=1;x=2;\n").unwrap();
-        assert_eq!(state.run_expression("x").unwrap(), 2.into());
-        assert_eq!(state.run_expression("y").unwrap(), 1.into());
+        state
+            .run_script("function h(n) {return n == 0 ? 0 : h(n - 1) + 1;}")
+            .unwrap();
+        assert_eq!(state.run_expression("h(3000)").unwrap(), 3000.into());
+        assert_eq!(
+            state.run_expression("h(5000)").unwrap_err().code(),
+            Some(ErrorCode::RecursionTooDeep)
+        );
     }
 
     #[test]
-    fn exceptions() {
+    fn scope_sizes() {
+        // Scopes switch to a hash map once they grow, without losing their order
+        let mut map = BindingMap::default();
+        for i in 0..20 {
+            map.set(format!("b{i}"), Binding::variable(Value::Number(i)));
+        }
+        assert_eq!(map.remove("b3").unwrap().value(), &Value::Number(3));
+        assert!(map.remove("b3").is_none());
+        map.set("b0".into(), Binding::variable(Value::Number(100)));
+        let names: Vec<_> = map.iter().map(|(ident, _)| ident).collect();
+        assert_eq!(names.len(), 19);
+        assert_eq!(&names[..4], ["b0", "b1", "b2", "b4"]);
+        assert_eq!(map.get("b19").unwrap().value(), &Value::Number(19));
+        assert_eq!(map.get("b0").unwrap().value(), &Value::Number(100));
+
         let mut state = Jabroni::new();
-        if let Err(err) = state.run_script("throw 'error';") {
-            assert!(matches!(err, JabroniError::Exception(_)));
-        } else {
-            panic!("Exception not thrown!");
+        let params: Vec<_> = (0..12).map(|i| format!("p{i}")).collect();
+        state
+            .run_script(&format!(
+                "function sum({}) {{return {};}}",
+                params.join(", "),
+                params.join(" + ")
+            ))
+            .unwrap();
+        let args: Vec<_> = (0..12).map(|i| i.to_string()).collect();
+        assert_eq!(
+            state
+                .run_expression(&format!("sum({})", args.join(", ")))
+                .unwrap(),
+            66.into()
+        );
+    }
+
+    #[test]
+    fn big_numbers() {
+        let mut state = Jabroni::new();
+        assert_eq!(
+            state.run_expression("1700000000 * 1000 + 999").unwrap(),
+            Value::Number(1_700_000_000_999)
+        );
+        assert_eq!(
+            state.run_expression("9223372036854775807").unwrap(),
+            Value::Number(Number::MAX)
+        );
+        for code in ["9223372036854775807 + 1", "0 - 9223372036854775807 - 2"] {
+            let error = state.run_expression(code).unwrap_err();
+            assert_eq!(error.code(), Some(ErrorCode::NumberOverflow));
         }
+        assert_eq!(
+            state
+                .run_expression("9223372036854775808")
+                .unwrap_err()
+                .code(),
+            Some(ErrorCode::InvalidLiteral)
+        );
     }
 
     #[test]
-    fn declarations() {
+    fn binding_attributes() {
         let mut state = Jabroni::new();
-        state.run_script("const x=4;").unwrap();
-        assert_eq!(state.run_expression("x").unwrap(), 4.into());
-        state.run_script("let y = 0;y=3;").unwrap();
-        assert_eq!(state.run_expression("y").unwrap(), 3.into());
+        let mut config = BindingMap::default();
+        config.set("port".into(), Binding::variable(80.into()).read_only());
+        config.set("secret".into(), Binding::variable("key".into()).hidden());
+        state
+            .define_constant("config", Value::Object(config.into()))
+            .unwrap();
 
-        // Make sure functions don't leak names
+        assert_eq!(
+            state
+                .run_expression("config.port = 8080")
+                .unwrap_err()
+                .code(),
+            Some(ErrorCode::ReadOnlyProperty)
+        );
+        // The host can still write read-only bindings, and scripts can write hidden ones
+        let config = state.run_expression("config").unwrap();
+        let config = config.as_object().unwrap();
+        config
+            .borrow_mut()
+            .get_mut("port")
+            .unwrap()
+            .set_value(8080.into())
+            .unwrap();
+        state.run_expression("config.secret = 'other'").unwrap();
+        assert_eq!(state.run_expression("config.port").unwrap(), 8080.into());
+        assert_eq!(
+            state.run_expression("config.secret").unwrap(),
+            "other".into()
+        );
+
+        let names: Vec<_> = config
+            .borrow()
+            .iter()
+            .map(|(ident, _)| ident.to_string())
+            .collect();
+        assert_eq!(names, ["port"]);
+        assert_eq!(
+            Message::try_from(&Value::Object(config.clone())).unwrap(),
+            Message::Object([("port".to_string(), Message::Number(8080))].into())
+        );
+    }
+
+    #[test]
+    fn binding_map_from_host() {
+        let mut map: BindingMap = (0..3)
+            .map(|i| (format!("b{i}"), Binding::constant(Value::Number(i))))
+            .collect();
+        map.extend([
+            ("b1".to_string(), Binding::constant("one".into())),
+            ("b3".to_string(), Binding::constant(3.into()).hidden()),
+        ]);
+        assert_eq!(map.len(), 3);
+        assert!(!map.is_empty());
+        assert!(BindingMap::default().is_empty());
+        assert_eq!(map.get("b1").unwrap().value(), &"one".into());
+
+        let entry = map
+            .entry("b2".into())
+            .and_modify(|binding| *binding = Binding::constant(20.into()));
+        assert_eq!(entry.key(), "b2");
+        assert_eq!(
+            entry.or_insert(Binding::constant(0.into())).value(),
+            &20.into()
+        );
+        map.entry("b4".into())
+            .and_modify(|_| unreachable!())
+            .or_insert_with(|| Binding::constant(4.into()));
+        let names: Vec<_> = map.iter().map(|(ident, _)| ident).collect();
+        assert_eq!(names, ["b0", "b1", "b2", "b4"]);
+    }
+
+    #[test]
+    fn block_scopes() {
+        let mut state = Jabroni::new();
+        state
+            .run_script("let x = 1; { let x = 2; let y = x; x = 3; } x = x + 1;")
+            .unwrap();
+        assert_eq!(state.run_expression("x").unwrap(), 2.into());
+        let error = state.run_expression("y").unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::UndefinedReference));
+
+        // The scope is gone even if the block throws
+        state
+            .run_script("try { { let z = 1; throw 'oops'; } } catch (e) {}")
+            .unwrap();
+        assert!(state.run_expression("z").is_err());
+        state.run_script("let z = 2;").unwrap();
+
+        // Function bodies share a scope with the parameters
+        state
+            .run_script("function f(a) { let a = 1; return a; }")
+            .unwrap();
+        let error = state.run_expression("f(0)").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::DoubleDefinition);
+        state
+            .run_script("function g(a) { { let a = a + 1; } return a; }")
+            .unwrap();
+        assert_eq!(state.run_expression("g(1)").unwrap(), 1.into());
+    }
+
+    #[test]
+    fn hoisting() {
         let mut state = Jabroni::new();
         state
             .run_script(
-                "\
-            function inner() {
-                const z = 4;
-                return 4;
-            }
-        ",
+                "const x = main();
+function main() {
+    return helper(2);
+}
+function helper(n) {
+    return n * 3;
+}",
             )
             .unwrap();
-        assert!(state.run_expression("z").is_err());
-        assert_eq!(state.run_expression("inner()").unwrap(), 4.into());
+        assert_eq!(state.run_expression("x").unwrap(), 6.into());
 
-        // Make sure we can shadow
+        // Functions in bodies aren't hoisted
+        state
+            .run_script("function outer() { inner(); function inner() {} }")
+            .unwrap();
+        let error = state.run_expression("outer()").unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::UndefinedReference));
+    }
+
+    #[test]
+    fn nested_assignment() {
+        let mut leaf = BindingMap::default();
+        leaf.set("count".into(), Binding::variable(Value::Number(0)));
+        let mut middle = BindingMap::default();
+        middle.set("leaf".into(), Binding::constant(Value::Object(leaf.into())));
+        let mut root = BindingMap::default();
+        root.set(
+            "middle".into(),
+            Binding::constant(Value::Object(middle.into())),
+        );
+        let mut state = Jabroni::new();
+        state
+            .define_constant("root", Value::Object(root.into()))
+            .unwrap();
+
+        state
+            .run_script("root.middle.leaf.count = root.middle.leaf.count + 1;")
+            .unwrap();
+        assert_eq!(
+            state.run_expression("root.middle.leaf.count").unwrap(),
+            1.into()
+        );
+        // The head of a chain can be any expression that gives an object
+        state
+            .run_script(
+                "function leaf() { return root.middle.leaf; }
+(leaf()).count = 5;
+(root.middle).leaf.count = (leaf()).count * 2;",
+            )
+            .unwrap();
+        assert_eq!(
+            state.run_expression("root.middle.leaf.count").unwrap(),
+            10.into()
+        );
+
+        let error = state
+            .run_script("root.middle.leaf.count.x = 1;")
+            .unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::NotAnObject));
+        let error = state.run_script("root.middle.missing.x = 1;").unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::UndefinedReference));
+        let error = state.run_script("root.middle.leaf = null;").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Type);
+    }
+
+    #[test]
+    fn postfix_chains() {
+        let mut server = BindingMap::default();
+        server.set("port".into(), Binding::variable(Value::Number(8080)));
+        let mut config = BindingMap::default();
+        config.set(
+            "server".into(),
+            Binding::constant(Value::Object(server.into())),
+        );
         let mut state = Jabroni::new();
+        state
+            .define_constant("config", Value::Object(config.into()))
+            .unwrap();
         state
             .run_script(
-                "\
-            const a = 3;
-            const b = 7;
-            function inner() {
-                const b = 9;
-                return a + b;
-            }
-        ",
+                "function getConfig() { return config; }
+function getServer() { return config.server; }
+function getter() { return getServer; }",
             )
             .unwrap();
-        assert_eq!(state.run_expression("inner()").unwrap(), 12.into());
+
+        assert_eq!(
+            state.run_expression("getConfig().server.port").unwrap(),
+            8080.into()
+        );
+        assert_eq!(
+            state.run_expression("getter()().port").unwrap(),
+            8080.into()
+        );
+        state.run_script("getConfig().server.port = 9090;").unwrap();
+        assert_eq!(
+            state.run_expression("(getter())().port").unwrap(),
+            9090.into()
+        );
+
+        let error = state.run_script("getConfig() = 1;").unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::Syntax));
+        let error = state.run_expression("getConfig()()").unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::NotAFunction));
+        let error = state.run_expression("getter().port").unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::NotAnObject));
+    }
+
+    #[test]
+    fn equality_semantics() {
+        let mut object = BindingMap::default();
+        object.set("x".into(), Binding::constant(Value::Number(1)));
+        let mut state = Jabroni::new();
+        state
+            .define_constant("a", Value::Object(object.clone().into()))
+            .unwrap();
+        state
+            .define_constant("b", Value::Object(object.into()))
+            .unwrap();
+        state
+            .run_script("function f() { return 1; } function g() { return 1; }")
+            .unwrap();
+
+        for (code, expected) in [
+            ("f === f", true),
+            ("f === g", false),
+            ("f !== g", true),
+            ("f == f", true),
+            ("f != g", true),
+            ("a === a", true),
+            ("a === b", false),
+            ("a == b", true),
+            ("null === null", true),
+            ("1 === '1'", false),
+            ("f === null", false),
+            ("a !== 1", true),
+        ] {
+            assert_eq!(
+                state.run_expression(code).unwrap(),
+                expected.into(),
+                "{}",
+                code
+            );
+        }
+        let error = state.run_expression("1 == '1'").unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::OperandTypeMismatch));
+        let error = state.run_expression("null == null").unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::NullComparison));
+
+        state.set_equality(Equality::StrictOnly);
+        assert_eq!(state.run_expression("1 !== 2").unwrap(), true.into());
+        let error = state.run_expression("1 != 2").unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::LooseEquality));
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+        let diagnostics = state.typecheck("const same = 1 == 1;");
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "1:14: error: '==' isn't allowed. Use '===' instead"
+        );
+    }
+
+    #[test]
+    fn shebang() {
+        for code in ["#!/usr/bin/env jabroni\nconst x = 1;", "const x = 1;"] {
+            let mut state = Jabroni::new();
+            state.run_script(code).unwrap();
+            assert_eq!(state.run_expression("x").unwrap(), Value::Number(1));
+            assert!(Jabroni::check(code).is_empty());
+            assert_eq!(ast::parse(code).unwrap().statements.len(), 1);
+        }
+        // Locations still count the shebang line
+        let error = Jabroni::new()
+            .run_script("#!/usr/bin/env jabroni\nconst x = ;")
+            .unwrap_err();
+        assert_eq!(
+            error.location(),
+            Some(Location {
+                line: 2,
+                column: 11
+            })
+        );
+        let diagnostics = Jabroni::check("#!/usr/bin/env jabroni\nconst x = ;");
+        assert_eq!(
+            diagnostics[0].location,
+            Location {
+                line: 2,
+                column: 11
+            }
+        );
+        // It has to be the first line
+        assert!(Jabroni::new()
+            .run_script("const x = 1;\n#!/usr/bin/env jabroni")
+            .is_err());
+        assert!(Jabroni::new().run_expression("#!x").is_err());
+    }
+
+    #[test]
+    fn signatures() {
+        let mut state = Jabroni::new();
+        state
+            .run_script("function add(a, b) { return a + b; } function f() {}")
+            .unwrap();
+        let signature = |state: &mut Jabroni, code| match state.run_expression(code).unwrap() {
+            Value::Subroutine(subroutine) => subroutine.signature().map(String::from),
+            _ => unreachable!(),
+        };
+        assert_eq!(signature(&mut state, "add").as_deref(), Some("add(a, b)"));
+        assert_eq!(signature(&mut state, "f").as_deref(), Some("f()"));
+
+        // Builtins are documented
+        state.enable_events().unwrap();
+        state.enable_bytes().unwrap();
+        for code in ["on", "emit", "bytes.toHex"] {
+            match state.run_expression(code).unwrap() {
+                Value::Subroutine(subroutine) => assert!(subroutine.doc().is_some()),
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(
+            signature(&mut state, "bytes.slice").as_deref(),
+            Some("bytes.slice(bytes, start, end)")
+        );
+    }
+
+    #[test]
+    fn describe() {
+        use crate::reflect::Parameter;
+
+        let mut state = Jabroni::new();
+        state.enable_events().unwrap();
+        let mut console = BindingMap::default();
+        let log = Subroutine::new_variadic(Box::new(|_, _| Ok(Value::Null)))
+            .named("log")
+            .documented("console.log(...values)", "Print values.");
+        let clamp = Subroutine::new(3, Box::new(|_, _| Ok(Value::Null))).with_parameters(vec![
+            Parameter::new("value").typed("number"),
+            Parameter::new("min"),
+            Parameter::new("max"),
+        ]);
+        console.set("log".into(), Binding::constant(log.into()));
+        console.set("clamp".into(), Binding::constant(clamp.into()));
+        console.set("level".into(), Binding::constant(1.into()));
+        let console = Value::from(console);
+        // Cycles are only described once
+        console
+            .as_object()
+            .unwrap()
+            .borrow_mut()
+            .set("console".into(), Binding::constant(console.clone()));
+        state.define_constant("console", console).unwrap();
+        state
+            .run_script("function add(a, b) {return a + b;}")
+            .unwrap();
+
+        let log = state.describe("console.log").unwrap();
+        assert_eq!(log.name.as_deref(), Some("log"));
+        assert_eq!(log.signature.as_deref(), Some("console.log(...values)"));
+        assert_eq!(log.doc.as_deref(), Some("Print values."));
+        assert_eq!(log.arity, None);
+        assert_eq!(log.parameters, None);
+        assert!(!log.scripted);
+        let clamp = state.describe("console.clamp").unwrap();
+        assert_eq!(clamp.arity, Some(3));
+        assert_eq!(
+            clamp.signature.as_deref(),
+            Some("console.clamp(value: number, min, max)")
+        );
+        let add = state.describe("add").unwrap();
+        assert!(add.scripted);
+        assert_eq!(
+            add.parameters,
+            Some(vec![Parameter::new("a"), Parameter::new("b")])
+        );
+
+        let code = |path| state.describe(path).unwrap_err().code();
+        assert_eq!(code("console.level"), Some(ErrorCode::NotAFunction));
+        assert_eq!(code("console.level.x"), Some(ErrorCode::NotAnObject));
+        assert_eq!(code("console.missing"), Some(ErrorCode::UndefinedReference));
+
+        let paths: Vec<_> = state
+            .describe_all()
+            .into_iter()
+            .map(|description| description.path)
+            .collect();
+        assert_eq!(paths, ["add", "console.clamp", "console.log", "emit", "on"]);
+    }
+    #[test]
+    fn templates() {
+        let mut state = Jabroni::new();
+        let mut user = BindingMap::default();
+        user.set("name".into(), Binding::constant("Ada".into()));
+        state
+            .define_constant("user", Value::Object(user.into()))
+            .unwrap();
+        state.run_script("let count = 2;").unwrap();
+        assert_eq!(
+            state
+                .render_template("Hello ${user.name}, you have ${count + 1} items")
+                .unwrap(),
+            "Hello Ada, you have 3 items"
+        );
+        // Closing delimiters in strings don't end expressions
+        assert_eq!(state.render_template("${'{}'}!").unwrap(), "{}!");
+        assert_eq!(
+            state.render_template("\\${count} ${count}").unwrap(),
+            "${count} 2"
+        );
+        assert_eq!(
+            state.render_template("no expressions").unwrap(),
+            "no expressions"
+        );
+
+        // Errors point into the template
+        let error = state.render_template("Hi\nthere ${count").unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::Syntax));
+        assert_eq!(error.location(), Some(Location { line: 2, column: 7 }));
+        let error = state.render_template("Hi\nthere ${ missing }").unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::UndefinedReference));
+        assert_eq!(
+            error.location(),
+            Some(Location {
+                line: 2,
+                column: 10
+            })
+        );
+        assert_eq!(error.source_code(), Some("Hi\nthere ${ missing }"));
+
+        let template = Template::new()
+            .delimiters("<%", "%>")
+            .escape(crate::template::escape_html);
+        state.run_script("const tag = '<b>';").unwrap();
+        assert_eq!(
+            template.render(&mut state, "<% tag %> ${tag}").unwrap(),
+            "&lt;b&gt; ${tag}"
+        );
+    }
+
+    #[test]
+    fn javascript() {
+        let mut state = Jabroni::new();
+        let js = "
+            /* Constants */
+            var base = 10
+            let offset = -3;
+            const scale = function (x) {
+                return x * base
+            };
+            const add = (a, b) => a + b
+            const twice = x => add(x, x);
+            const describe = (x) => {
+                return x > 20 ? `big: ${scale(x)}` : `small: ${twice(x)}`;
+            }
+            return describe(add(base, offset)) + describe(undefined === null ? 11 : 0)
+        ";
+        let error = state.run_js(js).unwrap_err();
+        // Strings can't be added, which is Jabroni's rule rather than the translation's
+        assert_eq!(error.kind(), ErrorKind::Type);
+        let js = js.replace(" + describe(undefined === null ? 11 : 0)", "");
+        let mut state = Jabroni::new();
+        assert_eq!(state.run_js(&js).unwrap(), "small: 14".into());
+        assert_eq!(
+            state.run_js("return `${21 + 1} is ${'big'}`").unwrap(),
+            "22 is big".into()
+        );
+        assert_eq!(
+            state.run_js("return `it's \\`quoted\\` \\${not}`").unwrap(),
+            "it's `quoted` ${not}".into()
+        );
+        assert_eq!(
+            state
+                .run_js("const f = (a) => `${a}`\nreturn f(`${`${1}`}`)")
+                .unwrap(),
+            "1".into()
+        );
+
+        // Unsupported JavaScript is rejected where it is
+        for (js, message, location) in [
+            (
+                "let x = 1;\nif (x) {}",
+                "'if' statements aren't supported. Use the ternary operator, 'condition ? a : b'",
+                (2, 1),
+            ),
+            ("while (true) {}", "Loops aren't supported", (1, 1)),
+            ("const a = [1, 2];", "Arrays aren't supported", (1, 11)),
+            ("const o = {a: 1};", "Object literals aren't supported", (1, 11)),
+            ("f(x => x);", "Arrow functions can only be the value of a declaration, like 'const double = (x) => x * 2;'", (1, 5)),
+            ("const b = a && c;", "The '&&' operator isn't supported", (1, 13)),
+            ("x++;", "The '++' operator isn't supported", (1, 2)),
+            ("const half = 0.5;", "Only whole numbers are supported", (1, 14)),
+            ("new Thing();", "Classes aren't supported", (1, 1)),
+            ("return `${[1]}`;", "Arrays aren't supported", (1, 11)),
+        ] {
+            let error = state.run_js(js).unwrap_err();
+            assert_eq!(error.code(), Some(ErrorCode::UnsupportedSyntax), "{}", js);
+            assert_eq!(error.message(), message);
+            assert_eq!(
+                error.location(),
+                Some(Location {
+                    line: location.0,
+                    column: location.1
+                })
+            );
+            assert_eq!(error.source_code(), Some(js));
+        }
+        let error = state.run_js("const s = `open").unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::Syntax));
+    }
+
+    #[test]
+    fn javascript_source_maps() {
+        use crate::debug::DebugEvent;
+
+        // Errors point at the JavaScript rather than the translation
+        let js = "var base = 10\nconst scale = (x) => x * missing\nconst total = scale(base)";
+        let mut state = Jabroni::new();
+        let error = state.run_js(js).unwrap_err();
+        assert_eq!(error.code(), Some(ErrorCode::UndefinedReference));
+        assert_eq!(
+            error.location(),
+            Some(Location {
+                line: 2,
+                column: 26
+            })
+        );
+        assert_eq!(error.source_code(), Some(js));
+        let stack: Vec<_> = error.stack().iter().map(ToString::to_string).collect();
+        assert_eq!(stack, ["at scale (2:26)", "at <script> (3:15)"]);
+        let error = Jabroni::new()
+            .run_js("const s = `${1}`\n  + `${'a' + 1}`")
+            .unwrap_err();
+        assert_eq!(error.location(), Some(Location { line: 2, column: 8 }));
+
+        // So do the debugger's positions
+        let js = js.replace("missing", "base");
+        let mut state = Jabroni::new();
+        let mut debugger = state.debug_js(&js).unwrap();
+        debugger.set_breakpoint(2);
+        match debugger.resume().unwrap() {
+            DebugEvent::Paused(pause) => {
+                assert_eq!(pause.location, Location { line: 2, column: 1 })
+            }
+            event => panic!("Expected a pause, got {:?}", event),
+        }
+        match debugger.resume().unwrap() {
+            DebugEvent::Paused(pause) => {
+                let stack: Vec<_> = pause.stack.iter().map(ToString::to_string).collect();
+                assert_eq!(stack, ["at scale (2:22)", "at <script> (3:15)"]);
+                assert_eq!(debugger.paused(), Some(pause));
+            }
+            event => panic!("Expected a pause, got {:?}", event),
+        }
+        assert_eq!(
+            debugger.resume().unwrap(),
+            DebugEvent::Finished(Value::Null)
+        );
     }
 }
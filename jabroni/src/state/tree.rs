@@ -0,0 +1,471 @@
+//! Running syntax trees, such as compiled scripts, without turning them back into code.
+
+use super::{error_object, DepthGuard, Evaluation, Jabroni, Yield};
+#[cfg(feature = "jit")]
+use crate::jit::HotFunction;
+use crate::{
+    ast::{DeclarationKind, Expression, ExpressionKind, Script, Statement, StatementKind},
+    audit::AuditEvent,
+    binding::{Binding, BindingMap},
+    debug::Suspend,
+    errors::{ErrorCode, JabroniError, JabroniResult, Source},
+    fmt,
+    object::Object,
+    reflect::Parameter,
+    utils,
+    value::{Args, Coercion, Subroutine},
+    Value,
+};
+#[cfg(feature = "jit")]
+use std::cell::RefCell;
+use std::{future::Future, pin::Pin, rc::Rc, sync::Arc};
+
+/// Where an expression in a syntax tree leads, which can be read, assigned to or called.
+enum Place<'a> {
+    /// A binding in scope, by name.
+    Ident(&'a str),
+    /// A property of an object.
+    Member(Object, &'a str),
+    /// A value that isn't kept anywhere, such as what a call returned.
+    Value(Value),
+}
+
+/// Describe a callee, like `console.log`, for messages and the audit log.
+fn callee_code(expression: &Expression) -> String {
+    match &expression.kind {
+        ExpressionKind::Ident(name) => name.clone(),
+        ExpressionKind::Member { object, property } => {
+            format!("{}.{}", callee_code(object), property.name)
+        }
+        ExpressionKind::Call { callee, .. } => format!("{}(...)", callee_code(callee)),
+        _ => "(...)".into(),
+    }
+}
+
+impl Jabroni {
+    /// Run a syntax tree, such as a compiled script loaded with
+    /// [`Script::from_bytes`](crate::ast::Script::from_bytes), attributing errors to `name`. The
+    /// tree is run as it is, rather than turned back into code, so errors name the script but
+    /// not where in it they were raised. Scripts run this way can't be debugged, traced, profiled
+    /// or covered, and aren't checked for warnings.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{ast::{self, Script}, Jabroni};
+    ///
+    /// let bytes = ast::parse("function double(x){return x*2;}")
+    ///     .unwrap()
+    ///     .to_bytes()
+    ///     .unwrap();
+    /// let script = Script::from_bytes(&bytes).unwrap();
+    /// let mut jabroni = Jabroni::new();
+    /// jabroni.run_compiled("double.jbc", &script).unwrap();
+    /// assert_eq!(jabroni.run_expression("double(4)").unwrap(), 8.into());
+    /// ```
+    pub fn run_compiled(&mut self, name: &str, script: &Script) -> JabroniResult<Value> {
+        utils::block_on(self.run_tree(name, script))
+    }
+
+    async fn run_tree(&mut self, name: &str, script: &Script) -> JabroniResult<Value> {
+        let start = self.begin_run();
+        let outer = self.source.replace(Arc::new(Source::new(Some(name), "")));
+        let scoped = self.globals_frozen();
+        if scoped {
+            self.bindings.push_scope();
+        }
+        let result = self.evaluate_tree(script).await;
+        if scoped {
+            self.bindings.pop_scope();
+        }
+        if let Err(error) = &result {
+            self.audit_failure(error);
+        }
+        self.source = outer;
+        self.end_run(start);
+        result
+    }
+
+    async fn evaluate_tree(&mut self, script: &Script) -> JabroniResult<Value> {
+        let (source, function) = (self.source.clone(), self.function.clone());
+        let result = async {
+            {
+                let runtime = self.runtime.borrow();
+                if !runtime.sandbox.is_open() {
+                    runtime.sandbox.check_script(script, "")?;
+                }
+            }
+            // Define functions first, so they can be called from above where they're written
+            for statement in &script.statements {
+                if let StatementKind::Function { .. } = statement.kind {
+                    self.define_tree_function(statement)?;
+                }
+            }
+            self.run_tree_statements(&script.statements).await
+        }
+        .await;
+        result.map_err(|e| {
+            e.in_source(source.as_ref())
+                .raised_in(function.as_deref(), source.as_ref())
+        })
+    }
+
+    /// Run the body of a function defined by a syntax tree.
+    async fn evaluate_tree_body(&mut self, body: &[Statement]) -> JabroniResult<Value> {
+        let (source, function) = (self.source.clone(), self.function.clone());
+        self.run_tree_statements(body).await.map_err(|e| {
+            e.in_source(source.as_ref())
+                .raised_in(function.as_deref(), source.as_ref())
+        })
+    }
+
+    async fn run_tree_statements(&mut self, statements: &[Statement]) -> JabroniResult<Value> {
+        let mut value = Value::Null;
+        for statement in statements {
+            value = self.tree_statement(statement).await?;
+            self.record_memory();
+        }
+        Ok(value)
+    }
+
+    /// Run statements in a scope of their own, stopping at the first error.
+    async fn tree_block(&mut self, statements: &[Statement]) -> JabroniResult<Value> {
+        self.bindings.push_scope();
+        let mut result = Ok(Value::Null);
+        for statement in statements {
+            result = self.tree_statement(statement).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        self.bindings.pop_scope();
+        result
+    }
+
+    fn tree_statement<'a>(&'a mut self, statement: &'a Statement) -> Evaluation<'a> {
+        Box::pin(utils::GrowStack::new(async move {
+            self.runtime.borrow_mut().stats.record_instruction();
+            if !matches!(statement.kind, StatementKind::Block(_)) && self.spend_budget() {
+                Suspend::default().await;
+                self.spend_budget();
+            }
+            match &statement.kind {
+                StatementKind::Expression(expression) => {
+                    self.tree_expression(expression).await?;
+                }
+                StatementKind::Block(statements) => return self.tree_block(statements).await,
+                StatementKind::Function { .. } => self.define_tree_function(statement)?,
+                StatementKind::Return(value) => return self.tree_expression(value).await,
+                StatementKind::Yield(value) => {
+                    let value = match value {
+                        Some(value) => self.tree_expression(value).await?,
+                        None => Value::Null,
+                    };
+                    let runtime = self.runtime.clone();
+                    Yield {
+                        value: Some(value),
+                        runtime,
+                    }
+                    .await?;
+                }
+                StatementKind::Declaration { kind, name, value } => {
+                    self.check_global_declaration(&name.name)?;
+                    let value = self.tree_expression(value).await?;
+                    self.audit(None, || AuditEvent::BindingMutated {
+                        target: name.name.clone(),
+                        value: value.clone(),
+                    });
+                    self.define_binding(&name.name, value, *kind == DeclarationKind::Let)?;
+                }
+                StatementKind::Throw(value) => {
+                    let value = self.tree_expression(value).await?;
+                    self.audit(None, || AuditEvent::Thrown {
+                        value: value.clone(),
+                    });
+                    return Err(JabroniError::throw(value));
+                }
+                StatementKind::Try {
+                    body,
+                    binding,
+                    handler,
+                } => {
+                    let error = match self.tree_block(body).await {
+                        Ok(value) => return Ok(value),
+                        Err(error) => {
+                            error.raised_in(self.function.as_deref(), self.source.as_ref())
+                        }
+                    };
+                    self.audit_failure(&error);
+                    self.bindings.push_scope();
+                    self.bindings.set(
+                        binding.name.clone(),
+                        Binding::constant(error_object(&error)),
+                    );
+                    let result = self.tree_block(handler).await;
+                    self.bindings.pop_scope();
+                    return result;
+                }
+                StatementKind::Import { names, specifier } => {
+                    let exports = self.import_module(specifier).await?;
+                    for name in names {
+                        let ident = name.name.as_str();
+                        let binding = exports.get(ident).map_err(|_| {
+                            JabroniError::from_code(
+                                ErrorCode::MissingExport,
+                                format!("'{ident}' is not exported by '{specifier}'"),
+                            )
+                        })?;
+                        self.check_global_declaration(ident)?;
+                        self.define_constant(ident, binding.value().clone())?;
+                    }
+                }
+            }
+            Ok(Value::Null)
+        }))
+    }
+
+    /// Define a function from its statement, keeping its body to run when it's called.
+    fn define_tree_function(&mut self, statement: &Statement) -> JabroniResult {
+        let (name, params, body) = match &statement.kind {
+            StatementKind::Function { name, params, body } => (name, params, body),
+            _ => unreachable!(),
+        };
+        self.check_global_declaration(&name.name)?;
+        let function: Rc<str> = name.name.as_str().into();
+        let params: Vec<_> = params
+            .iter()
+            .map(|param| utils::intern(&param.name))
+            .collect();
+        let num_args = params.len();
+        let signature = format!("{}({})", function, params.join(", "));
+        let parameters = params.iter().map(|param| Parameter::new(param)).collect();
+        // Snapshots save functions defined by scripts as code
+        let definition = fmt::format_script(&Script {
+            statements: vec![statement.clone()],
+        });
+
+        let body: Rc<[Statement]> = body.as_slice().into();
+        let params = Rc::new(params);
+        let runtime = self.runtime.clone();
+        let source = self.source.clone();
+        #[cfg(feature = "jit")]
+        let hot = Rc::new(RefCell::new(HotFunction::new(definition.trim_end())));
+        let callback = move |mut context: BindingMap, args: Args| {
+            let (body, params, runtime, source, function) = (
+                body.clone(),
+                params.clone(),
+                runtime.clone(),
+                source.clone(),
+                function.clone(),
+            );
+            #[cfg(feature = "jit")]
+            let hot = hot.clone();
+            async move {
+                #[cfg(feature = "jit")]
+                if let Some(value) = Self::call_native(&runtime, &hot, &context, &args) {
+                    return Ok(value);
+                }
+                // Bind params to args. Objects are shared with the caller
+                for (param, arg) in params.iter().zip(args) {
+                    context.set_interned(param.clone(), Binding::constant(arg));
+                }
+                let mut substate = Jabroni {
+                    bindings: context,
+                    runtime,
+                    source,
+                    function: Some(function),
+                    ..Default::default()
+                };
+                substate.evaluate_tree_body(&body).await
+            }
+        };
+        let subroutine = Subroutine::new_async_inline(Some(num_args), callback)
+            .named(&name.name)
+            .with_signature(signature)
+            .with_parameters(parameters)
+            .scripted(definition.trim_end());
+        self.bindings.set_interned(
+            utils::intern(&name.name),
+            Binding::constant(Value::Subroutine(subroutine)),
+        );
+        Ok(())
+    }
+
+    fn tree_expression<'a>(&'a mut self, expression: &'a Expression) -> Evaluation<'a> {
+        Box::pin(utils::GrowStack::new(async move {
+            self.runtime.borrow_mut().stats.record_instruction();
+            match &expression.kind {
+                ExpressionKind::Literal(value) => {
+                    self.runtime.borrow_mut().stats.record_value(value);
+                    Ok(value.clone())
+                }
+                ExpressionKind::Ident(_)
+                | ExpressionKind::Member { .. }
+                | ExpressionKind::Call { .. } => {
+                    let place = self.tree_place(expression).await?;
+                    self.with_tree_place(place, |binding| Ok(binding.value().clone()))
+                }
+                ExpressionKind::Group(inner) => self.tree_expression(inner).await,
+                ExpressionKind::Assignment { target, value } => {
+                    let operand = self.tree_expression(value).await?;
+                    match &target.kind {
+                        ExpressionKind::Ident(name)
+                            if self.globals_frozen() && self.bindings.is_outermost(name) =>
+                        {
+                            return Err(JabroniError::from_code(
+                                ErrorCode::FrozenGlobal,
+                                format!(
+                                    "Cannot assign to global '{name}' because globals are frozen"
+                                ),
+                            ));
+                        }
+                        ExpressionKind::Ident(_) | ExpressionKind::Member { .. } => (),
+                        _ => {
+                            return Err(JabroniError::from_code(
+                                ErrorCode::Syntax,
+                                "Can only assign to bindings and properties".into(),
+                            ))
+                        }
+                    }
+                    let place = self.tree_place(target).await?;
+                    self.with_tree_place(place, |binding| binding.assign(operand.clone()))?;
+                    self.audit(None, || AuditEvent::BindingMutated {
+                        target: callee_code(target),
+                        value: operand,
+                    });
+                    Ok(Value::Null)
+                }
+                ExpressionKind::Ternary {
+                    condition,
+                    consequent,
+                    alternative,
+                } => {
+                    let mut condition = self.tree_expression(condition).await?;
+                    if self.coercion() == Coercion::Loose {
+                        condition = condition.is_truthy().into();
+                    }
+                    match condition {
+                        Value::Boolean(true) => self.tree_expression(consequent).await,
+                        Value::Boolean(false) => self.tree_expression(alternative).await,
+                        _ => Err(JabroniError::from_code(
+                            ErrorCode::NonBooleanCondition,
+                            "Ternary condition must be boolean".into(),
+                        )),
+                    }
+                }
+                ExpressionKind::Binary { operator, lhs, rhs } => {
+                    let mut value = self.tree_expression(lhs).await?;
+                    let operand = self.tree_expression(rhs).await?;
+                    self.apply_operator(&mut value, operator.symbol(), operand)?;
+                    Ok(value)
+                }
+            }
+        }))
+    }
+
+    /// Follow an expression's property accesses and calls to the place it refers to. Bindings
+    /// are looked up rather than read along the way, so they can be assigned to.
+    fn tree_place<'a, 'p: 'a>(
+        &'a mut self,
+        expression: &'p Expression,
+    ) -> Pin<Box<dyn Future<Output = JabroniResult<Place<'p>>> + 'a>> {
+        Box::pin(utils::GrowStack::new(async move {
+            match &expression.kind {
+                ExpressionKind::Ident(name) => Ok(Place::Ident(name)),
+                ExpressionKind::Member { object, property } => {
+                    let place = self.tree_place(object).await?;
+                    let object = self.with_tree_place(place, |binding| {
+                        binding.value().as_object().cloned().ok_or_else(|| {
+                            JabroniError::from_code(ErrorCode::NotAnObject, "Not an object".into())
+                        })
+                    })?;
+                    Ok(Place::Member(object, &property.name))
+                }
+                ExpressionKind::Call { callee, args } => {
+                    let place = self.tree_place(callee).await?;
+                    let subroutine = self.tree_callee(place)?;
+                    let value = self.tree_call(subroutine, callee, args).await?;
+                    Ok(Place::Value(value))
+                }
+                _ => Ok(Place::Value(self.tree_expression(expression).await?)),
+            }
+        }))
+    }
+
+    /// Run `f` with the binding at a place, or a constant binding holding its value if it's not
+    /// stored anywhere.
+    fn with_tree_place<T>(
+        &mut self,
+        place: Place,
+        f: impl FnOnce(&mut Binding) -> JabroniResult<T>,
+    ) -> JabroniResult<T> {
+        match place {
+            Place::Ident(name) => f(self.get_binding_mut(name)?),
+            Place::Member(object, property) => f(object.borrow_mut().get_mut(property)?),
+            Place::Value(value) => f(&mut Binding::constant(value)),
+        }
+    }
+
+    fn tree_callee(&mut self, place: Place) -> JabroniResult<Subroutine> {
+        if let Place::Member(object, method) = &place {
+            let properties = object.borrow();
+            if let Some(handler) = properties.handler() {
+                if !properties.contains(method) && handler.has(method) {
+                    let (handler, method) = (handler.clone(), method.to_string());
+                    return Ok(Subroutine::new_variadic(Box::new(move |context, args| {
+                        handler.call(&method, context, args)
+                    })));
+                }
+            }
+        }
+        self.with_tree_place(place, |binding| {
+            binding.value().as_subroutine().cloned().ok_or_else(|| {
+                JabroniError::from_code(ErrorCode::NotAFunction, "Not a function".into())
+            })
+        })
+    }
+
+    /// Call a function with the values of its arguments.
+    fn tree_call<'a>(
+        &'a mut self,
+        subroutine: Subroutine,
+        callee: &'a Expression,
+        args: &'a [Expression],
+    ) -> Evaluation<'a> {
+        Box::pin(async move {
+            if self.runtime.borrow().pure && !subroutine.is_pure() {
+                return Err(JabroniError::from_code(
+                    ErrorCode::ImpureCall,
+                    format!(
+                        "Cannot call '{}' because it isn't pure",
+                        callee_code(callee)
+                    ),
+                ));
+            }
+            let _depth = DepthGuard::enter(&self.runtime)?;
+            let mut values = Args::new();
+            for arg in args {
+                values.push(self.tree_expression(arg).await?);
+            }
+            let scripted = subroutine.is_scripted();
+            if !scripted {
+                self.audit(None, || AuditEvent::HostCall {
+                    callee: callee_code(callee),
+                    args: values.to_vec(),
+                });
+            }
+            let result = subroutine
+                .call_inline(self.bindings.new_context(), values)
+                .await;
+            let value = result.map_err(|e| match scripted {
+                true => e.called_from(self.function.as_deref(), self.source.as_ref(), None),
+                false => e,
+            })?;
+            if !scripted {
+                let mut runtime = self.runtime.borrow_mut();
+                runtime.stats.record_host_call();
+                runtime.stats.record_value(&value);
+            }
+            Ok(value)
+        })
+    }
+}
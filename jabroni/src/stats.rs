@@ -0,0 +1,72 @@
+use crate::{binding::BindingMap, value::Value};
+use std::{collections::HashSet, mem, time::Duration};
+
+/// Resources used by a call to [`Jabroni::run_script`](crate::Jabroni::run_script),
+/// [`Jabroni::run_expression`](crate::Jabroni::run_expression), or one of their variants.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct RunStats {
+    /// Statements and expressions evaluated.
+    pub instructions: u64,
+    /// The most memory held by bindings after any statement in the run, in bytes. This is an estimate
+    /// based on the size of values, and doesn't include the interpreter's own overhead. It's only
+    /// measured if enabled with [`Jabroni::set_measure_memory`](crate::Jabroni::set_measure_memory),
+    /// and is 0 otherwise.
+    pub peak_memory: usize,
+    /// Strings and objects created by literals, copied out of bindings, or returned by the host,
+    /// each of which needed a heap allocation.
+    pub allocations: u64,
+    /// Calls from scripts to functions defined by the host.
+    pub host_calls: u64,
+    /// Wall-clock time taken by the run, including parsing.
+    pub elapsed: Duration,
+    /// The part of [`elapsed`](Self::elapsed) spent parsing, including function bodies and
+    /// modules.
+    pub parse_time: Duration,
+}
+
+impl RunStats {
+    pub(crate) fn record_instruction(&mut self) {
+        self.instructions += 1;
+    }
+
+    pub(crate) fn record_value(&mut self, value: &Value) {
+        if matches!(value, Value::String(_) | Value::Object(_)) {
+            self.allocations += 1;
+        }
+    }
+
+    pub(crate) fn record_parse(&mut self, time: Duration) {
+        self.parse_time += time;
+    }
+
+    pub(crate) fn record_host_call(&mut self) {
+        self.host_calls += 1;
+    }
+
+    pub(crate) fn record_memory(&mut self, bindings: &BindingMap) {
+        self.peak_memory = self
+            .peak_memory
+            .max(bindings_size(bindings, &mut HashSet::new()));
+    }
+}
+
+/// Estimate the memory used by bindings. Objects are only counted the first time they're
+/// `seen`, since they can be shared or contain themselves.
+fn bindings_size(bindings: &BindingMap, seen: &mut HashSet<*const ()>) -> usize {
+    bindings
+        .iter()
+        .map(|(ident, binding)| ident.len() + value_size(binding.value(), seen))
+        .sum()
+}
+
+fn value_size(value: &Value, seen: &mut HashSet<*const ()>) -> usize {
+    mem::size_of::<Value>()
+        + match value {
+            Value::String(string) => string.len(),
+            Value::Bytes(bytes) => bytes.capacity(),
+            Value::Object(object) if seen.insert(object.as_ptr()) => {
+                bindings_size(&object.borrow(), seen)
+            }
+            _ => 0,
+        }
+}
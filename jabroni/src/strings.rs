@@ -0,0 +1,193 @@
+use crate::{
+    binding::{Binding, BindingMap},
+    errors::{ErrorCode, JabroniError, JabroniResult},
+    value::{Subroutine, Value},
+};
+use std::cmp::Ordering;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+fn invalid_argument(message: &str) -> JabroniError {
+    JabroniError::from_code(ErrorCode::InvalidArgument, message.into())
+}
+
+fn string_arg(value: &Value) -> JabroniResult<&str> {
+    value
+        .as_string()
+        .map(AsRef::as_ref)
+        .ok_or_else(|| invalid_argument("Expected a string"))
+}
+
+fn ordering(ordering: Ordering) -> Value {
+    Value::Number(ordering as i64)
+}
+
+/// How a string sorts, level by level, roughly as in the default order of the Unicode Collation
+/// Algorithm: letters first, ignoring accents and case, then accents, then lowercase before
+/// uppercase.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct SortKey {
+    letters: Vec<char>,
+    /// The combining marks on each letter.
+    accents: Vec<Vec<char>>,
+    /// Whether each letter is uppercase.
+    cases: Vec<bool>,
+}
+
+impl SortKey {
+    fn new(string: &str) -> Self {
+        let mut key = Self {
+            letters: Vec::new(),
+            accents: Vec::new(),
+            cases: Vec::new(),
+        };
+        for c in string.nfd() {
+            if is_combining_mark(c) && !key.accents.is_empty() {
+                key.accents.last_mut().unwrap().push(c);
+                continue;
+            }
+            key.letters.extend(c.to_lowercase());
+            key.accents.push(Vec::new());
+            key.cases.push(c.is_uppercase());
+        }
+        key
+    }
+}
+
+/// Compare strings in the order people expect to see them sorted, falling back to comparing
+/// their code points so only canonically equivalent strings compare equal.
+fn locale_compare(a: &str, b: &str) -> Ordering {
+    SortKey::new(a)
+        .cmp(&SortKey::new(b))
+        .then_with(|| a.nfc().cmp(b.nfc()))
+}
+
+/// Compare strings ignoring case, once they're normalized, so composed and decomposed accents
+/// are the same.
+fn compare_ignoring_case(a: &str, b: &str) -> Ordering {
+    let fold = |string: &str| {
+        string
+            .nfc()
+            .flat_map(char::to_lowercase)
+            .collect::<String>()
+    };
+    fold(a).cmp(&fold(b))
+}
+
+fn normalize(string: &str, form: &str) -> JabroniResult<String> {
+    Ok(match form {
+        "NFC" => string.nfc().collect(),
+        "NFD" => string.nfd().collect(),
+        "NFKC" => string.nfkc().collect(),
+        "NFKD" => string.nfkd().collect(),
+        _ => {
+            return Err(invalid_argument(
+                "Expected a normalization form: 'NFC', 'NFD', 'NFKC' or 'NFKD'",
+            ))
+        }
+    })
+}
+
+/// Make a builtin, named after the start of its signature.
+fn builtin(
+    number_of_args: usize,
+    signature: &str,
+    doc: &str,
+    callback: impl Fn(&[Value]) -> JabroniResult<Value> + 'static,
+) -> Binding {
+    let name = signature.split('(').next().unwrap_or(signature);
+    let subroutine = Subroutine::new(number_of_args, Box::new(move |_, args| callback(args)))
+        .named(name)
+        .documented(&format!("strings.{signature}"), doc)
+        .pure();
+    Binding::constant(Value::Subroutine(subroutine))
+}
+
+/// The `strings` object, with functions for comparing and normalizing Unicode text.
+pub(crate) fn namespace() -> BindingMap {
+    let mut namespace = BindingMap::default();
+    let mut add = |name: &str, binding| namespace.set(name.into(), binding);
+    add(
+        "localeCompare",
+        builtin(
+            2,
+            "localeCompare(a, b)",
+            "-1, 0 or 1 as a sorts before, with or after b, comparing letters before accents \
+             and accents before case.",
+            |args| {
+                let (a, b) = (string_arg(&args[0])?, string_arg(&args[1])?);
+                Ok(ordering(locale_compare(a, b)))
+            },
+        ),
+    );
+    add(
+        "compareIgnoreCase",
+        builtin(
+            2,
+            "compareIgnoreCase(a, b)",
+            "-1, 0 or 1 as a sorts before, with or after b, ignoring case.",
+            |args| {
+                let (a, b) = (string_arg(&args[0])?, string_arg(&args[1])?);
+                Ok(ordering(compare_ignoring_case(a, b)))
+            },
+        ),
+    );
+    add(
+        "equalsIgnoreCase",
+        builtin(
+            2,
+            "equalsIgnoreCase(a, b)",
+            "Whether strings are the same, ignoring case.",
+            |args| {
+                let (a, b) = (string_arg(&args[0])?, string_arg(&args[1])?);
+                Ok(Value::Boolean(
+                    compare_ignoring_case(a, b) == Ordering::Equal,
+                ))
+            },
+        ),
+    );
+    add(
+        "normalize",
+        builtin(
+            2,
+            "normalize(string, form)",
+            "Normalize a string to 'NFC', 'NFD', 'NFKC' or 'NFKD'.",
+            |args| {
+                let (string, form) = (string_arg(&args[0])?, string_arg(&args[1])?);
+                Ok(Value::String(normalize(string, form)?.into()))
+            },
+        ),
+    );
+    namespace
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorting() {
+        let mut words = vec![
+            "zebra", "Émile", "apple", "Apple", "eclair", "éclair", "Zoo",
+        ];
+        words.sort_by(|a, b| locale_compare(a, b));
+        assert_eq!(
+            words,
+            ["apple", "Apple", "eclair", "éclair", "Émile", "zebra", "Zoo"]
+        );
+        // Composed and decomposed accents sort the same
+        assert_eq!(locale_compare("é", "e\u{301}"), Ordering::Equal);
+        assert_eq!(locale_compare("", "a"), Ordering::Less);
+    }
+
+    #[test]
+    fn ignoring_case() {
+        assert_eq!(
+            compare_ignoring_case("ÉCLAIR", "e\u{301}clair"),
+            Ordering::Equal
+        );
+        assert_eq!(compare_ignoring_case("a", "B"), Ordering::Less);
+        assert_eq!(normalize("e\u{301}", "NFC").unwrap(), "é");
+        assert_eq!(normalize("ﬁ", "NFKD").unwrap(), "fi");
+        assert!(normalize("x", "nfc").is_err());
+    }
+}
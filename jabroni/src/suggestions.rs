@@ -0,0 +1,52 @@
+/// Find the candidate closest to `ident`, if any is close enough to be a likely typo.
+pub fn closest<'a>(ident: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    // Short identifiers are too similar to each other for suggestions to be useful
+    let max_distance = ident.chars().count() / 3;
+    candidates
+        .filter(|candidate| *candidate != ident)
+        .map(|candidate| (edit_distance(ident, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between two strings, in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closest_candidate() {
+        assert_eq!(edit_distance("consle", "console"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+
+        let candidates = ["console", "const", "count", "x"];
+        assert_eq!(
+            closest("consle", candidates.iter().copied()),
+            Some("console")
+        );
+        assert_eq!(closest("cont", candidates.iter().copied()), Some("const"));
+        assert_eq!(closest("y", candidates.iter().copied()), None);
+        assert_eq!(closest("zebra", candidates.iter().copied()), None);
+        // Ties go to the alphabetically first candidate, so suggestions are stable
+        assert_eq!(
+            closest("cone", ["conn", "cond"].iter().copied()),
+            Some("cond")
+        );
+    }
+}
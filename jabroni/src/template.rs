@@ -0,0 +1,157 @@
+//! Rendering text with expressions embedded in it, like `Hello ${user.name}`.
+
+use crate::{
+    errors::{ErrorCode, JabroniError, JabroniResult, Location, Source},
+    tokens::{tokenize, TokenKind},
+    Jabroni,
+};
+use pest::Position;
+use std::{rc::Rc, sync::Arc};
+
+type Escape = dyn Fn(&str) -> String;
+
+/// Renders templates: text with expressions between delimiters, which are `${` and `}` by
+/// default. Each expression is run with the interpreter's bindings, and replaced with its value,
+/// escaped if an escape function is set. A backslash before an opening delimiter keeps it as
+/// text.
+///
+/// # Example
+/// ```
+/// use jabroni::{
+///     template::{self, Template},
+///     Jabroni,
+/// };
+///
+/// let mut jabroni = Jabroni::new();
+/// jabroni.run_script("const user = 'Tom & Jerry'; let count = 3;").unwrap();
+/// let template = Template::new()
+///     .delimiters("{{", "}}")
+///     .escape(template::escape_html);
+/// let text = "<p>{{user}} have {{count + 1}} items, not \\{{count}}</p>";
+/// assert_eq!(
+///     template.render(&mut jabroni, text).unwrap(),
+///     "<p>Tom &amp; Jerry have 4 items, not {{count}}</p>"
+/// );
+/// ```
+#[derive(Clone)]
+pub struct Template {
+    open: String,
+    close: String,
+    escape: Option<Rc<Escape>>,
+}
+
+impl Template {
+    /// A template with `${` and `}` as delimiters, that doesn't escape values.
+    pub fn new() -> Self {
+        Self {
+            open: "${".into(),
+            close: "}".into(),
+            escape: None,
+        }
+    }
+
+    /// Put expressions between `open` and `close`, which mustn't be empty.
+    pub fn delimiters(mut self, open: &str, close: &str) -> Self {
+        assert!(
+            !open.is_empty() && !close.is_empty(),
+            "Delimiters can't be empty"
+        );
+        self.open = open.into();
+        self.close = close.into();
+        self
+    }
+
+    /// Escape the values of expressions with `escape` before they're put in the text.
+    pub fn escape(mut self, escape: impl Fn(&str) -> String + 'static) -> Self {
+        self.escape = Some(Rc::new(escape));
+        self
+    }
+
+    /// Render `text`, running its expressions with `jabroni`. Errors point to where in the text
+    /// they came from.
+    pub fn render(&self, jabroni: &mut Jabroni, text: &str) -> JabroniResult<String> {
+        let source = Arc::new(Source::new(None, text));
+        let mut rendered = String::with_capacity(text.len());
+        let mut rest = 0;
+        while let Some(found) = text[rest..].find(&self.open) {
+            let open = rest + found;
+            if text[..open].ends_with('\\') {
+                rendered.push_str(&text[rest..open - 1]);
+                rendered.push_str(&self.open);
+                rest = open + self.open.len();
+                continue;
+            }
+            rendered.push_str(&text[rest..open]);
+            let start = open + self.open.len();
+            let end = start
+                + self.expression_len(&text[start..]).ok_or_else(|| {
+                    JabroniError::from_code(
+                        ErrorCode::Syntax,
+                        format!("Expected '{}' to close '{}'", self.close, self.open),
+                    )
+                    .located(|| location(text, open), self.open.len())
+                    .in_source(Some(&source))
+                })?;
+            let expression = &text[start..end];
+            let value = jabroni
+                .run_expression(expression)
+                .map_err(|e| e.embedded(expression, &source, location(text, start)))?;
+            let value = value.to_string();
+            match &self.escape {
+                Some(escape) => rendered.push_str(&escape(&value)),
+                None => rendered.push_str(&value),
+            }
+            rest = end + self.close.len();
+        }
+        rendered.push_str(&text[rest..]);
+        Ok(rendered)
+    }
+
+    /// Find the length of the expression at the start of `text`, which ends at the first closing
+    /// delimiter that isn't in a string.
+    fn expression_len(&self, text: &str) -> Option<usize> {
+        let strings: Vec<_> = tokenize(text)
+            .into_iter()
+            .filter(|token| token.kind == TokenKind::String)
+            .map(|token| token.span)
+            .collect();
+        let mut from = 0;
+        loop {
+            let end = from + text[from..].find(&self.close)?;
+            match strings
+                .iter()
+                .find(|span| span.start <= end && end < span.end)
+            {
+                Some(string) => from = string.end,
+                None => return Some(end),
+            }
+        }
+    }
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn location(text: &str, offset: usize) -> Location {
+    let (line, column) = Position::new(text, offset).unwrap().line_col();
+    Location { line, column }
+}
+
+/// Escape text for HTML, for use with [`Template::escape`].
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,439 @@
+//! Generating random scripts and values, for fuzzing the interpreter and property tests.
+
+use crate::{fmt, Binding, BindingMap, Value};
+use std::collections::HashSet;
+
+/// Syntax a [`Generator`] can use.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum Feature {
+    /// Function declarations and calls
+    Functions,
+    /// `try`, `catch` and `throw`
+    Exceptions,
+    /// `yield` statements, which only run with [`Jabroni::run_resumable`](crate::Jabroni::run_resumable)
+    Yield,
+    /// Blocks, which start a new scope
+    Blocks,
+    /// Assignments to variables
+    Assignments,
+    /// Ternary expressions
+    Ternaries,
+    /// Property access, which usually fails since there are no object literals
+    Members,
+}
+
+impl Feature {
+    const ALL: [Feature; 7] = [
+        Feature::Functions,
+        Feature::Exceptions,
+        Feature::Yield,
+        Feature::Blocks,
+        Feature::Assignments,
+        Feature::Ternaries,
+        Feature::Members,
+    ];
+}
+
+const OPERATORS: [&str; 12] = [
+    "+", "-", "*", "/", "==", "!=", "===", "!==", ">", ">=", "<", "<=",
+];
+/// Numbers that are likely to find edge cases, like overflow and division by zero.
+const NUMBERS: [i64; 6] = [0, 1, 2, 10, i64::MAX, 4611686018427387904];
+const CHARACTERS: [char; 10] = ['a', 'Z', '0', ' ', '\'', '"', '\\', '\n', 'é', '🦀'];
+
+/// Generates random scripts and values from a seed, so the same seed always generates the same
+/// ones. Scripts are always syntactically valid, and only use bindings that are in scope, though
+/// they may still fail when run, such as by adding a string to a number. Functions only call
+/// functions declared before them, so scripts always finish.
+///
+/// # Example
+/// ```
+/// use jabroni::{
+///     testing::{Feature, Generator},
+///     Jabroni,
+/// };
+///
+/// let mut generator = Generator::new(7)
+///     .max_statements(5)
+///     .without(Feature::Yield);
+/// for _ in 0..100 {
+///     let script = generator.script();
+///     assert!(jabroni::ast::parse(&script).is_ok());
+///     // Scripts may fail, but never panic
+///     let _ = Jabroni::new().run_script(&script);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Generator {
+    state: u64,
+    max_depth: usize,
+    max_statements: usize,
+    enabled: HashSet<Feature>,
+    /// Bindings in scope while generating a script, innermost scope last.
+    scopes: Vec<Vec<Name>>,
+    /// How many bindings have been named, so names aren't reused.
+    names: usize,
+    /// Whether a function body is being generated.
+    in_function: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Name {
+    name: String,
+    kind: NameKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NameKind {
+    Constant,
+    Variable,
+    /// A function, with how many parameters it has.
+    Function(usize),
+}
+
+impl Generator {
+    /// A generator seeded with `seed`, using every feature, with expressions and statements
+    /// nested up to 3 deep and up to 4 statements in each script, block and function.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: seed,
+            max_depth: 3,
+            max_statements: 4,
+            enabled: Feature::ALL.iter().copied().collect(),
+            scopes: Vec::new(),
+            names: 0,
+            in_function: false,
+        }
+    }
+
+    /// Nest expressions and statements up to `max_depth` deep.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Put up to `max_statements` statements in each script, block and function.
+    pub fn max_statements(mut self, max_statements: usize) -> Self {
+        self.max_statements = max_statements.max(1);
+        self
+    }
+
+    /// Use a feature.
+    pub fn with(mut self, feature: Feature) -> Self {
+        self.enabled.insert(feature);
+        self
+    }
+
+    /// Don't use a feature.
+    pub fn without(mut self, feature: Feature) -> Self {
+        self.enabled.remove(&feature);
+        self
+    }
+
+    /// Check if a feature is used.
+    pub fn enables(&self, feature: Feature) -> bool {
+        self.enabled.contains(&feature)
+    }
+
+    /// Generate a script.
+    pub fn script(&mut self) -> String {
+        self.scopes = vec![Vec::new()];
+        self.names = 0;
+        self.in_function = false;
+        let mut code = String::new();
+        for _ in 0..self.below(self.max_statements) + 1 {
+            self.statement(0, &mut code);
+        }
+        code
+    }
+
+    /// Generate an expression, using only literals.
+    pub fn expression(&mut self) -> String {
+        self.scopes = vec![Vec::new()];
+        self.names = 0;
+        self.expression_at(0)
+    }
+
+    /// Generate a value: a number, boolean, string, bytes, null, or an object of other values.
+    pub fn value(&mut self) -> Value {
+        self.value_at(0)
+    }
+
+    fn value_at(&mut self, depth: usize) -> Value {
+        let kinds = if depth < self.max_depth { 6 } else { 5 };
+        match self.below(kinds) {
+            0 => Value::Number(self.number()),
+            1 => Value::Boolean(self.chance(2)),
+            2 => Value::String(self.string().into()),
+            3 => Value::Bytes((0..self.below(8)).map(|_| self.next() as u8).collect()),
+            4 => Value::Null,
+            _ => {
+                let mut object = BindingMap::default();
+                for i in 0..self.below(4) {
+                    let value = self.value_at(depth + 1);
+                    object.set(format!("p{}", i), Binding::variable(value));
+                }
+                Value::Object(object.into())
+            }
+        }
+    }
+
+    /// Get the next random number, with SplitMix64.
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Get a random number less than `n`.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// Return true one time in `n`.
+    fn chance(&mut self, n: usize) -> bool {
+        self.below(n) == 0
+    }
+
+    fn number(&mut self) -> i64 {
+        match self.chance(4) {
+            true => NUMBERS[self.below(NUMBERS.len())],
+            false => self.below(100) as i64,
+        }
+    }
+
+    fn string(&mut self) -> String {
+        (0..self.below(6))
+            .map(|_| CHARACTERS[self.below(CHARACTERS.len())])
+            .collect()
+    }
+
+    fn name(&mut self, prefix: &str) -> String {
+        self.names += 1;
+        format!("{}{}", prefix, self.names)
+    }
+
+    fn declare(&mut self, name: &str, kind: NameKind) {
+        self.scopes.last_mut().unwrap().push(Name {
+            name: name.into(),
+            kind,
+        });
+    }
+
+    /// Pick a binding in scope that matches `filter`.
+    fn pick(&mut self, filter: impl Fn(NameKind) -> bool) -> Option<Name> {
+        let names: Vec<_> = self
+            .scopes
+            .iter()
+            .flatten()
+            .filter(|name| filter(name.kind))
+            .cloned()
+            .collect();
+        match names.is_empty() {
+            true => None,
+            false => Some(names[self.below(names.len())].clone()),
+        }
+    }
+
+    fn indent(depth: usize, code: &mut String) {
+        code.push_str(&"    ".repeat(depth));
+    }
+
+    fn block(&mut self, depth: usize, code: &mut String, scope: Vec<Name>) {
+        self.scopes.push(scope);
+        code.push_str("{\n");
+        for _ in 0..self.below(self.max_statements) + 1 {
+            self.statement(depth + 1, code);
+        }
+        Self::indent(depth, code);
+        code.push('}');
+        self.scopes.pop();
+    }
+
+    fn statement(&mut self, depth: usize, code: &mut String) {
+        Self::indent(depth, code);
+        let nested = depth < self.max_depth;
+        loop {
+            match self.below(9) {
+                0 | 1 => {
+                    let constant = self.chance(2);
+                    let name = self.name("v");
+                    let value = self.expression_at(0);
+                    let keyword = if constant { "const" } else { "let" };
+                    code.push_str(&format!("{} {} = {};", keyword, name, value));
+                    let kind = if constant {
+                        NameKind::Constant
+                    } else {
+                        NameKind::Variable
+                    };
+                    self.declare(&name, kind);
+                }
+                2 => code.push_str(&format!("{};", self.expression_at(0))),
+                3 if nested && self.enables(Feature::Functions) => {
+                    let name = self.name("f");
+                    let params: Vec<_> = (0..self.below(3)).map(|_| self.name("p")).collect();
+                    let scope = params
+                        .iter()
+                        .map(|param| Name {
+                            name: param.clone(),
+                            kind: NameKind::Variable,
+                        })
+                        .collect();
+                    code.push_str(&format!("function {}({}) ", name, params.join(", ")));
+                    let in_function = std::mem::replace(&mut self.in_function, true);
+                    self.block(depth, code, scope);
+                    self.in_function = in_function;
+                    // Declared after its body, so it can't call itself
+                    self.declare(&name, NameKind::Function(params.len()));
+                }
+                4 if nested && self.enables(Feature::Blocks) => self.block(depth, code, Vec::new()),
+                5 if nested && self.enables(Feature::Exceptions) => {
+                    code.push_str("try ");
+                    self.block(depth, code, Vec::new());
+                    let binding = self.name("e");
+                    code.push_str(&format!(" catch ({}) ", binding));
+                    let scope = vec![Name {
+                        name: binding,
+                        kind: NameKind::Constant,
+                    }];
+                    self.block(depth, code, scope);
+                }
+                6 if self.enables(Feature::Exceptions) && self.chance(2) => {
+                    code.push_str(&format!("throw {};", self.expression_at(0)));
+                }
+                7 if self.enables(Feature::Yield) => {
+                    match self.chance(2) {
+                        true => code.push_str("yield;"),
+                        false => code.push_str(&format!("yield {};", self.expression_at(0))),
+                    };
+                }
+                // Returning from the top level ends the script, so it's rarer there
+                8 if self.in_function || self.chance(4) => {
+                    code.push_str(&format!("return {};", self.expression_at(0)));
+                }
+                _ => continue,
+            }
+            break;
+        }
+        code.push('\n');
+    }
+
+    fn expression_at(&mut self, depth: usize) -> String {
+        if depth >= self.max_depth {
+            return self.atom();
+        }
+        loop {
+            let expression = match self.below(8) {
+                0 | 1 => self.atom(),
+                2 | 3 => {
+                    let operator = OPERATORS[self.below(OPERATORS.len())];
+                    format!(
+                        "({} {} {})",
+                        self.expression_at(depth + 1),
+                        operator,
+                        self.expression_at(depth + 1)
+                    )
+                }
+                4 if self.enables(Feature::Ternaries) => format!(
+                    "({} ? {} : {})",
+                    self.expression_at(depth + 1),
+                    self.expression_at(depth + 1),
+                    self.expression_at(depth + 1)
+                ),
+                5 if self.enables(Feature::Functions) => {
+                    match self.pick(|kind| matches!(kind, NameKind::Function(_))) {
+                        Some(Name {
+                            name,
+                            kind: NameKind::Function(params),
+                        }) => {
+                            // Sometimes with the wrong number of arguments
+                            let count = match self.chance(8) {
+                                true => self.below(4),
+                                false => params,
+                            };
+                            let args: Vec<_> =
+                                (0..count).map(|_| self.expression_at(depth + 1)).collect();
+                            format!("{}({})", name, args.join(", "))
+                        }
+                        _ => continue,
+                    }
+                }
+                6 if self.enables(Feature::Assignments) => {
+                    match self.pick(|kind| kind == NameKind::Variable) {
+                        Some(Name { name, .. }) => {
+                            format!("({} = {})", name, self.expression_at(depth + 1))
+                        }
+                        None => continue,
+                    }
+                }
+                7 if self.enables(Feature::Members) => {
+                    format!("{}.p{}", self.atom(), self.below(3))
+                }
+                _ => continue,
+            };
+            return expression;
+        }
+    }
+
+    /// Generate a literal or a binding in scope.
+    fn atom(&mut self) -> String {
+        if self.chance(2) {
+            let name = self.pick(|kind| !matches!(kind, NameKind::Function(_)));
+            if let Some(Name { name, .. }) = name {
+                return name;
+            }
+        }
+        match self.below(4) {
+            0 => self.number().to_string(),
+            1 => fmt::quote(&self.string()),
+            2 => ["true", "false"][self.below(2)].into(),
+            _ => "null".into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast, Jabroni, Step};
+
+    #[test]
+    fn generated_scripts_never_panic() {
+        for seed in 0..300 {
+            let mut generator = Generator::new(seed);
+            let script = generator.script();
+            if let Err(e) = ast::parse(&script) {
+                panic!("{}\n{}", script, e);
+            }
+            // Formatting doesn't change what a script means
+            let formatted = fmt::format(&script).unwrap();
+            assert_eq!(fmt::format(&formatted).unwrap(), formatted);
+            let _ = Jabroni::new().run_script(&script);
+            let mut jabroni = Jabroni::new();
+            let mut resumable = jabroni.run_resumable(&script);
+            while let Ok(Step::Yielded(_)) = resumable.resume() {}
+
+            let expression = generator.expression();
+            let _ = Jabroni::new().run_expression(&expression);
+            let _ = generator.value().to_string();
+        }
+    }
+
+    #[test]
+    fn generating_is_deterministic() {
+        let scripts = |seed| {
+            let mut generator = Generator::new(seed).max_depth(2).without(Feature::Yield);
+            [generator.script(), generator.script()]
+        };
+        assert_eq!(scripts(1), scripts(1));
+        assert_ne!(scripts(1), scripts(2));
+        let script = scripts(3).join("");
+        assert!(!script.contains("yield"));
+        assert_eq!(
+            Generator::new(5).value().to_string(),
+            Generator::new(5).value().to_string()
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! Splitting scripts into tokens, for editors that color code as it's typed.
+//!
+//! Unlike parsing, tokenizing never fails, so it works on scripts that are only partly written.
+
+use crate::ast::Span;
+
+/// Words reserved by the language.
+pub const KEYWORDS: [&str; 12] = [
+    "function", "return", "yield", "const", "let", "throw", "try", "catch", "import", "from",
+    "true", "false",
+];
+
+/// What a [`Token`] is.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum TokenKind {
+    /// A word reserved by the language, such as `function` or `true`
+    Keyword,
+    /// `null`
+    Null,
+    /// A name, such as a binding or member
+    Ident,
+    /// A number, such as `42` or `0xff`
+    Number,
+    /// A string, with its quotes. It may be missing its closing quote
+    String,
+    /// An operator, such as `+` or `===`
+    Operator,
+    /// A bracket: one of `(`, `)`, `{` or `}`
+    Bracket,
+    /// `.`, `,` or `;`
+    Punctuation,
+    /// A character that can't start a token
+    Unknown,
+}
+
+/// A piece of a script, with where it appears.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// Split a script into tokens, leaving out whitespace.
+///
+/// ```
+/// use jabroni::tokens::{tokenize, TokenKind};
+///
+/// let code = "let x = 'a";
+/// let kinds: Vec<_> = tokenize(code).iter().map(|token| token.kind).collect();
+/// assert_eq!(
+///     kinds,
+///     [TokenKind::Keyword, TokenKind::Ident, TokenKind::Operator, TokenKind::String]
+/// );
+/// ```
+pub fn tokenize(code: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = code.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let kind = match c {
+            c if c.is_whitespace() => continue,
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                while chars
+                    .next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
+                    .is_some()
+                {}
+                let end = chars.peek().map_or(code.len(), |(i, _)| *i);
+                match &code[start..end] {
+                    _ if c.is_ascii_digit() => TokenKind::Number,
+                    "null" => TokenKind::Null,
+                    word if KEYWORDS.contains(&word) => TokenKind::Keyword,
+                    _ => TokenKind::Ident,
+                }
+            }
+            '\'' | '"' => {
+                while let Some((_, next)) = chars.next() {
+                    if next == c {
+                        break;
+                    }
+                    if next == '\\' {
+                        chars.next();
+                    }
+                }
+                TokenKind::String
+            }
+            '=' | '!' => {
+                if chars.next_if(|(_, c)| *c == '=').is_some() {
+                    chars.next_if(|(_, c)| *c == '=');
+                }
+                TokenKind::Operator
+            }
+            '<' | '>' => {
+                chars.next_if(|(_, c)| *c == '=');
+                TokenKind::Operator
+            }
+            '+' | '-' | '*' | '/' | '?' | ':' => TokenKind::Operator,
+            '(' | ')' | '{' | '}' => TokenKind::Bracket,
+            '.' | ',' | ';' => TokenKind::Punctuation,
+            _ => TokenKind::Unknown,
+        };
+        let end = chars.peek().map_or(code.len(), |(i, _)| *i);
+        tokens.push(Token {
+            kind,
+            span: Span { start, end },
+        });
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(code: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(code)
+            .into_iter()
+            .map(|token| (token.kind, &code[token.span.start..token.span.end]))
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_scripts() {
+        use TokenKind::*;
+        assert_eq!(
+            tokens("function f(a) { return a.b !== null; }"),
+            [
+                (Keyword, "function"),
+                (Ident, "f"),
+                (Bracket, "("),
+                (Ident, "a"),
+                (Bracket, ")"),
+                (Bracket, "{"),
+                (Keyword, "return"),
+                (Ident, "a"),
+                (Punctuation, "."),
+                (Ident, "b"),
+                (Operator, "!=="),
+                (Null, "null"),
+                (Punctuation, ";"),
+                (Bracket, "}"),
+            ]
+        );
+        assert_eq!(
+            tokens("x>=0xff?'it\\'s':\"é\"#"),
+            [
+                (Ident, "x"),
+                (Operator, ">="),
+                (Number, "0xff"),
+                (Operator, "?"),
+                (String, "'it\\'s'"),
+                (Operator, ":"),
+                (String, "\"é\""),
+                (Unknown, "#"),
+            ]
+        );
+        assert_eq!(
+            tokens("'\\\\' + 1"),
+            [(String, "'\\\\'"), (Operator, "+"), (Number, "1")]
+        );
+        // Unfinished code still tokenizes
+        assert_eq!(
+            tokens("f(\"abc"),
+            [(Ident, "f"), (Bracket, "("), (String, "\"abc")]
+        );
+    }
+}
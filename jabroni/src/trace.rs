@@ -0,0 +1,39 @@
+use crate::{errors::Location, value::Value};
+
+/// Callbacks for following what a script does as it runs, set with
+/// [`Jabroni::set_tracer`](crate::Jabroni::set_tracer). Each callback gets the location of the
+/// code in its script and the code itself. They all do nothing by default.
+///
+/// # Example
+/// ```
+/// use jabroni::{errors::Location, Jabroni, Tracer, Value as JabroniValue};
+/// use std::{cell::RefCell, rc::Rc};
+///
+/// struct Console(Rc<RefCell<Vec<String>>>);
+///
+/// impl Tracer for Console {
+///     fn binding_mutated(&mut self, location: Location, ident: &str, value: &JabroniValue) {
+///         self.0.borrow_mut().push(format!("{location}: {ident} = {value}"));
+///     }
+/// }
+///
+/// let lines = Rc::new(RefCell::new(Vec::new()));
+/// let mut interpreter = Jabroni::new();
+/// interpreter.set_tracer(Console(lines.clone()));
+/// interpreter.run_script("let hp = 10;\nhp = hp - 3;").unwrap();
+/// assert_eq!(*lines.borrow(), ["1:1: hp = 10", "2:1: hp = 7"]);
+/// ```
+pub trait Tracer {
+    /// Called before a statement runs.
+    fn statement_enter(&mut self, _location: Location, _code: &str) {}
+
+    /// Called with the value of each expression and subexpression after it's evaluated.
+    fn expression_evaluated(&mut self, _location: Location, _code: &str, _value: &Value) {}
+
+    /// Called before a function is called, with the code for the function being called.
+    fn function_call(&mut self, _location: Location, _callee: &str, _args: &[Value]) {}
+
+    /// Called after a binding is declared or assigned, with the code for the binding, such as
+    /// `x` or `player.hp`.
+    fn binding_mutated(&mut self, _location: Location, _ident: &str, _value: &Value) {}
+}
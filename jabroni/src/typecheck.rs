@@ -0,0 +1,362 @@
+//! Finds type errors in a script without running it.
+
+use crate::{
+    ast::{
+        DeclarationKind, Expression, ExpressionKind, Ident, Script, Span, Statement, StatementKind,
+    },
+    binding::BindingMap,
+    diagnostics::{Diagnostic, Severity},
+    errors::ErrorCode,
+    value::{Coercion, Equality, Operator, Value},
+};
+use std::{collections::HashMap, mem};
+
+/// What's known about the type of a value before the script runs.
+#[derive(Debug, Clone)]
+enum Type {
+    Number,
+    Boolean,
+    String,
+    Bytes,
+    Null,
+    /// An object, with its value if it's known from the interpreter's bindings.
+    Object(Option<BindingMap>),
+    /// A function, with the number of arguments it takes if it isn't variadic.
+    Function(Option<usize>),
+    Unknown,
+}
+
+impl Type {
+    fn of(value: &Value) -> Self {
+        match value {
+            Value::Number(_) => Type::Number,
+            Value::Boolean(_) => Type::Boolean,
+            Value::String(_) => Type::String,
+            Value::Bytes(_) => Type::Bytes,
+            Value::Null => Type::Null,
+            Value::Object(object) => Type::Object(Some(object.borrow().clone())),
+            Value::Subroutine(subroutine) => Type::Function(subroutine.arity()),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Type::Number => "number",
+            Type::Boolean => "boolean",
+            Type::String => "string",
+            Type::Bytes => "bytes",
+            Type::Null => "null",
+            Type::Object(_) => "object",
+            Type::Function(_) => "function",
+            Type::Unknown => "unknown",
+        }
+    }
+
+    fn is_known(&self) -> bool {
+        !matches!(self, Type::Unknown)
+    }
+
+    /// Check if both types are known and different.
+    fn differs(&self, other: &Type) -> bool {
+        self.is_known() && other.is_known() && mem::discriminant(self) != mem::discriminant(other)
+    }
+}
+
+struct Declared {
+    ty: Type,
+    mutable: bool,
+}
+
+/// Find type errors in a script, given the bindings it will run with.
+pub fn typecheck(
+    script: &Script,
+    code: &str,
+    bindings: &BindingMap,
+    coercion: Coercion,
+    equality: Equality,
+) -> Vec<Diagnostic> {
+    let mut checker = TypeChecker {
+        code,
+        bindings,
+        coercion,
+        equality,
+        scopes: vec![HashMap::new()],
+        diagnostics: Vec::new(),
+    };
+    checker.statements(&script.statements);
+    checker.diagnostics
+}
+
+struct TypeChecker<'a> {
+    code: &'a str,
+    bindings: &'a BindingMap,
+    /// Loose coercion allows conditions of any type, and `==` between any types.
+    coercion: Coercion,
+    /// Whether `==` and `!=` are allowed.
+    equality: Equality,
+    /// Bindings declared by the script, innermost scope last.
+    scopes: Vec<HashMap<String, Declared>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl TypeChecker<'_> {
+    fn error(&mut self, span: Span, code: ErrorCode, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            message,
+            code: None,
+            location: span.location(self.code),
+            error_code: Some(code),
+        });
+    }
+
+    fn declare(&mut self, ident: &Ident, ty: Type, mutable: bool) {
+        // Scopes are never empty
+        let scope = self.scopes.last_mut().unwrap();
+        scope.insert(ident.name.clone(), Declared { ty, mutable });
+    }
+
+    /// Get the type of a binding and whether it's mutable.
+    fn lookup(&self, ident: &str) -> Option<(Type, bool)> {
+        if let Some(declared) = self.scopes.iter().rev().find_map(|scope| scope.get(ident)) {
+            return Some((declared.ty.clone(), declared.mutable));
+        }
+        // Values supplied by the host on access could be anything
+        let (_, binding) = self.bindings.iter().find(|(name, _)| *name == ident)?;
+        match binding.is_stored() {
+            true => Some((Type::of(binding.value()), binding.mutable())),
+            false => Some((Type::Unknown, binding.mutable())),
+        }
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Expression(expression)
+            | StatementKind::Return(expression)
+            | StatementKind::Throw(expression) => {
+                self.expression(expression);
+            }
+            StatementKind::Yield(expression) => {
+                if let Some(expression) = expression {
+                    self.expression(expression);
+                }
+            }
+            StatementKind::Block(statements) => {
+                self.scopes.push(HashMap::new());
+                self.statements(statements);
+                self.scopes.pop();
+            }
+            StatementKind::Function { name, params, body } => {
+                self.declare(name, Type::Function(Some(params.len())), false);
+                self.scopes.push(HashMap::new());
+                for param in params {
+                    self.declare(param, Type::Unknown, false);
+                }
+                self.statements(body);
+                self.scopes.pop();
+            }
+            StatementKind::Declaration { kind, name, value } => {
+                let ty = self.expression(value);
+                self.declare(name, ty, *kind == DeclarationKind::Let);
+            }
+            StatementKind::Try {
+                body,
+                binding,
+                handler,
+            } => {
+                self.scopes.push(HashMap::new());
+                self.statements(body);
+                self.scopes.pop();
+                self.scopes.push(HashMap::new());
+                self.declare(binding, Type::Object(None), false);
+                self.statements(handler);
+                self.scopes.pop();
+            }
+            StatementKind::Import { names, .. } => {
+                for name in names {
+                    self.declare(name, Type::Unknown, false);
+                }
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Type {
+        let span = expression.span;
+        match &expression.kind {
+            ExpressionKind::Literal(value) => Type::of(value),
+            ExpressionKind::Ident(ident) => self
+                .lookup(ident)
+                .map(|(ty, _)| ty)
+                .unwrap_or(Type::Unknown),
+            ExpressionKind::Group(expression) => self.expression(expression),
+            ExpressionKind::Member { object, property } => match self.expression(object) {
+                Type::Object(Some(object)) if object.handler().is_none() => object
+                    .iter()
+                    .find(|(name, _)| *name == property.name)
+                    .filter(|(_, binding)| binding.is_stored())
+                    .map(|(_, binding)| Type::of(binding.value()))
+                    .unwrap_or(Type::Unknown),
+                Type::Object(_) | Type::Unknown => Type::Unknown,
+                ty => {
+                    self.error(
+                        object.span,
+                        ErrorCode::NotAnObject,
+                        format!("Cannot access '{}' on {}", property.name, ty.name()),
+                    );
+                    Type::Unknown
+                }
+            },
+            ExpressionKind::Call { callee, args } => {
+                let ty = self.expression(callee);
+                for arg in args {
+                    self.expression(arg);
+                }
+                let name = &self.code[callee.span.start..callee.span.end];
+                match ty {
+                    Type::Function(Some(arity)) if arity != args.len() => {
+                        let message = format!(
+                            "'{}' takes {} arguments, but {} were given",
+                            name,
+                            arity,
+                            args.len()
+                        );
+                        self.error(span, ErrorCode::ArgumentCount, message);
+                    }
+                    Type::Function(_) | Type::Unknown => (),
+                    ty => {
+                        let message = format!("'{}' is {}, not a function", name, ty.name());
+                        self.error(callee.span, ErrorCode::NotAFunction, message);
+                    }
+                }
+                Type::Unknown
+            }
+            ExpressionKind::Assignment { target, value } => {
+                let value_type = self.expression(value);
+                let (target_type, mutable) = match &target.kind {
+                    ExpressionKind::Ident(ident) => {
+                        self.lookup(ident).unwrap_or((Type::Unknown, true))
+                    }
+                    _ => (self.expression(target), true),
+                };
+                let name = &self.code[target.span.start..target.span.end];
+                if !mutable {
+                    let message = format!("Cannot assign to constant '{}'", name);
+                    self.error(span, ErrorCode::ConstantAssignment, message);
+                } else if value_type.differs(&target_type) {
+                    let message = format!(
+                        "Cannot assign {} to '{}', which holds {}",
+                        value_type.name(),
+                        name,
+                        target_type.name()
+                    );
+                    self.error(span, ErrorCode::AssignmentTypeMismatch, message);
+                }
+                Type::Null
+            }
+            ExpressionKind::Ternary {
+                condition,
+                consequent,
+                alternative,
+            } => {
+                let condition_type = self.expression(condition);
+                if self.coercion == Coercion::Strict && condition_type.differs(&Type::Boolean) {
+                    self.error(
+                        condition.span,
+                        ErrorCode::NonBooleanCondition,
+                        format!(
+                            "Condition must be a boolean, found {}",
+                            condition_type.name()
+                        ),
+                    );
+                }
+                let consequent = self.expression(consequent);
+                let alternative = self.expression(alternative);
+                match consequent.differs(&alternative) || !alternative.is_known() {
+                    true => Type::Unknown,
+                    false => consequent,
+                }
+            }
+            ExpressionKind::Binary { operator, lhs, rhs } => {
+                let (lhs_type, rhs_type) = (self.expression(lhs), self.expression(rhs));
+                // Objects can overload operators to do anything
+                if matches!(lhs_type, Type::Object(_)) || matches!(rhs_type, Type::Object(_)) {
+                    return Type::Unknown;
+                }
+                self.binary(*operator, (lhs, lhs_type), (rhs, rhs_type))
+            }
+        }
+    }
+
+    fn expect_numbers(&mut self, symbol: &str, operands: [(&Expression, Type); 2]) {
+        for (operand, ty) in operands {
+            if ty.differs(&Type::Number) {
+                let message = format!("'{}' expects numbers, found {}", symbol, ty.name());
+                self.error(operand.span, ErrorCode::OperandTypeMismatch, message);
+            }
+        }
+    }
+
+    fn binary(
+        &mut self,
+        operator: Operator,
+        (lhs, lhs_type): (&Expression, Type),
+        (rhs, rhs_type): (&Expression, Type),
+    ) -> Type {
+        let symbol = operator.symbol();
+        let span = Span {
+            start: lhs.span.start,
+            end: rhs.span.end,
+        };
+        match operator {
+            Operator::Add | Operator::Subtract | Operator::Multiply => {
+                self.expect_numbers(symbol, [(lhs, lhs_type), (rhs, rhs_type)]);
+                Type::Number
+            }
+            // Not supported by the interpreter, which will say so when it's run
+            Operator::Divide => Type::Unknown,
+            Operator::Equal | Operator::NotEqual if self.equality == Equality::StrictOnly => {
+                self.error(
+                    span,
+                    ErrorCode::LooseEquality,
+                    format!("'{symbol}' isn't allowed. Use '{symbol}=' instead"),
+                );
+                Type::Boolean
+            }
+            Operator::Equal | Operator::NotEqual if self.coercion == Coercion::Loose => {
+                Type::Boolean
+            }
+            Operator::Equal | Operator::NotEqual => {
+                if lhs_type.differs(&rhs_type) {
+                    let message = format!(
+                        "Cannot compare {} with {} using '{}'",
+                        lhs_type.name(),
+                        rhs_type.name(),
+                        symbol
+                    );
+                    self.error(span, ErrorCode::OperandTypeMismatch, message);
+                } else if matches!(lhs_type, Type::Null) && matches!(rhs_type, Type::Null) {
+                    self.error(
+                        span,
+                        ErrorCode::NullComparison,
+                        "Can't compare null values. Use '===' or '!=='".into(),
+                    );
+                }
+                Type::Boolean
+            }
+            Operator::StrictEqual | Operator::StrictNotEqual => Type::Boolean,
+            Operator::Greater
+            | Operator::GreaterOrEqual
+            | Operator::Less
+            | Operator::LessOrEqual => {
+                self.expect_numbers(symbol, [(lhs, lhs_type), (rhs, rhs_type)]);
+                Type::Boolean
+            }
+        }
+    }
+}
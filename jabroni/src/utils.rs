@@ -1,16 +1,55 @@
-use crate::errors::{JabroniError, JabroniResult};
+use crate::errors::{ErrorCode, JabroniError, JabroniResult};
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+};
+
+thread_local! {
+    /// Identifiers that have been interned, and how many there were after unused ones were last
+    /// dropped.
+    static INTERNED: RefCell<(HashSet<Rc<str>>, usize)> = RefCell::new((HashSet::new(), 0));
+}
+
+/// Get a shared copy of an identifier, so bindings with the same name share one allocation and
+/// are cheap to clone. Identifiers nothing else holds are dropped as more are interned.
+pub(crate) fn intern(ident: &str) -> Rc<str> {
+    INTERNED.with(|interned| {
+        let (interned, pruned) = &mut *interned.borrow_mut();
+        if let Some(ident) = interned.get(ident) {
+            return ident.clone();
+        }
+        if interned.len() >= 2 * *pruned + 64 {
+            interned.retain(|ident| Rc::strong_count(ident) > 1);
+            *pruned = interned.len();
+        }
+        let ident: Rc<str> = ident.into();
+        interned.insert(ident.clone());
+        ident
+    })
+}
 
 pub fn unquote(string: &str) -> JabroniResult<String> {
     const ALREADY_PARSED_MESSAGE: &str = "Attempted to unquote an already unquoted string";
 
     if string.len() < 2 {
-        return Err(JabroniError::Parse(ALREADY_PARSED_MESSAGE.into()));
+        return Err(JabroniError::from_code(
+            ErrorCode::InvalidLiteral,
+            ALREADY_PARSED_MESSAGE.into(),
+        ));
     }
 
     let mut string = string.chars();
     let terminator = string.next().unwrap(); //Safe because we already checked length
     if terminator != '"' && terminator != '\'' {
-        return Err(JabroniError::Parse(ALREADY_PARSED_MESSAGE.into()));
+        return Err(JabroniError::from_code(
+            ErrorCode::InvalidLiteral,
+            ALREADY_PARSED_MESSAGE.into(),
+        ));
     }
 
     let mut output = String::new();
@@ -23,7 +62,8 @@ pub fn unquote(string: &str) -> JabroniResult<String> {
         }
         if c == terminator && !backslash {
             if string.next().is_some() {
-                return Err(JabroniError::Parse(
+                return Err(JabroniError::from_code(
+                    ErrorCode::InvalidLiteral,
                     "While parsing string, met terminator before end of string".into(),
                 ));
             }
@@ -40,7 +80,8 @@ pub fn unquote(string: &str) -> JabroniResult<String> {
             } else if c == '\\' || c == '\'' || c == '\"' {
                 output.push(c);
             } else {
-                return Err(JabroniError::Parse(
+                return Err(JabroniError::from_code(
+                    ErrorCode::InvalidLiteral,
                     "Found unknown escaped sequence while parsing string".into(),
                 ));
             }
@@ -50,7 +91,144 @@ pub fn unquote(string: &str) -> JabroniResult<String> {
         backslash = false;
     }
 
-    Err(JabroniError::Parse(
+    Err(JabroniError::from_code(
+        ErrorCode::InvalidLiteral,
         "String parsing unexpectedly cut short".into(),
     ))
 }
+
+/// Poll a future once, without anything to wake it.
+pub fn poll<F: Future + ?Sized>(future: Pin<&mut F>) -> Poll<F::Output> {
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    let waker = Waker::from(Arc::new(NoopWaker));
+    future.poll(&mut Context::from_waker(&waker))
+}
+
+/// Run a future to completion on the current thread. There's no executor to wait on, so a future
+/// that isn't ready when polled is an error.
+pub fn block_on<T>(future: impl Future<Output = JabroniResult<T>>) -> JabroniResult<T> {
+    match poll(Box::pin(future).as_mut()) {
+        Poll::Ready(result) => result,
+        Poll::Pending => Err(JabroniError::from_code(
+            ErrorCode::CannotSuspend,
+            "Cannot wait or yield outside of an async or resumable entry point".into(),
+        )),
+    }
+}
+
+/// How much stack must be left to poll a level of evaluation, which is enough for the parser and
+/// host functions as well.
+const RED_ZONE: usize = 1024 * 1024;
+
+/// How much stack to add when there isn't enough left.
+const STACK_SEGMENT: usize = 8 * 1024 * 1024;
+
+pin_project_lite::pin_project! {
+    /// A future that moves to a new stack segment when polled with too little stack left, so
+    /// evaluation can nest as deeply as the recursion limit allows without overflowing the
+    /// thread's stack.
+    pub(crate) struct GrowStack<F> {
+        #[pin]
+        future: F,
+    }
+}
+
+impl<F> GrowStack<F> {
+    pub(crate) fn new(future: F) -> Self {
+        Self { future }
+    }
+}
+
+impl<F: Future> Future for GrowStack<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<F::Output> {
+        let future = self.project().future;
+        stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || future.poll(context))
+    }
+}
+
+/// How deeply code can nest brackets, ternaries and assignments. The parser recurses for each
+/// level, so deeper code could overflow the stack.
+pub(crate) const MAX_NESTING: usize = 128;
+
+/// Find where code first nests deeper than [`MAX_NESTING`], without parsing it. Ternaries and
+/// assignments count as nesting until the end of the bracket, argument or statement they're in.
+pub(crate) fn nesting_overflow(code: &str) -> Option<usize> {
+    let mut depth = 0;
+    // The depth outside each open bracket
+    let mut brackets = Vec::new();
+    let mut quote = None;
+    let mut backslash = false;
+    let mut previous = ' ';
+    let mut chars = code.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        if let Some(terminator) = quote {
+            if c == terminator && !backslash {
+                quote = None;
+            }
+            backslash = c == '\\' && !backslash;
+            continue;
+        }
+        let next = chars.peek().map_or(' ', |(_, c)| *c);
+        match c {
+            '\'' | '"' => quote = Some(c),
+            '(' | '{' => {
+                brackets.push(depth);
+                depth += 1;
+            }
+            ')' | '}' => depth = brackets.pop().unwrap_or(0),
+            ';' | ',' => depth = brackets.last().map_or(0, |outside| outside + 1),
+            '?' => depth += 1,
+            '=' if !matches!(previous, '=' | '!' | '<' | '>') && next != '=' => depth += 1,
+            _ => (),
+        }
+        if depth > MAX_NESTING {
+            return Some(index);
+        }
+        previous = c;
+    }
+    None
+}
+
+/// Get the length of the shebang line at the start of `code`, without its newline, or 0 if there
+/// isn't one.
+pub(crate) fn shebang_len(code: &str) -> usize {
+    match code.starts_with("#!") {
+        true => code.find('\n').unwrap_or(code.len()),
+        false => 0,
+    }
+}
+
+/// Find the end of the statement at the start of `code`, without parsing it: just after the
+/// first `;` or closing `}` that isn't nested in braces or a string. Used to skip past a statement
+/// that failed to parse. Always skips at least one character of non-empty code.
+pub fn statement_end(code: &str) -> usize {
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut backslash = false;
+    for (index, c) in code.char_indices() {
+        let end = index + c.len_utf8();
+        if let Some(terminator) = quote {
+            if c == terminator && !backslash {
+                quote = None;
+            }
+            backslash = c == '\\' && !backslash;
+            continue;
+        }
+        match c {
+            '\'' | '"' => quote = Some(c),
+            '{' => depth += 1,
+            '}' if depth <= 1 => return end,
+            '}' => depth -= 1,
+            ';' if depth == 0 => return end,
+            _ => (),
+        }
+    }
+    code.len()
+}
@@ -1,18 +1,41 @@
 use crate::{
-    binding::BindingMap,
-    errors::{JabroniError, JabroniResult},
+    binding::{Binding, BindingMap},
+    bytes,
+    errors::{ErrorCode, JabroniError, JabroniResult},
+    object::Object,
+    reflect::Parameter,
     utils,
 };
 use enum_as_inner::EnumAsInner;
+use smallvec::SmallVec;
 use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
     fmt::{Debug, Display, Formatter},
+    future::{self, Future},
+    pin::Pin,
     rc::Rc,
 };
 
-type Number = i32;
+/// The type of numbers in scripts.
+pub type Number = i64;
 
 type SubroutineCallback = Box<dyn Fn(BindingMap, &mut [Value]) -> JabroniResult<Value>>;
 
+/// The future returned by an asynchronous Jabroni function.
+pub type SubroutineFuture = Pin<Box<dyn Future<Output = JabroniResult<Value>>>>;
+
+/// Arguments to a function call. Most calls pass only a few, so they're kept inline rather than
+/// allocated.
+pub(crate) type Args = SmallVec<[Value; 4]>;
+
+type AsyncSubroutineCallback = Box<dyn Fn(BindingMap, Args) -> SubroutineFuture>;
+
+enum Callback {
+    Sync(SubroutineCallback),
+    Async(AsyncSubroutineCallback),
+}
+
 #[derive(Clone)]
 /// A Jabroni function.
 /// # Example
@@ -33,7 +56,21 @@ type SubroutineCallback = Box<dyn Fn(BindingMap, &mut [Value]) -> JabroniResult<
 /// ```
 pub struct Subroutine {
     number_of_args: Option<usize>,
-    callback: Rc<SubroutineCallback>,
+    callback: Rc<Callback>,
+    name: Option<Rc<str>>,
+    /// How to call it, such as `log(...values)`.
+    signature: Option<Rc<str>>,
+    /// What it does, for people writing scripts.
+    doc: Option<Rc<str>>,
+    /// The names, and maybe types, of its parameters, if they're known.
+    parameters: Option<Rc<[Parameter]>>,
+    /// The `function` statement that defined it, if a script did, rather than the host.
+    definition: Option<Rc<str>>,
+    /// Whether it can return different results for the same arguments, such as by reading the
+    /// clock.
+    nondeterministic: bool,
+    /// Whether it has no side effects.
+    pure: bool,
 }
 
 impl Subroutine {
@@ -41,7 +78,14 @@ impl Subroutine {
     pub fn new(number_of_args: usize, callback: SubroutineCallback) -> Self {
         Self {
             number_of_args: Some(number_of_args),
-            callback: Rc::new(callback),
+            callback: Rc::new(Callback::Sync(callback)),
+            name: None,
+            signature: None,
+            doc: None,
+            parameters: None,
+            definition: None,
+            nondeterministic: false,
+            pure: false,
         }
     }
 
@@ -49,21 +93,310 @@ impl Subroutine {
     pub fn new_variadic(callback: SubroutineCallback) -> Self {
         Self {
             number_of_args: None,
+            callback: Rc::new(Callback::Sync(callback)),
+            name: None,
+            signature: None,
+            doc: None,
+            parameters: None,
+            definition: None,
+            nondeterministic: false,
+            pure: false,
+        }
+    }
+
+    /// Construct a new Jabroni function that returns a future. Scripts run with
+    /// [`Jabroni::run_script_async`](crate::Jabroni::run_script_async) await it, without blocking
+    /// the executor. Synchronous entry points can only call it if the future is ready when first
+    /// polled.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Jabroni, Subroutine, Value as JabroniValue};
+    ///
+    /// async fn fetch_score(player: JabroniValue) -> JabroniValue {
+    ///     // Imagine a database query here
+    ///     JabroniValue::Number(player.into_string().unwrap().len() as i64)
+    /// }
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.define_constant(
+    ///     "fetch_score",
+    ///     JabroniValue::Subroutine(Subroutine::new_async(Some(1), |_, mut args| async move {
+    ///         Ok(fetch_score(args.remove(0)).await)
+    ///     })),
+    /// );
+    /// assert_eq!(interpreter.run_expression("fetch_score('bob')").unwrap(), 3.into());
+    /// ```
+    pub fn new_async<F, Fut>(number_of_args: Option<usize>, callback: F) -> Self
+    where
+        F: Fn(BindingMap, Vec<Value>) -> Fut + 'static,
+        Fut: Future<Output = JabroniResult<Value>> + 'static,
+    {
+        Self::new_async_inline(number_of_args, move |context, args: Args| {
+            callback(context, args.into_vec())
+        })
+    }
+
+    /// Construct an asynchronous function that takes its arguments as they were collected, so
+    /// calls with few arguments don't allocate them.
+    pub(crate) fn new_async_inline<F, Fut>(number_of_args: Option<usize>, callback: F) -> Self
+    where
+        F: Fn(BindingMap, Args) -> Fut + 'static,
+        Fut: Future<Output = JabroniResult<Value>> + 'static,
+    {
+        Self {
+            number_of_args,
+            callback: Rc::new(Callback::Async(Box::new(move |context, args| {
+                Box::pin(callback(context, args))
+            }))),
+            name: None,
+            signature: None,
+            doc: None,
+            parameters: None,
+            definition: None,
+            nondeterministic: false,
+            pure: false,
+        }
+    }
+
+    /// Construct a function that's always called in `context`, rather than the caller's.
+    pub(crate) fn with_context(&self, context: BindingMap) -> Self {
+        let callback = match &*self.callback {
+            Callback::Sync(_) => {
+                let subroutine = self.clone();
+                Callback::Sync(Box::new(move |_, args: &mut [Value]| {
+                    subroutine.call(context.new_context(), args)
+                }))
+            }
+            Callback::Async(_) => {
+                let subroutine = self.clone();
+                Callback::Async(Box::new(move |_, args| {
+                    subroutine.call_inline(context.new_context(), args)
+                }))
+            }
+        };
+        Self {
+            number_of_args: self.number_of_args,
             callback: Rc::new(callback),
+            name: self.name.clone(),
+            signature: self.signature.clone(),
+            doc: self.doc.clone(),
+            parameters: self.parameters.clone(),
+            definition: self.definition.clone(),
+            nondeterministic: self.nondeterministic,
+            pure: self.pure,
         }
     }
 
-    /// Call the function.
-    pub fn call(&self, context: BindingMap, args: &mut [Value]) -> JabroniResult<Value> {
+    /// Construct a function that runs `check` before each call, failing if it does.
+    pub(crate) fn guarded(&self, check: impl Fn() -> JabroniResult + 'static) -> Self {
+        let check = Rc::new(check);
+        let subroutine = self.clone();
+        let callback = match &*self.callback {
+            Callback::Sync(_) => Callback::Sync(Box::new(move |context, args: &mut [Value]| {
+                check()?;
+                subroutine.call(context, args)
+            })),
+            Callback::Async(_) => Callback::Async(Box::new(move |context, args| {
+                if let Err(error) = check() {
+                    return Box::pin(future::ready(Err(error)));
+                }
+                subroutine.call_inline(context, args)
+            })),
+        };
+        Self {
+            number_of_args: self.number_of_args,
+            callback: Rc::new(callback),
+            name: self.name.clone(),
+            signature: self.signature.clone(),
+            doc: self.doc.clone(),
+            parameters: self.parameters.clone(),
+            definition: self.definition.clone(),
+            nondeterministic: self.nondeterministic,
+            pure: self.pure,
+        }
+    }
+
+    /// Give the function a name, which is shown when it's displayed.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Subroutine, Value as JabroniValue};
+    ///
+    /// let log = Subroutine::new_variadic(Box::new(|_, _| Ok(JabroniValue::Null)));
+    /// assert_eq!(JabroniValue::Subroutine(log.clone()).to_string(), "[function(...)]");
+    /// let log = log.named("log");
+    /// assert_eq!(log.name(), Some("log"));
+    /// assert_eq!(JabroniValue::Subroutine(log).to_string(), "[function log(...)]");
+    /// ```
+    pub fn named(mut self, name: &str) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// The function's name, if it has one. Functions defined by scripts are named.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Describe the function for people writing scripts, with how to call it, such as
+    /// `clamp(value, min, max)`, and what it does. Editors and the REPL's `:doc` command show
+    /// these.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Subroutine, Value as JabroniValue};
+    ///
+    /// let clamp = Subroutine::new(3, Box::new(|_, _| Ok(JabroniValue::Null)))
+    ///     .named("clamp")
+    ///     .documented("clamp(value, min, max)", "Limit a number to a range.");
+    /// assert_eq!(clamp.signature(), Some("clamp(value, min, max)"));
+    /// assert_eq!(clamp.doc(), Some("Limit a number to a range."));
+    /// ```
+    pub fn documented(mut self, signature: &str, doc: &str) -> Self {
+        self.signature = Some(signature.into());
+        self.doc = Some(doc.into());
+        self
+    }
+
+    /// How to call the function, if it's documented or defined by a script.
+    pub fn signature(&self) -> Option<&str> {
+        self.signature.as_deref()
+    }
+
+    /// What the function does, if it's documented.
+    pub fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+
+    /// Name the function's parameters, and maybe their types, for tools that describe it with
+    /// [`Jabroni::describe`](crate::Jabroni::describe). Functions defined by scripts name their
+    /// parameters themselves.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{reflect::Parameter, Subroutine, Value as JabroniValue};
+    ///
+    /// let repeat = Subroutine::new(2, Box::new(|_, _| Ok(JabroniValue::Null)))
+    ///     .with_parameters(vec![
+    ///         Parameter::new("text").typed("string"),
+    ///         Parameter::new("times").typed("number"),
+    ///     ]);
+    /// assert_eq!(repeat.parameters().unwrap()[1].name, "times");
+    /// ```
+    pub fn with_parameters(mut self, parameters: Vec<Parameter>) -> Self {
+        self.parameters = Some(parameters.into());
+        self
+    }
+
+    /// The function's parameters, if they're known.
+    pub fn parameters(&self) -> Option<&[Parameter]> {
+        self.parameters.as_deref()
+    }
+
+    /// Give the function a signature without documenting it, as for functions defined by scripts.
+    pub(crate) fn with_signature(mut self, signature: String) -> Self {
+        self.signature = Some(signature.into());
+        self
+    }
+
+    /// Mark the function as able to return different results for the same arguments, such as by
+    /// reading the clock or generating random numbers. Deterministic interpreters refuse to
+    /// define it.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Jabroni, Subroutine, Value as JabroniValue};
+    ///
+    /// let roll = Subroutine::new(0, Box::new(|_, _| Ok(JabroniValue::Number(4))));
+    /// assert!(!roll.is_nondeterministic());
+    /// let roll = roll.nondeterministic();
+    /// assert!(roll.is_nondeterministic());
+    ///
+    /// let mut interpreter = Jabroni::new();
+    /// interpreter.set_deterministic(true).unwrap();
+    /// assert!(interpreter.define_constant("roll", roll.into()).is_err());
+    /// ```
+    pub fn nondeterministic(mut self) -> Self {
+        self.nondeterministic = true;
+        self
+    }
+
+    /// Whether the function can return different results for the same arguments.
+    pub fn is_nondeterministic(&self) -> bool {
+        self.nondeterministic
+    }
+
+    /// Mark the function as having no side effects, so it can be called by expressions run with
+    /// [`Jabroni::evaluate_pure`](crate::Jabroni::evaluate_pure).
+    pub fn pure(mut self) -> Self {
+        self.pure = true;
+        self
+    }
+
+    /// Whether the function has no side effects.
+    pub fn is_pure(&self) -> bool {
+        self.pure
+    }
+
+    /// Mark this function as defined by a script, with the `function` statement that defined it.
+    pub(crate) fn scripted(mut self, definition: &str) -> Self {
+        self.definition = Some(definition.into());
+        self
+    }
+
+    /// Whether this function was defined by a script, rather than the host.
+    pub(crate) fn is_scripted(&self) -> bool {
+        self.definition.is_some()
+    }
+
+    /// The `function` statement that defined this function, if a script did.
+    #[cfg(any(feature = "serde", feature = "jit"))]
+    pub(crate) fn definition(&self) -> Option<&str> {
+        self.definition.as_deref()
+    }
+
+    /// The number of arguments the function takes, or `None` if it's variadic.
+    pub fn arity(&self) -> Option<usize> {
+        self.number_of_args
+    }
+
+    fn check_arguments(&self, args: &[Value]) -> JabroniResult {
         if let Some(number_of_args) = self.number_of_args {
             if args.len() != number_of_args {
-                return Err(JabroniError::InvalidArguments(
+                return Err(JabroniError::from_code(
+                    ErrorCode::ArgumentCount,
                     "Incorrect number of arguments".into(),
                 ));
             }
         }
-        let callback = self.callback.clone();
-        callback(context, args)
+        Ok(())
+    }
+
+    /// Call the function. Asynchronous functions must complete without waiting.
+    pub fn call(&self, context: BindingMap, args: &mut [Value]) -> JabroniResult<Value> {
+        self.check_arguments(args)?;
+        match &*self.callback {
+            Callback::Sync(callback) => callback(context, args),
+            Callback::Async(callback) => utils::block_on(callback(context, Args::from(&args[..]))),
+        }
+    }
+
+    /// Call the function, returning a future that resolves to its result.
+    pub fn call_async(&self, context: BindingMap, args: Vec<Value>) -> SubroutineFuture {
+        self.call_inline(context, Args::from_vec(args))
+    }
+
+    /// Call the function with arguments as they were collected, returning a future that resolves
+    /// to its result.
+    pub(crate) fn call_inline(&self, context: BindingMap, mut args: Args) -> SubroutineFuture {
+        if let Err(error) = self.check_arguments(&args) {
+            return Box::pin(future::ready(Err(error)));
+        }
+        match &*self.callback {
+            Callback::Sync(callback) => Box::pin(future::ready(callback(context, &mut args))),
+            Callback::Async(callback) => callback(context, args),
+        }
     }
 }
 
@@ -72,12 +405,130 @@ impl Debug for Subroutine {
         write!(f, "[function]")
     }
 }
+
+/// Displayed with its name and the number of arguments it takes, such as `[function add(2)]`, or
+/// `[function add(...)]` if it takes any number.
+impl Display for Subroutine {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "[function")?;
+        if let Some(name) = &self.name {
+            write!(f, " {name}")?;
+        }
+        match self.number_of_args {
+            Some(number_of_args) => write!(f, "({number_of_args})]"),
+            None => write!(f, "(...)]"),
+        }
+    }
+}
 impl PartialEq for Subroutine {
     fn eq(&self, other: &Self) -> bool {
         self.number_of_args == other.number_of_args && Rc::ptr_eq(&self.callback, &other.callback)
     }
 }
 
+/// Binary operators that can be overloaded by an [`ObjectHandler`](crate::ObjectHandler).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Operator {
+    /// `+`
+    Add,
+    /// `-`
+    Subtract,
+    /// `*`
+    Multiply,
+    /// `/`
+    Divide,
+    /// `==`
+    Equal,
+    /// `!=`
+    NotEqual,
+    /// `===`
+    StrictEqual,
+    /// `!==`
+    StrictNotEqual,
+    /// `>`
+    Greater,
+    /// `>=`
+    GreaterOrEqual,
+    /// `<`
+    Less,
+    /// `<=`
+    LessOrEqual,
+}
+
+impl Operator {
+    /// Get the operator corresponding to a symbol, such as `+`.
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        Some(match symbol {
+            "+" => Self::Add,
+            "-" => Self::Subtract,
+            "*" => Self::Multiply,
+            "/" => Self::Divide,
+            "==" => Self::Equal,
+            "!=" => Self::NotEqual,
+            "===" => Self::StrictEqual,
+            "!==" => Self::StrictNotEqual,
+            ">" => Self::Greater,
+            ">=" => Self::GreaterOrEqual,
+            "<" => Self::Less,
+            "<=" => Self::LessOrEqual,
+            _ => return None,
+        })
+    }
+
+    /// Get the symbol for the operator, such as `+`.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Subtract => "-",
+            Self::Multiply => "*",
+            Self::Divide => "/",
+            Self::Equal => "==",
+            Self::NotEqual => "!=",
+            Self::StrictEqual => "===",
+            Self::StrictNotEqual => "!==",
+            Self::Greater => ">",
+            Self::GreaterOrEqual => ">=",
+            Self::Less => "<",
+            Self::LessOrEqual => "<=",
+        }
+    }
+}
+
+/// How strictly scripts treat values of the wrong type, set with
+/// [`Jabroni::set_coercion`](crate::Jabroni::set_coercion).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Coercion {
+    /// Conditions must be booleans, and `==` fails on values of different types or on nulls.
+    Strict,
+    /// Conditions can be any value, and are true if the value is truthy, as
+    /// [`Value::is_truthy`] decides. `==` compares values of different types with
+    /// [`Value::loose_eq`].
+    Loose,
+}
+
+impl Default for Coercion {
+    fn default() -> Self {
+        Self::Strict
+    }
+}
+
+/// Which equality operators scripts can use, set with
+/// [`Jabroni::set_equality`](crate::Jabroni::set_equality).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Equality {
+    /// `==`, `!=`, `===` and `!==` can all be used.
+    Any,
+    /// Only `===` and `!==` can be used. `==` and `!=` fail with a
+    /// [`LooseEquality`](crate::errors::ErrorCode::LooseEquality) error.
+    StrictOnly,
+}
+
+impl Default for Equality {
+    fn default() -> Self {
+        Self::Any
+    }
+}
+
 #[derive(PartialEq, Debug, Clone, EnumAsInner)]
 /// Enumeration of the different types in Jabroni.
 pub enum Value {
@@ -86,9 +537,12 @@ pub enum Value {
     /// Boolean type
     Boolean(bool),
     /// String type
-    String(String),
+    String(Rc<str>),
+    /// Binary data. There's no literal for it, so it comes from the host or from the functions
+    /// added by [`Jabroni::enable_bytes`](crate::Jabroni::enable_bytes).
+    Bytes(Vec<u8>),
     /// Object type
-    Object(BindingMap),
+    Object(Object),
     /// Function type
     Subroutine(Subroutine),
     /// Null type - corresponds to Javascript's Null/Undefined
@@ -96,6 +550,40 @@ pub enum Value {
 }
 
 impl Value {
+    /// The name of the value's type, as used in messages, such as `"number"` or `"function"`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Boolean(_) => "boolean",
+            Value::String(_) => "string",
+            Value::Bytes(_) => "bytes",
+            Value::Null => "null",
+            Value::Object(_) => "object",
+            Value::Subroutine(_) => "function",
+        }
+    }
+
+    /// Find a nondeterministic function in the value, or in the properties of objects it holds.
+    pub(crate) fn find_nondeterministic(&self) -> Option<Subroutine> {
+        let mut seen = HashSet::new();
+        let mut pending = vec![self.clone()];
+        while let Some(value) = pending.pop() {
+            match value {
+                Value::Subroutine(subroutine) if subroutine.is_nondeterministic() => {
+                    return Some(subroutine)
+                }
+                Value::Object(object) if seen.insert(object.as_ptr()) => pending.extend(
+                    object
+                        .borrow()
+                        .stored_values()
+                        .map(|(value, _)| value.clone()),
+                ),
+                _ => (),
+            }
+        }
+        None
+    }
+
     /// Create a String value form a quoted string literal.
     ///
     /// #Example
@@ -105,7 +593,7 @@ impl Value {
     /// assert_eq!(value, JabroniValue::String("hello".into()));
     /// ```
     pub fn from_string_literal(literal: &str) -> JabroniResult<Self> {
-        Ok(Value::String(utils::unquote(literal)?))
+        Ok(Value::String(utils::unquote(literal)?.into()))
     }
 
     /// Construct a new Number value from a numeric literal.
@@ -120,8 +608,8 @@ impl Value {
         Ok(Value::Number(
             literal
                 .to_string()
-                .parse::<i32>()
-                .map_err(|e| JabroniError::Parse(e.to_string()))?,
+                .parse::<Number>()
+                .map_err(|e| JabroniError::from_code(ErrorCode::InvalidLiteral, e.to_string()))?,
         ))
     }
 
@@ -139,42 +627,91 @@ impl Value {
         } else if literal == "false" {
             Ok(Value::Boolean(false))
         } else {
-            Err(JabroniError::Parse(format!(
-                "Couldn't form boolean literal from '{}'",
-                literal
-            )))
+            Err(JabroniError::from_code(
+                ErrorCode::InvalidLiteral,
+                format!("Couldn't form boolean literal from '{}'", literal),
+            ))
         }
     }
 
     fn unwrap_into_number(self) -> JabroniResult<Number> {
         match self {
             Value::Number(value) => Ok(value),
-            _ => Err(JabroniError::Type("Expected number".into())),
+            _ => Err(JabroniError::from_code(
+                ErrorCode::OperandTypeMismatch,
+                "Expected number".into(),
+            )),
         }
     }
 
     fn unwrap_as_number(&mut self) -> JabroniResult<&mut Number> {
         match self {
             Value::Number(value) => Ok(value),
-            _ => Err(JabroniError::Type("Expected number".into())),
+            _ => Err(JabroniError::from_code(
+                ErrorCode::OperandTypeMismatch,
+                "Expected number".into(),
+            )),
+        }
+    }
+
+    /// Apply an operator overloaded by either operand's handler, giving precedence to the left
+    /// operand. Returns `None` if neither operand overloads it. Inequality falls back to the
+    /// inverse of an overloaded equality.
+    pub fn apply_overload(
+        operator: Operator,
+        lhs: &Value,
+        rhs: &Value,
+    ) -> JabroniResult<Option<Value>> {
+        for operand in [lhs, rhs] {
+            let handler = operand
+                .as_object()
+                .and_then(|object| object.borrow().handler().cloned());
+            if let Some(handler) = handler {
+                if let Some(value) = handler.operate(operator, lhs, rhs)? {
+                    return Ok(Some(value));
+                }
+            }
+        }
+
+        let equality = match operator {
+            Operator::NotEqual => Operator::Equal,
+            Operator::StrictNotEqual => Operator::StrictEqual,
+            _ => return Ok(None),
+        };
+        match Self::apply_overload(equality, lhs, rhs)? {
+            Some(mut value) => {
+                value.inverse()?;
+                Ok(Some(value))
+            }
+            None => Ok(None),
         }
     }
 
     /// Add a Number value
     pub fn add(&mut self, value: Value) -> JabroniResult {
-        *self.unwrap_as_number()? += value.unwrap_into_number()?;
-        Ok(())
+        self.apply_arithmetic(value, Number::checked_add)
     }
 
     /// Subtract a Number value
     pub fn subtract(&mut self, value: Value) -> JabroniResult {
-        *self.unwrap_as_number()? -= value.unwrap_into_number()?;
-        Ok(())
+        self.apply_arithmetic(value, Number::checked_sub)
     }
 
     /// Multiply with a Number value
     pub fn multiply(&mut self, value: Value) -> JabroniResult {
-        *self.unwrap_as_number()? *= value.unwrap_into_number()?;
+        self.apply_arithmetic(value, Number::checked_mul)
+    }
+
+    /// Combine with a Number value, failing if the result doesn't fit in a number.
+    fn apply_arithmetic(
+        &mut self,
+        value: Value,
+        operation: fn(Number, Number) -> Option<Number>,
+    ) -> JabroniResult {
+        let number = self.unwrap_as_number()?;
+        *number = operation(*number, value.unwrap_into_number()?).ok_or_else(|| {
+            JabroniError::from_code(ErrorCode::NumberOverflow, "Number overflow".into())
+        })?;
         Ok(())
     }
 
@@ -183,45 +720,54 @@ impl Value {
         match self {
             Self::Boolean(boolean) => *self = Self::Boolean(!*boolean),
             _ => {
-                return Err(JabroniError::Type("Cannot inverse a non-boolean".into()));
+                return Err(JabroniError::from_code(
+                    ErrorCode::OperandTypeMismatch,
+                    "Cannot inverse a non-boolean".into(),
+                ));
             }
         }
         Ok(())
     }
 
-    /// Compare equality. `allow_type_diff` allows for comparisons between different types (always
-    /// false)
+    /// Compare equality, as `===` does if `allow_type_diff` is set, or as `==` does if it's not.
+    /// Both compare numbers, booleans, strings and bytes by value, and functions by identity.
+    ///
+    /// `===` never fails: values of different types aren't equal, null equals null, and objects
+    /// are only equal to themselves. `==` fails on values of different types and on nulls, and
+    /// compares objects with [`Value::deep_eq`].
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{BindingMap, Value as JabroniValue};
+    ///
+    /// let compare = |mut a: JabroniValue, b, strict| a.compare(b, strict).map(|_| a);
+    /// let (a, b) = (BindingMap::default(), BindingMap::default());
+    /// let (a, b) = (JabroniValue::Object(a.into()), JabroniValue::Object(b.into()));
+    /// assert_eq!(compare(a.clone(), b.clone(), true).unwrap(), false.into());
+    /// assert_eq!(compare(a.clone(), b, false).unwrap(), true.into());
+    /// assert_eq!(compare(a.clone(), a.clone(), true).unwrap(), true.into());
+    /// assert_eq!(compare(a.clone(), 1.into(), true).unwrap(), false.into());
+    /// assert!(compare(a, 1.into(), false).is_err());
+    /// ```
     pub fn compare(&mut self, value: Value, allow_type_diff: bool) -> JabroniResult {
+        if allow_type_diff {
+            // Values compare objects and functions by identity
+            *self = Value::Boolean(*self == value);
+            return Ok(());
+        }
         if std::mem::discriminant(self) != std::mem::discriminant(&value) {
-            *self = false.into();
-            if allow_type_diff {
-                return Ok(());
-            } else {
-                return Err(JabroniError::Type(
-                    "Cannot compare between values of different types. Try using '===' or '!=='"
-                        .into(),
-                ));
-            }
+            return Err(JabroniError::from_code(
+                ErrorCode::OperandTypeMismatch,
+                "Cannot compare between values of different types. Try using '===' or '!=='".into(),
+            ));
         }
-
-        if matches!(value, Value::Null) && !allow_type_diff {
-            return Err(JabroniError::Type(
+        if matches!(value, Value::Null) {
+            return Err(JabroniError::from_code(
+                ErrorCode::NullComparison,
                 "Can't compare null values. Use '===' or '!=='".into(),
             ));
         }
-
-        let comparison = match self {
-            Value::Boolean(v) => v == value.as_boolean().unwrap(),
-            Value::Number(v) => v == value.as_number().unwrap(),
-            Value::String(v) => v == value.as_string().unwrap(),
-            Value::Null => true,
-            _ => {
-                return Err(JabroniError::Type(
-                    "Cannot compare values of this type".into(),
-                ));
-            }
-        };
-        *self = Value::Boolean(comparison);
+        *self = Value::Boolean(self.deep_eq(&value));
         Ok(())
     }
 
@@ -232,7 +778,8 @@ impl Value {
         comparator: &dyn Fn(Number, Number) -> bool,
     ) -> JabroniResult {
         if std::mem::discriminant(self) != std::mem::discriminant(&value) {
-            return Err(JabroniError::Type(
+            return Err(JabroniError::from_code(
+                ErrorCode::OperandTypeMismatch,
                 "Cannot compare between values of different types. Try using '===' or '!=='".into(),
             ));
         }
@@ -240,7 +787,8 @@ impl Value {
         let comparison = match self {
             Value::Number(v) => comparator(*v, *value.as_number().unwrap()),
             _ => {
-                return Err(JabroniError::Type(
+                return Err(JabroniError::from_code(
+                    ErrorCode::IncomparableValues,
                     "Cannot compare values of this type".into(),
                 ));
             }
@@ -248,6 +796,213 @@ impl Value {
         *self = Value::Boolean(comparison);
         Ok(())
     }
+
+    /// Check if two values are the same, comparing objects by their properties rather than by
+    /// identity, as `==` does in scripts. Functions are only equal to themselves. Objects that
+    /// contain themselves are equal if the rest of their properties are.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Binding, BindingMap, Value as JabroniValue};
+    ///
+    /// let point = |x| {
+    ///     let mut point = BindingMap::default();
+    ///     point.set("x".into(), Binding::constant(JabroniValue::Number(x)));
+    ///     JabroniValue::Object(point.into())
+    /// };
+    /// assert!(point(1).deep_eq(&point(1)));
+    /// assert!(!point(1).deep_eq(&point(2)));
+    /// // `==` on values checks if objects are the same object
+    /// assert_ne!(point(1), point(1));
+    /// ```
+    pub fn deep_eq(&self, other: &Value) -> bool {
+        self.deep_eq_in(other, &mut Vec::new())
+    }
+
+    /// Compare values, assuming pairs of objects already being compared are equal, so objects
+    /// that contain themselves are compared by the rest of their properties.
+    fn deep_eq_in(&self, other: &Value, comparing: &mut Vec<(*const (), *const ())>) -> bool {
+        match (self, other) {
+            (Value::Object(a), Value::Object(b)) => {
+                let pair = (a.as_ptr(), b.as_ptr());
+                if a.ptr_eq(b) || comparing.contains(&pair) {
+                    return true;
+                }
+                comparing.push(pair);
+                let (a, b) = (a.borrow(), b.borrow());
+                let equal = a.iter().count() == b.iter().count()
+                    && a.iter().all(|(ident, binding)| match b.get(ident) {
+                        Ok(other) if !other.is_hidden() => {
+                            binding.value().deep_eq_in(other.value(), comparing)
+                        }
+                        _ => false,
+                    });
+                comparing.pop();
+                equal
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    /// Check if the value counts as true in a condition when coercion is loose. False, 0, null,
+    /// and empty strings and bytes are falsy, and everything else is truthy, including objects
+    /// and functions.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Number(value) => *value != 0,
+            Value::Boolean(value) => *value,
+            Value::String(value) => !value.is_empty(),
+            Value::Bytes(value) => !value.is_empty(),
+            Value::Null => false,
+            Value::Object(_) | Value::Subroutine(_) => true,
+        }
+    }
+
+    /// Check if two values are equal, as `==` does in scripts when coercion is loose. Values of
+    /// the same type are compared like [`Value::deep_eq`], and null is only equal to null.
+    /// Otherwise, booleans, numbers and strings are compared as numbers, where true is 1, false
+    /// is 0, and strings are trimmed, with an empty string being 0. Objects, functions and bytes
+    /// are never equal to values of other types.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::Value as JabroniValue;
+    ///
+    /// assert!(JabroniValue::Number(1).loose_eq(&" 1 ".into()));
+    /// assert!(JabroniValue::Boolean(false).loose_eq(&"".into()));
+    /// assert!(JabroniValue::Null.loose_eq(&JabroniValue::Null));
+    /// assert!(!JabroniValue::Null.loose_eq(&0.into()));
+    /// assert!(!JabroniValue::Number(1).loose_eq(&"one".into()));
+    /// ```
+    pub fn loose_eq(&self, other: &Value) -> bool {
+        if std::mem::discriminant(self) == std::mem::discriminant(other) {
+            return self.deep_eq(other);
+        }
+        match (self.coerce_to_number(), other.coerce_to_number()) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// Convert a boolean, number or string to a number for loose comparisons.
+    fn coerce_to_number(&self) -> Option<Number> {
+        match self {
+            Value::Number(value) => Some(*value),
+            Value::Boolean(value) => Some((*value).into()),
+            Value::String(value) if value.trim().is_empty() => Some(0),
+            Value::String(value) => value.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Visit this value and every value nested in it, depth first, with the names of the
+    /// properties leading to each one. Properties are visited in the order they were set.
+    ///
+    /// # Example
+    /// ```
+    /// use jabroni::{Binding, BindingMap, Value as JabroniValue};
+    ///
+    /// let mut user = BindingMap::default();
+    /// user.set("name".into(), Binding::constant(JabroniValue::String("Ann".into())));
+    /// user.set("age".into(), Binding::constant(JabroniValue::Number(30)));
+    ///
+    /// let mut paths = Vec::new();
+    /// JabroniValue::Object(user.into()).walk(|path, value| {
+    ///     if value.as_object().is_none() {
+    ///         paths.push(format!("{}: {value}", path.join(".")));
+    ///     }
+    /// });
+    /// assert_eq!(paths, ["name: Ann", "age: 30"]);
+    /// ```
+    pub fn walk(&self, callback: impl FnMut(&[&str], &Value)) {
+        struct Walk<F>(F);
+        impl<F: FnMut(&[&str], &Value)> ValueVisitor for Walk<F> {
+            fn visit(&mut self, path: &[&str], value: &Value) -> bool {
+                (self.0)(path, value);
+                true
+            }
+        }
+        self.accept(&mut Walk(callback));
+    }
+
+    /// Visit this value and the values nested in it with `visitor`, depth first. Properties are
+    /// visited in the order they were set, and an object nested in itself is only visited once.
+    pub fn accept(&self, visitor: &mut impl ValueVisitor) {
+        self.accept_at(visitor, &mut Vec::new(), &mut Vec::new());
+    }
+
+    fn accept_at(
+        &self,
+        visitor: &mut impl ValueVisitor,
+        path: &mut Vec<String>,
+        ancestors: &mut Vec<*const ()>,
+    ) {
+        if let Value::Object(object) = self {
+            if ancestors.contains(&object.as_ptr()) {
+                return;
+            }
+        }
+        fn names(path: &[String]) -> Vec<&str> {
+            path.iter().map(String::as_str).collect()
+        }
+        if !visitor.visit(&names(path), self) {
+            return;
+        }
+        if let Value::Object(object) = self {
+            ancestors.push(object.as_ptr());
+            for (name, binding) in object.borrow().iter() {
+                path.push(name.into());
+                binding.value().accept_at(visitor, path, ancestors);
+                path.pop();
+            }
+            ancestors.pop();
+            visitor.leave_object(&names(path), &object.borrow());
+        }
+    }
+}
+
+/// Visits values nested in a value, for host code that needs to inspect, validate or redact what
+/// scripts produce, with [`Value::accept`]. Each method gets the names of the properties leading
+/// to the value from the one being visited.
+///
+/// # Example
+/// ```
+/// use jabroni::{Binding, BindingMap, Jabroni, Value as JabroniValue, ValueVisitor};
+///
+/// // Find strings that look like secrets, without looking inside `public`
+/// struct Secrets(Vec<String>);
+///
+/// impl ValueVisitor for Secrets {
+///     fn visit(&mut self, path: &[&str], value: &JabroniValue) -> bool {
+///         if let JabroniValue::String(string) = value {
+///             if string.starts_with("sk-") {
+///                 self.0.push(path.join("."));
+///             }
+///         }
+///         path.last() != Some(&"public")
+///     }
+/// }
+///
+/// let mut public = BindingMap::default();
+/// public.set("key".into(), Binding::constant(JabroniValue::String("sk-public".into())));
+/// let mut config = BindingMap::default();
+/// config.set("key".into(), Binding::constant(JabroniValue::String("sk-123".into())));
+/// config.set("public".into(), Binding::constant(JabroniValue::Object(public.into())));
+///
+/// let mut interpreter = Jabroni::new();
+/// interpreter.define_constant("config", JabroniValue::Object(config.into()));
+/// let config = interpreter.run_expression("config").unwrap();
+/// let mut secrets = Secrets(Vec::new());
+/// config.accept(&mut secrets);
+/// assert_eq!(secrets.0, ["key"]);
+/// ```
+pub trait ValueVisitor {
+    /// Called for each value before the values nested in it. Return `false` to skip the values
+    /// nested in it.
+    fn visit(&mut self, path: &[&str], value: &Value) -> bool;
+
+    /// Called after the properties of an object have been visited.
+    fn leave_object(&mut self, _path: &[&str], _object: &BindingMap) {}
 }
 
 impl From<bool> for Value {
@@ -262,16 +1017,228 @@ impl From<Number> for Value {
     }
 }
 
+impl From<&str> for Value {
+    fn from(value: &str) -> Value {
+        Value::String(value.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Value {
+        Value::String(value.into())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(value: Vec<u8>) -> Value {
+        Value::Bytes(value)
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(value: &[u8]) -> Value {
+        Value::Bytes(value.into())
+    }
+}
+
+impl From<()> for Value {
+    fn from(_: ()) -> Value {
+        Value::Null
+    }
+}
+
+/// `None` becomes null.
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Value {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl From<Object> for Value {
+    fn from(value: Object) -> Value {
+        Value::Object(value)
+    }
+}
+
+impl From<BindingMap> for Value {
+    fn from(value: BindingMap) -> Value {
+        Value::Object(value.into())
+    }
+}
+
+/// Makes an object with a constant property for each entry, in order of their keys.
+///
+/// # Example
+/// ```
+/// use jabroni::{Jabroni, Value as JabroniValue};
+/// use std::collections::HashMap;
+///
+/// let mut interpreter = Jabroni::new();
+/// interpreter.define_constant("name", "dave".into()).unwrap();
+/// let mut config = HashMap::new();
+/// config.insert("debug".to_string(), true.into());
+/// config.insert("motd".to_string(), None::<&str>.into());
+/// interpreter.define_constant("config", config.into()).unwrap();
+/// assert_eq!(
+///     interpreter.run_expression("config.debug ? name : null").unwrap(),
+///     "dave".into()
+/// );
+/// assert_eq!(interpreter.run_expression("config.motd").unwrap(), ().into());
+/// ```
+impl<S: Into<String>> From<HashMap<S, Value>> for Value {
+    fn from(value: HashMap<S, Value>) -> Value {
+        // Hash maps iterate in a random order, so properties are sorted to always be in the same
+        let mut entries: Vec<(String, Value)> = value
+            .into_iter()
+            .map(|(ident, value)| (ident.into(), value))
+            .collect();
+        entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        let mut object = BindingMap::default();
+        for (ident, value) in entries {
+            object.set(ident, Binding::constant(value));
+        }
+        object.into()
+    }
+}
+
+impl From<Subroutine> for Value {
+    fn from(value: Subroutine) -> Value {
+        Value::Subroutine(value)
+    }
+}
+
+fn unexpected_type(expected: &str, value: &Value) -> JabroniError {
+    JabroniError::from_code(
+        ErrorCode::UnexpectedType,
+        format!("Expected {}, found {}", expected, value.type_name()),
+    )
+}
+
+/// Get a number out of a value, failing with a Type error if it's anything else.
+///
+/// # Example
+/// ```
+/// use jabroni::{errors::ErrorKind, Jabroni};
+/// use std::convert::TryFrom;
+///
+/// let mut interpreter = Jabroni::new();
+/// let total = i64::try_from(interpreter.run_expression("2 + 3").unwrap()).unwrap();
+/// assert_eq!(total, 5);
+/// let error = i64::try_from(interpreter.run_expression("'five'").unwrap()).unwrap_err();
+/// assert_eq!(error.kind(), ErrorKind::Type);
+/// ```
+impl TryFrom<Value> for Number {
+    type Error = JabroniError;
+
+    fn try_from(value: Value) -> JabroniResult<Number> {
+        match value {
+            Value::Number(value) => Ok(value),
+            value => Err(unexpected_type("number", &value)),
+        }
+    }
+}
+
+/// Get a number out of a value, for hosts that keep numbers in an `i32`. Fails with a Type error
+/// if it's anything else, and a Limit error if the number doesn't fit.
+///
+/// # Example
+/// ```
+/// use jabroni::{errors::ErrorCode, Jabroni};
+/// use std::convert::TryFrom;
+///
+/// let mut interpreter = Jabroni::new();
+/// let total = i32::try_from(interpreter.run_expression("2 + 3").unwrap()).unwrap();
+/// assert_eq!(total, 5);
+/// let error = i32::try_from(interpreter.run_expression("3000000000").unwrap()).unwrap_err();
+/// assert_eq!(error.code(), Some(ErrorCode::NumberOverflow));
+/// ```
+impl TryFrom<Value> for i32 {
+    type Error = JabroniError;
+
+    fn try_from(value: Value) -> JabroniResult<i32> {
+        i32::try_from(Number::try_from(value)?).map_err(|_| {
+            JabroniError::from_code(
+                ErrorCode::NumberOverflow,
+                "Number doesn't fit in 32 bits".into(),
+            )
+        })
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = JabroniError;
+
+    fn try_from(value: Value) -> JabroniResult<bool> {
+        match value {
+            Value::Boolean(value) => Ok(value),
+            value => Err(unexpected_type("boolean", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = JabroniError;
+
+    fn try_from(value: Value) -> JabroniResult<String> {
+        match value {
+            Value::String(value) => Ok(value.to_string()),
+            value => Err(unexpected_type("string", &value)),
+        }
+    }
+}
+
+/// Get the properties of an object, converting each of their values.
+///
+/// # Example
+/// ```
+/// use jabroni::{Binding, BindingMap, Jabroni};
+/// use std::{collections::HashMap, convert::TryFrom};
+///
+/// let mut interpreter = Jabroni::new();
+/// let mut stats = BindingMap::default();
+/// stats.set("hp".into(), Binding::variable(5.into()));
+/// stats.set("mp".into(), Binding::variable(3.into()));
+/// interpreter.define_constant("stats", stats.into()).unwrap();
+/// interpreter.run_script("stats.hp = stats.hp * 2;").unwrap();
+/// let stats = interpreter.run_expression("stats").unwrap();
+/// let stats = HashMap::<String, i32>::try_from(stats).unwrap();
+/// assert_eq!(stats["hp"], 10);
+/// assert_eq!(stats["mp"], 3);
+/// ```
+impl<T> TryFrom<Value> for HashMap<String, T>
+where
+    T: TryFrom<Value>,
+    T::Error: Into<JabroniError>,
+{
+    type Error = JabroniError;
+
+    fn try_from(value: Value) -> JabroniResult<HashMap<String, T>> {
+        let object = match value {
+            Value::Object(object) => object,
+            value => return Err(unexpected_type("object", &value)),
+        };
+        let properties = object
+            .borrow()
+            .iter()
+            .map(|(ident, binding)| Ok((ident.to_string(), binding.read()?)))
+            .collect::<JabroniResult<Vec<_>>>()?;
+        properties
+            .into_iter()
+            .map(|(ident, value)| Ok((ident, T::try_from(value).map_err(Into::into)?)))
+            .collect()
+    }
+}
+
 impl Display for Value {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
             Value::Number(value) => write!(f, "{}", value),
             Value::Boolean(value) => write!(f, "{}", value),
             Value::String(value) => write!(f, "{}", value),
+            Value::Bytes(value) => write!(f, "[bytes {}]", bytes::to_hex(value)),
             Value::Null => write!(f, "null"),
-            // These aren't consistent with JavaScript
-            Value::Object(_) => write!(f, "[function]"),
-            Value::Subroutine(_) => write!(f, "[object]"),
+            Value::Object(object) => Display::fmt(object, f),
+            Value::Subroutine(subroutine) => write!(f, "{}", subroutine),
         }
     }
 }
@@ -0,0 +1,82 @@
+//! Checks for code that runs, but probably doesn't do what was intended.
+
+use crate::state::{collapse, Rule};
+use pest::{
+    iterators::{Pair, Pairs},
+    Span,
+};
+use std::collections::HashSet;
+
+/// Find variables declared in function bodies that are never used. Top-level declarations are
+/// left alone, because the host might use them.
+pub fn unused_locals(pairs: Pairs<Rule>) -> Vec<Span> {
+    let mut unused = Vec::new();
+    for pair in pairs.flatten() {
+        if pair.as_rule() != Rule::function_statement {
+            continue;
+        }
+        let body = pair.into_inner().last().unwrap();
+        let mut declared = Vec::new();
+        declarations(body.clone(), &mut declared);
+        let mut used = HashSet::new();
+        uses(body, &mut used);
+        unused.extend(
+            declared
+                .into_iter()
+                .filter(|ident| !used.contains(ident.as_str())),
+        );
+    }
+    unused
+}
+
+/// Collect the identifiers declared in a function body, but not in functions nested in it.
+fn declarations<'a>(pair: Pair<'a, Rule>, declared: &mut Vec<Span<'a>>) {
+    match pair.as_rule() {
+        Rule::function_statement => (),
+        Rule::declaration_statement => declared.push(pair.into_inner().nth(1).unwrap().as_span()),
+        _ => {
+            for pair in pair.into_inner() {
+                declarations(pair, declared);
+            }
+        }
+    }
+}
+
+/// Collect the identifiers referred to in code, including in nested functions, which can see
+/// the bindings of their caller.
+fn uses<'a>(pair: Pair<'a, Rule>, used: &mut HashSet<&'a str>) {
+    match pair.as_rule() {
+        Rule::ident => {
+            used.insert(pair.as_str());
+        }
+        // Skip the identifier being declared
+        Rule::declaration_statement => {
+            for pair in pair.into_inner().skip(2) {
+                uses(pair, used);
+            }
+        }
+        // Skip the property name
+        Rule::property => (),
+        _ => {
+            for pair in pair.into_inner() {
+                uses(pair, used);
+            }
+        }
+    }
+}
+
+/// Find assignments used as the condition of a ternary, which are probably meant to be
+/// comparisons.
+pub fn assignments_in_conditions(pairs: Pairs<Rule>) -> Vec<Span> {
+    pairs
+        .flatten()
+        .filter(|pair| pair.as_rule() == Rule::ternary && pair.clone().into_inner().count() > 1)
+        .filter_map(|pair| {
+            let mut condition = collapse(pair.into_inner().next().unwrap());
+            while condition.as_rule() == Rule::expression {
+                condition = collapse(condition.into_inner().next().unwrap());
+            }
+            (condition.as_rule() == Rule::assignment).then(|| condition.as_span())
+        })
+        .collect()
+}